@@ -0,0 +1,191 @@
+//! A persistent record of triggers and command runs, written as one JSON object per line.
+
+use std::{
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+    process::ExitStatus,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Context;
+
+use crate::watcher::ExecutionTrigger;
+
+/// Appends events to a file, if one was requested.
+pub struct EventLog {
+    file: Option<File>,
+}
+
+#[derive(serde::Serialize)]
+struct Record<'a> {
+    time: String,
+    #[serde(flatten)]
+    event: Event<'a>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Event<'a> {
+    Trigger {
+        kind: Option<&'static str>,
+        addr: Option<String>,
+        paths: &'a [PathBuf],
+        events: usize,
+        group: Option<&'a str>,
+        rule: Option<&'a str>,
+    },
+    Started {
+        pid: Option<u32>,
+        command: &'a [String],
+    },
+    Exited {
+        code: Option<i32>,
+        signal: Option<i32>,
+        success: bool,
+    },
+    TimedOut {
+        timeout_ms: u128,
+    },
+}
+
+impl EventLog {
+    pub fn open(path: Option<&Path>) -> anyhow::Result<EventLog> {
+        let file = match path {
+            None => None,
+            Some(path) => Some(
+                File::options()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .with_context(|| format!("failed to open event log: {}", path.display()))?,
+            ),
+        };
+        Ok(EventLog { file })
+    }
+
+    pub fn trigger(&mut self, trigger: &ExecutionTrigger) {
+        self.record(Event::Trigger {
+            kind: trigger.source.map(|source| source.kind()),
+            addr: trigger
+                .source
+                .and_then(|source| source.addr())
+                .map(|addr| addr.to_string()),
+            paths: &trigger.paths,
+            events: trigger.events,
+            group: trigger.group.as_deref(),
+            rule: trigger.rule.as_deref(),
+        })
+    }
+
+    pub fn started(&mut self, pid: Option<u32>, command: &[String]) {
+        self.record(Event::Started { pid, command })
+    }
+
+    pub fn exited(&mut self, status: ExitStatus) {
+        #[cfg(unix)]
+        let signal = std::os::unix::process::ExitStatusExt::signal(&status);
+        #[cfg(not(unix))]
+        let signal = None;
+
+        self.record(Event::Exited {
+            code: status.code(),
+            signal,
+            success: status.success(),
+        })
+    }
+
+    pub fn timed_out(&mut self, timeout: Duration) {
+        self.record(Event::TimedOut {
+            timeout_ms: timeout.as_millis(),
+        })
+    }
+
+    fn record(&mut self, event: Event) {
+        let file = match &mut self.file {
+            None => return,
+            Some(file) => file,
+        };
+
+        let record = Record {
+            time: humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+            event,
+        };
+
+        let mut line = serde_json::to_vec(&record).expect("events are always valid JSON");
+        line.push(b'\n');
+
+        // write the whole line at once and flush it, so that a crash does not lose records
+        if let Err(error) = file.write_all(&line).and_then(|()| file.flush()) {
+            warn!(%error, "failed to write to event log");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_events(path: &Path) -> Vec<serde_json::Value> {
+        std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn record_events() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.jsonl");
+        let mut log = EventLog::open(Some(&path)).unwrap();
+
+        let trigger = ExecutionTrigger {
+            paths: vec![PathBuf::from("src/main.rs")],
+            source: Some(crate::watcher::TriggerSource::File),
+            events: 2,
+            time: None,
+            group: Some("rust".into()),
+            ..ExecutionTrigger::default()
+        };
+        log.trigger(&trigger);
+        log.started(Some(42), &["cargo".into(), "test".into()]);
+        log.timed_out(Duration::from_secs(3));
+
+        let events = read_events(&path);
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0]["event"], "trigger");
+        assert_eq!(events[0]["kind"], "file");
+        assert_eq!(events[0]["paths"][0], "src/main.rs");
+        assert_eq!(events[0]["events"], 2);
+        assert_eq!(events[0]["group"], "rust");
+        assert!(events[0]["rule"].is_null());
+        assert_eq!(events[1]["event"], "started");
+        assert_eq!(events[1]["pid"], 42);
+        assert_eq!(events[1]["command"][1], "test");
+        assert_eq!(events[2]["event"], "timed_out");
+        assert_eq!(events[2]["timeout_ms"], 3000);
+        assert!(events.iter().all(|event| event["time"].is_string()));
+    }
+
+    /// Events are appended to an existing log
+    #[test]
+    fn append() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.jsonl");
+        EventLog::open(Some(&path))
+            .unwrap()
+            .timed_out(Duration::ZERO);
+        EventLog::open(Some(&path))
+            .unwrap()
+            .timed_out(Duration::ZERO);
+        assert_eq!(read_events(&path).len(), 2);
+    }
+
+    #[test]
+    fn not_writable() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(EventLog::open(Some(dir.path())).is_err());
+        assert!(EventLog::open(Some(&dir.path().join("missing/events.jsonl"))).is_err());
+    }
+}
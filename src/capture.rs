@@ -0,0 +1,148 @@
+//! Capturing the output of the command to a log file, and showing it only when the command fails.
+
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::Write,
+    path::PathBuf,
+    process::ExitStatus,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Context;
+
+use crate::cli;
+
+/// How much of the latest output is kept in memory for `--show-on-failure`. Anything before that
+/// is only available in the `--log-output` file.
+const TAIL_LIMIT: usize = 64 * 1024;
+
+/// Collects the output of each run. Cheap to clone, since the clones share the same state.
+#[derive(Clone)]
+pub struct Capture {
+    inner: Arc<Mutex<Inner>>,
+    /// Output is shown as it arrives, unless it is held back until the command fails
+    pub live: bool,
+}
+
+struct Inner {
+    log: Option<(PathBuf, File)>,
+    /// The latest output of the current run, with `--show-on-failure`
+    tail: Option<Tail>,
+}
+
+/// The last bytes written, bounded in size
+#[derive(Default)]
+struct Tail {
+    bytes: VecDeque<u8>,
+    /// Number of bytes which were dropped from the front
+    omitted: usize,
+}
+
+impl Capture {
+    /// Set up capturing, if any of the options ask for it
+    pub fn from_args(behaviour: &cli::BehaviourOptions) -> anyhow::Result<Option<Capture>> {
+        if behaviour.log_output.is_none() && !behaviour.show_on_failure {
+            return Ok(None);
+        }
+
+        let log = match &behaviour.log_output {
+            None => None,
+            Some(path) => {
+                let file = File::options()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .with_context(|| format!("failed to open output log: {}", path.display()))?;
+                Some((path.clone(), file))
+            }
+        };
+
+        let inner = Inner {
+            log,
+            tail: behaviour.show_on_failure.then(Tail::default),
+        };
+        Ok(Some(Capture {
+            inner: Arc::new(Mutex::new(inner)),
+            live: !behaviour.show_on_failure,
+        }))
+    }
+
+    /// A new run of the command is starting, so forget the output of any earlier run which was
+    /// cut short
+    pub fn begin(&self) {
+        if let Some(tail) = &mut self.inner.lock().unwrap().tail {
+            *tail = Tail::default();
+        }
+    }
+
+    /// Record some output of the command
+    pub fn record(&self, output: &[u8]) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some((path, file)) = &mut inner.log {
+            if let Err(error) = file.write_all(output) {
+                warn!(%error, ?path, "failed to write to output log");
+            }
+        }
+        if let Some(tail) = &mut inner.tail {
+            tail.push(output);
+        }
+    }
+
+    /// The command has exited, so show its output if it failed, and start over for the next run
+    pub fn finished(&self, status: ExitStatus) -> std::io::Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        let tail = match &mut inner.tail {
+            Some(tail) => std::mem::take(tail),
+            None => return Ok(()),
+        };
+        if status.success() {
+            return Ok(());
+        }
+
+        let mut stdout = std::io::stdout().lock();
+        if tail.omitted != 0 {
+            match &inner.log {
+                Some((path, _)) => writeln!(
+                    stdout,
+                    "... {} bytes omitted, see {}",
+                    tail.omitted,
+                    path.display()
+                )?,
+                None => writeln!(stdout, "... {} bytes omitted", tail.omitted)?,
+            }
+        }
+        let (front, back) = tail.bytes.as_slices();
+        stdout.write_all(front)?;
+        stdout.write_all(back)?;
+        stdout.flush()
+    }
+}
+
+impl Tail {
+    fn push(&mut self, output: &[u8]) {
+        self.bytes.extend(output);
+        let excess = self.bytes.len().saturating_sub(TAIL_LIMIT);
+        self.bytes.drain(..excess);
+        self.omitted += excess;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tail_is_bounded() {
+        let mut tail = Tail::default();
+        tail.push(b"first\n");
+        assert_eq!(tail.bytes, b"first\n");
+
+        let chatty = vec![b'x'; TAIL_LIMIT];
+        tail.push(&chatty);
+        tail.push(b"last\n");
+        assert_eq!(tail.bytes.len(), TAIL_LIMIT);
+        assert_eq!(tail.omitted, 11);
+        assert!(tail.bytes.iter().rev().take(5).eq(b"last\n".iter().rev()));
+    }
+}
@@ -0,0 +1,157 @@
+//! Ranges of IP addresses in CIDR notation, such as `192.168.0.0/16`.
+
+use std::{net::IpAddr, str::FromStr};
+
+use anyhow::{anyhow, Context};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    /// Returns `true` if the address is within the range
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        match (self.addr, canonical(addr)) {
+            (IpAddr::V4(range), IpAddr::V4(addr)) => prefix_matches(
+                u32::from(range).into(),
+                u32::from(addr).into(),
+                32,
+                self.prefix,
+            ),
+            (IpAddr::V6(range), IpAddr::V6(addr)) => {
+                prefix_matches(u128::from(range), u128::from(addr), 128, self.prefix)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Returns `true` if the address is allowed by any of the ranges, or if there are no ranges.
+pub fn allowed(ranges: &[Cidr], addr: IpAddr) -> bool {
+    ranges.is_empty() || ranges.iter().any(|range| range.contains(addr))
+}
+
+/// IPv4 clients connecting to an IPv6 socket show up as IPv4-mapped addresses
+fn canonical(addr: IpAddr) -> IpAddr {
+    match addr {
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => IpAddr::V4(v4),
+            None => addr,
+        },
+        IpAddr::V4(_) => addr,
+    }
+}
+
+fn prefix_matches(range: u128, addr: u128, bits: u8, prefix: u8) -> bool {
+    let ignored = u32::from(bits - prefix);
+    range.checked_shr(ignored).unwrap_or(0) == addr.checked_shr(ignored).unwrap_or(0)
+}
+
+impl FromStr for Cidr {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> anyhow::Result<Cidr> {
+        let (addr, prefix) = match text.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (text, None),
+        };
+
+        let addr = addr
+            .parse::<IpAddr>()
+            .with_context(|| format!("invalid IP address: {addr}"))?;
+        let bits = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+
+        // a single address if no prefix is given
+        let prefix = match prefix {
+            None => bits,
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix| *prefix <= bits)
+                .ok_or_else(|| anyhow!("invalid prefix length for {addr}: {prefix}"))?,
+        };
+
+        Ok(Cidr { addr, prefix })
+    }
+}
+
+impl std::fmt::Display for Cidr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cidr(text: &str) -> Cidr {
+        text.parse().unwrap()
+    }
+
+    fn ip(text: &str) -> IpAddr {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn ipv4_ranges() {
+        let range = cidr("192.168.0.0/16");
+        assert!(range.contains(ip("192.168.0.1")));
+        assert!(range.contains(ip("192.168.255.255")));
+        assert!(!range.contains(ip("192.169.0.1")));
+        assert!(!range.contains(ip("10.0.0.1")));
+
+        assert!(cidr("10.1.2.3").contains(ip("10.1.2.3")));
+        assert!(!cidr("10.1.2.3").contains(ip("10.1.2.4")));
+        assert!(cidr("0.0.0.0/0").contains(ip("8.8.8.8")));
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(cidr("192.168.0.0/16").to_string(), "192.168.0.0/16");
+        assert_eq!(cidr("10.1.2.3").to_string(), "10.1.2.3/32");
+        assert_eq!(cidr("fd00::/8").to_string(), "fd00::/8");
+    }
+
+    #[test]
+    fn ipv6_ranges() {
+        let range = cidr("fd00::/8");
+        assert!(range.contains(ip("fd12:3456::1")));
+        assert!(!range.contains(ip("fe80::1")));
+        assert!(!range.contains(ip("10.0.0.1")));
+        assert!(cidr("::1").contains(ip("::1")));
+    }
+
+    #[test]
+    fn ipv4_mapped() {
+        assert!(cidr("127.0.0.0/8").contains(ip("::ffff:127.0.0.1")));
+    }
+
+    #[test]
+    fn invalid() {
+        for text in [
+            "",
+            "10.0.0.0/33",
+            "::/129",
+            "10.0.0/8",
+            "10.0.0.0/",
+            "host/8",
+        ] {
+            assert!(text.parse::<Cidr>().is_err(), "{text}");
+        }
+    }
+
+    #[test]
+    fn empty_allows_everything() {
+        assert!(allowed(&[], ip("8.8.8.8")));
+        let ranges = [cidr("10.0.0.0/8"), cidr("127.0.0.1")];
+        assert!(allowed(&ranges, ip("10.1.1.1")));
+        assert!(allowed(&ranges, ip("127.0.0.1")));
+        assert!(!allowed(&ranges, ip("127.0.0.2")));
+    }
+}
@@ -1,21 +1,78 @@
+mod config;
 mod parse;
+mod serialize;
 
-use std::{ffi::OsString, path::PathBuf, str::FromStr, time::Duration};
+use std::{
+    ffi::{OsStr, OsString},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 use anyhow::{anyhow, Context};
 
 /// Trigger a command in response to certain events
-#[derive(Debug, clap::Parser)]
+#[derive(Debug, Clone, clap::Parser, serde::Serialize)]
 #[clap(version = env!("CARGO_PKG_VERSION"))]
 #[clap(author = "Christofer Nolander <christofer.nolander@gmail.com>")]
 #[clap(global_setting = clap::AppSettings::DeriveDisplayOrder)]
 #[clap(trailing_var_arg(true))]
 #[clap(color = clap::ColorChoice::Auto)]
 pub struct Arguments {
-    /// Enable more verbose logging.
-    #[clap(long)]
+    /// Enable more verbose logging. Repeat for even more: `-v` shows what witness is doing, `-vv`
+    /// the details of every event, and `-vvv` everything.
+    #[clap(long, short, parse(from_occurrences))]
+    #[clap(global = true)]
+    pub verbose: u8,
+
+    /// Only log errors, and never clear the screen. Cannot be combined with `--verbose`.
+    #[clap(long, short)]
+    #[clap(global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// The format of log messages
+    #[clap(long, arg_enum)]
+    #[clap(default_value = "text")]
+    #[clap(global = true)]
+    pub log_format: LogFormat,
+
+    /// When to color the output: the command banner, its exit status and log messages. `auto`
+    /// colors output to a terminal, unless `NO_COLOR` is set.
+    #[clap(long, arg_enum, value_name = "WHEN")]
+    #[clap(default_value = "auto")]
+    #[clap(global = true)]
+    pub color: ColorMode,
+
+    /// Prefix log messages and the command banner with this name, which tells apart several
+    /// instances sharing a terminal. Included as the `label` field with `--log-format json`.
+    #[clap(long, value_name = "NAME")]
     #[clap(global = true)]
-    pub verbose: bool,
+    pub label: Option<String>,
+
+    /// Read default arguments from this file instead of the closest `witness.toml`
+    #[clap(long, value_name = "PATH")]
+    #[clap(parse(from_os_str))]
+    pub config: Option<PathBuf>,
+
+    /// Print a script which completes arguments in the shell (bash, zsh, fish, powershell or
+    /// elvish), then exit
+    #[clap(long, value_name = "SHELL")]
+    #[clap(hide = true)]
+    #[serde(serialize_with = "serialize::optional_display")]
+    pub completions: Option<clap_complete::Shell>,
+
+    /// Print whether a change to this path would trigger execution, and why not if it wouldn't,
+    /// then exit. Takes the other file options into account, without watching anything.
+    #[clap(long, value_name = "PATH")]
+    #[clap(parse(from_os_str))]
+    pub check_path: Option<PathBuf>,
+
+    /// Print the arguments as they are after applying the config file, environment variables and
+    /// defaults, then exit. Keys are redacted. Given as `--print-config` or `--print-config=json`.
+    #[clap(long, arg_enum, value_name = "FORMAT")]
+    #[clap(min_values = 0, require_equals = true, default_missing_value = "toml")]
+    #[serde(skip)]
+    pub print_config: Option<ConfigFormat>,
 
     /// Watch over file changes
     #[clap(next_help_heading = "FILES")]
@@ -36,29 +93,67 @@ pub struct Arguments {
     /// If you want to chain commands or pipe output from one command to another, surround the
     /// commands in quotes. Example: `witness "ls | less"` would run `ls` and pipe its output to
     /// `less`.
-    #[clap(required_unless_present = "trigger")]
+    #[clap(required_unless_present_any = &[
+        "trigger",
+        "dry-run",
+        "emit-triggers",
+        "completions",
+        "check-path",
+        "print-config",
+    ])]
     #[clap(multiple_values = true)]
     #[clap(value_hint = clap::ValueHint::CommandWithArguments)]
     pub command: Vec<String>,
+
+    /// Rules from the config file, each watching its own paths and running its own command.
+    /// Empty if a command was given.
+    #[clap(skip)]
+    pub rules: Vec<Rule>,
+
+    /// The name of the rule these arguments were derived from
+    #[clap(skip)]
+    pub rule: Option<String>,
 }
 
 /// Options affecting how watched files are treated.
-#[derive(Debug, clap::Parser)]
+#[derive(Debug, Clone, clap::Parser, serde::Serialize)]
 #[clap(
     group = clap::ArgGroup::new("files")
-        .args(&["paths", "ignore", "debounce", "extensions", "no-git-ignore"])
+        .args(&[
+            "paths",
+            "ignore",
+            "include",
+            "exclude",
+            "path-regex",
+            "under",
+            "not-under",
+            "debounce",
+            "debounce-mode",
+            "debounce-groups",
+            "debounce-adaptive",
+            "debounce-max",
+            "poll",
+            "extensions",
+            "ignore-case-ext",
+            "exclude-extensions",
+            "no-git-ignore",
+            "no-ignore",
+            "max-depth",
+            "ignore-dirs",
+            "events",
+            "follow-symlinks",
+            "content-hash",
+            "on-git-change",
+            "hidden",
+            "no-hidden",
+            "control",
+        ])
         .multiple(true)
 )]
 pub struct FileOptions {
-    /// Paths to watch for changes
-    #[clap(long = "path")]
-    #[clap(default_value = ".")]
-    #[clap(default_value_if("udp", None, None))]
-    #[clap(default_value_if("tcp", None, None))]
-    #[clap(value_delimiter = ',')]
-    #[clap(multiple_occurrences = true)]
-    #[clap(parse(from_os_str))]
-    pub paths: Vec<PathBuf>,
+    #[clap(flatten)]
+    #[serde(flatten)]
+    pub paths: WatchedPaths,
 
     /// Modifications to these paths will be ignored
     #[clap(short, long = "ignore")]
@@ -67,27 +162,185 @@ pub struct FileOptions {
     #[clap(parse(from_os_str))]
     pub ignore: Vec<PathBuf>,
 
-    /// Duration between when a file changes and execution is triggered
+    /// Only paths matching one of these glob patterns trigger execution
+    ///
+    /// Patterns are matched against paths relative to the watched path, e.g. `src/**/*.rs`.
+    #[clap(long = "include")]
+    #[clap(multiple_occurrences = true)]
+    #[clap(parse(try_from_str = parse::glob_from_str))]
+    #[serde(serialize_with = "serialize::display_all")]
+    pub include: Vec<globset::Glob>,
+
+    /// Paths matching any of these glob patterns will be ignored
+    #[clap(long = "exclude")]
+    #[clap(multiple_occurrences = true)]
+    #[clap(parse(try_from_str = parse::glob_from_str))]
+    #[serde(serialize_with = "serialize::display_all")]
+    pub exclude: Vec<globset::Glob>,
+
+    /// Only paths matching one of these regular expressions trigger execution
+    ///
+    /// Like globs, patterns are matched against paths relative to the watched path. A pattern
+    /// matches anywhere in the path unless anchored with `^` and `$`.
+    #[clap(long = "path-regex", value_name = "PATTERN")]
+    #[clap(multiple_occurrences = true)]
+    #[clap(parse(try_from_str = parse::regex_from_str))]
+    #[serde(serialize_with = "serialize::display_all")]
+    pub path_regex: Vec<regex::Regex>,
+
+    /// Only paths within one of these directories trigger execution. Unlike globs, these are
+    /// plain directories, compared after resolving `.`, `..` and symlinks.
+    #[clap(long, value_name = "DIR")]
+    #[clap(multiple_occurrences = true)]
+    #[clap(parse(from_os_str))]
+    pub under: Vec<PathBuf>,
+
+    /// Paths within these directories never trigger execution
+    #[clap(long, value_name = "DIR")]
+    #[clap(multiple_occurrences = true)]
+    #[clap(parse(from_os_str))]
+    pub not_under: Vec<PathBuf>,
+
+    /// How long to wait for further changes before triggering execution (see `--debounce-mode`)
     #[clap(long)]
     #[clap(default_value = "100ms")]
     #[clap(parse(try_from_str = parse::duration_from_str))]
+    #[serde(serialize_with = "serialize::duration")]
     pub debounce: Duration,
 
+    /// How changes are grouped into a single execution
+    #[clap(long, arg_enum, value_name = "MODE")]
+    #[clap(default_value = "settle")]
+    pub debounce_mode: DebounceMode,
+
+    /// Debounce files with these extensions separately from other changes, written as
+    /// `<name>:<extensions>:<duration>` (e.g. `assets:png,svg:2s`). The name of the group is
+    /// passed to the command in `WITNESS_TRIGGER_GROUP`.
+    #[clap(long = "debounce-group", value_name = "NAME:EXTENSIONS:DURATION")]
+    #[clap(multiple_occurrences = true)]
+    #[clap(parse(try_from_str = parse::debounce_group_from_str))]
+    pub debounce_groups: Vec<DebounceGroup>,
+
+    /// Lengthen the debounce window while changes keep arriving just after it closes, and shorten
+    /// it again once things quiet down. The window never gets shorter than `--debounce` (or the
+    /// duration of a `--debounce-group`), nor longer than `--debounce-max`.
+    #[clap(long)]
+    pub debounce_adaptive: bool,
+
+    /// The longest debounce window used with `--debounce-adaptive`
+    #[clap(long, value_name = "DURATION")]
+    #[clap(default_value = "2s")]
+    #[clap(parse(try_from_str = parse::duration_from_str))]
+    #[serde(serialize_with = "serialize::duration")]
+    pub debounce_max: Duration,
+
+    /// Don't watch directories with these names directly within the watched paths, which saves
+    /// the OS from reporting changes to build output and dependencies. Use `--ignore-dir=` to
+    /// watch everything.
+    #[clap(long = "ignore-dir", value_name = "NAMES")]
+    #[clap(value_delimiter = ',')]
+    #[clap(multiple_occurrences = true)]
+    #[clap(default_values = &["target", "node_modules", ".git"])]
+    #[clap(parse(from_os_str))]
+    #[serde(serialize_with = "serialize::os_strings")]
+    pub ignore_dirs: Vec<OsString>,
+
+    /// Poll for changes at this interval instead of relying on notifications from the OS. Useful
+    /// for network filesystems (NFS, SMB) and some container mounts.
+    #[clap(long, value_name = "INTERVAL")]
+    #[clap(parse(try_from_str = parse::duration_from_str))]
+    #[serde(serialize_with = "serialize::optional_duration")]
+    pub poll: Option<Duration>,
+
+    /// Only these kinds of file events trigger execution. Leave out `chmod` to ignore tools which
+    /// touch the permissions of files without changing them.
+    #[clap(long, arg_enum, value_name = "EVENTS")]
+    #[clap(value_delimiter = ',')]
+    #[clap(multiple_occurrences = true)]
+    #[clap(default_values = &["write", "create", "remove", "rename", "chmod"])]
+    pub events: Vec<FileEvent>,
+
+    /// Watch the directories which symlinks within the watched paths point to. Symlinks leading
+    /// back to a directory which is already watched, such as one of their ancestors, are skipped.
+    #[clap(long)]
+    pub follow_symlinks: bool,
+
+    /// Only trigger if the contents of a file changed, so that saving a file without editing it
+    /// (or a tool rewriting it as-is) is ignored. Costs a read of every changed file.
+    #[clap(long)]
+    pub content_hash: bool,
+
+    /// Also trigger when a ref of the git repository containing the current directory changes,
+    /// such as when switching branches, committing or pulling
+    #[clap(long)]
+    pub on_git_change: bool,
+
     /// Only files with these extensions trigger execution
     #[clap(short, long)]
     #[clap(value_delimiter = ',')]
+    #[serde(serialize_with = "serialize::optional_os_strings")]
     pub extensions: Option<Vec<OsString>>,
 
+    /// Files with these extensions never trigger execution, even if allowed by `--extensions`
+    #[clap(long, value_name = "EXTENSIONS")]
+    #[clap(value_delimiter = ',')]
+    #[serde(serialize_with = "serialize::optional_os_strings")]
+    pub exclude_extensions: Option<Vec<OsString>>,
+
+    /// Compare extensions case-insensitively, so that `-e jpg` also matches `photo.JPG`
+    #[clap(long)]
+    pub ignore_case_ext: bool,
+
     /// Include files excluded by Git
     #[clap(long)]
     pub no_git_ignore: bool,
+
+    /// Include files excluded by Git, `.ignore` or `.witnessignore` files
+    #[clap(long)]
+    pub no_ignore: bool,
+
+    /// Only watch files at most this many directories below a watched path. Files directly
+    /// within the path are at depth 1.
+    #[clap(long, value_name = "N")]
+    pub max_depth: Option<usize>,
+
+    /// Include hidden files and directories, whose names start with a `.`
+    #[clap(long, overrides_with = "no-hidden")]
+    pub hidden: bool,
+
+    /// Ignore hidden files and directories (default)
+    #[clap(long, overrides_with = "hidden")]
+    pub no_hidden: bool,
+
+    /// Listen on this Unix socket for commands which add or remove watched paths at runtime:
+    /// `watch <path>` and `unwatch <path>`, one per line.
+    #[clap(long, value_name = "PATH")]
+    #[clap(parse(from_os_str))]
+    pub control: Option<PathBuf>,
 }
 
 /// Options affecting how network connections are treated
-#[derive(Debug, clap::Parser)]
+#[derive(Debug, Clone, clap::Parser, serde::Serialize)]
 #[clap(
     group = clap::ArgGroup::new("network")
-        .args(&["udp", "tcp", "key", "trigger"])
+        .args(&[
+            "udp",
+            "tcp",
+            "http",
+            "unix",
+            "fifo",
+            "bind",
+            "target",
+            "key",
+            "key-file",
+            "hmac",
+            "rate-limit",
+            "allow",
+            "ack",
+            "tcp-json",
+            "tcp-timeout",
+            "trigger",
+        ])
         .multiple(true)
 )]
 pub struct NetworkOptions {
@@ -103,11 +356,96 @@ pub struct NetworkOptions {
     #[clap(multiple_occurrences = true)]
     pub tcp: Vec<u16>,
 
+    /// HTTP POST requests to these ports trigger execution. The key is read from the
+    /// `X-Witness-Key` header, or the request body if the header is missing.
+    #[clap(long)]
+    #[clap(value_delimiter = ',')]
+    #[clap(multiple_occurrences = true)]
+    pub http: Vec<u16>,
+
+    /// Listen for triggers on a Unix domain socket at this path. The socket is removed on exit.
+    /// Only supported on Unix-like systems.
+    #[clap(long, value_name = "PATH")]
+    #[clap(multiple_occurrences = true)]
+    #[clap(parse(from_os_str))]
+    pub unix: Vec<PathBuf>,
+
+    /// Read triggers from the named pipe (FIFO) at this path, one key per line. The FIFO is
+    /// created if it doesn't exist. Only supported on Unix-like systems.
+    #[clap(long, value_name = "PATH")]
+    #[clap(multiple_occurrences = true)]
+    #[clap(parse(from_os_str))]
+    pub fifo: Vec<PathBuf>,
+
+    /// The address to listen on. Use `0.0.0.0` to accept triggers from other machines.
+    #[clap(long)]
+    #[clap(default_value_t = IpAddr::V4(Ipv4Addr::LOCALHOST))]
+    pub bind: IpAddr,
+
+    /// The host to send triggers to when using `--trigger`, such as `build-box`, `10.0.0.2` or
+    /// `[::1]:8000`. A port given here is used instead of the ports of `--udp`, `--tcp` and
+    /// `--http`.
+    #[clap(long, value_name = "HOST[:PORT]")]
+    #[clap(default_value = "127.0.0.1")]
+    #[clap(parse(try_from_str = parse::target_from_str))]
+    pub target: Target,
+
     /// Only network requests containing this exact string will trigger execution.
-    /// Set to the empty string to allow any request.
+    /// Set to the empty string to allow any request. May be repeated to accept any of several
+    /// keys, in which case `--trigger` sends the first one.
     #[clap(long = "key")]
+    #[clap(multiple_occurrences = true)]
     #[clap(default_value = DEFAULT_KEY)]
-    pub key: String,
+    #[serde(serialize_with = "serialize::redacted")]
+    pub key: Vec<String>,
+
+    /// Read the key from this file instead, which keeps it out of the process list. A trailing
+    /// newline is ignored. Takes precedence over the `WITNESS_KEY` environment variable, which in
+    /// turn takes precedence over `--key`.
+    #[clap(long, value_name = "PATH")]
+    #[clap(parse(from_os_str))]
+    pub key_file: Option<PathBuf>,
+
+    /// Authenticate triggers with an HMAC-SHA256 of the current time using this shared secret,
+    /// instead of sending the key in plaintext. Both sides need the same secret and roughly
+    /// synchronized clocks.
+    #[clap(long, value_name = "SECRET")]
+    #[serde(serialize_with = "serialize::optional_redacted")]
+    pub hmac: Option<String>,
+
+    /// Accept at most this many triggers from the network within the duration, such as `10/1m`.
+    /// Further triggers are dropped until the limit allows more.
+    #[clap(long, value_name = "COUNT/DURATION")]
+    #[clap(parse(try_from_str = parse::rate_limit_from_str))]
+    pub rate_limit: Option<RateLimit>,
+
+    /// Only accept triggers from addresses within these ranges, such as `10.0.0.0/8` or
+    /// `192.168.1.5`. Accepts triggers from anywhere by default.
+    #[clap(long, value_name = "CIDR")]
+    #[clap(value_delimiter = ',')]
+    #[clap(multiple_occurrences = true)]
+    #[serde(serialize_with = "serialize::display_all")]
+    pub allow: Vec<crate::cidr::Cidr>,
+
+    /// Reply to TCP triggers with `OK` or `DENIED` once the key has been checked. With
+    /// `--trigger`, wait for the reply and fail unless the trigger was accepted. Both sides need to
+    /// agree on this flag.
+    #[clap(long)]
+    pub ack: bool,
+
+    /// TCP clients send a line of JSON naming the rule to trigger and arguments for the command,
+    /// such as `{"key": "secret", "rule": "deploy", "args": ["--fast"]}`, instead of just the
+    /// key. Every line is answered with a line of JSON.
+    #[clap(long)]
+    pub tcp_json: bool,
+
+    /// How long TCP, HTTP and Unix socket clients have to send their key (or a line of JSON, or
+    /// a request) before the connection is dropped. Raise it for slow or high-latency links.
+    #[clap(long, value_name = "DURATION")]
+    #[clap(default_value = "5s")]
+    #[clap(parse(try_from_str = parse::nonzero_duration_from_str))]
+    #[serde(serialize_with = "serialize::duration")]
+    pub tcp_timeout: Duration,
 
     /// Send a network packet instead of listening for it. Can be used to trigger another instance
     /// of witness running on the same machine.
@@ -116,30 +454,772 @@ pub struct NetworkOptions {
     pub trigger: bool,
 }
 
+impl Arguments {
+    /// How to clear the screen before running the command
+    pub fn clear(&self) -> ClearMode {
+        match self.quiet {
+            true => ClearMode::None,
+            false => self.behaviour.clear(),
+        }
+    }
+
+    /// Whether to color the output, according to `--color`
+    pub fn color(&self) -> bool {
+        match self.color {
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+            ColorMode::Auto => {
+                use std::io::IsTerminal;
+                std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+                    && std::io::stdout().is_terminal()
+            }
+        }
+    }
+
+    /// The arguments in the given format, for `--print-config`
+    pub fn to_config(&self, format: ConfigFormat) -> anyhow::Result<String> {
+        match format {
+            // going through a value puts tables after the plain values, as TOML requires
+            ConfigFormat::Toml => Ok(toml::to_string(&toml::Value::try_from(self)?)?),
+            ConfigFormat::Json => Ok(serde_json::to_string_pretty(self)? + "\n"),
+        }
+    }
+
+    /// The arguments for running each of the rules on its own
+    pub fn rule_arguments(&self) -> anyhow::Result<Vec<Arguments>> {
+        let network = &self.network;
+        if !(network.udp.is_empty()
+            && network.tcp.is_empty()
+            && network.http.is_empty()
+            && network.unix.is_empty()
+            && network.fifo.is_empty())
+        {
+            return Err(anyhow!("network triggers cannot be combined with rules"));
+        }
+        if self.files.control.is_some() {
+            return Err(anyhow!("a control socket cannot be combined with rules"));
+        }
+
+        let rules = self.rules.iter().map(|rule| {
+            let mut args = self.clone();
+            args.rules = Vec::new();
+            args.rule = Some(rule.name.clone());
+            args.files.paths = WatchedPaths::from(rule.paths.clone());
+            if rule.extensions.is_some() {
+                args.files.extensions = rule.extensions.clone();
+            }
+            args.command = rule.command.clone();
+            args
+        });
+
+        Ok(rules.collect())
+    }
+}
+
+impl BehaviourOptions {
+    /// The flag passed to the shell before the command
+    pub fn shell_arg(&self) -> &str {
+        if let Some(flag) = &self.shell_arg {
+            return flag;
+        }
+
+        let name = Path::new(&self.shell).file_stem().unwrap_or_default();
+        match name.eq_ignore_ascii_case("cmd") {
+            true => "/C",
+            false => "-c",
+        }
+    }
+
+    /// Time to wait before retrying the command after the given number of retries
+    pub fn delay_before_retry(&self, retries: u32) -> Duration {
+        match self.retry_backoff {
+            true => self
+                .retry_delay
+                .saturating_mul(2u32.saturating_pow(retries)),
+            false => self.retry_delay,
+        }
+    }
+
+    /// Time to wait before `--trigger` tries to connect again after the given number of retries.
+    /// The delay always doubles, and `jitter` (between 0 and 1) adds up to half of it again so
+    /// that clients waiting for the same listener don't reconnect in lockstep.
+    pub fn delay_before_reconnect(&self, retries: u32, jitter: f64) -> Duration {
+        let delay = self
+            .retry_delay
+            .saturating_mul(2u32.saturating_pow(retries));
+        delay.saturating_add(delay.mul_f64(jitter.clamp(0.0, 1.0) / 2.0))
+    }
+
+    /// How the screen should be cleared, taking `--no-clear` into account
+    pub fn clear(&self) -> ClearMode {
+        match self.no_clear {
+            true => ClearMode::None,
+            false => self.clear_mode,
+        }
+    }
+}
+
+/// A number of events allowed within a period of time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub struct RateLimit {
+    pub count: u32,
+    #[serde(serialize_with = "serialize::duration")]
+    pub period: Duration,
+}
+
+/// A host to send triggers to, with an optional port
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct Target {
+    pub host: String,
+    pub port: Option<u16>,
+}
+
+impl Target {
+    /// Find the address to send a trigger to, given the port of the protocol. Host names are
+    /// looked up, and the first address found is used.
+    pub async fn resolve(&self, port: u16) -> anyhow::Result<SocketAddr> {
+        let port = self.port.unwrap_or(port);
+        tokio::net::lookup_host((self.host.as_str(), port))
+            .await
+            .with_context(|| format!("failed to resolve host: {}", self.host))?
+            .next()
+            .ok_or_else(|| anyhow!("no addresses found for host: {}", self.host))
+    }
+}
+
 /// The default key used for network transmissions.
 const DEFAULT_KEY: &str = "witness-key";
 
 /// Options affecting behaivour of this utility
-#[derive(Debug, clap::Parser)]
+#[derive(Debug, Clone, clap::Parser, serde::Serialize)]
 pub struct BehaviourOptions {
-    /// Don't clear the screen before command invocation
-    #[clap(short = 'c', long)]
+    /// Don't clear the screen before command invocation. Same as `--clear-mode=none`.
+    #[clap(short = 'c', long, overrides_with = "clear-mode")]
     pub no_clear: bool,
 
-    /// Wait on the command to finish before restarting
+    /// How the screen is cleared before command invocation
+    #[clap(long, arg_enum, value_name = "MODE")]
+    #[clap(default_value = "hard", overrides_with = "no-clear")]
+    pub clear_mode: ClearMode,
+
+    /// Run this command through the shell to clear the screen, such as `tput reset`, instead of
+    /// writing escape codes to the terminal. Disabled by `--no-clear`.
+    #[clap(long, value_name = "COMMAND")]
+    pub clear_command: Option<String>,
+
+    /// Don't print the command before running it, or its exit status once it finishes
+    #[clap(long)]
+    pub no_banner: bool,
+
+    /// Don't print how many times the command succeeded and failed when interrupted
+    #[clap(long)]
+    pub no_summary: bool,
+
+    /// Pass the modified files to the command as arguments. If the command contains `{}`, it is
+    /// replaced by the files. Otherwise they are appended to the command. No files are passed when
+    /// the command is triggered by the network.
+    #[clap(long)]
+    pub pass_paths: bool,
+
+    /// Run the command once on startup instead of waiting for the first trigger
+    #[clap(long)]
+    pub initial: bool,
+
+    /// Wait on the command to finish before restarting. Any number of triggers while it runs
+    /// result in a single run afterwards, which sees the changes of all of them, unless
+    /// `--queue-runs` is given.
     #[clap(short, long)]
     pub wait: bool,
 
+    /// With `--wait`, queue up to this many runs while the command is running instead of a
+    /// single one, each seeing the changes of its own trigger. Triggers beyond that are combined
+    /// into the last queued run.
+    #[clap(long, value_name = "COUNT", requires = "wait")]
+    #[clap(parse(try_from_str = parse::positive_from_str))]
+    pub queue_runs: Option<u32>,
+
+    /// Let the command finish, and drop any triggers which arrive while it runs. Unlike `--wait`,
+    /// which runs the command once more afterwards, only triggers arriving once the command has
+    /// finished run it again.
+    #[clap(long, conflicts_with = "wait")]
+    pub ignore_while_running: bool,
+
+    /// Exit after the command has run once, with the same exit code as the command. Always waits
+    /// for the command to finish, ignoring any triggers while it runs.
+    #[clap(long)]
+    pub once: bool,
+
+    /// Exit after the command has finished this many times, with the same exit code as the last
+    /// run. Runs cut short by a new trigger don't count. `--once` is the same as `--max-runs 1`,
+    /// except that it also ignores triggers while the command runs.
+    #[clap(long, value_name = "COUNT", conflicts_with = "once")]
+    #[clap(parse(try_from_str = parse::positive_from_str))]
+    pub max_runs: Option<u32>,
+
+    /// Print the modified files whenever execution would be triggered instead of running the
+    /// command. Useful for testing which files pass the filters.
+    #[clap(long)]
+    pub dry_run: bool,
+
+    /// Print one line per trigger instead of running the command, with the path, the kind of
+    /// change and the time separated by tabs. Unlike `--dry-run`, the columns are stable, which
+    /// makes witness an event source for other tools.
+    #[clap(long, conflicts_with = "dry-run")]
+    pub emit_triggers: bool,
+
+    /// End every field of `--emit-triggers` with a NUL byte instead of a tab or newline, so that
+    /// paths may contain any character
+    #[clap(long, requires = "emit-triggers")]
+    pub null: bool,
+
+    /// Show a desktop notification whenever the command finishes. Requires witness to be built
+    /// with the `desktop-notifications` feature.
+    #[clap(long)]
+    pub notify: bool,
+
+    /// Ring the terminal bell when the command fails, or whenever it finishes with
+    /// `--bell=always`.
+    #[clap(long, arg_enum, value_name = "WHEN")]
+    #[clap(
+        min_values = 0,
+        require_equals = true,
+        default_missing_value = "failure"
+    )]
+    pub bell: Option<Bell>,
+
+    /// Prefix each line printed by the command with the time it was printed. With
+    /// `--timestamps=tagged` the lines are also marked with the stream they were printed to.
+    #[clap(long, arg_enum, value_name = "STYLE")]
+    #[clap(min_values = 0, require_equals = true, default_missing_value = "plain")]
+    pub timestamps: Option<Timestamps>,
+
+    /// The signal used to terminate the command before restarting it (e.g. TERM, INT, HUP, KILL).
+    /// Only KILL is supported on Windows.
+    #[clap(long)]
+    #[clap(default_value = "KILL")]
+    #[clap(parse(try_from_str = parse::signal_from_str))]
+    pub signal: Signal,
+
+    /// Kill the command if it has not terminated this long after being signaled
+    #[clap(long)]
+    #[clap(default_value = "5s")]
+    #[clap(parse(try_from_str = parse::duration_from_str))]
+    #[serde(serialize_with = "serialize::duration")]
+    pub kill_timeout: Duration,
+
+    /// On Ctrl-C, send `--signal` to the command and give it this long to exit before killing
+    /// it, instead of killing it right away.
+    #[clap(long, value_name = "DURATION")]
+    #[clap(min_values = 0, require_equals = true, default_missing_value = "5s")]
+    #[clap(parse(try_from_str = parse::duration_from_str))]
+    #[serde(serialize_with = "serialize::optional_duration")]
+    pub graceful_shutdown: Option<Duration>,
+
+    /// Wait this long after the command terminates before starting it again
+    #[clap(long)]
+    #[clap(default_value = "0s")]
+    #[clap(parse(try_from_str = parse::duration_from_str))]
+    #[serde(serialize_with = "serialize::duration")]
+    pub restart_delay: Duration,
+
+    /// Ignore all triggers for this long after starting, such as the burst of file events from a
+    /// `git checkout` which just finished. Triggers within this time are dropped, not delayed.
+    #[clap(long, value_name = "DURATION")]
+    #[clap(parse(try_from_str = parse::duration_from_str))]
+    #[serde(serialize_with = "serialize::optional_duration")]
+    pub startup_grace: Option<Duration>,
+
+    /// Also trigger the command every time this much time has passed, whether or not anything
+    /// changed. Combines with the other sources, and counts from when `witness` starts.
+    #[clap(long, value_name = "DURATION")]
+    #[clap(parse(try_from_str = parse::nonzero_duration_from_str))]
+    #[serde(serialize_with = "serialize::optional_duration")]
+    pub interval: Option<Duration>,
+
+    /// Terminate the command if it runs for longer than this. The command is terminated the same
+    /// way as when it is restarted, and counts as a failure.
+    #[clap(long, value_name = "DURATION")]
+    #[clap(parse(try_from_str = parse::duration_from_str))]
+    #[serde(serialize_with = "serialize::optional_duration")]
+    pub command_timeout: Option<Duration>,
+
+    /// Run this shell command repeatedly after starting the command, until it succeeds, and log
+    /// that the command is ready. Useful to know when a server accepts connections, such as
+    /// `curl -sf localhost:8080/health`.
+    #[clap(long, value_name = "COMMAND")]
+    pub ready_check: Option<String>,
+
+    /// How long to wait between runs of the `--ready-check`
+    #[clap(long, value_name = "DURATION")]
+    #[clap(default_value = "500ms")]
+    #[clap(parse(try_from_str = parse::nonzero_duration_from_str))]
+    #[serde(serialize_with = "serialize::duration")]
+    pub ready_interval: Duration,
+
+    /// Give up on the `--ready-check` if the command isn't ready after this long
+    #[clap(long, value_name = "DURATION")]
+    #[clap(default_value = "30s")]
+    #[clap(parse(try_from_str = parse::nonzero_duration_from_str))]
+    #[serde(serialize_with = "serialize::duration")]
+    pub ready_timeout: Duration,
+
+    /// Run the command again up to this many times if it fails. A new trigger starts over with
+    /// the full number of retries. With `--trigger`, try connecting over TCP or HTTP again up to
+    /// this many times while the listener is unavailable.
+    #[clap(long, value_name = "COUNT")]
+    #[clap(default_value = "0")]
+    pub retry: u32,
+
+    /// Never start the command again within this long after it last started, no matter how soon
+    /// triggers arrive. Unlike `--debounce`, this counts from when the command started.
+    #[clap(long, value_name = "DURATION")]
+    #[clap(parse(try_from_str = parse::duration_from_str))]
+    #[serde(serialize_with = "serialize::optional_duration")]
+    pub cooldown: Option<Duration>,
+
+    /// What happens to triggers within the `--cooldown`
+    #[clap(long, arg_enum, value_name = "MODE")]
+    #[clap(default_value = "defer")]
+    pub cooldown_mode: CooldownMode,
+
+    /// Stop retrying a failed command after this many retries within `--restart-window`, and
+    /// wait for the next trigger instead. Bounds how fast `--retry` re-runs a command which
+    /// keeps failing, while a new trigger still runs it.
+    #[clap(long, value_name = "COUNT")]
+    #[clap(requires = "retry")]
+    pub max_restarts: Option<u32>,
+
+    /// The window in which `--max-restarts` are counted
+    #[clap(long, value_name = "DURATION")]
+    #[clap(requires = "max-restarts")]
+    #[clap(default_value = "10s")]
+    #[clap(parse(try_from_str = parse::duration_from_str))]
+    #[serde(serialize_with = "serialize::duration")]
+    pub restart_window: Duration,
+
+    /// Time to wait before retrying a failed command
+    #[clap(long, value_name = "DURATION")]
+    #[clap(default_value = "1s")]
+    #[clap(parse(try_from_str = parse::duration_from_str))]
+    #[serde(serialize_with = "serialize::duration")]
+    pub retry_delay: Duration,
+
+    /// Double the retry delay after every failed retry
+    #[clap(long)]
+    pub retry_backoff: bool,
+
+    /// Append a JSON line to this file for every trigger and every time the command starts,
+    /// exits or times out
+    #[clap(long, value_name = "PATH")]
+    #[clap(parse(from_os_str))]
+    pub event_log: Option<PathBuf>,
+
+    /// Append everything the command prints, to both stdout and stderr, to this file
+    #[clap(long, value_name = "PATH")]
+    #[clap(parse(from_os_str))]
+    pub log_output: Option<PathBuf>,
+
+    /// Hold back the output of the command, and only show it once the command fails. Only the
+    /// last 64 KiB are kept; use `--log-output` to keep all of it.
+    #[clap(long)]
+    pub show_on_failure: bool,
+
+    /// Keep this file up to date with whether the command is `idle`, `running` or has
+    /// `exited:<code>`, along with the process ID of witness. Removed on exit.
+    #[clap(long, value_name = "PATH")]
+    #[clap(parse(from_os_str))]
+    pub status_file: Option<PathBuf>,
+
+    /// Run the command in this directory. Does not affect which paths are watched.
+    #[clap(long, visible_alias = "chdir", value_name = "PATH")]
+    #[clap(parse(from_os_str))]
+    pub workdir: Option<PathBuf>,
+
+    /// Set an environment variable for the command, such as `RUST_LOG=debug`. May be given
+    /// several times.
+    #[clap(long, value_name = "KEY=VALUE")]
+    #[clap(multiple_occurrences = true)]
+    #[clap(parse(try_from_str = parse::env_from_str))]
+    pub env: Vec<(String, String)>,
+
+    /// Remove an environment variable which the command would otherwise inherit. May be given
+    /// several times.
+    #[clap(long, value_name = "KEY")]
+    #[clap(multiple_occurrences = true)]
+    pub env_remove: Vec<String>,
+
+    /// Start the command with an empty environment, except for variables given by `--env` and
+    /// those set by witness
+    #[clap(long)]
+    pub env_clear: bool,
+
+    /// Run this command instead when files are deleted. It is interpreted by the shell, and
+    /// receives the same environment variables as the main command.
+    #[clap(long, value_name = "COMMAND")]
+    pub on_delete: Option<String>,
+
+    /// Run this command for changes in a `--debounce-group`, given as `<group>:<command>`. Each
+    /// group then has a command of its own, which a change in another group leaves running. The
+    /// command is interpreted by the shell.
+    #[clap(long = "group-command", value_name = "GROUP:COMMAND")]
+    #[clap(multiple_occurrences = true)]
+    #[clap(conflicts_with_all = &["once", "wait", "interactive", "retry", "command-timeout"])]
+    #[clap(parse(try_from_str = parse::group_command_from_str))]
+    pub group_commands: Vec<GroupCommand>,
+
+    /// Don't let the command read from the terminal: its standard input is always empty
+    #[clap(long, conflicts_with = "stdin-file")]
+    pub no_stdin: bool,
+
+    /// Feed the contents of this file to the standard input of the command, instead of the
+    /// terminal. The file is read again each time the command runs.
+    #[clap(long, value_name = "PATH")]
+    #[clap(parse(from_os_str))]
+    pub stdin_file: Option<PathBuf>,
+
+    /// Read commands from the standard input of witness: each line typed replaces the command and
+    /// runs it right away. The original command is used again once the input ends. The command
+    /// itself gets an empty standard input, unless `--stdin-file` is given.
+    #[clap(long, conflicts_with = "no-stdin")]
+    pub interactive: bool,
+
+    /// Shut down, the same as when interrupted with Ctrl-C, once the standard input of witness is
+    /// closed. Useful when witness is managed by another process. The command itself gets an
+    /// empty standard input.
+    #[clap(long, conflicts_with_all = &["interactive", "stdin-file"])]
+    pub exit_on_stdin_eof: bool,
+
     /// The shell used to interpret commands
     #[clap(long)]
     #[clap(default_value = "sh")]
     #[clap(env = "SHELL")]
+    #[serde(serialize_with = "serialize::os_string")]
     pub shell: OsString,
+
+    /// The flag which tells the shell to run the command that follows it. Defaults to `/C` for
+    /// `cmd` on Windows, and `-c` for every other shell.
+    #[clap(long, value_name = "FLAG")]
+    #[clap(forbid_empty_values = true, allow_hyphen_values = true)]
+    pub shell_arg: Option<String>,
+}
+
+/// Kinds of file system events which may trigger execution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ArgEnum, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FileEvent {
+    /// The contents of a file changed
+    Write,
+    /// A file was created
+    Create,
+    /// A file was removed
+    Remove,
+    /// A file was renamed, or moved into or out of a watched directory
+    Rename,
+    /// The permissions or other metadata of a file changed
+    Chmod,
+}
+
+/// Strategies for grouping a burst of file changes into a single execution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ArgEnum, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DebounceMode {
+    /// Trigger on the first change and ignore any further changes within the debounce window.
+    /// Reacts the fastest, but may run before an editor or build tool has finished writing.
+    Leading,
+    /// Trigger once the debounce window has passed since the first change. Bounds the delay, but
+    /// may split a long burst of changes into several executions.
+    Trailing,
+    /// Trigger once no files have changed for the debounce window. Never splits a burst, but a
+    /// file which changes continuously delays execution indefinitely.
+    Settle,
+}
+
+/// What to do with triggers arriving too soon after the command started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ArgEnum, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CooldownMode {
+    /// Run the command once the cooldown has passed
+    Defer,
+    /// Ignore the trigger
+    Drop,
+}
+
+/// Ways of clearing the screen before running the command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ArgEnum, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ClearMode {
+    /// Reset the terminal, clearing both the screen and its history
+    Hard,
+    /// Clear the visible screen, keeping earlier output in the scrollback
+    Soft,
+    /// Clear the visible screen and the scrollback
+    Scrollback,
+    /// Leave the screen as is
+    None,
+}
+
+/// When to ring the terminal bell
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ArgEnum, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Bell {
+    /// When the command exits with a non-zero status
+    Failure,
+    /// Whenever the command finishes
+    Always,
+}
+
+/// How lines printed by the command are marked with timestamps
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ArgEnum, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Timestamps {
+    /// Only the time: `2022-04-02T12:00:00.000Z hello`
+    Plain,
+    /// The time and the stream: `2022-04-02T12:00:00.000Z [out] hello`
+    Tagged,
+}
+
+/// When to color the output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ArgEnum, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ColorMode {
+    /// Only when writing to a terminal
+    Auto,
+    Always,
+    Never,
+}
+
+/// The format of log messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ArgEnum, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogFormat {
+    /// Human-readable text
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+/// Formats of `--print-config`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ArgEnum)]
+pub enum ConfigFormat {
+    Toml,
+    Json,
+}
+
+/// The paths given with `--path`, and the extensions given for some of them
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize)]
+pub struct WatchedPaths {
+    pub paths: Vec<PathBuf>,
+    pub root_extensions: Vec<RootExtensions>,
+}
+
+impl From<Vec<PathBuf>> for WatchedPaths {
+    fn from(paths: Vec<PathBuf>) -> Self {
+        WatchedPaths {
+            paths,
+            root_extensions: Vec::new(),
+        }
+    }
+}
+
+impl std::ops::Deref for WatchedPaths {
+    type Target = [PathBuf];
+
+    fn deref(&self) -> &[PathBuf] {
+        &self.paths
+    }
+}
+
+// Written by hand since the extensions in a value of `--path` are separated by commas just like
+// the paths, so each value has to be split as a whole.
+impl clap::Args for WatchedPaths {
+    fn augment_args(command: clap::Command<'_>) -> clap::Command<'_> {
+        command.arg(
+            clap::Arg::new("paths")
+                .long("path")
+                .value_name("PATHS")
+                .help(
+                    "Paths to watch for changes, separated by commas. Glob patterns, such as \
+                     `crates/*/src`, are expanded on startup. Extensions after a colon replace \
+                     `--extensions` within the path before it, such as `frontend:ts,tsx`.",
+                )
+                .takes_value(true)
+                .allow_invalid_utf8(true)
+                .multiple_occurrences(true)
+                .default_value(".")
+                .default_value_if("udp", None, None)
+                .default_value_if("tcp", None, None)
+                .default_value_if("http", None, None)
+                .default_value_if("unix", None, None)
+                .default_value_if("fifo", None, None),
+        )
+    }
+
+    fn augment_args_for_update(command: clap::Command<'_>) -> clap::Command<'_> {
+        Self::augment_args(command)
+    }
+}
+
+impl clap::FromArgMatches for WatchedPaths {
+    fn from_arg_matches(matches: &clap::ArgMatches) -> clap::Result<Self> {
+        let mut watched = WatchedPaths::default();
+        for value in matches.values_of_os("paths").into_iter().flatten() {
+            let (paths, extensions) = parse::paths_from_os_str(value);
+            if let (Some(root), Some(extensions)) = (paths.last(), extensions) {
+                watched.root_extensions.push(RootExtensions {
+                    root: root.clone(),
+                    extensions,
+                });
+            }
+            watched.paths.extend(paths);
+        }
+        Ok(watched)
+    }
+
+    fn update_from_arg_matches(&mut self, matches: &clap::ArgMatches) -> clap::Result<()> {
+        *self = Self::from_arg_matches(matches)?;
+        Ok(())
+    }
+}
+
+/// Extensions which replace `--extensions` within a watched path
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct RootExtensions {
+    pub root: PathBuf,
+    #[serde(serialize_with = "serialize::os_strings")]
+    pub extensions: Vec<OsString>,
+}
+
+/// A named set of paths to watch and the command to run when they change, from the config file
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct Rule {
+    pub name: String,
+    pub paths: Vec<PathBuf>,
+    #[serde(serialize_with = "serialize::optional_os_strings")]
+    pub extensions: Option<Vec<OsString>>,
+    pub command: Vec<String>,
+}
+
+/// Files which are debounced separately from other changes
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct DebounceGroup {
+    pub name: String,
+    #[serde(serialize_with = "serialize::os_strings")]
+    pub extensions: Vec<OsString>,
+    #[serde(serialize_with = "serialize::duration")]
+    pub debounce: Duration,
+}
+
+/// The command run for the changes in a `--debounce-group`, given by `--group-command`
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct GroupCommand {
+    pub group: String,
+    pub command: String,
+}
+
+/// A signal which can be sent to the command
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Signal {
+    Hup,
+    Int,
+    Quit,
+    Kill,
+    Usr1,
+    Usr2,
+    Term,
+}
+
+impl Signal {
+    #[cfg(unix)]
+    pub fn number(self) -> libc::c_int {
+        match self {
+            Signal::Hup => libc::SIGHUP,
+            Signal::Int => libc::SIGINT,
+            Signal::Quit => libc::SIGQUIT,
+            Signal::Kill => libc::SIGKILL,
+            Signal::Usr1 => libc::SIGUSR1,
+            Signal::Usr2 => libc::SIGUSR2,
+            Signal::Term => libc::SIGTERM,
+        }
+    }
 }
 
 impl Arguments {
-    pub fn parse() -> Arguments {
-        <Arguments as clap::Parser>::parse()
+    pub fn parse() -> anyhow::Result<Arguments> {
+        let args = std::env::args_os().collect::<Vec<_>>();
+        let dir = std::env::current_dir().context("failed to get the current directory")?;
+        let config = Self::load_config(&args, &dir)?;
+        Ok(Self::parse_with_config(config.as_ref(), args).unwrap_or_else(|error| error.exit()))
+    }
+
+    /// Load the config file given by `--config`, or the closest one to the directory
+    fn load_config(args: &[OsString], dir: &Path) -> anyhow::Result<Option<config::Config>> {
+        use clap::CommandFactory;
+
+        // other errors are reported once the arguments are parsed for real
+        let matches = Self::command()
+            .ignore_errors(true)
+            .try_get_matches_from(args)
+            .ok();
+        let explicit = matches
+            .as_ref()
+            .and_then(|matches| matches.value_of_os("config"))
+            .map(Path::new);
+
+        config::Config::load(explicit, dir)
+    }
+
+    /// Parse the arguments, using values from the config file as defaults
+    fn parse_with_config(
+        config: Option<&config::Config>,
+        args: Vec<OsString>,
+    ) -> clap::Result<Arguments> {
+        use clap::{CommandFactory, FromArgMatches};
+
+        let defaults = config.map(config::Config::defaults).unwrap_or_default();
+
+        let mut command = Self::command();
+        for (id, values) in defaults.iter() {
+            let values = values.iter().map(String::as_str).collect::<Vec<_>>();
+            command = command.mut_arg(*id, |arg| match *id {
+                "command" => optional_command(arg).default_values(&values),
+                _ => arg.default_values(&values),
+            });
+        }
+
+        let rules = config.map(config::Config::rules).unwrap_or_default();
+        if !rules.is_empty() {
+            command = command.mut_arg("command", optional_command);
+        }
+
+        let matches = command.try_get_matches_from(args)?;
+        let mut arguments = Self::from_arg_matches(&matches)?;
+
+        // a command on the command line takes precedence over the rules
+        if arguments.command.is_empty() {
+            arguments.rules = rules;
+        }
+
+        let groups = &arguments.files.debounce_groups;
+        for command in &arguments.behaviour.group_commands {
+            if !groups.iter().any(|group| group.name == command.group) {
+                let message = format!(
+                    "`--group-command` names an unknown debounce group: {}",
+                    command.group
+                );
+                return Err(clap::Error::raw(clap::ErrorKind::InvalidValue, message)
+                    .format(&mut Self::command()));
+            }
+        }
+
+        // the shell from the config file is more specific than the login shell
+        if let Some((_, shell)) = defaults.iter().find(|(id, _)| *id == "shell") {
+            if matches.value_source("shell") == Some(clap::ValueSource::EnvVariable) {
+                arguments.behaviour.shell = OsString::from(&shell[0]);
+            }
+        }
+
+        Ok(arguments)
     }
 
     #[allow(dead_code)]
@@ -149,6 +1229,29 @@ impl Arguments {
     }
 }
 
+/// Write a script which completes the arguments of witness in the shell
+pub fn write_completions(shell: clap_complete::Shell, output: &mut dyn std::io::Write) {
+    let mut command = <Arguments as clap::CommandFactory>::command();
+    let name = command.get_name().to_owned();
+    clap_complete::generate(shell, &mut command, name, output);
+}
+
+/// The command argument without its requirement, which clap only considers satisfied by values
+/// given on the command line, not by a default.
+fn optional_command(arg: clap::Arg) -> clap::Arg {
+    let mut optional = clap::Arg::new(arg.get_id())
+        .takes_value(true)
+        .multiple_values(true)
+        .value_hint(arg.get_value_hint());
+    if let Some(help) = arg.get_help() {
+        optional = optional.help(help);
+    }
+    if let Some(help) = arg.get_long_help() {
+        optional = optional.long_help(help);
+    }
+    optional
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -162,14 +1265,14 @@ mod tests {
     #[test]
     fn watch_default() {
         let args = parse_args("witness cargo check");
-        assert_eq!(args.files.paths, [PathBuf::from(".")]);
+        assert_eq!(args.files.paths.paths, [PathBuf::from(".")]);
     }
 
     /// Watch a specific path
     #[test]
     fn watch_specific() {
         let args = parse_args("witness --path src cargo check");
-        assert_eq!(args.files.paths, [PathBuf::from("src")]);
+        assert_eq!(args.files.paths.paths, [PathBuf::from("src")]);
     }
 
     /// Multiple paths using a single flag
@@ -177,7 +1280,7 @@ mod tests {
     fn watch_many() {
         let args = parse_args("witness --path=src,test,examples cargo check");
         assert_eq!(
-            args.files.paths,
+            args.files.paths.paths,
             [
                 PathBuf::from("src"),
                 PathBuf::from("test"),
@@ -191,7 +1294,7 @@ mod tests {
     fn watch_multiple() {
         let args = parse_args("witness --path=src --path=test --path=examples cargo check");
         assert_eq!(
-            args.files.paths,
+            args.files.paths.paths,
             [
                 PathBuf::from("src"),
                 PathBuf::from("test"),
@@ -204,7 +1307,7 @@ mod tests {
     #[test]
     fn udp_disables_files() {
         let args = parse_args("witness --udp=1234 cargo check");
-        assert_eq!(args.files.paths, Vec::<PathBuf>::new());
+        assert_eq!(args.files.paths.paths, Vec::<PathBuf>::new());
         assert_eq!(args.network.udp, vec![1234]);
     }
 
@@ -212,7 +1315,7 @@ mod tests {
     #[test]
     fn tcp_disables_files() {
         let args = parse_args("witness --tcp=1234 cargo check");
-        assert_eq!(args.files.paths, Vec::<PathBuf>::new());
+        assert_eq!(args.files.paths.paths, Vec::<PathBuf>::new());
         assert_eq!(args.network.tcp, vec![1234]);
     }
 
@@ -220,7 +1323,629 @@ mod tests {
     #[test]
     fn udp_and_files() {
         let args = parse_args("witness --udp=1234 --path src cargo check");
-        assert_eq!(args.files.paths, vec![PathBuf::from("src")]);
+        assert_eq!(args.files.paths.paths, vec![PathBuf::from("src")]);
         assert_eq!(args.network.udp, vec![1234]);
     }
+
+    /// Polling is disabled unless requested
+    #[test]
+    fn poll_interval() {
+        let args = parse_args("witness cargo check");
+        assert_eq!(args.files.poll, None);
+
+        let args = parse_args("witness --poll 500ms cargo check");
+        assert_eq!(args.files.poll, Some(Duration::from_millis(500)));
+    }
+
+    /// Signals may be named with or without the `SIG` prefix, in any case
+    #[test]
+    fn signal_names() {
+        assert_eq!(
+            parse_args("witness cargo run").behaviour.signal,
+            Signal::Kill
+        );
+        assert_eq!(
+            parse_args("witness --signal TERM cargo run")
+                .behaviour
+                .signal,
+            Signal::Term
+        );
+        assert_eq!(
+            parse_args("witness --signal sigint cargo run")
+                .behaviour
+                .signal,
+            Signal::Int
+        );
+        assert!(Arguments::try_parse_from(["witness", "--signal", "FOO", "cargo"]).is_err());
+    }
+
+    /// Network listeners only accept local connections by default
+    #[test]
+    fn bind_loopback() {
+        let args = parse_args("witness --tcp=1234 cargo check");
+        assert_eq!(args.network.bind, IpAddr::V4(Ipv4Addr::LOCALHOST));
+        assert_eq!(args.network.target.host, "127.0.0.1");
+
+        let args = parse_args("witness --tcp=1234 --bind 0.0.0.0 cargo check");
+        assert_eq!(args.network.bind, IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+    }
+
+    #[test]
+    fn file_events() {
+        let args = parse_args("witness cargo check");
+        assert_eq!(args.files.events.len(), 5);
+
+        let args = parse_args("witness --events write,create --events remove cargo check");
+        assert_eq!(
+            args.files.events,
+            [FileEvent::Write, FileEvent::Create, FileEvent::Remove]
+        );
+
+        let error = Arguments::try_parse_from(["witness", "--events", "write,open", "cargo"])
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("open"), "{error}");
+        assert!(error.contains("chmod"), "{error}");
+    }
+
+    #[test]
+    fn bell_modes() {
+        assert_eq!(parse_args("witness true").behaviour.bell, None);
+        assert_eq!(
+            parse_args("witness --bell true").behaviour.bell,
+            Some(Bell::Failure)
+        );
+        assert_eq!(
+            parse_args("witness --bell=always true").behaviour.bell,
+            Some(Bell::Always)
+        );
+        assert_eq!(parse_args("witness --bell true").command, ["true"]);
+    }
+
+    fn parse_with_config(config: &str, args: &str) -> clap::Result<Arguments> {
+        let config = config::Config::from_str(config).unwrap();
+        let args = args.split_whitespace().map(OsString::from).collect();
+        Arguments::parse_with_config(Some(&config), args)
+    }
+
+    /// `--print-config` shows the resolved values, with the command line taking precedence over
+    /// the config file
+    #[test]
+    fn print_config() {
+        let config = r#"
+            debounce = "1s"
+            shell = "bash"
+            key = "secret"
+        "#;
+        let args =
+            parse_with_config(config, "witness --print-config --debounce 250ms make").unwrap();
+        assert_eq!(args.print_config, Some(ConfigFormat::Toml));
+
+        let text = args.to_config(ConfigFormat::Toml).unwrap();
+        let value = text.parse::<toml::Value>().unwrap();
+        assert_eq!(value["files"]["debounce"].as_str(), Some("250ms"));
+        assert_eq!(value["behaviour"]["shell"].as_str(), Some("bash"));
+        assert_eq!(value["command"][0].as_str(), Some("make"));
+        assert!(!text.contains("secret"), "{text}");
+
+        let args = parse_with_config(config, "witness --print-config=json").unwrap();
+        let text = args.to_config(ConfigFormat::Json).unwrap();
+        let value = serde_json::from_str::<serde_json::Value>(&text).unwrap();
+        assert_eq!(value["files"]["debounce"], "1s");
+        assert_eq!(value["network"]["key"][0], "<redacted>");
+        assert!(!text.contains("secret"), "{text}");
+    }
+
+    /// Values from the config file are used when not given on the command line
+    #[test]
+    fn config_defaults() {
+        let config = r#"
+            paths = ["src", "tests"]
+            extensions = ["rs", "toml"]
+            debounce = "1s"
+            shell = "bash"
+            command = "cargo test"
+            udp = [1234]
+            key = "secret"
+        "#;
+        let args = parse_with_config(config, "witness").unwrap();
+        assert_eq!(
+            args.files.paths.paths,
+            [PathBuf::from("src"), PathBuf::from("tests")]
+        );
+        assert_eq!(
+            args.files.extensions,
+            Some(vec![OsString::from("rs"), OsString::from("toml")])
+        );
+        assert_eq!(args.files.debounce, Duration::from_secs(1));
+        assert_eq!(args.behaviour.shell, "bash");
+        assert_eq!(args.command, ["cargo test"]);
+        assert_eq!(args.network.udp, [1234]);
+        assert_eq!(args.network.key, ["secret"]);
+    }
+
+    /// Arguments on the command line take precedence over the config file
+    #[test]
+    fn config_precedence() {
+        let config = r#"
+            paths = ["src"]
+            debounce = "1s"
+            shell = "bash"
+            command = ["cargo", "test"]
+            key = "secret"
+        "#;
+        let args = parse_with_config(
+            config,
+            "witness --path lib --debounce 5ms --shell zsh --key other cargo build",
+        )
+        .unwrap();
+        assert_eq!(args.files.paths.paths, [PathBuf::from("lib")]);
+        assert_eq!(args.files.debounce, Duration::from_millis(5));
+        assert_eq!(args.behaviour.shell, "zsh");
+        assert_eq!(args.network.key, ["other"]);
+        assert_eq!(args.command, ["cargo", "build"]);
+
+        let args = parse_with_config(config, "witness").unwrap();
+        assert_eq!(args.command, ["cargo", "test"]);
+    }
+
+    #[test]
+    fn config_malformed() {
+        assert!(config::Config::from_str("debounce = \"soon\"").is_err());
+        assert!(config::Config::from_str("bind = \"localhost\"").is_err());
+        assert!(config::Config::from_str("paths = [").is_err());
+        assert!(config::Config::from_str("unknown = 1").is_err());
+        assert!(config::Config::from_str("paths = \"src\"").is_err());
+    }
+
+    /// The closest config file is used unless another one is given
+    #[test]
+    fn config_search() {
+        let dir = tempfile::tempdir().unwrap();
+        let nested = dir.path().join("src/cli");
+        std::fs::create_dir_all(&nested).unwrap();
+        std::fs::write(dir.path().join(config::FILE_NAME), "debounce = \"1s\"").unwrap();
+
+        let args = |args: &str| {
+            args.split_whitespace()
+                .map(OsString::from)
+                .collect::<Vec<_>>()
+        };
+        let found = Arguments::load_config(&args("witness true"), &nested).unwrap();
+        assert!(found.is_some());
+
+        let empty = tempfile::tempdir().unwrap();
+        let missing = Arguments::load_config(&args("witness true"), empty.path()).unwrap();
+        assert!(missing.is_none());
+
+        let explicit = args("witness --config does-not-exist.toml true");
+        let error = Arguments::load_config(&explicit, &nested).unwrap_err();
+        assert!(error.to_string().contains("does-not-exist.toml"), "{error}");
+    }
+
+    /// A command from the config file does not prevent sending triggers
+    #[test]
+    fn config_trigger() {
+        let args = parse_with_config(
+            "command = \"cargo test\"\nudp = [1234]",
+            "witness --trigger",
+        );
+        assert!(args.unwrap().network.trigger);
+    }
+
+    #[test]
+    fn clear_modes() {
+        let clear = |args: &str| parse_args(args).behaviour.clear();
+        assert_eq!(clear("witness true"), ClearMode::Hard);
+        assert_eq!(clear("witness --clear-mode soft true"), ClearMode::Soft);
+        assert_eq!(
+            clear("witness --clear-mode=scrollback true"),
+            ClearMode::Scrollback
+        );
+        assert_eq!(clear("witness --clear-mode none true"), ClearMode::None);
+        assert_eq!(clear("witness --no-clear true"), ClearMode::None);
+        assert_eq!(clear("witness -c true"), ClearMode::None);
+
+        // the last one given wins
+        assert_eq!(
+            clear("witness --no-clear --clear-mode soft true"),
+            ClearMode::Soft
+        );
+        assert_eq!(
+            clear("witness --clear-mode soft --no-clear true"),
+            ClearMode::None
+        );
+    }
+
+    #[test]
+    fn retry_delay() {
+        let args = parse_args("witness --retry 3 --retry-delay 100ms true");
+        assert_eq!(args.behaviour.retry, 3);
+        assert_eq!(
+            args.behaviour.delay_before_retry(0),
+            Duration::from_millis(100)
+        );
+        assert_eq!(
+            args.behaviour.delay_before_retry(2),
+            Duration::from_millis(100)
+        );
+
+        let args = parse_args("witness --retry 3 --retry-delay 100ms --retry-backoff true");
+        assert_eq!(
+            args.behaviour.delay_before_retry(0),
+            Duration::from_millis(100)
+        );
+        assert_eq!(
+            args.behaviour.delay_before_retry(2),
+            Duration::from_millis(400)
+        );
+        // does not overflow
+        assert!(args.behaviour.delay_before_retry(40) > Duration::from_secs(86400));
+    }
+
+    #[test]
+    fn rate_limit() {
+        let rate_limit = |args: &str| parse_args(args).network.rate_limit;
+        assert_eq!(rate_limit("witness --udp 1234 true"), None);
+        assert_eq!(
+            rate_limit("witness --udp 1234 --rate-limit 10/1m true"),
+            Some(RateLimit {
+                count: 10,
+                period: Duration::from_secs(60)
+            })
+        );
+        assert_eq!(
+            rate_limit("witness --udp 1234 --rate-limit 3/s true"),
+            Some(RateLimit {
+                count: 3,
+                period: Duration::from_secs(1)
+            })
+        );
+
+        for invalid in ["10", "0/1s", "10/0s", "ten/1s", "10/soon"] {
+            assert!(parse::rate_limit_from_str(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn graceful_shutdown() {
+        let args = parse_args("witness true");
+        assert_eq!(args.behaviour.graceful_shutdown, None);
+        let args = parse_args("witness --graceful-shutdown true");
+        assert_eq!(
+            args.behaviour.graceful_shutdown,
+            Some(Duration::from_secs(5))
+        );
+        let args = parse_args("witness --graceful-shutdown=30s true");
+        assert_eq!(
+            args.behaviour.graceful_shutdown,
+            Some(Duration::from_secs(30))
+        );
+    }
+
+    /// Group commands must name a debounce group
+    #[test]
+    fn group_commands() {
+        let args =
+            parse_args("witness --debounce-group web:ts:100ms --group-command web:make true");
+        assert_eq!(
+            args.behaviour.group_commands,
+            [GroupCommand {
+                group: "web".to_owned(),
+                command: "make".to_owned(),
+            }]
+        );
+
+        let unknown = "witness --debounce-group web:ts:100ms --group-command api:make true";
+        assert!(
+            Arguments::parse_with_config(None, unknown.split(' ').map(Into::into).collect())
+                .is_err()
+        );
+        let once = "witness --debounce-group web:ts:100ms --group-command web:make --once true";
+        assert!(Arguments::try_parse_from(once.split(' ')).is_err());
+    }
+
+    #[test]
+    fn debounce_groups() {
+        let args = parse_args(
+            "witness --debounce-group rust:rs:100ms --debounce-group assets:png,svg:2s true",
+        );
+        assert_eq!(
+            args.files.debounce_groups,
+            [
+                DebounceGroup {
+                    name: "rust".into(),
+                    extensions: vec!["rs".into()],
+                    debounce: Duration::from_millis(100),
+                },
+                DebounceGroup {
+                    name: "assets".into(),
+                    extensions: vec!["png".into(), "svg".into()],
+                    debounce: Duration::from_secs(2),
+                },
+            ]
+        );
+
+        for group in ["rust:rs", ":rs:1s", "rust::1s", "rust:rs:soon"] {
+            let args = ["witness", "--debounce-group", group, "true"];
+            assert!(Arguments::try_parse_from(args).is_err(), "{group}");
+        }
+    }
+
+    /// `--quiet` never clears the screen, and cannot be combined with `--verbose`
+    #[test]
+    fn quiet() {
+        assert_eq!(parse_args("witness true").clear(), ClearMode::Hard);
+        assert_eq!(parse_args("witness -q true").clear(), ClearMode::None);
+        let args = ["witness", "--quiet", "--verbose", "true"];
+        assert!(Arguments::try_parse_from(args).is_err());
+    }
+
+    /// `--check-path` doesn't need a command
+    #[test]
+    fn check_path() {
+        let args = parse_args("witness --check-path src/main.rs --extensions rs");
+        assert_eq!(args.check_path, Some(PathBuf::from("src/main.rs")));
+        assert!(args.command.is_empty());
+    }
+
+    #[test]
+    fn completions() {
+        let args = parse_args("witness --completions bash");
+        assert_eq!(args.completions, Some(clap_complete::Shell::Bash));
+
+        let mut output = Vec::new();
+        write_completions(clap_complete::Shell::Bash, &mut output);
+        let script = String::from_utf8(output).unwrap();
+        assert!(!script.is_empty());
+        for flag in ["--path", "--debounce", "--udp", "--initial"] {
+            assert!(script.contains(flag), "missing {flag}");
+        }
+    }
+
+    /// Each `[[rule]]` table is run on its own, unless a command is given on the command line
+    #[test]
+    fn config_rules() {
+        let config = r#"
+            debounce = "1s"
+
+            [[rule]]
+            name = "backend"
+            paths = ["server"]
+            extensions = ["rs"]
+            command = "cargo test"
+
+            [[rule]]
+            name = "frontend"
+            command = ["npm", "test"]
+        "#;
+        let args = parse_with_config(config, "witness -e txt").unwrap();
+        let rules = args.rule_arguments().unwrap();
+        assert_eq!(rules.len(), 2);
+
+        assert_eq!(rules[0].rule.as_deref(), Some("backend"));
+        assert_eq!(rules[0].files.paths.paths, [PathBuf::from("server")]);
+        assert_eq!(rules[0].files.extensions, Some(vec![OsString::from("rs")]));
+        assert_eq!(rules[0].files.debounce, Duration::from_secs(1));
+        assert_eq!(rules[0].command, ["cargo test"]);
+
+        assert_eq!(rules[1].rule.as_deref(), Some("frontend"));
+        assert_eq!(rules[1].files.paths.paths, [PathBuf::from(".")]);
+        assert_eq!(rules[1].files.extensions, Some(vec![OsString::from("txt")]));
+        assert_eq!(rules[1].command, ["npm", "test"]);
+
+        let args = parse_with_config(config, "witness make").unwrap();
+        assert!(args.rules.is_empty());
+        assert_eq!(args.command, ["make"]);
+
+        let args = parse_with_config(config, "witness --udp 1234").unwrap();
+        assert!(args.rule_arguments().is_err());
+    }
+
+    #[test]
+    fn config_rules_malformed() {
+        let both = "command = \"make\"\n[[rule]]\nname = \"a\"\ncommand = \"make\"";
+        assert!(config::Config::from_str(both).is_err());
+
+        let duplicate = "[[rule]]\nname = \"a\"\ncommand = \"make\"\n\
+                         [[rule]]\nname = \"a\"\ncommand = \"make\"";
+        assert!(config::Config::from_str(duplicate).is_err());
+
+        assert!(config::Config::from_str("[[rule]]\nname = \"a\"").is_err());
+    }
+
+    #[test]
+    fn stdin_options() {
+        let args = Arguments::try_parse_from(["witness", "--no-stdin", "cat"]).unwrap();
+        assert!(args.behaviour.no_stdin);
+
+        let args = Arguments::try_parse_from(["witness", "--stdin-file", "in.txt", "cat"]).unwrap();
+        assert_eq!(args.behaviour.stdin_file, Some(PathBuf::from("in.txt")));
+
+        let both = ["witness", "--no-stdin", "--stdin-file", "in.txt", "cat"];
+        assert!(Arguments::try_parse_from(both).is_err());
+
+        let both = ["witness", "--no-stdin", "--interactive", "cat"];
+        assert!(Arguments::try_parse_from(both).is_err());
+    }
+
+    #[test]
+    fn ignore_while_running() {
+        let args = parse_args("witness --ignore-while-running true");
+        assert!(args.behaviour.ignore_while_running);
+
+        let both = ["witness", "--ignore-while-running", "--wait", "true"];
+        assert!(Arguments::try_parse_from(both).is_err());
+    }
+
+    #[test]
+    fn exit_on_stdin_eof() {
+        let args = parse_args("witness --exit-on-stdin-eof true");
+        assert!(args.behaviour.exit_on_stdin_eof);
+
+        for other in ["--interactive", "--stdin-file=in.txt"] {
+            let both = ["witness", "--exit-on-stdin-eof", other, "true"];
+            assert!(Arguments::try_parse_from(both).is_err(), "{other}");
+        }
+    }
+
+    #[test]
+    fn max_runs() {
+        let args = parse_args("witness --max-runs 3 true");
+        assert_eq!(args.behaviour.max_runs, Some(3));
+
+        assert!(Arguments::try_parse_from(["witness", "--max-runs", "0", "true"]).is_err());
+        let both = ["witness", "--max-runs", "2", "--once", "true"];
+        assert!(Arguments::try_parse_from(both).is_err());
+    }
+
+    #[test]
+    fn emit_triggers() {
+        let args = parse_args("witness --emit-triggers --null");
+        assert!(args.behaviour.emit_triggers);
+        assert!(args.behaviour.null);
+
+        assert!(Arguments::try_parse_from(["witness", "--null", "true"]).is_err());
+        let both = ["witness", "--emit-triggers", "--dry-run"];
+        assert!(Arguments::try_parse_from(both).is_err());
+    }
+
+    #[test]
+    fn reconnect_delay() {
+        let args = parse_args("witness --trigger --tcp 8000 --retry 3 --retry-delay 100ms");
+        let delay = |retries, jitter| args.behaviour.delay_before_reconnect(retries, jitter);
+        assert_eq!(delay(0, 0.0), Duration::from_millis(100));
+        assert_eq!(delay(2, 0.0), Duration::from_millis(400));
+        assert_eq!(delay(2, 1.0), Duration::from_millis(600));
+        assert_eq!(delay(2, 7.0), Duration::from_millis(600));
+
+        // backoff is exponential even without `--retry-backoff`
+        assert!(!args.behaviour.retry_backoff);
+        assert!(delay(40, 1.0) > Duration::from_secs(86400));
+    }
+
+    #[tokio::test]
+    async fn trigger_destination() {
+        let target = |text: &str| {
+            parse_args(&format!("witness --trigger --target {text}"))
+                .network
+                .target
+        };
+
+        let addr = target("10.0.0.2").resolve(8000).await.unwrap();
+        assert_eq!(addr, "10.0.0.2:8000".parse().unwrap());
+
+        // a port given with the host takes precedence
+        let addr = target("10.0.0.2:9000").resolve(8000).await.unwrap();
+        assert_eq!(addr, "10.0.0.2:9000".parse().unwrap());
+
+        let addr = target("[::1]:9000").resolve(8000).await.unwrap();
+        assert_eq!(addr, "[::1]:9000".parse().unwrap());
+
+        let addr = target("localhost").resolve(8000).await.unwrap();
+        assert!(addr.ip().is_loopback());
+        assert_eq!(addr.port(), 8000);
+
+        let addr = parse_args("witness --trigger").network.target;
+        assert_eq!(
+            addr.resolve(8000).await.unwrap(),
+            "127.0.0.1:8000".parse().unwrap()
+        );
+
+        let error = target("nonexistent.invalid")
+            .resolve(8000)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("nonexistent.invalid"));
+    }
+
+    #[test]
+    fn ready_check() {
+        let args = parse_args("witness --ready-check true cargo run");
+        assert_eq!(args.behaviour.ready_check.as_deref(), Some("true"));
+        assert_eq!(args.behaviour.ready_interval, Duration::from_millis(500));
+        assert_eq!(args.behaviour.ready_timeout, Duration::from_secs(30));
+
+        let zero = "witness --ready-check true --ready-interval 0s cargo run";
+        assert!(Arguments::try_parse_from(zero.split_whitespace()).is_err());
+    }
+
+    /// `--max-restarts` only bounds `--retry`, so it is useless without it
+    #[test]
+    fn max_restarts() {
+        let args = parse_args("witness --retry 100 --max-restarts 5 cargo run");
+        assert_eq!(args.behaviour.max_restarts, Some(5));
+        assert_eq!(args.behaviour.restart_window, Duration::from_secs(10));
+
+        for args in [
+            "witness --max-restarts 5 cargo run",
+            "witness --retry 100 --restart-window 1m cargo run",
+        ] {
+            assert!(Arguments::try_parse_from(args.split_whitespace()).is_err());
+        }
+    }
+
+    #[test]
+    fn tcp_timeout() {
+        let args = parse_args("witness --tcp=1234 cargo check");
+        assert_eq!(args.network.tcp_timeout, Duration::from_secs(5));
+
+        let args = parse_args("witness --tcp=1234 --tcp-timeout 30s cargo check");
+        assert_eq!(args.network.tcp_timeout, Duration::from_secs(30));
+
+        let zero = "witness --tcp=1234 --tcp-timeout 0s cargo check";
+        assert!(Arguments::try_parse_from(zero.split_whitespace()).is_err());
+    }
+
+    #[test]
+    fn interval() {
+        let args = parse_args("witness cargo check");
+        assert_eq!(args.behaviour.interval, None);
+
+        let args = parse_args("witness --interval 1m cargo check");
+        assert_eq!(args.behaviour.interval, Some(Duration::from_secs(60)));
+
+        let zero = "witness --interval 0s cargo check";
+        assert!(Arguments::try_parse_from(zero.split_whitespace()).is_err());
+    }
+
+    #[test]
+    fn color() {
+        let args = parse_args("witness cargo check");
+        assert_eq!(args.color, ColorMode::Auto);
+
+        let args = parse_args("witness --color always cargo check");
+        assert!(args.color());
+
+        let args = parse_args("witness --color never cargo check");
+        assert!(!args.color());
+    }
+
+    #[test]
+    fn path_extensions() {
+        let args = parse_args("witness --path src,tests --path frontend:ts,tsx cargo check");
+        assert_eq!(
+            args.files.paths.paths,
+            ["src", "tests", "frontend"].map(PathBuf::from)
+        );
+        assert_eq!(
+            args.files.paths.root_extensions,
+            [RootExtensions {
+                root: PathBuf::from("frontend"),
+                extensions: vec!["ts".into(), "tsx".into()],
+            }]
+        );
+
+        let args = parse_args("witness --path backend:rs --extensions md cargo check");
+        assert_eq!(args.files.paths.paths, [PathBuf::from("backend")]);
+        assert_eq!(args.files.paths.root_extensions[0].extensions, ["rs"]);
+        assert_eq!(args.files.extensions, Some(vec!["md".into()]));
+
+        let args = parse_args("witness --path logs:2024 --path notes:draft: cargo check");
+        assert_eq!(
+            args.files.paths.paths,
+            ["logs:2024", "notes:draft"].map(PathBuf::from)
+        );
+        assert!(args.files.paths.root_extensions.is_empty());
+    }
 }
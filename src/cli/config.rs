@@ -0,0 +1,175 @@
+//! Defaults for the command line arguments read from a `witness.toml` file.
+//!
+//! Values from the file become the defaults of the corresponding arguments, so anything given on
+//! the command line takes precedence.
+
+use std::{
+    net::IpAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context};
+
+/// Name of the config file searched for in the current directory and its ancestors
+pub const FILE_NAME: &str = "witness.toml";
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    paths: Option<Vec<String>>,
+    extensions: Option<Vec<String>>,
+    #[serde(default, deserialize_with = "duration")]
+    debounce: Option<Duration>,
+    shell: Option<String>,
+    shell_arg: Option<String>,
+    command: Option<CommandLine>,
+    udp: Option<Vec<u16>>,
+    tcp: Option<Vec<u16>>,
+    http: Option<Vec<u16>>,
+    bind: Option<IpAddr>,
+    target: Option<String>,
+    key: Option<String>,
+    hmac: Option<String>,
+    #[serde(default, rename = "rule")]
+    rules: Vec<RuleConfig>,
+}
+
+/// A `[[rule]]` table
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleConfig {
+    name: String,
+    paths: Option<Vec<String>>,
+    extensions: Option<Vec<String>>,
+    command: CommandLine,
+}
+
+/// A command given either as a single shell script or as separate arguments
+#[derive(Debug, serde::Deserialize)]
+#[serde(untagged)]
+enum CommandLine {
+    Script(String),
+    Arguments(Vec<String>),
+}
+
+impl Config {
+    /// Load the config from the given path, or search for one starting in the directory
+    pub fn load(explicit: Option<&Path>, dir: &Path) -> anyhow::Result<Option<Config>> {
+        let path = match explicit {
+            Some(path) => path.to_path_buf(),
+            None => match find(dir) {
+                Some(path) => path,
+                None => return Ok(None),
+            },
+        };
+
+        let text = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read config file: {}", path.display()))?;
+        let config = Self::from_str(&text)
+            .with_context(|| format!("invalid config file: {}", path.display()))?;
+
+        info!(?path, "loaded config file");
+        Ok(Some(config))
+    }
+
+    pub fn from_str(text: &str) -> anyhow::Result<Config> {
+        let config: Config = toml::from_str(text)?;
+
+        if config.command.is_some() && !config.rules.is_empty() {
+            return Err(anyhow!("use either `command` or `[[rule]]`, not both"));
+        }
+
+        let mut names = std::collections::BTreeSet::new();
+        for rule in config.rules.iter() {
+            if !names.insert(rule.name.as_str()) {
+                return Err(anyhow!("there is more than one rule named `{}`", rule.name));
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// The rules to run instead of a single command
+    pub fn rules(&self) -> Vec<super::Rule> {
+        self.rules
+            .iter()
+            .map(|rule| super::Rule {
+                name: rule.name.clone(),
+                paths: match &rule.paths {
+                    Some(paths) => paths.iter().map(PathBuf::from).collect(),
+                    None => vec![PathBuf::from(".")],
+                },
+                extensions: rule
+                    .extensions
+                    .as_ref()
+                    .map(|extensions| extensions.iter().map(Into::into).collect()),
+                command: rule.command.arguments(),
+            })
+            .collect()
+    }
+
+    /// The default values of each argument, by argument id
+    pub fn defaults(&self) -> Vec<(&'static str, Vec<String>)> {
+        fn ports(ports: &Option<Vec<u16>>) -> Option<Vec<String>> {
+            ports
+                .as_ref()
+                .map(|ports| ports.iter().map(u16::to_string).collect())
+        }
+
+        let command = self.command.as_ref().map(CommandLine::arguments);
+
+        let values = [
+            ("paths", self.paths.clone()),
+            ("extensions", self.extensions.clone()),
+            (
+                "debounce",
+                self.debounce
+                    .map(|value| vec![format!("{}ns", value.as_nanos())]),
+            ),
+            ("shell", self.shell.clone().map(|value| vec![value])),
+            ("shell-arg", self.shell_arg.clone().map(|value| vec![value])),
+            ("command", command),
+            ("udp", ports(&self.udp)),
+            ("tcp", ports(&self.tcp)),
+            ("http", ports(&self.http)),
+            ("bind", self.bind.map(|value| vec![value.to_string()])),
+            ("target", self.target.clone().map(|value| vec![value])),
+            ("key", self.key.clone().map(|value| vec![value])),
+            ("hmac", self.hmac.clone().map(|value| vec![value])),
+        ];
+
+        values
+            .into_iter()
+            .filter_map(|(id, values)| Some((id, values?)))
+            .collect()
+    }
+}
+
+impl CommandLine {
+    fn arguments(&self) -> Vec<String> {
+        match self {
+            CommandLine::Script(script) => vec![script.clone()],
+            CommandLine::Arguments(arguments) => arguments.clone(),
+        }
+    }
+}
+
+/// Parse a duration in the same format as on the command line
+fn duration<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use serde::{de::Error, Deserialize};
+    let text = String::deserialize(deserializer)?;
+    super::parse::duration_from_str(&text)
+        .map(Some)
+        .map_err(D::Error::custom)
+}
+
+/// Find the closest config file in the directory or any of its ancestors
+fn find(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|dir| dir.join(FILE_NAME))
+        .find(|path| path.is_file())
+}
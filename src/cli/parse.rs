@@ -1,49 +1,477 @@
 use super::*;
 
+/// Parse a duration made up of one or more components, such as `500ms`, `1.5h` or `1h2m3s`.
+/// Components must be given from the largest unit to the smallest, and each unit may only occur
+/// once.
 pub fn duration_from_str(text: &str) -> anyhow::Result<Duration> {
-    fn parse_digits<T>(digits: &str, unit: &str) -> anyhow::Result<T>
-    where
-        T: FromStr,
-        T::Err: std::error::Error + Send + Sync + 'static,
-    {
+    if text.is_empty() {
+        return Err(anyhow!("expected a duration, such as `500ms` or `1m30s`"));
+    }
+
+    let mut total = Duration::ZERO;
+    let mut previous: Option<&str> = None;
+    let mut rest = text;
+
+    while !rest.is_empty() {
+        let number_len = rest
+            .find(|char: char| !(char.is_ascii_digit() || char == '.'))
+            .unwrap_or(rest.len());
+        let (number, after) = rest.split_at(number_len);
+        if number.is_empty() {
+            return Err(anyhow!("expected a number at `{rest}` in duration: {text}"));
+        }
+
+        let unit_len = after
+            .find(|char: char| !char.is_ascii_alphabetic())
+            .unwrap_or(after.len());
+        let (unit, after) = after.split_at(unit_len);
+        if unit.is_empty() {
+            return Err(anyhow!("missing unit after `{number}` in duration: {text}"));
+        }
+
+        let component = duration_component(number, unit)
+            .with_context(|| format!("not a valid duration: {text}"))?;
+
+        // `1s1m` or `1m1m` are most likely typos, so reject them rather than guess
+        if let Some(previous) = previous {
+            if unit_rank(unit) >= unit_rank(previous) {
+                return Err(anyhow!(
+                    "`{unit}` may not come after `{previous}` in duration: {text}"
+                ));
+            }
+        }
+        previous = Some(unit);
+
+        total = total
+            .checked_add(component)
+            .ok_or_else(|| anyhow!("duration is too long: {text}"))?;
+        rest = after;
+    }
+
+    Ok(total)
+}
+
+/// Parse a duration which must be longer than zero, such as a timeout
+pub fn nonzero_duration_from_str(text: &str) -> anyhow::Result<Duration> {
+    let duration = duration_from_str(text)?;
+    if duration.is_zero() {
+        return Err(anyhow!("the duration must be longer than zero: {text}"));
+    }
+    Ok(duration)
+}
+
+/// Parse a count which must be at least one
+pub fn positive_from_str(text: &str) -> anyhow::Result<u32> {
+    let count = text
+        .parse()
+        .with_context(|| format!("expected a number: {text}"))?;
+    if count == 0 {
+        return Err(anyhow!("must be at least 1"));
+    }
+    Ok(count)
+}
+
+/// Units in order of increasing size
+const DURATION_UNITS: &[&str] = &["ns", "us", "ms", "s", "m", "h", "d", "w"];
+
+fn unit_rank(unit: &str) -> usize {
+    DURATION_UNITS
+        .iter()
+        .position(|&known| known == unit)
+        .unwrap_or(usize::MAX)
+}
+
+/// Seconds and larger units may be fractional (`1.5h`), while `ms`, `us` and `ns` must be whole
+/// numbers since there is rarely a need for more precision than that.
+fn duration_component(number: &str, unit: &str) -> anyhow::Result<Duration> {
+    fn parse_integer(digits: &str, unit: &str) -> anyhow::Result<u64> {
         digits
             .parse()
             .with_context(|| format!("invalid number of {unit}: {digits}"))
     }
 
-    if let Some(digits) = text.strip_suffix("ns") {
-        return Ok(Duration::from_nanos(parse_digits(digits, "nanoseconds")?));
+    fn parse_seconds(digits: &str, unit: &str, scale: f64) -> anyhow::Result<Duration> {
+        let count: f64 = digits
+            .parse()
+            .with_context(|| format!("invalid number of {unit}: {digits}"))?;
+        Duration::try_from_secs_f64(count * scale)
+            .with_context(|| format!("too many {unit}: {digits}"))
+    }
+
+    match unit {
+        "ns" => Ok(Duration::from_nanos(parse_integer(number, "nanoseconds")?)),
+        "us" => Ok(Duration::from_micros(parse_integer(
+            number,
+            "microseconds",
+        )?)),
+        "ms" => Ok(Duration::from_millis(parse_integer(
+            number,
+            "milliseconds",
+        )?)),
+        "s" => parse_seconds(number, "seconds", 1.0),
+        "m" => parse_seconds(number, "minutes", 60.0),
+        "h" => parse_seconds(number, "hours", 60.0 * 60.0),
+        "d" => parse_seconds(number, "days", 60.0 * 60.0 * 24.0),
+        "w" => parse_seconds(number, "weeks", 60.0 * 60.0 * 24.0 * 7.0),
+        _ => Err(anyhow!("unknown unit: {unit}")),
     }
+}
 
-    if let Some(digits) = text.strip_suffix("us") {
-        return Ok(Duration::from_micros(parse_digits(digits, "microseconds")?));
+pub fn debounce_group_from_str(text: &str) -> anyhow::Result<DebounceGroup> {
+    let mut parts = text.splitn(3, ':');
+    let (name, extensions, debounce) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(name), Some(extensions), Some(debounce)) => (name, extensions, debounce),
+        _ => {
+            return Err(anyhow!(
+                "expected a debounce group of the form <name>:<extensions>:<duration>: {text}"
+            ))
+        }
+    };
+
+    if name.is_empty() {
+        return Err(anyhow!("missing name of debounce group: {text}"));
     }
 
-    if let Some(digits) = text.strip_suffix("ms") {
-        return Ok(Duration::from_millis(parse_digits(digits, "milliseconds")?));
+    let extensions = extensions
+        .split(',')
+        .filter(|ext| !ext.is_empty())
+        .map(OsString::from)
+        .collect::<Vec<_>>();
+    if extensions.is_empty() {
+        return Err(anyhow!("missing extensions of debounce group: {text}"));
     }
 
-    if let Some(digits) = text.strip_suffix("s") {
-        return Ok(Duration::from_secs_f64(parse_digits(digits, "seconds")?));
+    Ok(DebounceGroup {
+        name: name.to_owned(),
+        extensions,
+        debounce: duration_from_str(debounce)?,
+    })
+}
+
+/// Split a value of `--path` into the paths it lists, separated by commas, and the extensions given
+/// for the last of them after a colon, as in `src,frontend:ts,tsx`. Extensions start with a letter,
+/// so `logs:2024` is a path, and a trailing colon keeps the colons in a path, as in `notes:draft:`.
+pub fn paths_from_os_str(text: &OsStr) -> (Vec<PathBuf>, Option<Vec<OsString>>) {
+    let text = match text.to_str() {
+        Some(text) => text,
+        None => return (vec![PathBuf::from(text)], None),
+    };
+
+    // extensions never contain separators, which tells them apart from `C:\` on Windows
+    let is_extension = |ext: &str| {
+        ext.starts_with(|c: char| c.is_alphabetic())
+            && ext
+                .chars()
+                .all(|c| c.is_alphanumeric() || matches!(c, '.' | '_' | '-'))
+    };
+    let (paths, extensions) = match text.strip_suffix(':') {
+        Some(paths) => (paths, None),
+        None => match text.rsplit_once(':') {
+            Some((paths, extensions))
+                if !paths.is_empty() && extensions.split(',').all(is_extension) =>
+            {
+                (paths, Some(extensions))
+            }
+            _ => (text, None),
+        },
+    };
+
+    let paths = paths
+        .split(',')
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+        .collect();
+    let extensions =
+        extensions.map(|extensions| extensions.split(',').map(OsString::from).collect());
+    (paths, extensions)
+}
+
+pub fn glob_from_str(text: &str) -> anyhow::Result<globset::Glob> {
+    globset::GlobBuilder::new(text)
+        .literal_separator(true)
+        .build()
+        .with_context(|| format!("invalid glob pattern: {text}"))
+}
+
+pub fn regex_from_str(text: &str) -> anyhow::Result<regex::Regex> {
+    regex::Regex::new(text).with_context(|| format!("invalid path regex: {text}"))
+}
+
+pub fn group_command_from_str(text: &str) -> anyhow::Result<GroupCommand> {
+    match text.split_once(':') {
+        Some((group, command)) if !group.is_empty() && !command.trim().is_empty() => {
+            Ok(GroupCommand {
+                group: group.to_owned(),
+                command: command.to_owned(),
+            })
+        }
+        _ => Err(anyhow!(
+            "expected a group command of the form <group>:<command>: {text}"
+        )),
+    }
+}
+
+pub fn env_from_str(text: &str) -> anyhow::Result<(String, String)> {
+    match text.split_once('=') {
+        Some((key, value)) if !key.is_empty() && !key.contains('\0') => {
+            Ok((key.to_owned(), value.to_owned()))
+        }
+        _ => Err(anyhow!(
+            "expected a variable in the form KEY=VALUE, such as `RUST_LOG=debug`"
+        )),
     }
+}
 
-    if let Some(digits) = text.strip_suffix("m") {
-        return Ok(Duration::from_secs_f64(
-            parse_digits::<f64>(digits, "minutes")? * 60.0,
-        ));
+pub fn signal_from_str(text: &str) -> anyhow::Result<Signal> {
+    let name = text.to_ascii_uppercase();
+    let name = name.strip_prefix("SIG").unwrap_or(&name);
+    match name {
+        "HUP" => Ok(Signal::Hup),
+        "INT" => Ok(Signal::Int),
+        "QUIT" => Ok(Signal::Quit),
+        "KILL" => Ok(Signal::Kill),
+        "USR1" => Ok(Signal::Usr1),
+        "USR2" => Ok(Signal::Usr2),
+        "TERM" => Ok(Signal::Term),
+        _ => Err(anyhow!("not a valid signal name: {}", text)),
     }
+}
 
-    if let Some(digits) = text.strip_suffix("h") {
-        return Ok(Duration::from_secs_f64(
-            parse_digits::<f64>(digits, "minutes")? * 60.0 * 60.0,
-        ));
+pub fn rate_limit_from_str(text: &str) -> anyhow::Result<RateLimit> {
+    let (count, period) = text
+        .split_once('/')
+        .ok_or_else(|| anyhow!("expected a rate limit of the form <count>/<duration>: {text}"))?;
+
+    let count = count
+        .parse()
+        .with_context(|| format!("invalid number of triggers: {count}"))?;
+    if count == 0 {
+        return Err(anyhow!("the rate limit must allow at least one trigger"));
     }
 
-    if let Some(digits) = text.strip_suffix("d") {
-        return Ok(Duration::from_secs_f64(
-            parse_digits::<f64>(digits, "minutes")? * 60.0 * 60.0 * 24.0,
-        ));
+    // allow `10/s` as a shorthand for `10/1s`
+    let period = match period.starts_with(|char: char| char.is_ascii_digit()) {
+        true => duration_from_str(period)?,
+        false => duration_from_str(&format!("1{period}"))?,
+    };
+    if period.is_zero() {
+        return Err(anyhow!("the rate limit period must be longer than zero"));
     }
 
-    Err(anyhow!("not a valid duration specifier: {}", text))
+    Ok(RateLimit { count, period })
+}
+
+/// Parse a host with an optional port, such as `build-box`, `10.0.0.2:8000`, `::1` or `[::1]:8000`
+pub fn target_from_str(text: &str) -> anyhow::Result<Target> {
+    fn port(text: &str) -> anyhow::Result<Option<u16>> {
+        let port = text
+            .parse()
+            .with_context(|| format!("invalid port: {text}"))?;
+        Ok(Some(port))
+    }
+
+    // IPv6 addresses contain colons, so they need brackets to be followed by a port
+    if let Ok(addr) = text.parse::<IpAddr>() {
+        let host = addr.to_string();
+        return Ok(Target { host, port: None });
+    }
+
+    let (host, port) = match text.strip_prefix('[') {
+        Some(rest) => match rest.split_once(']') {
+            Some((host, "")) => (host, None),
+            Some((host, after)) => match after.strip_prefix(':') {
+                Some(after) => (host, port(after)?),
+                None => return Err(anyhow!("expected a port after the address: {text}")),
+            },
+            None => return Err(anyhow!("missing `]` after the address: {text}")),
+        },
+        None => match text.split_once(':') {
+            None => (text, None),
+            Some((host, after)) => (host, port(after)?),
+        },
+    };
+
+    if host.is_empty() {
+        return Err(anyhow!("expected a host name or address: {text}"));
+    }
+
+    Ok(Target {
+        host: host.to_owned(),
+        port,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn duration(text: &str) -> Duration {
+        duration_from_str(text).unwrap()
+    }
+
+    #[test]
+    fn single_unit_durations() {
+        assert_eq!(duration("100ns"), Duration::from_nanos(100));
+        assert_eq!(duration("100us"), Duration::from_micros(100));
+        assert_eq!(duration("500ms"), Duration::from_millis(500));
+        assert_eq!(duration("2s"), Duration::from_secs(2));
+        assert_eq!(duration("1.5s"), Duration::from_millis(1500));
+        assert_eq!(duration("3m"), Duration::from_secs(180));
+        assert_eq!(duration("1h"), Duration::from_secs(3600));
+        assert_eq!(duration("1d"), Duration::from_secs(86400));
+        assert_eq!(duration("2w"), Duration::from_secs(2 * 7 * 86400));
+    }
+
+    #[test]
+    fn fractional_durations() {
+        assert_eq!(duration("0.5s"), Duration::from_millis(500));
+        assert_eq!(duration("1.5m"), Duration::from_secs(90));
+        assert_eq!(duration("1.5h"), Duration::from_secs(5400));
+        assert_eq!(duration("0.5d"), Duration::from_secs(43200));
+        assert_eq!(duration("0.5w"), Duration::from_secs(302400));
+        assert!(duration_from_str("1.5ms").is_err());
+    }
+
+    #[test]
+    fn paths() {
+        let paths = |text: &str| paths_from_os_str(OsStr::new(text));
+        let bufs = |paths: &[&str]| paths.iter().map(PathBuf::from).collect::<Vec<_>>();
+        let exts = |exts: &[&str]| Some(exts.iter().map(OsString::from).collect::<Vec<_>>());
+
+        assert_eq!(paths("src"), (bufs(&["src"]), None));
+        assert_eq!(paths("src,tests"), (bufs(&["src", "tests"]), None));
+        assert_eq!(
+            paths("frontend:ts,tsx"),
+            (bufs(&["frontend"]), exts(&["ts", "tsx"]))
+        );
+        assert_eq!(
+            paths("src,backend:rs"),
+            (bufs(&["src", "backend"]), exts(&["rs"]))
+        );
+        assert_eq!(
+            paths("crates/*/src:rs"),
+            (bufs(&["crates/*/src"]), exts(&["rs"]))
+        );
+
+        // not a list of extensions
+        assert_eq!(paths(r"C:\project"), (bufs(&[r"C:\project"]), None));
+        assert_eq!(paths(":ts"), (bufs(&[":ts"]), None));
+        assert_eq!(paths("logs:2024"), (bufs(&["logs:2024"]), None));
+        assert_eq!(
+            paths("frontend:ts,2024"),
+            (bufs(&["frontend:ts", "2024"]), None)
+        );
+
+        // a trailing colon keeps the colons in the path
+        assert_eq!(paths("dist:"), (bufs(&["dist"]), None));
+        assert_eq!(paths("notes:draft:"), (bufs(&["notes:draft"]), None));
+        assert_eq!(
+            paths("src,notes:draft:"),
+            (bufs(&["src", "notes:draft"]), None)
+        );
+    }
+
+    /// Errors name the unit that failed to parse
+    #[test]
+    fn duration_error_units() {
+        let error = |text: &str| format!("{:#}", duration_from_str(text).unwrap_err());
+        assert!(error("1.5.0h").contains("invalid number of hours"));
+        assert!(error("1.5.0d").contains("invalid number of days"));
+        assert!(error("1.5.0w").contains("invalid number of weeks"));
+        assert!(error("1.5.0m").contains("invalid number of minutes"));
+        assert!(error("1.5ms").contains("invalid number of milliseconds"));
+    }
+
+    #[test]
+    fn multi_component_durations() {
+        assert_eq!(duration("1m30s"), Duration::from_secs(90));
+        assert_eq!(duration("2m30s"), Duration::from_secs(150));
+        assert_eq!(duration("1h2m3s"), Duration::from_secs(3723));
+        assert_eq!(duration("1s500ms"), Duration::from_millis(1500));
+        assert_eq!(duration("1d1h"), Duration::from_secs(90000));
+    }
+
+    #[test]
+    fn malformed_durations() {
+        for text in [
+            "", "1", "1x", "m5", "m", "1m5", "1.2.3s", "1s1m", "1m1m", "s1s", "1 s",
+        ] {
+            assert!(duration_from_str(text).is_err(), "{text:?} was accepted");
+        }
+    }
+
+    #[test]
+    fn positive_counts() {
+        assert_eq!(positive_from_str("3").unwrap(), 3);
+        assert!(positive_from_str("0").is_err());
+        assert!(positive_from_str("-1").is_err());
+    }
+
+    #[test]
+    fn group_commands() {
+        let command = group_command_from_str("docs:mdbook build --open").unwrap();
+        assert_eq!(command.group, "docs");
+        assert_eq!(command.command, "mdbook build --open");
+        assert_eq!(group_command_from_str("a:b:c").unwrap().command, "b:c");
+        assert!(group_command_from_str("docs").is_err());
+        assert!(group_command_from_str(":make").is_err());
+        assert!(group_command_from_str("docs: ").is_err());
+    }
+
+    #[test]
+    fn env_variables() {
+        let var = |key: &str, value: &str| (key.to_owned(), value.to_owned());
+        assert_eq!(
+            env_from_str("RUST_LOG=debug").unwrap(),
+            var("RUST_LOG", "debug")
+        );
+        assert_eq!(env_from_str("EMPTY=").unwrap(), var("EMPTY", ""));
+        assert_eq!(env_from_str("URL=a=b").unwrap(), var("URL", "a=b"));
+        assert!(env_from_str("RUST_LOG").is_err());
+        assert!(env_from_str("=debug").is_err());
+    }
+
+    #[test]
+    fn regexes() {
+        assert!(regex_from_str(r"^src/.*\.rs$").is_ok());
+        let error = regex_from_str("src/(").unwrap_err();
+        assert!(error.to_string().contains("invalid path regex: src/("));
+    }
+
+    #[test]
+    fn nonzero_durations() {
+        assert_eq!(
+            nonzero_duration_from_str("1ms").unwrap(),
+            Duration::from_millis(1)
+        );
+        assert!(nonzero_duration_from_str("0s").is_err());
+        assert!(nonzero_duration_from_str("0m0s").is_err());
+    }
+
+    #[test]
+    fn targets() {
+        let target = |text: &str| {
+            let target = target_from_str(text).unwrap();
+            (target.host, target.port)
+        };
+        assert_eq!(target("build-box"), ("build-box".into(), None));
+        assert_eq!(target("build-box:8000"), ("build-box".into(), Some(8000)));
+        assert_eq!(target("10.0.0.2"), ("10.0.0.2".into(), None));
+        assert_eq!(target("10.0.0.2:8000"), ("10.0.0.2".into(), Some(8000)));
+        assert_eq!(target("::1"), ("::1".into(), None));
+        assert_eq!(target("[::1]"), ("::1".into(), None));
+        assert_eq!(target("[::1]:8000"), ("::1".into(), Some(8000)));
+
+        for text in [
+            "",
+            ":8000",
+            "host:",
+            "host:port",
+            "host:99999",
+            "[::1",
+            "[::1]8000",
+        ] {
+            assert!(target_from_str(text).is_err(), "{text:?} was accepted");
+        }
+    }
 }
@@ -0,0 +1,75 @@
+//! Serializing the resolved arguments for `--print-config`.
+//!
+//! Values which don't serialize to anything readable on their own, such as durations and globs,
+//! are written the same way as on the command line. Keys are never written.
+
+use std::{ffi::OsString, fmt::Display, time::Duration};
+
+use serde::Serializer;
+
+/// Written in place of secrets
+const REDACTED: &str = "<redacted>";
+
+pub fn duration<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    let text = humantime::format_duration(*duration).to_string();
+    serializer.serialize_str(&text.replace(' ', ""))
+}
+
+pub fn optional_duration<S: Serializer>(
+    value: &Option<Duration>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match value {
+        Some(value) => duration(value, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+pub fn os_string<S: Serializer>(value: &OsString, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&value.to_string_lossy())
+}
+
+pub fn os_strings<S: Serializer>(values: &[OsString], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(values.iter().map(|value| value.to_string_lossy()))
+}
+
+pub fn optional_os_strings<S: Serializer>(
+    values: &Option<Vec<OsString>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match values {
+        Some(values) => os_strings(values, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+pub fn display_all<T: Display, S: Serializer>(
+    values: &[T],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(values.iter().map(ToString::to_string))
+}
+
+pub fn optional_display<T: Display, S: Serializer>(
+    value: &Option<T>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match value {
+        Some(value) => serializer.collect_str(value),
+        None => serializer.serialize_none(),
+    }
+}
+
+pub fn redacted<S: Serializer>(values: &[String], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(values.iter().map(|_| REDACTED))
+}
+
+pub fn optional_redacted<S: Serializer>(
+    value: &Option<String>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match value {
+        Some(_) => serializer.serialize_str(REDACTED),
+        None => serializer.serialize_none(),
+    }
+}
@@ -0,0 +1,587 @@
+//! Construction of the command executed in response to triggers.
+
+use std::{
+    ffi::OsString,
+    io::Write,
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+
+use anyhow::Context;
+use tokio::process::Command;
+
+use crate::{cli, watcher::ExecutionTrigger};
+
+/// Replaced by the modified paths when using `--pass-paths`.
+const PATHS_PLACEHOLDER: &str = "{}";
+
+/// Environment variable containing the path of the file which triggered execution.
+const CHANGED_PATH_VARIABLE: &str = "WITNESS_CHANGED_PATH";
+
+/// Environment variable containing the kind of source which triggered execution.
+const TRIGGER_KIND_VARIABLE: &str = "WITNESS_TRIGGER_KIND";
+
+/// Environment variable containing the address of the client which triggered execution.
+const TRIGGER_ADDR_VARIABLE: &str = "WITNESS_TRIGGER_ADDR";
+
+/// Environment variable containing the number of events combined into the trigger.
+const EVENT_COUNT_VARIABLE: &str = "WITNESS_EVENT_COUNT";
+
+/// Environment variable containing the time of the trigger in RFC 3339 format.
+const TRIGGER_TIME_VARIABLE: &str = "WITNESS_TRIGGER_TIME";
+
+/// Environment variable containing the path of a file listing all changed paths, one per line.
+const CHANGED_LIST_VARIABLE: &str = "WITNESS_CHANGED_LIST";
+
+/// Environment variable containing the name of the rule from the config file being run.
+const RULE_VARIABLE: &str = "WITNESS_RULE";
+
+/// Environment variable containing the `--debounce-group` of the changed files.
+const TRIGGER_GROUP_VARIABLE: &str = "WITNESS_TRIGGER_GROUP";
+
+/// Environment variable containing the rule named by a `--tcp-json` client.
+const TRIGGER_RULE_VARIABLE: &str = "WITNESS_TRIGGER_RULE";
+
+/// The standard input of the command, which is inherited from witness unless told otherwise.
+pub fn stdin(options: &cli::BehaviourOptions) -> anyhow::Result<Stdio> {
+    if options.no_stdin {
+        return Ok(Stdio::null());
+    }
+
+    match &options.stdin_file {
+        None if inherits_stdin(options) => Ok(Stdio::inherit()),
+        None => Ok(Stdio::null()),
+        Some(path) => {
+            let file = std::fs::File::open(path)
+                .with_context(|| format!("failed to open stdin file: {}", path.display()))?;
+            Ok(Stdio::from(file))
+        }
+    }
+}
+
+/// Whether the command shares the standard input of witness
+fn inherits_stdin(options: &cli::BehaviourOptions) -> bool {
+    // with `--interactive` or `--exit-on-stdin-eof` the input is meant for us
+    !options.no_stdin
+        && options.stdin_file.is_none()
+        && !(options.interactive || options.exit_on_stdin_eof)
+}
+
+/// Whether the command reads from the terminal. Only the foreground process group of the terminal
+/// may read from it, and any other process which tries is stopped.
+fn reads_terminal(options: &cli::BehaviourOptions) -> bool {
+    use std::io::IsTerminal;
+    inherits_stdin(options) && std::io::stdin().is_terminal()
+}
+
+/// Setup options for launching the command line in response to the trigger.
+pub fn build(
+    args: &cli::Arguments,
+    command_line: &[String],
+    trigger: &ExecutionTrigger,
+) -> Command {
+    let changed_paths = changed_paths(args, trigger);
+
+    let paths = if args.behaviour.pass_paths {
+        Some(changed_paths.as_slice())
+    } else {
+        None
+    };
+
+    let mut command: Command;
+    if command_line.len() == 1 {
+        let mut script = match paths {
+            Some(paths) => shell_with_paths(&command_line[0], paths),
+            None => command_line[0].clone(),
+        };
+        for argument in &trigger.args {
+            script.push(' ');
+            script.push_str(&shell_quote(argument));
+        }
+        command = Command::new(&args.behaviour.shell);
+        command.arg(args.behaviour.shell_arg()).arg(script);
+    } else {
+        command = Command::new(&command_line[0]);
+        match paths {
+            Some(paths) => command.args(arguments_with_paths(&command_line[1..], paths)),
+            None => command.args(&command_line[1..]),
+        };
+        command.args(&trigger.args);
+    }
+
+    command
+        .kill_on_drop(true)
+        .stdin(Stdio::inherit())
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit());
+
+    // Run the command in its own process group. Signals then reach any processes it started, and
+    // a Ctrl-C in the terminal only reaches us, so that we decide how the command is stopped. A
+    // command reading from the terminal has to stay in our group, or it would be stopped.
+    #[cfg(unix)]
+    if !reads_terminal(&args.behaviour) {
+        // SAFETY: `setpgid` is async-signal-safe, and nothing is allocated in the closure
+        unsafe {
+            command.pre_exec(|| match libc::setpgid(0, 0) {
+                0 => Ok(()),
+                _ => Err(std::io::Error::last_os_error()),
+            });
+        }
+    }
+
+    if let Some(workdir) = &args.behaviour.workdir {
+        command.current_dir(workdir);
+    }
+
+    // Our own variables are set below, so that they are never removed
+    if args.behaviour.env_clear {
+        command.env_clear();
+    }
+    for key in &args.behaviour.env_remove {
+        command.env_remove(key);
+    }
+    command.envs(args.behaviour.env.iter().map(|(key, value)| (key, value)));
+
+    // Tell the command which file changed
+    if let Some(path) = changed_paths.last() {
+        command.env(CHANGED_PATH_VARIABLE, path);
+    } else {
+        command.env_remove(CHANGED_PATH_VARIABLE);
+    }
+
+    // Tell the command what triggered it
+    let source = trigger.source.as_ref();
+    let variables = [
+        (
+            TRIGGER_KIND_VARIABLE,
+            source.map(|source| source.kind().to_owned()),
+        ),
+        (
+            TRIGGER_ADDR_VARIABLE,
+            source
+                .and_then(|source| source.addr())
+                .map(|addr| addr.to_string()),
+        ),
+        (
+            EVENT_COUNT_VARIABLE,
+            source.map(|_| trigger.events.to_string()),
+        ),
+        (
+            TRIGGER_TIME_VARIABLE,
+            trigger
+                .time
+                .map(|time| humantime::format_rfc3339_millis(time).to_string()),
+        ),
+        (TRIGGER_GROUP_VARIABLE, trigger.group.clone()),
+        (TRIGGER_RULE_VARIABLE, trigger.rule.clone()),
+        (RULE_VARIABLE, args.rule.clone()),
+    ];
+    for (name, value) in variables {
+        match value {
+            Some(value) => command.env(name, value),
+            None => command.env_remove(name),
+        };
+    }
+
+    command
+}
+
+/// Setup a script which runs through the shell alongside the command, such as the
+/// `--clear-command` or `--ready-check`
+pub fn shell_script(args: &cli::Arguments, script: &str) -> Command {
+    let mut command = Command::new(&args.behaviour.shell);
+    command
+        .arg(args.behaviour.shell_arg())
+        .arg(script)
+        .kill_on_drop(true)
+        .stdin(Stdio::null());
+    if let Some(workdir) = &args.behaviour.workdir {
+        command.current_dir(workdir);
+    }
+    command
+}
+
+/// Write the changed paths to a temporary file, one per line, and tell the command where to find
+/// it. The file is deleted when the returned path is dropped, which should happen once the command
+/// has exited.
+pub fn write_changed_list(
+    command: &mut Command,
+    args: &cli::Arguments,
+    trigger: &ExecutionTrigger,
+) -> anyhow::Result<Option<tempfile::TempPath>> {
+    if trigger.paths.is_empty() {
+        command.env_remove(CHANGED_LIST_VARIABLE);
+        return Ok(None);
+    }
+
+    let mut file = tempfile::Builder::new()
+        .prefix("witness-changed-")
+        .tempfile()
+        .context("failed to create the list of changed paths")?;
+
+    let mut contents = Vec::new();
+    for path in changed_paths(args, trigger) {
+        contents.extend_from_slice(&path_bytes(&path));
+        contents.push(b'\n');
+    }
+    file.write_all(&contents)
+        .context("failed to write the list of changed paths")?;
+
+    let path = file.into_temp_path();
+    command.env(CHANGED_LIST_VARIABLE, &path);
+    Ok(Some(path))
+}
+
+/// The changed paths as they should be seen by the command
+fn changed_paths(args: &cli::Arguments, trigger: &ExecutionTrigger) -> Vec<PathBuf> {
+    // paths relative to our own directory would be wrong in another working directory
+    match &args.behaviour.workdir {
+        Some(_) => trigger.paths.iter().map(|path| absolute(path)).collect(),
+        None => trigger.paths.clone(),
+    }
+}
+
+#[cfg(unix)]
+fn path_bytes(path: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+fn path_bytes(path: &Path) -> Vec<u8> {
+    path.to_string_lossy().into_owned().into_bytes()
+}
+
+/// Substitute the placeholder arguments with the paths, or append them if there is no placeholder.
+fn arguments_with_paths(arguments: &[String], paths: &[PathBuf]) -> Vec<OsString> {
+    let mut result = Vec::with_capacity(arguments.len() + paths.len());
+
+    if arguments
+        .iter()
+        .any(|argument| argument == PATHS_PLACEHOLDER)
+    {
+        for argument in arguments {
+            if argument == PATHS_PLACEHOLDER {
+                result.extend(paths.iter().map(|path| path.clone().into_os_string()));
+            } else {
+                result.push(argument.into());
+            }
+        }
+    } else {
+        result.extend(arguments.iter().map(OsString::from));
+        result.extend(paths.iter().map(|path| path.clone().into_os_string()));
+    }
+
+    result
+}
+
+/// Substitute the placeholder in the shell script with the quoted paths, or append them if there
+/// is no placeholder.
+fn shell_with_paths(script: &str, paths: &[PathBuf]) -> String {
+    let quoted = paths
+        .iter()
+        .map(|path| shell_quote(&path.to_string_lossy()))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    if script.contains(PATHS_PLACEHOLDER) {
+        script.replace(PATHS_PLACEHOLDER, &quoted)
+    } else if quoted.is_empty() {
+        script.to_owned()
+    } else {
+        format!("{script} {quoted}")
+    }
+}
+
+fn absolute(path: &Path) -> PathBuf {
+    match std::env::current_dir() {
+        Ok(cwd) => cwd.join(path),
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+/// Quote the text so that a POSIX shell treats it as a single word.
+fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn build_command(args: &[&str], paths: &[&str]) -> Vec<String> {
+        let args = cli::Arguments::parse_from(["witness", "--shell", "sh"].iter().chain(args));
+        let trigger = ExecutionTrigger {
+            paths: paths.iter().map(PathBuf::from).collect(),
+            ..ExecutionTrigger::default()
+        };
+        let command = build(&args, &args.command, &trigger);
+        let command = command.as_std();
+        std::iter::once(command.get_program())
+            .chain(command.get_args())
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    /// Arguments from a `--tcp-json` client follow the command
+    #[test]
+    fn trigger_args() {
+        let build_with_args = |args: &[&str]| {
+            let args = cli::Arguments::parse_from(["witness", "--shell", "sh"].iter().chain(args));
+            let trigger = ExecutionTrigger {
+                args: vec!["--fast".to_owned(), "it's".to_owned()],
+                ..ExecutionTrigger::default()
+            };
+            let command = build(&args, &args.command, &trigger);
+            let command = command.as_std();
+            std::iter::once(command.get_program())
+                .chain(command.get_args())
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            build_with_args(&["./deploy.sh"]),
+            ["sh", "-c", r"./deploy.sh '--fast' 'it'\''s'"]
+        );
+        assert_eq!(
+            build_with_args(&["./deploy.sh", "prod"]),
+            ["./deploy.sh", "prod", "--fast", "it's"]
+        );
+    }
+
+    #[test]
+    fn shell_arg() {
+        let shell_command = |args: &[&str]| {
+            let args = cli::Arguments::parse_from(["witness"].iter().chain(args));
+            let command = build(&args, &args.command, &ExecutionTrigger::default());
+            let command = command.as_std();
+            std::iter::once(command.get_program())
+                .chain(command.get_args())
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            shell_command(&["--shell", "bash", "cargo build"]),
+            ["bash", "-c", "cargo build"]
+        );
+        assert_eq!(
+            shell_command(&["--shell", "nu", "--shell-arg", "--commands", "cargo build"]),
+            ["nu", "--commands", "cargo build"]
+        );
+
+        // `cmd` takes its own kind of flags
+        assert_eq!(
+            shell_command(&["--shell", "cmd", "cargo build"]),
+            ["cmd", "/C", "cargo build"]
+        );
+        assert_eq!(
+            shell_command(&["--shell", "CMD.EXE", "cargo build"]),
+            ["CMD.EXE", "/C", "cargo build"]
+        );
+
+        // commands with arguments don't go through the shell
+        assert_eq!(
+            shell_command(&["--shell", "cmd", "cargo", "build"]),
+            ["cargo", "build"]
+        );
+
+        let empty = cli::Arguments::try_parse_from(["witness", "--shell-arg=", "cargo build"]);
+        assert!(empty.is_err());
+    }
+
+    #[test]
+    fn paths_not_passed_by_default() {
+        assert_eq!(build_command(&["echo", "{}"], &["a.rs"]), ["echo", "{}"]);
+    }
+
+    #[test]
+    fn paths_appended() {
+        assert_eq!(
+            build_command(&["--pass-paths", "eslint", "--fix"], &["a.js", "b.js"]),
+            ["eslint", "--fix", "a.js", "b.js"]
+        );
+    }
+
+    #[test]
+    fn paths_placeholder() {
+        assert_eq!(
+            build_command(&["--pass-paths", "cp", "{}", "dist"], &["a.js", "b.js"]),
+            ["cp", "a.js", "b.js", "dist"]
+        );
+    }
+
+    #[test]
+    fn paths_shell_appended() {
+        assert_eq!(
+            build_command(&["--pass-paths", "eslint --fix"], &["a.js", "it's.js"]),
+            ["sh", "-c", r"eslint --fix 'a.js' 'it'\''s.js'"]
+        );
+    }
+
+    #[test]
+    fn paths_shell_placeholder() {
+        assert_eq!(
+            build_command(&["--pass-paths", "cat {} | wc -l"], &["a b.txt"]),
+            ["sh", "-c", "cat 'a b.txt' | wc -l"]
+        );
+    }
+
+    /// Network triggers have no paths
+    #[test]
+    fn paths_empty() {
+        assert_eq!(
+            build_command(&["--pass-paths", "cargo test"], &[]),
+            ["sh", "-c", "cargo test"]
+        );
+        assert_eq!(build_command(&["--pass-paths", "ls", "{}"], &[]), ["ls"]);
+    }
+
+    fn environment(trigger: &ExecutionTrigger) -> Vec<(String, Option<String>)> {
+        let args = cli::Arguments::parse_from(["witness", "true"]);
+        let command = build(&args, &args.command, trigger);
+        let mut variables = command
+            .as_std()
+            .get_envs()
+            .map(|(name, value)| {
+                let value = value.map(|value| value.to_string_lossy().into_owned());
+                (name.to_string_lossy().into_owned(), value)
+            })
+            .collect::<Vec<_>>();
+        variables.sort();
+        variables
+    }
+
+    fn variable(variables: &[(String, Option<String>)], name: &str) -> Option<String> {
+        let (_, value) = variables.iter().find(|(key, _)| key == name).unwrap();
+        value.clone()
+    }
+
+    #[test]
+    fn trigger_variables() {
+        use crate::watcher::TriggerSource;
+
+        let time = std::time::UNIX_EPOCH + std::time::Duration::from_millis(1500);
+        let trigger = ExecutionTrigger {
+            paths: vec![PathBuf::from("a.rs")],
+            source: Some(TriggerSource::File),
+            events: 3,
+            time: Some(time),
+            group: Some("rust".into()),
+            ..ExecutionTrigger::default()
+        };
+        let variables = environment(&trigger);
+        assert_eq!(
+            variable(&variables, "WITNESS_TRIGGER_KIND").unwrap(),
+            "file"
+        );
+        assert_eq!(variable(&variables, "WITNESS_EVENT_COUNT").unwrap(), "3");
+        assert_eq!(
+            variable(&variables, "WITNESS_TRIGGER_TIME").unwrap(),
+            "1970-01-01T00:00:01.500Z"
+        );
+        assert_eq!(variable(&variables, "WITNESS_TRIGGER_ADDR"), None);
+        assert_eq!(
+            variable(&variables, "WITNESS_TRIGGER_GROUP").unwrap(),
+            "rust"
+        );
+
+        let addr = "127.0.0.1:4000".parse().unwrap();
+        let mut trigger = ExecutionTrigger::network(TriggerSource::Tcp(addr));
+        trigger.rule = Some("deploy".into());
+        let variables = environment(&trigger);
+        assert_eq!(variable(&variables, "WITNESS_TRIGGER_KIND").unwrap(), "tcp");
+        assert_eq!(variable(&variables, "WITNESS_EVENT_COUNT").unwrap(), "1");
+        assert_eq!(
+            variable(&variables, "WITNESS_TRIGGER_ADDR").unwrap(),
+            "127.0.0.1:4000"
+        );
+        assert_eq!(
+            variable(&variables, "WITNESS_TRIGGER_RULE").unwrap(),
+            "deploy"
+        );
+    }
+
+    /// Nothing triggered the initial run
+    #[test]
+    fn initial_variables() {
+        let variables = environment(&ExecutionTrigger::default());
+        for name in [
+            "WITNESS_TRIGGER_KIND",
+            "WITNESS_TRIGGER_ADDR",
+            "WITNESS_EVENT_COUNT",
+            "WITNESS_TRIGGER_TIME",
+            "WITNESS_TRIGGER_GROUP",
+            "WITNESS_TRIGGER_RULE",
+        ] {
+            assert_eq!(variable(&variables, name), None, "{name}");
+        }
+    }
+
+    #[test]
+    fn workdir() {
+        let args =
+            cli::Arguments::parse_from(["witness", "--chdir", "/tmp", "--pass-paths", "ls", "-l"]);
+        let trigger = ExecutionTrigger {
+            paths: vec![PathBuf::from("src/main.rs")],
+            ..ExecutionTrigger::default()
+        };
+        let command = build(&args, &args.command, &trigger);
+        let command = command.as_std();
+        assert_eq!(command.get_current_dir(), Some(Path::new("/tmp")));
+
+        // the paths are still valid from the other directory
+        let cwd = std::env::current_dir().unwrap();
+        let path = command.get_args().last().unwrap();
+        assert_eq!(Path::new(path), cwd.join("src/main.rs"));
+    }
+
+    #[test]
+    fn changed_list() {
+        let args = cli::Arguments::parse_from(["witness", "true"]);
+        let trigger = ExecutionTrigger {
+            paths: vec![PathBuf::from("src/main.rs"), PathBuf::from("src/cli.rs")],
+            ..ExecutionTrigger::default()
+        };
+        let mut command = build(&args, &args.command, &trigger);
+        let list = write_changed_list(&mut command, &args, &trigger)
+            .unwrap()
+            .unwrap();
+
+        let variables = command
+            .as_std()
+            .get_envs()
+            .map(|(key, value)| (key.to_owned(), value.map(ToOwned::to_owned)))
+            .collect::<Vec<_>>();
+        let (_, value) = variables
+            .iter()
+            .find(|(key, _)| key == "WITNESS_CHANGED_LIST")
+            .unwrap();
+        assert_eq!(value.as_deref(), Some(list.as_os_str()));
+
+        let contents = std::fs::read_to_string(&list).unwrap();
+        assert_eq!(contents, "src/main.rs\nsrc/cli.rs\n");
+
+        // the file is removed once the command is done
+        let path = list.to_path_buf();
+        drop(list);
+        assert!(!path.exists());
+    }
+
+    /// There is no list when no files changed
+    #[test]
+    fn changed_list_without_paths() {
+        let args = cli::Arguments::parse_from(["witness", "true"]);
+        let trigger = ExecutionTrigger::default();
+        let mut command = build(&args, &args.command, &trigger);
+        let list = write_changed_list(&mut command, &args, &trigger).unwrap();
+        assert!(list.is_none());
+        let removed = command
+            .as_std()
+            .get_envs()
+            .any(|(key, value)| key == "WITNESS_CHANGED_LIST" && value.is_none());
+        assert!(removed);
+    }
+}
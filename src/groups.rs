@@ -0,0 +1,170 @@
+//! Running a command of its own for each `--debounce-group`, with `--group-command`. A change in
+//! one group only restarts the command of that group, leaving the others running.
+
+use std::{
+    collections::BTreeMap,
+    process::{ExitCode, ExitStatus},
+    time::Instant,
+};
+
+use anyhow::Context;
+use tokio::{process::Child, sync::oneshot, task::JoinHandle};
+
+use crate::{cli, command, watcher};
+
+/// The commands which are running, keyed by the group they belong to. Changes outside any group
+/// with a command of its own run the main command, which is keyed by `None`.
+#[derive(Default)]
+struct Children {
+    running: BTreeMap<Option<String>, Running>,
+}
+
+/// A command running in the background until it exits or is stopped
+struct Running {
+    /// Tells the command to terminate
+    stop: oneshot::Sender<()>,
+    /// Completes once the command has exited
+    done: JoinHandle<()>,
+}
+
+pub async fn run(args: &cli::Arguments, mut watcher: watcher::Watcher) -> anyhow::Result<ExitCode> {
+    let interrupt = crate::shutdown(&args.behaviour);
+    tokio::pin!(interrupt);
+
+    let mut children = Children::default();
+
+    if args.behaviour.initial {
+        let trigger = watcher::ExecutionTrigger::default();
+        children.restart(args, None, &trigger).await?;
+        for command in &args.behaviour.group_commands {
+            let trigger = watcher::ExecutionTrigger {
+                group: Some(command.group.clone()),
+                ..watcher::ExecutionTrigger::default()
+            };
+            children
+                .restart(args, Some(&command.command), &trigger)
+                .await?;
+        }
+    }
+
+    loop {
+        tokio::select! {
+            event = watcher.recv() => match event {
+                Err(error) => {
+                    children.stop_all().await;
+                    return Err(error);
+                }
+                Ok(trigger) => {
+                    let command = group_command(args, trigger.group.as_deref());
+                    children.restart(args, command, &trigger).await?;
+                }
+            },
+            _ = &mut interrupt => {
+                children.stop_all().await;
+                return Ok(ExitCode::SUCCESS);
+            }
+        }
+    }
+}
+
+/// The command given for the group, if it has one
+fn group_command<'a>(args: &'a cli::Arguments, group: Option<&str>) -> Option<&'a str> {
+    let group = group?;
+    args.behaviour
+        .group_commands
+        .iter()
+        .find(|command| command.group == group)
+        .map(|command| command.command.as_str())
+}
+
+impl Children {
+    /// Stop the command of the group, if it is still running, and start it again
+    async fn restart(
+        &mut self,
+        args: &cli::Arguments,
+        group_command: Option<&str>,
+        trigger: &watcher::ExecutionTrigger,
+    ) -> anyhow::Result<()> {
+        let key = group_command.and(trigger.group.clone());
+        if let Some(running) = self.running.remove(&key) {
+            running.stop().await;
+        }
+
+        let command_line = match group_command {
+            Some(command) => vec![command.to_owned()],
+            None => args.command.clone(),
+        };
+
+        crate::clear_screen(args.clear()).context("failed to clear the screen")?;
+        let banner = !args.behaviour.no_banner && !args.quiet;
+        let label = key.as_deref().or(args.label.as_deref()).map(str::to_owned);
+        if banner {
+            crate::print_banner(label.as_deref(), &command_line, false, args.color())
+                .context("failed to print the command")?;
+        }
+        let color = args.color();
+        let exited = move |status| {
+            if banner {
+                if let Err(error) = crate::print_exit_status(label.as_deref(), status, color) {
+                    warn!(%error, "failed to print the exit status");
+                }
+            }
+        };
+
+        let mut command = command::build(args, &command_line, trigger);
+        command.stdin(command::stdin(&args.behaviour)?);
+        let child = command
+            .spawn()
+            .with_context(|| format!("failed to run command: {}", command_line.join(" ")))?;
+
+        let (stop, stopped) = oneshot::channel();
+        let behaviour = args.behaviour.clone();
+        let done = tokio::spawn(supervise(child, key.clone(), behaviour, stopped, exited));
+        self.running.insert(key, Running { stop, done });
+        Ok(())
+    }
+
+    /// Stop every command which is still running
+    async fn stop_all(&mut self) {
+        for (_, running) in std::mem::take(&mut self.running) {
+            running.stop().await;
+        }
+    }
+}
+
+impl Running {
+    async fn stop(self) {
+        // the command may have exited on its own already
+        let _ = self.stop.send(());
+        if let Err(error) = self.done.await {
+            warn!(%error, "failed to wait for the command");
+        }
+    }
+}
+
+/// Wait for the command to exit, or terminate it when told to. Only a command which exits on its
+/// own is reported to `exited`.
+async fn supervise(
+    mut child: Child,
+    group: Option<String>,
+    behaviour: cli::BehaviourOptions,
+    stopped: oneshot::Receiver<()>,
+    exited: impl FnOnce(ExitStatus),
+) {
+    let started = Instant::now();
+    tokio::select! {
+        status = child.wait() => match status {
+            Ok(status) => {
+                let elapsed = started.elapsed();
+                info!(?group, exit_status = status.code(), ?elapsed, "command terminated");
+                exited(status);
+            }
+            Err(error) => warn!(?group, %error, "failed to wait for the command"),
+        },
+        _ = stopped => {
+            if let Err(error) = crate::terminate_process(&mut child, &behaviour).await {
+                warn!(?group, %error, "failed to terminate the command");
+            }
+        }
+    }
+}
@@ -0,0 +1,84 @@
+//! Replacing the command by typing a new one into our standard input, with `--interactive`.
+
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+use crate::cli;
+
+/// Lines typed into our standard input
+pub struct Input {
+    lines: UnboundedReceiver<String>,
+}
+
+pub enum Line {
+    /// A new command to run through the shell
+    Command(String),
+    /// Nothing more will be typed
+    Eof,
+}
+
+impl Input {
+    /// Start reading our standard input, if asked to
+    pub fn from_args(behaviour: &cli::BehaviourOptions) -> Option<Input> {
+        if !behaviour.interactive {
+            return None;
+        }
+
+        // a blocking read of the standard input would keep the runtime from shutting down, so it
+        // is read on a thread of its own
+        let (sender, lines) = unbounded_channel();
+        std::thread::spawn(move || {
+            for line in std::io::stdin().lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(error) => {
+                        warn!(%error, "failed to read a command from standard input");
+                        break;
+                    }
+                };
+                let command = line.trim();
+                if !command.is_empty() && sender.send(command.to_owned()).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Some(Input { lines })
+    }
+
+    async fn next(&mut self) -> Line {
+        match self.lines.recv().await {
+            Some(command) => Line::Command(command),
+            None => Line::Eof,
+        }
+    }
+}
+
+/// Wait for the next line, or forever if there is no input to read
+pub async fn next_line(input: &mut Option<Input>) -> Line {
+    match input {
+        Some(input) => input.next().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Update the command with the typed line, returning `true` if the new command should run now.
+/// Once the input ends, the original command is used again.
+pub fn switch_command(
+    line: Line,
+    input: &mut Option<Input>,
+    command: &mut Option<Vec<String>>,
+) -> bool {
+    match line {
+        Line::Command(typed) => {
+            info!(command = %typed, "switching to the typed command");
+            *command = Some(vec![typed]);
+            true
+        }
+        Line::Eof => {
+            info!("end of standard input, reverting to the original command");
+            *input = None;
+            *command = None;
+            false
+        }
+    }
+}
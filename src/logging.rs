@@ -0,0 +1,269 @@
+//! Configuration of the log output.
+
+use std::{io::Write, sync::Arc};
+
+use anyhow::{anyhow, Context};
+use tracing::{level_filters::LevelFilter, Subscriber};
+use tracing_subscriber::{filter::EnvFilter, fmt::MakeWriter, util::SubscriberInitExt};
+
+use crate::cli;
+
+/// Environment variable which overrides the log directives
+const VARIABLE_NAME: &str = "WITNESS_LOG";
+
+pub fn init(args: &cli::Arguments) -> anyhow::Result<()> {
+    let directives = match std::env::var(VARIABLE_NAME) {
+        Err(std::env::VarError::NotPresent) => String::new(),
+        Err(std::env::VarError::NotUnicode(_)) => {
+            return Err(anyhow!(
+                "{VARIABLE_NAME} did not contain valid Unicode data"
+            ))
+        }
+        Ok(level) => level,
+    };
+
+    let env_filter = env_filter(args, &directives)?;
+    let writer = Labelled::new(args.log_format, args.label.as_deref(), std::io::stdout);
+    subscriber(args.log_format, args.color(), env_filter, writer).try_init()?;
+
+    Ok(())
+}
+
+fn env_filter(args: &cli::Arguments, directives: &str) -> anyhow::Result<EnvFilter> {
+    EnvFilter::builder()
+        .with_default_directive(default_level(args).into())
+        .parse(directives)
+        .with_context(|| format!("{VARIABLE_NAME} contained an invalid directive: {directives:?}"))
+}
+
+/// The level logged unless `WITNESS_LOG` says otherwise
+fn default_level(args: &cli::Arguments) -> LevelFilter {
+    if args.quiet {
+        return LevelFilter::ERROR;
+    }
+    match args.verbose {
+        0 => LevelFilter::WARN,
+        1 => LevelFilter::INFO,
+        2 => LevelFilter::DEBUG,
+        _ => LevelFilter::TRACE,
+    }
+}
+
+fn subscriber<W>(
+    format: cli::LogFormat,
+    ansi: bool,
+    env_filter: EnvFilter,
+    writer: W,
+) -> Box<dyn Subscriber + Send + Sync>
+where
+    W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(env_filter)
+        .with_ansi(ansi)
+        .with_writer(writer);
+
+    match format {
+        cli::LogFormat::Text => Box::new(builder.finish()),
+        cli::LogFormat::Json => Box::new(builder.json().flatten_event(true).finish()),
+    }
+}
+
+/// Marks every log message with the `--label` of this instance
+#[derive(Clone)]
+struct Labelled<W> {
+    inner: W,
+    /// Replaces the start of every message
+    prefix: Option<Prefix>,
+}
+
+#[derive(Clone)]
+enum Prefix {
+    /// Written before the message
+    Text(Arc<[u8]>),
+    /// Replaces the opening brace of the JSON object, adding a field
+    Json(Arc<[u8]>),
+}
+
+/// Writes a single message, with the prefix in front
+struct LabelledWriter<W> {
+    inner: W,
+    prefix: Option<Prefix>,
+}
+
+impl<W> Labelled<W> {
+    fn new(format: cli::LogFormat, label: Option<&str>, inner: W) -> Labelled<W> {
+        let prefix = label.map(|label| match format {
+            cli::LogFormat::Text => Prefix::Text(format!("[{label}] ").into_bytes().into()),
+            cli::LogFormat::Json => {
+                let field = serde_json::to_string(label).expect("strings are valid JSON");
+                Prefix::Json(format!("{{\"label\":{field},").into_bytes().into())
+            }
+        });
+        Labelled { inner, prefix }
+    }
+}
+
+impl<'a, W: MakeWriter<'a>> MakeWriter<'a> for Labelled<W> {
+    type Writer = LabelledWriter<W::Writer>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        LabelledWriter {
+            inner: self.inner.make_writer(),
+            prefix: self.prefix.clone(),
+        }
+    }
+}
+
+impl<W: Write> Write for LabelledWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        // a writer is made for every message, so only its first write starts a message
+        let message = match (self.prefix.take(), buf) {
+            (None, _) => return self.inner.write(buf),
+            (Some(Prefix::Text(prefix)), _) => [&prefix, buf].concat(),
+            (Some(Prefix::Json(prefix)), [b'{', rest @ ..]) => [&prefix, rest].concat(),
+            (Some(Prefix::Json(_)), _) => buf.to_vec(),
+        };
+        self.inner.write_all(&message)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use std::sync::{Arc, Mutex};
+
+    /// Collects all log output in memory
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Output {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for Output {
+        type Writer = Output;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    impl Output {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    /// Log a few events with the given arguments and return the output
+    fn capture_logs(args: &str) -> String {
+        let args = cli::Arguments::parse_from(args.split_whitespace());
+        let output = Output::default();
+        let env_filter = env_filter(&args, "").unwrap();
+        let writer = Labelled::new(args.log_format, args.label.as_deref(), output.clone());
+        let subscriber = subscriber(args.log_format, args.color(), env_filter, writer);
+
+        tracing::subscriber::with_default(subscriber, || {
+            let path = std::path::Path::new("src/main.rs");
+            info!(?path, "file trigger");
+            warn!(exit_status = 3, "command terminated");
+        });
+
+        output.contents()
+    }
+
+    #[test]
+    fn text_format() {
+        let logs = capture_logs("witness --verbose cargo check");
+        assert!(logs.contains("file trigger"));
+        assert!(serde_json::from_str::<serde_json::Value>(logs.lines().next().unwrap()).is_err());
+    }
+
+    #[test]
+    fn json_format() {
+        let logs = capture_logs("witness --verbose --log-format json cargo check");
+        let events = logs
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["level"], "INFO");
+        assert_eq!(events[0]["message"], "file trigger");
+        assert_eq!(events[0]["path"], "\"src/main.rs\"");
+        assert_eq!(events[1]["level"], "WARN");
+        assert_eq!(events[1]["exit_status"], 3);
+    }
+
+    #[test]
+    fn label() {
+        let logs = capture_logs("witness --verbose --label api cargo check");
+        assert_eq!(logs.lines().count(), 2);
+        assert!(
+            logs.lines().all(|line| line.starts_with("[api] ")),
+            "{logs}"
+        );
+
+        let logs = capture_logs("witness --verbose --log-format json --label api cargo check");
+        let events = logs
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(events.len(), 2);
+        assert!(events.iter().all(|event| event["label"] == "api"));
+        assert_eq!(events[0]["message"], "file trigger");
+    }
+
+    #[test]
+    fn quiet_level() {
+        let args = cli::Arguments::parse_from(["witness", "--quiet", "cargo", "check"]);
+        let env_filter = env_filter(&args, "").unwrap();
+        assert_eq!(env_filter.max_level_hint(), Some(LevelFilter::ERROR));
+
+        let logs = capture_logs("witness -q cargo check");
+        assert!(logs.is_empty(), "{logs}");
+    }
+
+    #[test]
+    fn verbosity_levels() {
+        let level = |args: &str| {
+            let args = cli::Arguments::parse_from(args.split_whitespace());
+            default_level(&args)
+        };
+        assert_eq!(level("witness cargo check"), LevelFilter::WARN);
+        assert_eq!(level("witness -v cargo check"), LevelFilter::INFO);
+        assert_eq!(level("witness --verbose cargo check"), LevelFilter::INFO);
+        assert_eq!(level("witness -vv cargo check"), LevelFilter::DEBUG);
+        assert_eq!(
+            level("witness -v --verbose cargo check"),
+            LevelFilter::DEBUG
+        );
+        assert_eq!(level("witness -vvv cargo check"), LevelFilter::TRACE);
+        assert_eq!(level("witness -vvvvv cargo check"), LevelFilter::TRACE);
+        assert_eq!(level("witness -q cargo check"), LevelFilter::ERROR);
+    }
+
+    /// `WITNESS_LOG` takes precedence over the verbosity flags
+    #[test]
+    fn directives_override_verbosity() {
+        let args = cli::Arguments::parse_from(["witness", "-vvv", "cargo", "check"]);
+        let filter = env_filter(&args, "warn").unwrap();
+        assert_eq!(filter.max_level_hint(), Some(LevelFilter::WARN));
+
+        let args = cli::Arguments::parse_from(["witness", "cargo", "check"]);
+        let filter = env_filter(&args, "witness=debug").unwrap();
+        assert_eq!(filter.max_level_hint(), Some(LevelFilter::DEBUG));
+    }
+}
@@ -5,46 +5,143 @@
 #[macro_use]
 extern crate tracing;
 
+mod audit;
+mod capture;
+mod cidr;
 mod cli;
+mod command;
+mod groups;
+mod interactive;
+mod logging;
+mod notification;
+mod pending;
+mod ready;
+mod restarts;
+mod status;
+mod summary;
+mod timestamps;
 mod watcher;
 
-use anyhow::{anyhow, Context};
-use tokio::{
-    io::AsyncWriteExt,
-    process::{Child, Command},
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::Path,
+    process::{ExitCode, ExitStatus},
+    time::{Duration, Instant},
 };
 
+use anyhow::{anyhow, Context};
+use owo_colors::{OwoColorize, Style};
+use tokio::{io::AsyncWriteExt, process::Child};
+
 #[tokio::main(flavor = "current_thread")]
-async fn main() -> anyhow::Result<()> {
-    let args = cli::Arguments::parse();
-    init_tracing(&args).context("failed to initialize logging")?;
+async fn main() -> anyhow::Result<ExitCode> {
+    let args = cli::Arguments::parse()?;
+
+    if let Some(shell) = args.completions {
+        cli::write_completions(shell, &mut std::io::stdout());
+        return Ok(ExitCode::SUCCESS);
+    }
 
-    if args.network.trigger {
-        run_trigger(&args.network).await
+    if let Some(format) = args.print_config {
+        print!("{}", args.to_config(format)?);
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    logging::init(&args).context("failed to initialize logging")?;
+
+    if let Some(path) = &args.check_path {
+        check_path(&args.files, path)
+    } else if args.network.trigger {
+        run_trigger(&args).await?;
+        Ok(ExitCode::SUCCESS)
+    } else if !args.rules.is_empty() {
+        run_rules(&args).await
     } else {
         run_watch(&args).await
     }
 }
 
-async fn run_trigger(args: &cli::NetworkOptions) -> anyhow::Result<()> {
-    trigger_udp(&args.udp, &args.key).await?;
-    trigger_tcp(&args.tcp, &args.key).await?;
-    Ok(())
-}
+/// Watch each rule from the config file concurrently, until all of them are done or one fails
+async fn run_rules(args: &cli::Arguments) -> anyhow::Result<ExitCode> {
+    use tracing::Instrument;
 
-async fn trigger_udp(ports: &[u16], key: &str) -> anyhow::Result<()> {
-    use std::net::SocketAddr;
+    let rules = args.rule_arguments()?;
+    let count = rules.len();
 
-    let socket = tokio::net::UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0)))
+    let local = tokio::task::LocalSet::new();
+    let (sender, mut results) = tokio::sync::mpsc::unbounded_channel();
+    for rule in rules {
+        let sender = sender.clone();
+        let span = info_span!("rule", name = rule.rule.as_deref().unwrap_or_default());
+        local.spawn_local(
+            async move {
+                let result = run_watch(&rule).await;
+                let _ = sender.send(result);
+            }
+            .instrument(span),
+        );
+    }
+
+    local
+        .run_until(async move {
+            // the first rule to fail decides the exit code
+            let mut exit_code = ExitCode::SUCCESS;
+            for _ in 0..count {
+                let code = results.recv().await.expect("rules stopped unexpectedly")?;
+                if exit_code == ExitCode::SUCCESS {
+                    exit_code = code;
+                }
+            }
+            Ok(exit_code)
+        })
         .await
-        .context("failed to bind UDP socket")?;
+}
+
+/// Print whether a change to the path would trigger execution, for `--check-path`. Exits with
+/// failure if it wouldn't.
+fn check_path(options: &cli::FileOptions, path: &Path) -> anyhow::Result<ExitCode> {
+    let mut options = options.clone();
+    options.paths.paths = watcher::expand_globs(&options.paths)?;
+    let filter = watcher::FileFilter::from_args(&options)?;
+
+    // relative paths are taken from the current directory, like the watched paths
+    let cwd = std::env::current_dir().context("failed to get the current directory")?;
+    match filter.matches_path(&cwd.join(path)) {
+        Ok(()) => {
+            println!("{}: would trigger", path.display());
+            Ok(ExitCode::SUCCESS)
+        }
+        Err(reason) => {
+            println!("{}: ignored, because {reason}", path.display());
+            Ok(ExitCode::FAILURE)
+        }
+    }
+}
+
+async fn run_trigger(args: &cli::Arguments) -> anyhow::Result<()> {
+    let network = &args.network;
+    let auth = watcher::Auth::from_args(network)?;
+    let message = auth.message();
+    trigger_udp(&network.target, &network.udp, &message).await?;
+    trigger_tcp(network, &args.behaviour, &message).await?;
+    trigger_http(network, &args.behaviour, &message).await?;
+    trigger_unix(&network.unix, &message).await?;
+    trigger_fifo(&network.fifo, &message)?;
+    Ok(())
+}
 
+async fn trigger_udp(target: &cli::Target, ports: &[u16], message: &[u8]) -> anyhow::Result<()> {
     for &port in ports {
+        let addr = target.resolve(port).await?;
+        let socket = tokio::net::UdpSocket::bind(SocketAddr::new(unspecified(addr.ip()), 0))
+            .await
+            .context("failed to bind UDP socket")?;
+
         let count = socket
-            .send_to(key.as_bytes(), SocketAddr::from(([0, 0, 0, 0], port)))
+            .send_to(message, addr)
             .await
-            .with_context(|| format!("failed to send UDP trigger on port {port}"))?;
-        if count != key.len() {
+            .with_context(|| format!("failed to send UDP trigger to {addr}"))?;
+        if count != message.len() {
             return Err(anyhow!(
                 "failed to send entire key over UDP. Maybe it's too big?"
             ));
@@ -54,133 +151,1083 @@ async fn trigger_udp(ports: &[u16], key: &str) -> anyhow::Result<()> {
     Ok(())
 }
 
-async fn trigger_tcp(ports: &[u16], key: &str) -> anyhow::Result<()> {
-    use std::net::SocketAddr;
+/// The unspecified address in the same family as the given address.
+fn unspecified(addr: IpAddr) -> IpAddr {
+    match addr {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    }
+}
 
-    for &port in ports {
-        let mut stream = tokio::net::TcpStream::connect(SocketAddr::from(([0, 0, 0, 0], port)))
+/// Connect to the listener, retrying with exponential backoff (up to `--retry` times) while it
+/// is unavailable, such as when it is restarting
+async fn connect(
+    addr: SocketAddr,
+    behaviour: &cli::BehaviourOptions,
+    protocol: &str,
+) -> anyhow::Result<tokio::net::TcpStream> {
+    let mut retries = 0;
+    loop {
+        match tokio::net::TcpStream::connect(addr).await {
+            Ok(stream) => return Ok(stream),
+            Err(error) if retries < behaviour.retry => {
+                let delay = behaviour.delay_before_reconnect(retries, jitter());
+                info!(%addr, %error, ?delay, "failed to connect, retrying");
+                tokio::time::sleep(delay).await;
+                retries += 1;
+            }
+            Err(error) => {
+                return Err(error).with_context(|| match retries {
+                    0 => format!("failed to connect to {addr} over {protocol}"),
+                    _ => format!(
+                        "failed to connect to {addr} over {protocol} after {retries} retries"
+                    ),
+                })
+            }
+        }
+    }
+}
+
+/// A random fraction between 0 and 1
+fn jitter() -> f64 {
+    use std::hash::{BuildHasher, Hasher};
+
+    let random = std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish();
+    (random >> 11) as f64 / (1u64 << 53) as f64
+}
+
+async fn trigger_tcp(
+    args: &cli::NetworkOptions,
+    behaviour: &cli::BehaviourOptions,
+    message: &[u8],
+) -> anyhow::Result<()> {
+    for &port in &args.tcp {
+        let addr = args.target.resolve(port).await?;
+        let mut stream = connect(addr, behaviour, "TCP").await?;
+
+        stream
+            .write_all(message)
             .await
-            .with_context(|| format!("failed to connect to TCP port {port}"))?;
+            .with_context(|| format!("failed to write to {addr} over TCP"))?;
+
+        if args.ack {
+            wait_for_ack(&mut stream)
+                .await
+                .with_context(|| format!("TCP trigger to {addr} was not accepted"))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Wait for the listener to acknowledge the trigger
+async fn wait_for_ack(stream: &mut tokio::net::TcpStream) -> anyhow::Result<()> {
+    use tokio::io::AsyncReadExt;
 
+    let timeout = Duration::from_secs(5);
+    let mut response = Vec::new();
+    let mut reader = stream.take(16);
+    match tokio::time::timeout(timeout, reader.read_to_end(&mut response)).await {
+        Err(_) => Err(anyhow!("no response within {timeout:?}")),
+        Ok(result) => match result.context("failed to read response")? {
+            _ if response == watcher::ACK_OK => Ok(()),
+            _ if response == watcher::ACK_DENIED => Err(anyhow!("the trigger was denied")),
+            0 => Err(anyhow!("the connection was closed without a response")),
+            _ => Err(anyhow!(
+                "unexpected response: {:?}",
+                String::from_utf8_lossy(&response)
+            )),
+        },
+    }
+}
+
+async fn trigger_http(
+    args: &cli::NetworkOptions,
+    behaviour: &cli::BehaviourOptions,
+    message: &[u8],
+) -> anyhow::Result<()> {
+    use tokio::io::AsyncReadExt;
+
+    for &port in &args.http {
+        let addr = args.target.resolve(port).await?;
+        let mut stream = connect(addr, behaviour, "HTTP").await?;
+
+        let mut request = format!(
+            "POST / HTTP/1.1\r\nHost: {addr}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            message.len()
+        )
+        .into_bytes();
+        request.extend_from_slice(message);
         stream
-            .write_all(key.as_bytes())
+            .write_all(&request)
             .await
-            .with_context(|| format!("failed to write to TCP port {port}"))?;
+            .with_context(|| format!("failed to write to {addr} over HTTP"))?;
+
+        let mut response = String::new();
+        stream
+            .read_to_string(&mut response)
+            .await
+            .with_context(|| format!("failed to read response from {addr} over HTTP"))?;
+
+        let status = response.lines().next().unwrap_or_default();
+        if !status.contains(" 200 ") {
+            return Err(anyhow!("HTTP trigger to {addr} was rejected: {status}"));
+        }
     }
 
     Ok(())
 }
 
-async fn run_watch(args: &cli::Arguments) -> anyhow::Result<()> {
-    // watch sources for updates
-    let mut watcher = watcher::Watcher::new(args)?;
+#[cfg(unix)]
+async fn trigger_unix(paths: &[std::path::PathBuf], message: &[u8]) -> anyhow::Result<()> {
+    for path in paths {
+        let mut stream = tokio::net::UnixStream::connect(path)
+            .await
+            .with_context(|| format!("failed to connect to Unix socket: {}", path.display()))?;
 
-    // Setup options for launching the specified command
-    let mut command: Command;
-    if args.command.len() == 1 {
-        command = Command::new(&args.behaviour.shell);
-        command.arg("-c").arg(&args.command[0]);
-    } else {
-        command = Command::new(&args.command[0]);
-        command.args(&args.command[1..]);
+        stream
+            .write_all(message)
+            .await
+            .with_context(|| format!("failed to write to Unix socket: {}", path.display()))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+async fn trigger_unix(paths: &[std::path::PathBuf], _message: &[u8]) -> anyhow::Result<()> {
+    match paths.first() {
+        None => Ok(()),
+        Some(path) => Err(anyhow!(
+            "Unix sockets are not supported on this platform: {}",
+            path.display()
+        )),
+    }
+}
+
+#[cfg(unix)]
+fn trigger_fifo(paths: &[std::path::PathBuf], message: &[u8]) -> anyhow::Result<()> {
+    use std::{io::Write, os::unix::fs::OpenOptionsExt};
+
+    let mut line = message.to_vec();
+    line.push(b'\n');
+    for path in paths {
+        // fail instead of blocking when nobody is reading from the FIFO
+        let mut fifo = std::fs::OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(path)
+            .with_context(|| format!("failed to open FIFO: {}", path.display()))?;
+
+        fifo.write_all(&line)
+            .with_context(|| format!("failed to write to FIFO: {}", path.display()))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn trigger_fifo(paths: &[std::path::PathBuf], _message: &[u8]) -> anyhow::Result<()> {
+    match paths.first() {
+        None => Ok(()),
+        Some(path) => Err(anyhow!(
+            "FIFOs are not supported on this platform: {}",
+            path.display()
+        )),
     }
+}
 
-    command
-        .kill_on_drop(true)
-        .stdin(std::process::Stdio::inherit())
-        .stdout(std::process::Stdio::inherit())
-        .stderr(std::process::Stdio::inherit());
+async fn run_watch(args: &cli::Arguments) -> anyhow::Result<ExitCode> {
+    if let Some(workdir) = &args.behaviour.workdir {
+        if !workdir.is_dir() {
+            return Err(anyhow!(
+                "working directory does not exist: {}",
+                workdir.display()
+            ));
+        }
+    }
 
-    let interrupt = tokio::signal::ctrl_c();
+    // watch sources for updates
+    let watcher = watcher::Watcher::new(args)?;
+    run_with_watcher(args, watcher).await
+}
+
+/// Run the command whenever the watcher is triggered, until interrupted
+async fn run_with_watcher(
+    args: &cli::Arguments,
+    mut watcher: watcher::Watcher,
+) -> anyhow::Result<ExitCode> {
+    let mut event_log = audit::EventLog::open(args.behaviour.event_log.as_deref())?;
+    let status_file = status::StatusFile::create(args.behaviour.status_file.as_deref())?;
+    let capture = capture::Capture::from_args(&args.behaviour)?;
+
+    let interrupt = shutdown(&args.behaviour);
     tokio::pin!(interrupt);
 
+    let notifier = notification::from_args(args);
+
+    if args.behaviour.dry_run || args.behaviour.emit_triggers {
+        loop {
+            tokio::select! {
+                event = watcher.recv() => match event {
+                    Err(error) => return Err(error),
+                    Ok(event) if args.behaviour.emit_triggers => {
+                        emit_trigger(&event, args.behaviour.null).await?
+                    }
+                    Ok(event) => print_trigger(&event).await?,
+                },
+                _ = &mut interrupt => return Ok(ExitCode::SUCCESS),
+            }
+        }
+    }
+
+    // each group with a command of its own is restarted separately
+    if !args.behaviour.group_commands.is_empty() {
+        return groups::run(args, watcher).await;
+    }
+
+    // the trigger which caused the latest execution
+    let mut trigger = watcher::ExecutionTrigger::default();
+
+    // how the command has fared so far, shown once we are interrupted
+    let mut summary = summary::Summary::default();
+
+    // commands typed with `--interactive`, and the latest of them
+    let mut input = interactive::Input::from_args(&args.behaviour);
+    let mut typed_command: Option<Vec<String>> = None;
+
+    if !args.behaviour.initial {
+        // wait for the first trigger before running the command
+        loop {
+            tokio::select! {
+                event = watcher.recv() => match event {
+                    Err(error) => return Err(error),
+                    Ok(event) => {
+                        event_log.trigger(&event);
+                        trigger = event;
+                        break;
+                    }
+                },
+                line = interactive::next_line(&mut input) => {
+                    if interactive::switch_command(line, &mut input, &mut typed_command) {
+                        break;
+                    }
+                },
+                _ = &mut interrupt => return interrupted(args, &summary),
+            }
+        }
+    }
+
+    // how many times the command has been retried since the latest trigger
+    let mut retries = 0;
+
+    // whether the command has run before
+    let mut first_run = true;
+
+    // keeps a command which keeps failing from being re-run in a tight loop
+    let mut restarts =
+        restarts::RestartLimit::new(args.behaviour.max_restarts, args.behaviour.restart_window);
+
+    // when the command was last started, for `--cooldown`
+    let mut last_start: Option<Instant> = None;
+
+    // triggers which arrived while the command was running with `--wait`
+    let mut pending = pending::PendingRuns::new(args.behaviour.queue_runs);
+
     'outer: loop {
+        // keep runs of the command at least the cooldown apart
+        let cooldown_end = args
+            .behaviour
+            .cooldown
+            .zip(last_start)
+            .map(|(cooldown, last)| last + cooldown);
+        if let Some(end) = cooldown_end.filter(|&end| end > Instant::now()) {
+            info!(remaining = ?(end - Instant::now()), "waiting for the cooldown to pass");
+            let sleep = tokio::time::sleep_until(end.into());
+            tokio::pin!(sleep);
+            loop {
+                tokio::select! {
+                    _ = &mut sleep => break,
+                    event = watcher.recv() => match event {
+                        Err(error) => break 'outer Err(error),
+                        Ok(event) => {
+                            event_log.trigger(&event);
+                            match args.behaviour.cooldown_mode {
+                                cli::CooldownMode::Defer => trigger.merge(event),
+                                cli::CooldownMode::Drop => debug!("dropping trigger during cooldown"),
+                            }
+                        }
+                    },
+                    _ = &mut interrupt => return interrupted(args, &summary),
+                }
+            }
+        }
+
+        // removed files run the `--on-delete` command instead, if there is one
+        let command_line = match (&args.behaviour.on_delete, trigger.kind) {
+            (Some(on_delete), Some(watcher::EventKind::Removed)) => std::slice::from_ref(on_delete),
+            _ => typed_command.as_deref().unwrap_or(&args.command),
+        };
+        // a command typed while this one runs must not change it
+        let command_line = command_line.to_vec();
+        let command_line = command_line.as_slice();
+
         // Clear screen before running command
-        let clear = !args.behaviour.no_clear;
-        if clear {
-            let mut stdout = tokio::io::stdout();
-            stdout.write_all(b"\x1bc").await?; // <-- VT100 escape code to clear screen
-            stdout.flush().await?;
+        let clear = args.clear();
+        match &args.behaviour.clear_command {
+            Some(script) if clear != cli::ClearMode::None => run_clear_command(args, script).await,
+            _ => clear_screen(clear).context("failed to clear the screen")?,
+        }
+        if !args.behaviour.no_banner && !args.quiet {
+            // keep the output of consecutive runs apart when the screen is not cleared
+            let separate = !first_run && clear == cli::ClearMode::None;
+            print_banner(args.label.as_deref(), command_line, separate, args.color())
+                .context("failed to print the command")?;
         }
+        first_run = false;
+
+        let mut command = command::build(args, command_line, &trigger);
+        command.stdin(command::stdin(&args.behaviour)?);
+        if args.behaviour.timestamps.is_some() || capture.is_some() {
+            command
+                .stdout(std::process::Stdio::piped())
+                .stderr(std::process::Stdio::piped());
+        }
+
+        // removed once we are done with this run of the command
+        let _changed_list = command::write_changed_list(&mut command, args, &trigger)?;
 
         let mut child = command
             .spawn()
-            .with_context(|| format!("failed to run command: {}", args.command.join(" ")))?;
+            .with_context(|| format!("failed to run command: {}", command_line.join(" ")))?;
+        let started = Instant::now();
+        last_start = Some(started);
+        event_log.started(child.id(), command_line);
+        status_file.update(status::State::Running);
 
-        // if the child process should be restarted as soon as it's done
-        let mut restart_pending = false;
+        // prefixes the command's output with timestamps, or captures it
+        let mut output = match (args.behaviour.timestamps, &capture) {
+            (None, None) => None,
+            (style, capture) => {
+                if let Some(capture) = capture {
+                    capture.begin();
+                }
+                let capture = capture.clone();
+                Some(timestamps::Forwarder::start(&mut child, style, capture))
+            }
+        };
+
+        // set once the command has finished on its own
+        let mut finished = false;
+
+        // runs the `--ready-check` until the command is ready
+        let ready = ready::wait(args);
+        tokio::pin!(ready);
+        let mut checked = false;
+
+        // set if the command failed and should be run again
+        let mut retry = false;
+
+        // kill the command if it runs for too long
+        let command_timeout = args.behaviour.command_timeout;
+        let timeout = tokio::time::sleep(command_timeout.unwrap_or_default());
+        tokio::pin!(timeout);
 
         loop {
-            tokio::select! {
-                // wait for the child to terminate before restarting
-                exit_status = child.wait(), if restart_pending => {
-                    let status = exit_status.context("waiting for child to terminate")?;
-                    info!(exit_status = status.code(), "command terminated");
-                    break;
+            let status = tokio::select! {
+                // wait for the child to terminate
+                exit_status = child.wait(), if !finished => {
+                    exit_status.context("waiting for child to terminate")?
                 }
 
-                // look for execution triggers
-                event = watcher.receiver.recv() => {
+                // the command ran for too long
+                _ = &mut timeout, if !finished && command_timeout.is_some() => {
+                    warn!(timeout = ?command_timeout, "command timed out, terminating it");
+                    event_log.timed_out(command_timeout.unwrap_or_default());
+                    terminate_process(&mut child, &args.behaviour).await?;
+                    child.wait().await.context("waiting for child to terminate")?
+                }
+
+                // the command is ready, or never will be
+                readiness = &mut ready, if !checked && !finished => {
+                    checked = true;
+                    readiness.report(args.behaviour.ready_timeout);
+                    continue;
+                }
+
+                // look for execution triggers, unless we only run the command once
+                event = watcher.recv(), if !args.behaviour.once => {
                     match event {
-                        None => break 'outer Err(anyhow!("file watcher closed unexpectedly")),
-                        Some(watcher::ExecutionTrigger) => {
-                            if args.behaviour.wait {
-                                restart_pending = true;
+                        Err(error) => break 'outer Err(error),
+                        Ok(event) => {
+                            event_log.trigger(&event);
+                            if dropped_by_cooldown(&args.behaviour, started) {
+                                debug!("dropping trigger during cooldown");
+                                continue;
+                            }
+                            if !finished && args.behaviour.ignore_while_running {
+                                debug!("dropping trigger while the command runs");
+                                continue;
+                            }
+                            retries = 0;
+                            restarts.reset();
+                            if finished {
+                                trigger = event;
+                                break
+                            } else if args.behaviour.wait {
+                                pending.push(event);
                             } else {
-                                terminate_process(child).await?;
+                                trigger = event;
+                                terminate_process(&mut child, &args.behaviour).await?;
                                 break
                             }
+                            continue;
                         },
                     }
                 }
 
-                // catch any interrupts so that we can cleanup properly
+                // a typed command replaces the running one
+                line = interactive::next_line(&mut input) => {
+                    if interactive::switch_command(line, &mut input, &mut typed_command) {
+                        trigger = watcher::ExecutionTrigger::default();
+                        retries = 0;
+                        restarts.reset();
+                        if !finished {
+                            terminate_process(&mut child, &args.behaviour).await?;
+                        }
+                        break
+                    }
+                    continue;
+                }
+
+                // catch any interrupts so that we can cleanup properly. The command is in its own
+                // process group, so the interrupt from the terminal only reached us.
                 _ = &mut interrupt => {
-                    return Ok(())
+                    if !finished {
+                        match args.behaviour.graceful_shutdown {
+                            Some(timeout) => {
+                                info!(?timeout, "shutting down gracefully");
+                                let signal = args.behaviour.signal;
+                                signal_process(&mut child, signal, timeout).await?;
+                            }
+                            None => {
+                                let timeout = args.behaviour.kill_timeout;
+                                signal_process(&mut child, cli::Signal::Int, timeout).await?;
+                            }
+                        }
+                    }
+                    return interrupted(args, &summary)
+                }
+            };
+
+            if let Some(output) = &mut output {
+                output.finish().await;
+            }
+
+            event_log.exited(status);
+            status_file.update(status::State::Exited(status));
+            if let Some(capture) = &capture {
+                capture
+                    .finished(status)
+                    .context("failed to show the output of the command")?;
+            }
+            let elapsed = started.elapsed();
+            summary.record(status, elapsed);
+            command_finished(args, command_line, notifier.as_deref(), status, elapsed);
+            let max_runs = args.behaviour.max_runs.map(u64::from);
+            if max_runs.is_some_and(|max_runs| summary.runs >= max_runs) {
+                info!(runs = summary.runs, "reached the maximum number of runs");
+                return Ok(exit_code(status));
+            }
+            if let Some(next) = pending.pop() {
+                trigger = next;
+                break;
+            }
+            if !status.success() && retries < args.behaviour.retry {
+                if restarts.allow(Instant::now()) {
+                    retry = true;
+                    break;
+                }
+                warn!(
+                    max_restarts = args.behaviour.max_restarts,
+                    window = ?args.behaviour.restart_window,
+                    "command restarted too often, waiting for the next trigger"
+                );
+            }
+            if args.behaviour.once {
+                return Ok(exit_code(status));
+            }
+            finished = true;
+        }
+
+        if retry {
+            let delay = args.behaviour.delay_before_retry(retries);
+            retries += 1;
+            info!(attempt = retries, ?delay, "retrying failed command");
+
+            // a new trigger starts over with a fresh set of retries
+            let sleep = tokio::time::sleep(delay);
+            tokio::pin!(sleep);
+            loop {
+                tokio::select! {
+                    _ = &mut sleep => break,
+                    event = watcher.recv(), if !args.behaviour.once => match event {
+                        Err(error) => break 'outer Err(error),
+                        Ok(event) => {
+                            event_log.trigger(&event);
+                            trigger = event;
+                            retries = 0;
+                            restarts.reset();
+                            break;
+                        }
+                    },
+                    line = interactive::next_line(&mut input) => {
+                        if interactive::switch_command(line, &mut input, &mut typed_command) {
+                            trigger = watcher::ExecutionTrigger::default();
+                            retries = 0;
+                            restarts.reset();
+                            break;
+                        }
+                    },
+                    _ = &mut interrupt => return interrupted(args, &summary),
                 }
             }
         }
+
+        // give the OS a chance to release any resources held by the previous command
+        let delay = args.behaviour.restart_delay;
+        if !delay.is_zero() {
+            info!(?delay, "waiting before restarting command");
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = &mut interrupt => return interrupted(args, &summary),
+            }
+        }
     }
 }
 
-async fn terminate_process(mut child: Child) -> anyhow::Result<()> {
-    info!(
-        pid = child.id(),
-        "waiting for child process to terminate..."
-    );
-    let _ = child.start_kill();
-    child.wait().await?;
-    Ok(())
+/// Completes once witness should shut down: when interrupted with Ctrl-C, or once its standard
+/// input is closed with `--exit-on-stdin-eof`
+async fn shutdown(behaviour: &cli::BehaviourOptions) {
+    let stdin_closed = async {
+        if !behaviour.exit_on_stdin_eof {
+            return std::future::pending().await;
+        }
+
+        // a blocking read of the standard input would keep the runtime from shutting down, so it
+        // is read on a thread of its own
+        let (closed, on_closed) = tokio::sync::oneshot::channel();
+        std::thread::spawn(move || {
+            let _ = std::io::copy(&mut std::io::stdin().lock(), &mut std::io::sink());
+            let _ = closed.send(());
+        });
+        let _ = on_closed.await;
+        info!("standard input was closed, shutting down");
+    };
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = stdin_closed => {}
+    }
 }
 
-fn init_tracing(args: &cli::Arguments) -> anyhow::Result<()> {
-    use tracing::level_filters::LevelFilter;
-    let default_filter = if args.verbose {
-        LevelFilter::INFO
-    } else {
-        LevelFilter::WARN
+/// Check if triggers are ignored this soon after the command started
+fn dropped_by_cooldown(behaviour: &cli::BehaviourOptions, started: Instant) -> bool {
+    behaviour.cooldown_mode == cli::CooldownMode::Drop
+        && behaviour
+            .cooldown
+            .is_some_and(|cooldown| started.elapsed() < cooldown)
+}
+
+/// Show the summary of the session before exiting with the exit code of the latest run which
+/// finished on its own. A run cut short by the interrupt doesn't count.
+fn interrupted(args: &cli::Arguments, summary: &summary::Summary) -> anyhow::Result<ExitCode> {
+    if !args.behaviour.no_summary && !args.quiet {
+        use std::io::Write;
+
+        let mut stdout = std::io::stdout().lock();
+        writeln!(stdout, "{summary}").context("failed to print the summary")?;
+        stdout.flush()?;
+    }
+    Ok(summary.last.map_or(ExitCode::SUCCESS, exit_code))
+}
+
+/// Show which command is about to run
+fn print_banner(
+    label: Option<&str>,
+    command: &[String],
+    separate: bool,
+    color: bool,
+) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let mut stdout = std::io::stdout().lock();
+    if separate {
+        writeln!(stdout)?;
+    }
+    if let Some(label) = label {
+        write!(stdout, "[{label}] ")?;
+    }
+    let banner = format!("> {}", command.join(" "));
+    writeln!(stdout, "{}", paint(&banner, Style::new().bold(), color))?;
+    stdout.flush()
+}
+
+/// Show how the command exited: in green if it succeeded, and red otherwise
+fn print_exit_status(label: Option<&str>, status: ExitStatus, color: bool) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let style = match status.success() {
+        true => Style::new().green(),
+        false => Style::new().red(),
     };
 
-    let variable_name = "WITNESS_LOG";
+    let mut stdout = std::io::stdout().lock();
+    if let Some(label) = label {
+        write!(stdout, "[{label}] ")?;
+    }
+    writeln!(stdout, "{}", paint(&status.to_string(), style, color))?;
+    stdout.flush()
+}
 
-    let directives = match std::env::var(variable_name) {
-        Err(std::env::VarError::NotPresent) => String::new(),
-        Err(std::env::VarError::NotUnicode(_)) => {
-            return Err(anyhow!("WITNESS_LOG did not contain valid Unicode data"))
+/// Apply the style to the text, if the output is colored
+fn paint(text: &str, style: Style, color: bool) -> String {
+    match color {
+        true => text.style(style).to_string(),
+        false => text.to_owned(),
+    }
+}
+
+/// Run the `--clear-command`. The command runs regardless of whether it succeeds.
+async fn run_clear_command(args: &cli::Arguments, script: &str) {
+    match command::shell_script(args, script).status().await {
+        Ok(status) if status.success() => {}
+        Ok(status) => warn!(command = script, %status, "clear command failed"),
+        Err(error) => warn!(command = script, %error, "failed to run clear command"),
+    }
+}
+
+/// Clear the screen of the terminal.
+///
+/// Terminals differ in how they treat these, so changes here are best verified by hand in a few
+/// terminals: `witness --clear-mode=<mode> seq 1000` followed by scrolling up after a trigger.
+fn clear_screen(mode: cli::ClearMode) -> std::io::Result<()> {
+    use crossterm::{
+        cursor::MoveTo,
+        execute,
+        terminal::{Clear, ClearType},
+    };
+    use std::io::Write;
+
+    let mut stdout = std::io::stdout();
+    match mode {
+        cli::ClearMode::None => Ok(()),
+        cli::ClearMode::Soft => execute!(stdout, Clear(ClearType::All), MoveTo(0, 0)),
+        cli::ClearMode::Scrollback => execute!(
+            stdout,
+            Clear(ClearType::Purge),
+            Clear(ClearType::All),
+            MoveTo(0, 0)
+        ),
+        // Windows consoles do not support resetting the terminal
+        cli::ClearMode::Hard if cfg!(windows) => clear_screen(cli::ClearMode::Scrollback),
+        cli::ClearMode::Hard => {
+            stdout.write_all(b"\x1bc")?; // <-- VT100 escape code to reset the terminal
+            stdout.flush()
         }
-        Ok(level) => level,
+    }
+}
+
+/// Print the time of the trigger followed by the paths which caused it
+async fn print_trigger(trigger: &watcher::ExecutionTrigger) -> anyhow::Result<()> {
+    let time = humantime::format_rfc3339_millis(std::time::SystemTime::now());
+
+    let mut output = String::new();
+    match trigger.source {
+        None | Some(watcher::TriggerSource::File) => {}
+        Some(source) => match source.addr() {
+            Some(addr) => output += &format!("{time} ({} trigger from {addr})\n", source.kind()),
+            None => output += &format!("{time} ({} trigger)\n", source.kind()),
+        },
+    }
+    for path in trigger.paths.iter() {
+        output += &format!("{time} {}\n", path.display());
+    }
+
+    let mut stdout = tokio::io::stdout();
+    stdout.write_all(output.as_bytes()).await?;
+    stdout.flush().await?;
+    Ok(())
+}
+
+/// Print a record for every path of the trigger, or a single record without a path if it didn't
+/// come from files, for `--emit-triggers`
+async fn emit_trigger(trigger: &watcher::ExecutionTrigger, null: bool) -> anyhow::Result<()> {
+    let time = trigger.time.unwrap_or_else(std::time::SystemTime::now);
+    let time = humantime::format_rfc3339_millis(time).to_string();
+    let kind = match (trigger.kind, trigger.source) {
+        (Some(kind), _) => kind.name(),
+        (None, Some(source)) => source.kind(),
+        (None, None) => "initial",
     };
 
-    let env_filter = tracing_subscriber::filter::EnvFilter::builder()
-        .with_default_directive(default_filter.into())
-        .parse(&directives)
-        .with_context(|| {
-            format!("{variable_name} contained an invalid directive: {directives:?}")
-        })?;
+    let (separator, terminator) = match null {
+        true => ('\0', '\0'),
+        false => ('\t', '\n'),
+    };
+    let paths = match trigger.paths.is_empty() {
+        true => vec![String::new()],
+        false => trigger
+            .paths
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect(),
+    };
 
-    tracing_subscriber::FmtSubscriber::builder()
-        .with_env_filter(env_filter)
-        .init();
+    let mut output = String::new();
+    for path in paths {
+        output += &format!("{path}{separator}{kind}{separator}{time}{terminator}");
+    }
 
+    let mut stdout = tokio::io::stdout();
+    stdout.write_all(output.as_bytes()).await?;
+    stdout.flush().await?;
     Ok(())
 }
+
+/// Report that the command finished on its own
+fn command_finished(
+    args: &cli::Arguments,
+    command: &[String],
+    notifier: Option<&dyn notification::Notifier>,
+    status: ExitStatus,
+    elapsed: Duration,
+) {
+    info!(exit_status = status.code(), ?elapsed, "command terminated");
+
+    if !args.behaviour.no_banner && !args.quiet {
+        if let Err(error) = print_exit_status(args.label.as_deref(), status, args.color()) {
+            warn!(%error, "failed to print the exit status");
+        }
+    }
+
+    if let Err(error) = notification::ring_bell(args.behaviour.bell, status, &mut std::io::stdout())
+    {
+        warn!(%error, "failed to ring the terminal bell");
+    }
+
+    if let Some(notifier) = notifier {
+        notification::command_finished(notifier, command, status);
+    }
+}
+
+/// The exit code which best represents the status of the command
+fn exit_code(status: ExitStatus) -> ExitCode {
+    if let Some(code) = status.code() {
+        return ExitCode::from(exit_code_byte(code));
+    }
+
+    // follow the shell convention for processes terminated by a signal
+    #[cfg(unix)]
+    if let Some(signal) = std::os::unix::process::ExitStatusExt::signal(&status) {
+        return ExitCode::from(128u8.wrapping_add(signal as u8));
+    }
+
+    ExitCode::FAILURE
+}
+
+/// An exit code which fits in a byte. Codes outside the range, possible on Windows, are clamped so
+/// they never turn into success.
+fn exit_code_byte(code: i32) -> u8 {
+    u8::try_from(code).unwrap_or_else(|_| code.clamp(1, 255) as u8)
+}
+
+async fn terminate_process(
+    child: &mut Child,
+    behaviour: &cli::BehaviourOptions,
+) -> anyhow::Result<()> {
+    signal_process(child, behaviour.signal, behaviour.kill_timeout).await
+}
+
+/// Send the signal to the child and wait for it to exit, killing it if it takes longer than the
+/// timeout. Any processes the child left behind are killed as well.
+async fn signal_process(
+    child: &mut Child,
+    signal: cli::Signal,
+    timeout: Duration,
+) -> anyhow::Result<()> {
+    let pid = child.id();
+    info!(pid, ?signal, "waiting for child process to terminate...");
+
+    // only a child which is still around tells whether it has a process group of its own
+    let group = pid.filter(|pid| leads_process_group(*pid));
+
+    if let Err(error) = send_signal(child, signal) {
+        warn!(%error, ?signal, "failed to signal child process");
+    }
+
+    let exited = match signal {
+        cli::Signal::Kill => false,
+        _ => tokio::time::timeout(timeout, wait_exited(child))
+            .await
+            .is_ok(),
+    };
+    if !exited {
+        if signal != cli::Signal::Kill {
+            warn!(
+                ?timeout,
+                "child process did not terminate in time, killing it"
+            );
+        }
+        let _ = send_signal(child, cli::Signal::Kill);
+    }
+    wait_exited(child).await?;
+
+    // background processes started by a shell ignore interrupts, so they may still be around. The
+    // child is not reaped yet, so its process group id can't have been reused.
+    if let Some(pid) = group {
+        kill_process_group(pid);
+    }
+    child.wait().await?;
+
+    Ok(())
+}
+
+/// Wait for the child to exit, without reaping it. Until it is reaped its process id, and that of
+/// its process group, can't be taken by another process.
+#[cfg(unix)]
+async fn wait_exited(child: &mut Child) -> std::io::Result<()> {
+    let pid = match child.id() {
+        Some(pid) => pid as libc::id_t,
+        // the process has already been reaped
+        None => return Ok(()),
+    };
+
+    loop {
+        // SAFETY: `siginfo_t` is plain data, which `waitid` fills in
+        let exited = unsafe {
+            let mut info = std::mem::zeroed::<libc::siginfo_t>();
+            let flags = libc::WEXITED | libc::WNOHANG | libc::WNOWAIT;
+            if libc::waitid(libc::P_PID, pid, &mut info, flags) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            info.si_pid() != 0
+        };
+        if exited {
+            return Ok(());
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_exited(child: &mut Child) -> std::io::Result<()> {
+    child.wait().await.map(drop)
+}
+
+/// Send a signal to the child and the rest of its process group
+#[cfg(unix)]
+fn send_signal(child: &mut Child, signal: cli::Signal) -> std::io::Result<()> {
+    let pid = match child.id() {
+        Some(pid) => pid as libc::pid_t,
+        // the process has already exited
+        None => return Ok(()),
+    };
+
+    // a command reading from the terminal shares our process group, so only it is signalled
+    let target = match leads_process_group(pid as u32) {
+        true => -pid,
+        false => pid,
+    };
+
+    // SAFETY: `kill` has no memory safety requirements
+    let result = unsafe { libc::kill(target, signal.number()) };
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(unix))]
+fn send_signal(child: &mut Child, _signal: cli::Signal) -> std::io::Result<()> {
+    child.start_kill()
+}
+
+/// Check if the child runs in a process group of its own, rather than ours
+#[cfg(unix)]
+fn leads_process_group(pid: u32) -> bool {
+    let pid = pid as libc::pid_t;
+    // SAFETY: `getpgid` has no memory safety requirements
+    unsafe { libc::getpgid(pid) == pid }
+}
+
+#[cfg(not(unix))]
+fn leads_process_group(_pid: u32) -> bool {
+    false
+}
+
+/// Kill whatever is left of the process group of an exited child
+#[cfg(unix)]
+fn kill_process_group(pid: u32) {
+    // SAFETY: `kill` has no memory safety requirements
+    let result = unsafe { libc::kill(-(pid as libc::pid_t), libc::SIGKILL) };
+    if result == 0 {
+        debug!(pid, "killed the remaining processes of the command");
+    }
+}
+
+#[cfg(not(unix))]
+fn kill_process_group(_pid: u32) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn arguments(flags: &[&str], command: &str) -> cli::Arguments {
+        let args = ["witness", "--shell", "sh", "--no-clear", "--no-banner"]
+            .into_iter()
+            .chain(flags.iter().copied())
+            .chain([command]);
+        cli::Arguments::parse_from(args)
+    }
+
+    /// The process group of an exited child stays reserved until the child is reaped
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn wait_exited_keeps_process_group() {
+        let mut command = tokio::process::Command::new("sh");
+        command.args(["-c", "exit 3"]);
+        unsafe {
+            command.pre_exec(|| match libc::setpgid(0, 0) {
+                0 => Ok(()),
+                _ => Err(std::io::Error::last_os_error()),
+            });
+        }
+        let mut child = command.spawn().unwrap();
+        let pid = child.id().unwrap();
+
+        wait_exited(&mut child).await.unwrap();
+        assert!(leads_process_group(pid));
+
+        assert_eq!(child.wait().await.unwrap().code(), Some(3));
+        assert!(!leads_process_group(pid));
+    }
+
+    fn changed(path: &str) -> watcher::ExecutionTrigger {
+        watcher::ExecutionTrigger {
+            paths: vec![path.into()],
+            source: Some(watcher::TriggerSource::File),
+            events: 1,
+            ..Default::default()
+        }
+    }
+
+    /// A command which logs when it starts, with the number of events which triggered it, and when
+    /// it ends
+    fn logging_command(log: &std::path::Path) -> String {
+        let log = log.display();
+        format!("echo start $WITNESS_EVENT_COUNT >> {log}; sleep 0.3; echo end >> {log}")
+    }
+
+    /// With `--wait`, triggers during a run don't interrupt it, but are combined into a single run
+    /// once it finishes
+    #[tokio::test]
+    async fn wait_runs_again_after_finishing() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("log");
+        let args = arguments(&["--wait", "--max-runs", "2"], &logging_command(&log));
+        let (watcher, triggers) = watcher::Watcher::test_channel(&args);
+
+        let send = async {
+            triggers.send(changed("a.rs")).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            triggers.send(changed("b.rs")).await.unwrap();
+            triggers.send(changed("c.rs")).await.unwrap();
+        };
+        let (code, ()) = tokio::join!(run_with_watcher(&args, watcher), send);
+        assert_eq!(code.unwrap(), ExitCode::SUCCESS);
+
+        let log = std::fs::read_to_string(&log).unwrap();
+        assert_eq!(log, "start 1\nend\nstart 2\nend\n");
+    }
+
+    /// With `--queue-runs`, triggers arriving back-to-back each get a run of their own
+    #[tokio::test]
+    async fn queue_runs_keeps_triggers_apart() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("log");
+        let flags = ["--wait", "--queue-runs", "4", "--max-runs", "4"];
+        let args = arguments(&flags, &logging_command(&log));
+        let (watcher, triggers) = watcher::Watcher::test_channel(&args);
+
+        for path in ["a.rs", "b.rs", "c.rs", "d.rs"] {
+            triggers.send(changed(path)).await.unwrap();
+        }
+        let run = run_with_watcher(&args, watcher);
+        let code = tokio::time::timeout(Duration::from_secs(10), run).await;
+        assert_eq!(code.unwrap().unwrap(), ExitCode::SUCCESS);
+
+        let log = std::fs::read_to_string(&log).unwrap();
+        assert_eq!(log, "start 1\nend\n".repeat(4));
+    }
+
+    /// Without `--wait`, a trigger during a run restarts the command right away
+    #[tokio::test]
+    async fn trigger_restarts_running_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("log");
+        let args = arguments(&["--max-runs", "1"], &logging_command(&log));
+        let (watcher, triggers) = watcher::Watcher::test_channel(&args);
+
+        let send = async {
+            triggers.send(changed("a.rs")).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            triggers.send(changed("b.rs")).await.unwrap();
+        };
+        let (code, ()) = tokio::join!(run_with_watcher(&args, watcher), send);
+        assert_eq!(code.unwrap(), ExitCode::SUCCESS);
+
+        let log = std::fs::read_to_string(&log).unwrap();
+        assert_eq!(log, "start 1\nstart 1\nend\n");
+    }
+
+    /// With `--ignore-while-running`, only triggers after the command finished run it again
+    #[tokio::test]
+    async fn ignore_while_running() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("log");
+        let flags = ["--ignore-while-running", "--max-runs", "2"];
+        let args = arguments(&flags, &logging_command(&log));
+        let (watcher, triggers) = watcher::Watcher::test_channel(&args);
+
+        let send = async {
+            triggers.send(changed("a.rs")).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            triggers.send(changed("b.rs")).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            triggers.send(changed("c.rs")).await.unwrap();
+            triggers.send(changed("d.rs")).await.unwrap();
+        };
+        let (code, ()) = tokio::join!(run_with_watcher(&args, watcher), send);
+        assert_eq!(code.unwrap(), ExitCode::SUCCESS);
+
+        // the trigger during the first run neither restarted it nor ran it again afterwards
+        let log = std::fs::read_to_string(&log).unwrap();
+        assert_eq!(log, "start 1\nend\nstart 2\nend\n");
+    }
+
+    #[test]
+    fn exit_code_fits_in_byte() {
+        assert_eq!(exit_code_byte(0), 0);
+        assert_eq!(exit_code_byte(3), 3);
+        assert_eq!(exit_code_byte(255), 255);
+        assert_eq!(exit_code_byte(256), 255);
+        assert_eq!(exit_code_byte(512), 255);
+        assert_eq!(exit_code_byte(-1), 1);
+        assert_eq!(exit_code_byte(-1073741819), 1);
+    }
+}
@@ -0,0 +1,168 @@
+//! Notifying the user when the command finishes.
+
+use std::{io::Write, process::ExitStatus};
+
+use crate::cli;
+
+/// Shows notifications to the user.
+pub trait Notifier {
+    fn show(&self, summary: &str, body: &str) -> anyhow::Result<()>;
+}
+
+/// The notifier requested by the arguments, if any
+pub fn from_args(args: &cli::Arguments) -> Option<Box<dyn Notifier>> {
+    if !args.behaviour.notify {
+        return None;
+    }
+
+    #[cfg(feature = "desktop-notifications")]
+    return Some(Box::new(Desktop));
+
+    #[cfg(not(feature = "desktop-notifications"))]
+    {
+        warn!("witness was built without the `desktop-notifications` feature, ignoring --notify");
+        None
+    }
+}
+
+/// Notify the user that the command finished with the given status
+pub fn command_finished(notifier: &dyn Notifier, command: &[String], status: ExitStatus) {
+    let summary = if status.success() {
+        "Command succeeded"
+    } else {
+        "Command failed"
+    };
+    let body = format!("{}\n{status}", command.join(" "));
+
+    // not every system runs a notification daemon, which should not stop the watcher
+    if let Err(error) = notifier.show(summary, &body) {
+        warn!(%error, "failed to show desktop notification");
+    }
+}
+
+/// The ASCII BEL character, which makes the terminal ring its bell
+const BELL: &[u8] = b"\x07";
+
+/// Ring the terminal bell if requested for the given status
+pub fn ring_bell(
+    bell: Option<cli::Bell>,
+    status: ExitStatus,
+    output: &mut impl Write,
+) -> std::io::Result<()> {
+    let ring = match bell {
+        None => false,
+        Some(cli::Bell::Failure) => !status.success(),
+        Some(cli::Bell::Always) => true,
+    };
+
+    if ring {
+        // flush right away so that the bell is not held back until the next screen clear
+        output.write_all(BELL)?;
+        output.flush()?;
+    }
+
+    Ok(())
+}
+
+/// Posts notifications using the native notification system.
+#[cfg(feature = "desktop-notifications")]
+struct Desktop;
+
+#[cfg(feature = "desktop-notifications")]
+impl Notifier for Desktop {
+    fn show(&self, summary: &str, body: &str) -> anyhow::Result<()> {
+        notify_rust::Notification::new()
+            .appname("witness")
+            .summary(summary)
+            .body(body)
+            .show()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    /// Records notifications instead of showing them
+    #[derive(Default)]
+    struct Recorder {
+        shown: RefCell<Vec<(String, String)>>,
+        fail: bool,
+    }
+
+    impl Notifier for Recorder {
+        fn show(&self, summary: &str, body: &str) -> anyhow::Result<()> {
+            self.shown.borrow_mut().push((summary.into(), body.into()));
+            match self.fail {
+                true => Err(anyhow::anyhow!("no notification daemon")),
+                false => Ok(()),
+            }
+        }
+    }
+
+    fn status(code: i32) -> ExitStatus {
+        std::process::Command::new("sh")
+            .args(["-c", &format!("exit {code}")])
+            .status()
+            .unwrap()
+    }
+
+    fn command() -> Vec<String> {
+        vec!["cargo".into(), "test".into()]
+    }
+
+    #[test]
+    fn notify_success() {
+        let recorder = Recorder::default();
+        command_finished(&recorder, &command(), status(0));
+        let shown = recorder.shown.into_inner();
+        assert_eq!(shown.len(), 1);
+        assert_eq!(shown[0].0, "Command succeeded");
+        assert!(shown[0].1.starts_with("cargo test\n"), "{}", shown[0].1);
+    }
+
+    #[test]
+    fn notify_failure() {
+        let recorder = Recorder::default();
+        command_finished(&recorder, &command(), status(3));
+        let shown = recorder.shown.into_inner();
+        assert_eq!(shown[0].0, "Command failed");
+        assert!(shown[0].1.contains('3'), "{}", shown[0].1);
+    }
+
+    #[test]
+    fn missing_daemon() {
+        let recorder = Recorder {
+            fail: true,
+            ..Recorder::default()
+        };
+        // only logs a warning
+        command_finished(&recorder, &command(), status(0));
+        assert_eq!(recorder.shown.into_inner().len(), 1);
+    }
+
+    fn bell_output(bell: Option<cli::Bell>, code: i32) -> Vec<u8> {
+        let mut output = Vec::new();
+        ring_bell(bell, status(code), &mut output).unwrap();
+        output
+    }
+
+    #[test]
+    fn bell_on_failure() {
+        assert_eq!(bell_output(Some(cli::Bell::Failure), 1), BELL);
+        assert_eq!(bell_output(Some(cli::Bell::Failure), 0), b"");
+    }
+
+    #[test]
+    fn bell_always() {
+        assert_eq!(bell_output(Some(cli::Bell::Always), 1), BELL);
+        assert_eq!(bell_output(Some(cli::Bell::Always), 0), BELL);
+    }
+
+    #[test]
+    fn no_bell() {
+        assert_eq!(bell_output(None, 1), b"");
+    }
+}
@@ -0,0 +1,80 @@
+//! Runs of the command waiting for the current one to finish, with `--wait`.
+
+use std::collections::VecDeque;
+
+use crate::watcher::ExecutionTrigger;
+
+/// Triggers which arrived while the command was running. Each is run in turn once the command
+/// finishes.
+#[derive(Debug)]
+pub struct PendingRuns {
+    /// How many runs may be queued, beyond which triggers are combined into the last run
+    limit: usize,
+    runs: VecDeque<ExecutionTrigger>,
+}
+
+impl PendingRuns {
+    /// Queue at most `limit` runs, or combine all triggers into a single run if `None`
+    pub fn new(limit: Option<u32>) -> PendingRuns {
+        PendingRuns {
+            limit: limit.map_or(1, |limit| limit.max(1) as usize),
+            runs: VecDeque::new(),
+        }
+    }
+
+    pub fn push(&mut self, trigger: ExecutionTrigger) {
+        let full = self.runs.len() >= self.limit;
+        match self.runs.back_mut() {
+            Some(last) if full => last.merge(trigger),
+            _ => self.runs.push_back(trigger),
+        }
+    }
+
+    /// The trigger of the next run
+    pub fn pop(&mut self) -> Option<ExecutionTrigger> {
+        self.runs.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn changed(path: &str) -> ExecutionTrigger {
+        ExecutionTrigger {
+            paths: vec![PathBuf::from(path)],
+            events: 1,
+            ..ExecutionTrigger::default()
+        }
+    }
+
+    #[test]
+    fn coalesced() {
+        let mut pending = PendingRuns::new(None);
+        pending.push(changed("a.rs"));
+        pending.push(changed("b.rs"));
+        pending.push(changed("a.rs"));
+
+        let run = pending.pop().unwrap();
+        assert_eq!(run.paths, ["a.rs", "b.rs"].map(PathBuf::from));
+        assert_eq!(run.events, 3);
+        assert!(pending.pop().is_none());
+    }
+
+    #[test]
+    fn queued() {
+        let mut pending = PendingRuns::new(Some(2));
+        pending.push(changed("a.rs"));
+        pending.push(changed("b.rs"));
+        pending.push(changed("c.rs"));
+
+        assert_eq!(pending.pop().unwrap().paths, [PathBuf::from("a.rs")]);
+
+        // the triggers beyond the limit are combined into the last run
+        let run = pending.pop().unwrap();
+        assert_eq!(run.paths, ["b.rs", "c.rs"].map(PathBuf::from));
+        assert_eq!(run.events, 2);
+        assert!(pending.pop().is_none());
+    }
+}
@@ -0,0 +1,48 @@
+//! Finding out when the command is ready, such as a server accepting connections, with
+//! `--ready-check`.
+
+use std::time::{Duration, Instant};
+
+use crate::{cli, command};
+
+pub enum Readiness {
+    /// The check succeeded this long after the command started
+    Ready(Duration),
+    /// The check kept failing until the `--ready-timeout`
+    TimedOut,
+}
+
+/// Run the `--ready-check` until it succeeds or times out. Without one, this never completes.
+pub async fn wait(args: &cli::Arguments) -> Readiness {
+    let script = match &args.behaviour.ready_check {
+        Some(script) => script,
+        None => return std::future::pending().await,
+    };
+
+    let started = Instant::now();
+    let checks = async {
+        loop {
+            // the check is killed if it is still running once we time out
+            match command::shell_script(args, script).status().await {
+                Ok(status) if status.success() => return,
+                Ok(status) => debug!(command = script, %status, "command is not ready yet"),
+                Err(error) => warn!(command = script, %error, "failed to run ready check"),
+            }
+            tokio::time::sleep(args.behaviour.ready_interval).await;
+        }
+    };
+
+    match tokio::time::timeout(args.behaviour.ready_timeout, checks).await {
+        Ok(()) => Readiness::Ready(started.elapsed()),
+        Err(_) => Readiness::TimedOut,
+    }
+}
+
+impl Readiness {
+    pub fn report(&self, timeout: Duration) {
+        match self {
+            Readiness::Ready(elapsed) => info!(?elapsed, "command is ready"),
+            Readiness::TimedOut => warn!(?timeout, "command did not become ready in time"),
+        }
+    }
+}
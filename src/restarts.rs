@@ -0,0 +1,79 @@
+//! Bounding how often the command is re-run without a new trigger.
+
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+/// Remembers when the command was last re-run on its own, such as when retrying a failure
+#[derive(Debug)]
+pub struct RestartLimit {
+    max: Option<u32>,
+    window: Duration,
+    restarts: VecDeque<Instant>,
+}
+
+impl RestartLimit {
+    /// Allow at most `max` restarts within any `window`, or any number of restarts if `None`
+    pub fn new(max: Option<u32>, window: Duration) -> RestartLimit {
+        RestartLimit {
+            max,
+            window,
+            restarts: VecDeque::new(),
+        }
+    }
+
+    /// Check if the command may be restarted at the given time, and record the restart if so
+    pub fn allow(&mut self, now: Instant) -> bool {
+        let Some(max) = self.max else {
+            return true;
+        };
+
+        while let Some(&oldest) = self.restarts.front() {
+            if now.saturating_duration_since(oldest) < self.window {
+                break;
+            }
+            self.restarts.pop_front();
+        }
+
+        if self.restarts.len() >= max as usize {
+            return false;
+        }
+        self.restarts.push_back(now);
+        true
+    }
+
+    /// Forget about earlier restarts, such as when a new trigger arrives
+    pub fn reset(&mut self) {
+        self.restarts.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bounded_within_window() {
+        let start = Instant::now();
+        let mut limit = RestartLimit::new(Some(2), Duration::from_secs(10));
+
+        assert!(limit.allow(start));
+        assert!(limit.allow(start + Duration::from_secs(1)));
+        assert!(!limit.allow(start + Duration::from_secs(2)));
+
+        // the first restart has left the window
+        assert!(limit.allow(start + Duration::from_secs(10)));
+        assert!(!limit.allow(start + Duration::from_secs(10)));
+
+        limit.reset();
+        assert!(limit.allow(start + Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn unlimited() {
+        let start = Instant::now();
+        let mut limit = RestartLimit::new(None, Duration::from_secs(10));
+        assert!((0..1000).all(|_| limit.allow(start)));
+    }
+}
@@ -0,0 +1,128 @@
+//! A file reflecting whether the command is running, for supervisors to check on.
+
+use std::{
+    fmt,
+    io::Write,
+    path::{Path, PathBuf},
+    process::ExitStatus,
+};
+
+use anyhow::Context;
+
+/// Keeps the status file up to date, if one was requested. The file is removed when dropped.
+pub struct StatusFile {
+    path: Option<PathBuf>,
+}
+
+/// What the command is currently doing
+#[derive(Debug, Clone, Copy)]
+pub enum State {
+    /// The command has not run yet
+    Idle,
+    Running,
+    Exited(ExitStatus),
+}
+
+impl StatusFile {
+    /// Create the status file, starting out as idle
+    pub fn create(path: Option<&Path>) -> anyhow::Result<StatusFile> {
+        let status = StatusFile {
+            path: path.map(Path::to_path_buf),
+        };
+        if let Some(path) = path {
+            write_atomically(path, &contents(State::Idle))
+                .with_context(|| format!("failed to write status file: {}", path.display()))?;
+        }
+        Ok(status)
+    }
+
+    pub fn update(&self, state: State) {
+        if let Some(path) = &self.path {
+            if let Err(error) = write_atomically(path, &contents(state)) {
+                warn!(%error, ?path, "failed to update status file");
+            }
+        }
+    }
+}
+
+impl Drop for StatusFile {
+    fn drop(&mut self) {
+        if let Some(path) = &self.path {
+            if let Err(error) = std::fs::remove_file(path) {
+                warn!(%error, ?path, "failed to remove status file");
+            }
+        }
+    }
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            State::Idle => write!(f, "idle"),
+            State::Running => write!(f, "running"),
+            State::Exited(status) => match status.code() {
+                Some(code) => write!(f, "exited:{code}"),
+                None => write!(f, "exited:{}", signal_name(*status)),
+            },
+        }
+    }
+}
+
+#[cfg(unix)]
+fn signal_name(status: ExitStatus) -> String {
+    use std::os::unix::process::ExitStatusExt;
+    match status.signal() {
+        Some(signal) => format!("signal:{signal}"),
+        None => "unknown".to_owned(),
+    }
+}
+
+#[cfg(not(unix))]
+fn signal_name(_status: ExitStatus) -> String {
+    "unknown".to_owned()
+}
+
+fn contents(state: State) -> String {
+    format!("state={state}\npid={}\n", std::process::id())
+}
+
+/// Replace the file in a single step, so that readers never see a partial write
+fn write_atomically(path: &Path, contents: &str) -> std::io::Result<()> {
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let mut file = tempfile::NamedTempFile::new_in(dir)?;
+    file.write_all(contents.as_bytes())?;
+    file.persist(path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("status");
+        let read = || std::fs::read_to_string(&path).unwrap();
+        let pid = std::process::id();
+
+        let status = StatusFile::create(Some(&path)).unwrap();
+        assert_eq!(read(), format!("state=idle\npid={pid}\n"));
+
+        status.update(State::Running);
+        assert_eq!(read(), format!("state=running\npid={pid}\n"));
+
+        let exited = std::process::Command::new("sh")
+            .args(["-c", "exit 3"])
+            .status()
+            .unwrap();
+        status.update(State::Exited(exited));
+        assert_eq!(read(), format!("state=exited:3\npid={pid}\n"));
+
+        drop(status);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,94 @@
+//! Statistics about the runs of the command, shown when witness is interrupted.
+
+use std::{fmt, process::ExitStatus, time::Duration};
+
+/// Counts how the command fared over the whole session
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub runs: u64,
+    pub successes: u64,
+    pub failures: u64,
+    /// The time spent running the command, across all runs
+    pub total: Duration,
+    /// How the latest run finished, which decides the exit code of witness
+    pub last: Option<ExitStatus>,
+}
+
+impl Summary {
+    /// Record a run of the command which finished with the given status
+    pub fn record(&mut self, status: ExitStatus, elapsed: Duration) {
+        self.runs += 1;
+        if status.success() {
+            self.successes += 1;
+        } else {
+            self.failures += 1;
+        }
+        self.total = self.total.saturating_add(elapsed);
+        self.last = Some(status);
+    }
+
+    /// The average time it took the command to finish
+    pub fn average(&self) -> Duration {
+        match u32::try_from(self.runs) {
+            Ok(0) => Duration::ZERO,
+            Ok(runs) => self.total / runs,
+            Err(_) => Duration::from_secs_f64(self.total.as_secs_f64() / self.runs as f64),
+        }
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let runs = if self.runs == 1 { "run" } else { "runs" };
+        write!(
+            f,
+            "{} {runs}: {} succeeded, {} failed",
+            self.runs, self.successes, self.failures
+        )?;
+        if self.runs != 0 {
+            // sub-millisecond precision is mostly noise
+            let average = Duration::from_millis(self.average().as_millis() as u64);
+            write!(f, ", {} on average", humantime::format_duration(average))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    fn status(code: i32) -> ExitStatus {
+        std::os::unix::process::ExitStatusExt::from_raw(code << 8)
+    }
+
+    #[cfg(windows)]
+    fn status(code: i32) -> ExitStatus {
+        std::os::windows::process::ExitStatusExt::from_raw(code as u32)
+    }
+
+    #[test]
+    fn counts() {
+        let mut summary = Summary::default();
+        assert_eq!(summary.to_string(), "0 runs: 0 succeeded, 0 failed");
+        assert_eq!(summary.last, None);
+
+        summary.record(status(0), Duration::from_millis(100));
+        assert_eq!(
+            summary.to_string(),
+            "1 run: 1 succeeded, 0 failed, 100ms on average"
+        );
+
+        summary.record(status(1), Duration::from_millis(300));
+        summary.record(status(0), Duration::from_micros(1_100_400));
+        assert_eq!(summary.runs, 3);
+        assert_eq!(summary.successes, 2);
+        assert_eq!(summary.failures, 1);
+        assert_eq!(summary.last, Some(status(0)));
+        assert_eq!(
+            summary.to_string(),
+            "3 runs: 2 succeeded, 1 failed, 500ms on average"
+        );
+    }
+}
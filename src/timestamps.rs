@@ -0,0 +1,174 @@
+//! Forwarding the output of the command, such as to prefix each line with the time it was printed.
+
+use std::time::{Duration, SystemTime};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    process::Child,
+    task::JoinHandle,
+};
+
+use crate::{capture::Capture, cli};
+
+/// How long to wait for the remaining output once the command has exited. Processes started by
+/// the command in the background may hold on to its output forever.
+const DRAIN_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Lines longer than this are forwarded in pieces, so that they don't all need to fit in memory
+const MAX_LINE: u64 = 64 * 1024;
+
+/// Forwards the piped output of a child process, one line at a time.
+pub struct Forwarder {
+    tasks: Vec<JoinHandle<()>>,
+}
+
+/// What happens to each line of output
+#[derive(Clone)]
+struct Lines {
+    /// Prefix each line with the time
+    timestamps: bool,
+    /// Marks whether the line came from stdout or stderr
+    tag: Option<&'static str>,
+    /// Also record the line here
+    capture: Option<Capture>,
+}
+
+impl Forwarder {
+    /// Start forwarding the stdout and stderr of the child to our own, and to the capture
+    pub fn start(
+        child: &mut Child,
+        style: Option<cli::Timestamps>,
+        capture: Option<Capture>,
+    ) -> Forwarder {
+        let tagged = style == Some(cli::Timestamps::Tagged);
+        let lines = |tag| Lines {
+            timestamps: style.is_some(),
+            tag: tagged.then_some(tag),
+            capture: capture.clone(),
+        };
+        let mut tasks = Vec::with_capacity(2);
+
+        if let Some(stdout) = child.stdout.take() {
+            let lines = lines("out");
+            tasks.push(tokio::spawn(forward(stdout, tokio::io::stdout(), lines)));
+        }
+        if let Some(stderr) = child.stderr.take() {
+            let lines = lines("err");
+            tasks.push(tokio::spawn(forward(stderr, tokio::io::stderr(), lines)));
+        }
+
+        Forwarder { tasks }
+    }
+
+    /// Wait for the output of the exited child to be forwarded
+    pub async fn finish(&mut self) {
+        for task in self.tasks.iter_mut() {
+            if tokio::time::timeout(DRAIN_TIMEOUT, task).await.is_err() {
+                warn!("the command's output is still open after it exited");
+            }
+        }
+        self.tasks.clear();
+    }
+}
+
+impl Drop for Forwarder {
+    fn drop(&mut self) {
+        for task in self.tasks.iter() {
+            task.abort();
+        }
+    }
+}
+
+/// Copy lines from the reader to the writer, prefixing each with a timestamp and the tag.
+async fn forward(reader: impl AsyncRead + Unpin, writer: impl AsyncWrite + Unpin, lines: Lines) {
+    if let Err(error) = forward_lines(reader, writer, &lines).await {
+        warn!(%error, "failed to forward the command's output");
+    }
+}
+
+async fn forward_lines(
+    reader: impl AsyncRead + Unpin,
+    mut writer: impl AsyncWrite + Unpin,
+    lines: &Lines,
+) -> std::io::Result<()> {
+    let mut reader = BufReader::new(reader);
+    let mut line = Vec::new();
+
+    loop {
+        line.clear();
+        let mut limited = (&mut reader).take(MAX_LINE);
+        if limited.read_until(b'\n', &mut line).await? == 0 {
+            return Ok(());
+        }
+        if lines.timestamps && !line.ends_with(b"\n") {
+            // the last line is not always terminated
+            line.push(b'\n');
+        }
+
+        // the whole line is written at once so that it is not split by other output
+        let mut output = match (lines.timestamps, lines.tag) {
+            (false, _) => Vec::new(),
+            (true, None) => format!("{} ", timestamp()).into_bytes(),
+            (true, Some(tag)) => format!("{} [{tag}] ", timestamp()).into_bytes(),
+        };
+        output.extend_from_slice(&line);
+
+        if let Some(capture) = &lines.capture {
+            capture.record(&output);
+        }
+        if lines.capture.as_ref().is_none_or(|capture| capture.live) {
+            writer.write_all(&output).await?;
+            writer.flush().await?;
+        }
+    }
+}
+
+fn timestamp() -> impl std::fmt::Display {
+    humantime::format_rfc3339_millis(SystemTime::now())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn prefixed(input: &[u8], tag: Option<&'static str>) -> Vec<(SystemTime, String)> {
+        let lines = Lines {
+            timestamps: true,
+            tag,
+            capture: None,
+        };
+        let mut output = Vec::new();
+        forward_lines(input, &mut output, &lines).await.unwrap();
+
+        String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| {
+                let (time, rest) = line.split_once(' ').unwrap();
+                let time = humantime::parse_rfc3339(time).unwrap();
+                (time, rest.to_owned())
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn timestamps() {
+        let before = SystemTime::now() - Duration::from_millis(1);
+        let lines = prefixed(b"first\n\nlast without newline", None).await;
+
+        let text = lines
+            .iter()
+            .map(|(_, text)| text.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(text, ["first", "", "last without newline"]);
+        for (time, _) in lines {
+            assert!(time >= before);
+        }
+    }
+
+    #[tokio::test]
+    async fn tagged() {
+        let lines = prefixed(b"warning: unused\n", Some("err")).await;
+        assert_eq!(lines[0].1, "[err] warning: unused");
+    }
+}
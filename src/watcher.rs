@@ -1,37 +1,397 @@
+mod control;
 mod files;
 mod network;
 
-use anyhow::Context;
-use tokio::sync::mpsc::Receiver;
+use std::{
+    net::SocketAddr,
+    path::PathBuf,
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::{anyhow, Context};
+use tokio::sync::mpsc::{Receiver, Sender, UnboundedReceiver};
 
 use crate::cli;
 
+pub use self::files::{expand_globs, FileFilter};
+pub use self::network::{auth::Auth, ACK_DENIED, ACK_OK};
+
 /// Watches for events on a set of sources
 pub struct Watcher {
     #[allow(dead_code)]
     files: Option<files::FileWatcher>,
     #[allow(dead_code)]
+    git: Option<files::GitWatcher>,
+    #[allow(dead_code)]
     network: Option<network::NetworkWatcher>,
-    pub receiver: Receiver<ExecutionTrigger>,
+    #[allow(dead_code)]
+    control: Option<control::ControlSocket>,
+    #[allow(dead_code)]
+    interval: Option<IntervalTimer>,
+    receiver: Receiver<ExecutionTrigger>,
+    /// Errors which keep a source from sending any more triggers
+    failures: UnboundedReceiver<anyhow::Error>,
+    /// Triggers are dropped until this time has passed
+    grace_until: Option<Instant>,
+    /// Triggers which arrived at once are combined into one, unless runs are queued with
+    /// `--queue-runs`
+    combine: bool,
 }
 
 /// Sent when a source triggers re-execution of the command
-pub struct ExecutionTrigger;
+#[derive(Debug, Default)]
+pub struct ExecutionTrigger {
+    /// The files modified within the debounce window, in the order they were first modified.
+    ///
+    /// Empty if the trigger did not originate from a file change.
+    pub paths: Vec<PathBuf>,
+
+    /// The source of the trigger, or `None` if the command runs without being triggered.
+    pub source: Option<TriggerSource>,
+
+    /// The number of events combined into this trigger
+    pub events: usize,
+
+    /// When the trigger was sent
+    pub time: Option<SystemTime>,
+
+    /// The `--debounce-group` of the changed files, if any
+    pub group: Option<String>,
+
+    /// What happened to the changed files, if the trigger came from files
+    pub kind: Option<EventKind>,
+
+    /// The rule named by a `--tcp-json` client
+    pub rule: Option<String>,
+
+    /// Arguments for the command sent by a `--tcp-json` client
+    pub args: Vec<String>,
+}
+
+/// What happened to a changed file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    Created,
+    Modified,
+    Removed,
+    Renamed,
+}
+
+impl EventKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventKind::Created => "created",
+            EventKind::Modified => "modified",
+            EventKind::Removed => "removed",
+            EventKind::Renamed => "renamed",
+        }
+    }
+}
+
+/// Where an execution trigger came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerSource {
+    File,
+    /// A ref of the git repository changed, with `--on-git-change`
+    Git,
+    Udp(SocketAddr),
+    Tcp(SocketAddr),
+    Http(SocketAddr),
+    Unix,
+    Fifo,
+    /// The `--interval` passed
+    Interval,
+}
+
+impl ExecutionTrigger {
+    /// A trigger caused by a single network message
+    pub fn network(source: TriggerSource) -> ExecutionTrigger {
+        ExecutionTrigger {
+            paths: Vec::new(),
+            source: Some(source),
+            events: 1,
+            time: Some(SystemTime::now()),
+            group: None,
+            kind: None,
+            rule: None,
+            args: Vec::new(),
+        }
+    }
+
+    /// Combine a later trigger into this one. The combined trigger lists the paths of both, and
+    /// counts as a removal if either of them was.
+    pub fn merge(&mut self, later: ExecutionTrigger) {
+        for path in later.paths {
+            if !self.paths.contains(&path) {
+                self.paths.push(path);
+            }
+        }
+        self.events += later.events;
+        self.kind = match (self.kind, later.kind) {
+            (Some(EventKind::Removed), _) => Some(EventKind::Removed),
+            (kind, None) => kind,
+            (_, kind) => kind,
+        };
+        if self.group != later.group {
+            self.group = None;
+        }
+        // a later file change shouldn't discard what a client asked for
+        if later.rule.is_some() || !later.args.is_empty() {
+            self.rule = later.rule;
+            self.args = later.args;
+        }
+        self.source = later.source.or(self.source);
+        self.time = later.time.or(self.time);
+    }
+}
+
+impl TriggerSource {
+    /// Short name of the kind of source
+    pub fn kind(&self) -> &'static str {
+        match self {
+            TriggerSource::File => "file",
+            TriggerSource::Git => "git",
+            TriggerSource::Udp(_) => "udp",
+            TriggerSource::Tcp(_) => "tcp",
+            TriggerSource::Http(_) => "http",
+            TriggerSource::Unix => "unix",
+            TriggerSource::Fifo => "fifo",
+            TriggerSource::Interval => "interval",
+        }
+    }
+
+    /// The address of the client which sent the trigger
+    pub fn addr(&self) -> Option<SocketAddr> {
+        match self {
+            TriggerSource::File
+            | TriggerSource::Git
+            | TriggerSource::Unix
+            | TriggerSource::Fifo
+            | TriggerSource::Interval => None,
+            TriggerSource::Udp(addr) | TriggerSource::Tcp(addr) | TriggerSource::Http(addr) => {
+                Some(*addr)
+            }
+        }
+    }
+}
+
+/// How many triggers may be queued before further ones are dropped. Queued triggers are combined
+/// when they are received, so a dropped trigger still results in a run once the queue drains.
+const TRIGGER_CAPACITY: usize = 64;
 
 impl Watcher {
     pub fn new(args: &cli::Arguments) -> anyhow::Result<Watcher> {
-        let (sender, receiver) = tokio::sync::mpsc::channel(1);
+        let (sender, receiver) = tokio::sync::mpsc::channel(TRIGGER_CAPACITY);
+        let (failed, failures) = tokio::sync::mpsc::unbounded_channel();
 
-        let files = files::FileWatcher::new(&args.files, sender.clone())
+        let files = files::FileWatcher::new(&args.files, sender.clone(), failed.clone())
             .context("failed to create file watcher")?;
 
+        let git = match args.files.on_git_change {
+            false => None,
+            true => Some(
+                files::GitWatcher::new(&args.files, sender.clone(), failed)
+                    .context("failed to watch git refs")?,
+            ),
+        };
+
+        let interval = args
+            .behaviour
+            .interval
+            .map(|period| IntervalTimer::spawn(period, sender.clone()));
+
         let network = network::NetworkWatcher::new(&args.network, sender)
             .context("failed to create network listener")?;
 
+        let control = match &args.files.control {
+            None => None,
+            Some(path) => Some(
+                control::ControlSocket::bind(path, files.handle())
+                    .context("failed to create control socket")?,
+            ),
+        };
+
         Ok(Watcher {
             files: Some(files),
+            git,
             network: Some(network),
+            control,
+            interval,
+            receiver,
+            failures,
+            grace_until: args
+                .behaviour
+                .startup_grace
+                .map(|grace| Instant::now() + grace),
+            combine: args.behaviour.queue_runs.is_none(),
+        })
+    }
+
+    /// A watcher without any sources, which only receives the triggers sent through the returned
+    /// sender. Lets tests drive the command loop without real files or sockets.
+    #[cfg(test)]
+    pub fn test_channel(
+        args: &cli::Arguments,
+    ) -> (Watcher, tokio::sync::mpsc::Sender<ExecutionTrigger>) {
+        let (sender, receiver) = tokio::sync::mpsc::channel(TRIGGER_CAPACITY);
+        let (_, failures) = tokio::sync::mpsc::unbounded_channel();
+        let watcher = Watcher {
+            files: None,
+            git: None,
+            network: None,
+            control: None,
+            interval: None,
             receiver,
+            failures,
+            grace_until: None,
+            combine: args.behaviour.queue_runs.is_none(),
+        };
+        (watcher, sender)
+    }
+
+    /// Wait for the next trigger, failing if a source stopped or all of them have closed.
+    /// Triggers which arrived while nobody was waiting are combined into a single trigger, except
+    /// with `--queue-runs`, where each of them is its own run.
+    pub async fn recv(&mut self) -> anyhow::Result<ExecutionTrigger> {
+        loop {
+            let mut trigger = tokio::select! {
+                trigger = self.receiver.recv() => match trigger {
+                    Some(trigger) => trigger,
+                    None => return Err(anyhow!("file watcher closed unexpectedly")),
+                },
+                Some(error) = self.failures.recv() => return Err(error),
+            };
+            match self.grace_until {
+                Some(until) if Instant::now() < until => {
+                    debug!(?trigger, "dropping trigger during startup grace period")
+                }
+                _ => {
+                    if self.combine {
+                        while let Ok(later) = self.receiver.try_recv() {
+                            trigger.merge(later);
+                        }
+                    }
+                    return Ok(trigger);
+                }
+            }
+        }
+    }
+}
+
+/// Sends a trigger every time a fixed period has passed, until dropped
+struct IntervalTimer {
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl IntervalTimer {
+    fn spawn(period: Duration, triggers: Sender<ExecutionTrigger>) -> IntervalTimer {
+        let task = tokio::spawn(async move {
+            // the first tick would complete immediately, which is what `--initial` is for
+            let start = tokio::time::Instant::now() + period;
+            let mut interval = tokio::time::interval_at(start, period);
+            // a long run shouldn't cause a burst of triggers once it finishes
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                debug!(?period, "interval passed");
+                let trigger = ExecutionTrigger::network(TriggerSource::Interval);
+                if let Err(tokio::sync::mpsc::error::TrySendError::Closed(_)) =
+                    triggers.try_send(trigger)
+                {
+                    break;
+                }
+            }
+        });
+        IntervalTimer { task }
+    }
+}
+
+impl Drop for IntervalTimer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file_trigger(paths: &[&str], kind: EventKind) -> ExecutionTrigger {
+        ExecutionTrigger {
+            paths: paths.iter().map(PathBuf::from).collect(),
+            source: Some(TriggerSource::File),
+            events: paths.len(),
+            time: Some(SystemTime::now()),
+            group: None,
+            kind: Some(kind),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn merge() {
+        let mut trigger = file_trigger(&["a.rs", "b.rs"], EventKind::Removed);
+        trigger.merge(file_trigger(&["b.rs", "c.rs"], EventKind::Modified));
+        assert_eq!(trigger.paths, ["a.rs", "b.rs", "c.rs"].map(PathBuf::from));
+        assert_eq!(trigger.events, 4);
+        assert_eq!(trigger.kind, Some(EventKind::Removed));
+
+        let addr = "127.0.0.1:8000".parse().unwrap();
+        trigger.merge(ExecutionTrigger::network(TriggerSource::Tcp(addr)));
+        assert_eq!(trigger.source, Some(TriggerSource::Tcp(addr)));
+        assert_eq!(trigger.events, 5);
+        assert_eq!(trigger.kind, Some(EventKind::Removed));
+    }
+
+    #[tokio::test]
+    async fn interval_timer() {
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(TRIGGER_CAPACITY);
+        let start = Instant::now();
+        let timer = IntervalTimer::spawn(Duration::from_millis(50), sender);
+
+        for _ in 0..3 {
+            let trigger = receiver.recv().await.unwrap();
+            assert_eq!(trigger.source, Some(TriggerSource::Interval));
+        }
+        assert!(start.elapsed() >= Duration::from_millis(150));
+
+        // the sender is dropped along with the timer
+        drop(timer);
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while receiver.recv().await.is_some() {}
         })
+        .await
+        .unwrap();
+    }
+
+    /// A source which stopped is reported instead of waiting forever
+    #[tokio::test]
+    async fn source_failure() {
+        let (sender, receiver) = tokio::sync::mpsc::channel(TRIGGER_CAPACITY);
+        let (failed, failures) = tokio::sync::mpsc::unbounded_channel();
+        let mut watcher = Watcher {
+            files: None,
+            git: None,
+            network: None,
+            control: None,
+            interval: None,
+            receiver,
+            failures,
+            grace_until: None,
+            combine: true,
+        };
+
+        failed
+            .send(anyhow!("file watcher stopped after panicking"))
+            .unwrap();
+        let error = watcher.recv().await.unwrap_err();
+        assert_eq!(error.to_string(), "file watcher stopped after panicking");
+
+        // other sources keep working until they close
+        drop(failed);
+        sender.send(ExecutionTrigger::default()).await.unwrap();
+        assert!(watcher.recv().await.is_ok());
+        drop(sender);
+        assert!(watcher.recv().await.is_err());
     }
 }
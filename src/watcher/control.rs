@@ -0,0 +1,160 @@
+//! A Unix socket which accepts commands to change the watched paths at runtime.
+
+use std::path::Path;
+
+#[cfg(unix)]
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::task::JoinHandle;
+
+use super::files::WatchHandle;
+
+/// Accepts control commands until dropped
+pub struct ControlSocket {
+    handle: JoinHandle<()>,
+}
+
+impl ControlSocket {
+    #[cfg(unix)]
+    pub fn bind(path: &Path, files: WatchHandle) -> anyhow::Result<ControlSocket> {
+        let socket = super::network::SocketFile::bind(path)?;
+        info!(path = ?socket.path, "listening for control commands");
+        Ok(ControlSocket {
+            handle: tokio::spawn(handle_clients(socket, files)),
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn bind(path: &Path, _files: WatchHandle) -> anyhow::Result<ControlSocket> {
+        Err(anyhow::anyhow!(
+            "control sockets are not supported on this platform: {}",
+            path.display()
+        ))
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        // dropping the listener removes the socket file
+        self.handle.abort();
+    }
+}
+
+#[cfg(unix)]
+async fn handle_clients(socket: super::network::SocketFile, files: WatchHandle) {
+    loop {
+        let (stream, _) = match socket.listener.accept().await {
+            Ok(incoming) => incoming,
+            Err(error) => {
+                warn!(%error, "failed to accept control client");
+                continue;
+            }
+        };
+
+        let files = files.clone();
+        tokio::spawn(async move {
+            if let Err(error) = handle_client(stream, &files).await {
+                debug!(%error, "control client disconnected");
+            }
+        });
+    }
+}
+
+/// Respond to each command sent by the client with `ok` or `err <reason>`
+#[cfg(unix)]
+async fn handle_client(
+    stream: impl tokio::io::AsyncRead + tokio::io::AsyncWrite,
+    files: &WatchHandle,
+) -> std::io::Result<()> {
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Some(line) = lines.next_line().await? {
+        let response = match execute(line.trim(), files) {
+            Ok(()) => "ok\n".to_owned(),
+            Err(error) => {
+                warn!(command = line.trim(), "{error:#}");
+                format!("err {error:#}\n")
+            }
+        };
+        writer.write_all(response.as_bytes()).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn execute(command: &str, files: &WatchHandle) -> anyhow::Result<()> {
+    let (name, argument) = command.split_once(' ').unwrap_or((command, ""));
+    let path = Path::new(argument.trim());
+
+    match name {
+        "watch" | "unwatch" if argument.trim().is_empty() => {
+            Err(anyhow::anyhow!("missing path: {command}"))
+        }
+        "watch" => files.watch(path),
+        "unwatch" => files.unwatch(path),
+        _ => Err(anyhow::anyhow!("unknown command: {command}")),
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::watcher::files::FileWatcher;
+    use clap::Parser;
+
+    #[tokio::test]
+    async fn commands() {
+        let dir = tempfile::tempdir().unwrap();
+        let other = tempfile::tempdir().unwrap();
+
+        let args = crate::cli::Arguments::parse_from([
+            "witness",
+            "--path",
+            dir.path().to_str().unwrap(),
+            "true",
+        ]);
+        let (sender, _receiver) = tokio::sync::mpsc::channel(1);
+        let (failed, _failures) = tokio::sync::mpsc::unbounded_channel();
+        let watcher = FileWatcher::new(&args.files, sender, failed).unwrap();
+        let files = watcher.handle();
+
+        let (client, server) = tokio::io::duplex(1024);
+        let session = tokio::spawn(async move { handle_client(server, &files).await });
+
+        let other = other.path().display();
+        let commands = [
+            format!("watch {other}"),
+            format!("watch {other}"),
+            format!("unwatch {other}"),
+            format!("unwatch {other}"),
+            "watch".to_owned(),
+            "remove /".to_owned(),
+        ];
+
+        let (reader, mut writer) = tokio::io::split(client);
+        for command in commands {
+            writer
+                .write_all(format!("{command}\n").as_bytes())
+                .await
+                .unwrap();
+        }
+        writer.shutdown().await.unwrap();
+
+        let mut lines = BufReader::new(reader).lines();
+        let mut responses = Vec::new();
+        while let Some(line) = lines.next_line().await.unwrap() {
+            responses.push(line);
+        }
+
+        assert_eq!(responses.len(), 6);
+        assert_eq!(responses[0], "ok");
+        assert!(responses[1].starts_with("err already watching"));
+        assert_eq!(responses[2], "ok");
+        assert!(responses[3].starts_with("err not watching"));
+        assert!(responses[4].starts_with("err missing path"));
+        assert!(responses[5].starts_with("err unknown command"));
+
+        session.await.unwrap().unwrap();
+    }
+}
@@ -1,206 +1,3528 @@
+mod clock;
+mod git_refs;
+mod gitignore;
+
 use anyhow::Context;
+use globset::{GlobSet, GlobSetBuilder};
 use std::{
-    collections::BTreeSet,
+    collections::{btree_map::Entry, BTreeMap, BTreeSet, HashMap},
     ffi::{OsStr, OsString},
     path::{Path, PathBuf},
-    time::Duration,
+    sync::{mpsc::RecvTimeoutError, Arc, Mutex, RwLock},
+    time::{Duration, Instant},
 };
-use tokio::sync::mpsc::Sender;
+use tokio::sync::mpsc::{Sender, UnboundedSender};
 
 use crate::cli;
 
-use super::ExecutionTrigger;
+pub use self::git_refs::GitWatcher;
+use self::{
+    clock::{Clock, SystemClock},
+    gitignore::{GitIgnore, IgnoreFiles},
+};
+use super::{EventKind, ExecutionTrigger, TriggerSource};
 
 pub struct FileWatcher {
     /// We keep the watcher around so that it keeps sending events in the background
-    #[allow(dead_code)]
-    watcher: notify::RecommendedWatcher,
+    watcher: Arc<Mutex<Backend>>,
+
+    /// Shared with the background thread so that watched paths can change at runtime
+    filter: Arc<RwLock<FileFilter>>,
+}
+
+/// Adds and removes watched paths while the watcher is running
+#[derive(Clone)]
+pub struct WatchHandle {
+    watcher: Arc<Mutex<Backend>>,
+    filter: Arc<RwLock<FileFilter>>,
+}
+
+/// Watches the paths, leaving out directories which are never interesting
+struct Backend {
+    notifier: Notifier,
+
+    /// Names of the directories given by `--ignore-dir`
+    ignore_dirs: BTreeSet<OsString>,
+
+    /// Watched directories containing an ignored directory or a skipped symlink, directly or
+    /// further down. These are watched without recursion, and each of their other subdirectories
+    /// is watched separately.
+    split: BTreeMap<PathBuf, Vec<PathBuf>>,
+
+    /// Symlinks to directories are followed, except for those which would loop
+    follow_symlinks: bool,
+
+    /// The directories watched for the given paths: the paths themselves, or the parents of files
+    watched: BTreeSet<PathBuf>,
+
+    /// Paths given to watch which didn't exist yet
+    missing: Vec<MissingPath>,
+}
+
+/// A path which didn't exist when it was given. Its closest existing ancestor is watched instead,
+/// until the path is created.
+struct MissingPath {
+    path: PathBuf,
+    ancestor: PathBuf,
+}
+
+/// The events to handle in place of an event on the ancestor of a missing path
+#[derive(Default)]
+struct Arrivals {
+    events: Vec<notify::RawEvent>,
+    /// Missing paths which were created, and are watched now
+    created: Vec<PathBuf>,
+}
+
+/// The mechanism used to detect file changes
+enum Notifier {
+    /// Rely on notifications from the operating system
+    Native(notify::RecommendedWatcher),
+    /// Periodically scan the file system for changes
+    Poll(notify::PollWatcher),
 }
 
 impl FileWatcher {
     pub fn new(
         options: &cli::FileOptions,
         triggers: Sender<ExecutionTrigger>,
+        failures: UnboundedSender<anyhow::Error>,
     ) -> anyhow::Result<FileWatcher> {
-        use notify::Watcher as _;
-
-        let debounce = options.debounce;
+        let mut options = options.clone();
+        options.paths.paths = expand_globs(&options.paths)?;
+        let options = &options;
 
         let (sender, receiver) = std::sync::mpsc::channel();
-        let mut watcher = notify::raw_watcher(sender)?;
+        let mut watcher = Backend::new(options.poll, &options.ignore_dirs, sender)?;
+        watcher.follow_symlinks = options.follow_symlinks;
 
         // Watch the given path
-        for path in options.paths.iter() {
-            info!(?path, "watching path");
-            watcher
-                .watch(path, notify::RecursiveMode::Recursive)
-                .with_context(|| format!("failed to watch path: {}", path.display()))?;
+        for path in minimal_paths(&options.paths) {
+            watcher.watch_path(path)?;
         }
 
-        let filter = FileFilter::from_args(options);
+        let watcher = Arc::new(Mutex::new(watcher));
+        let filter = Arc::new(RwLock::new(FileFilter::from_args(options)?));
 
         // Create a thread to glue sync and async parts together
-        std::thread::spawn(move || {
-            while let Ok(event) = receiver.recv() {
-                if let Some(path) = Self::modified_file(&event) {
-                    match filter.matches_path(path) {
-                        Ok(()) => {
-                            info!(?path, ?event, "file trigger");
-                            let _ = triggers.try_send(ExecutionTrigger);
-
-                            // skip all remaining values to avoid triggering twice
-                            Self::skip_for_duration(&receiver, debounce);
-                        }
-                        Err(reason) => {
-                            info!(?reason, ?path, "ignoring modification");
-                        }
-                    }
-                }
-            }
+        let shared = (watcher.clone(), filter.clone(), options.clone());
+        spawn_event_thread(receiver, failures, move |receiver| {
+            let (watcher, filter, options) = &shared;
+            Self::handle_events(
+                receiver,
+                Some(watcher.clone()),
+                filter.clone(),
+                Debounce::from_args(options),
+                triggers.clone(),
+                SystemClock,
+            )
         });
 
-        Ok(FileWatcher { watcher })
+        Ok(FileWatcher { watcher, filter })
     }
 
-    /// Skip all elements in the receiver for the full duration
-    fn skip_for_duration<T>(receiver: &std::sync::mpsc::Receiver<T>, duration: Duration) {
-        let deadline = std::time::Instant::now() + duration;
+    pub fn handle(&self) -> WatchHandle {
+        WatchHandle {
+            watcher: self.watcher.clone(),
+            filter: self.filter.clone(),
+        }
+    }
+
+    /// Turn file events into execution triggers until the watcher is dropped
+    fn handle_events(
+        receiver: &std::sync::mpsc::Receiver<notify::RawEvent>,
+        watcher: Option<Arc<Mutex<Backend>>>,
+        filter: Arc<RwLock<impl EventFilter>>,
+        mut debounce: Debounce,
+        triggers: Sender<ExecutionTrigger>,
+        clock: impl Clock,
+    ) {
+        if debounce.is_immediate() {
+            return Self::handle_events_immediately(receiver, watcher, filter, debounce, triggers);
+        }
+
+        let mut windows = Windows::new(clock);
+
         loop {
-            // how much time until the deadline is reached?
-            let now = std::time::Instant::now();
-            let remaining = match deadline.checked_duration_since(now) {
-                Some(duration) => duration,
+            windows.close_expired(&mut debounce, &triggers);
+
+            // wait for the next event, or until the first window closes
+            let event = match windows.until_next_deadline() {
+                None => receiver.recv().ok(),
+                Some(timeout) => match receiver.recv_timeout(timeout) {
+                    Ok(event) => Some(event),
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => None,
+                },
+            };
+
+            let event = match event {
+                Some(event) => event,
                 None => break,
             };
 
-            // skip messages while we are within the deadline
-            if receiver.recv_timeout(remaining).is_err() {
-                break;
+            for event in Self::track_event(watcher.as_deref(), &filter, event) {
+                let (path, kind) = match filter.read().unwrap().accepted_path(&event) {
+                    Some(accepted) => accepted,
+                    None => continue,
+                };
+
+                windows.insert(&mut debounce, &triggers, path, kind);
+            }
+        }
+
+        // the watcher is gone, so send whatever changes are left
+        windows.close_all(&mut debounce, &triggers);
+    }
+
+    /// Send a trigger as soon as a change is complete, without any debounce windows.
+    ///
+    /// A single change usually results in several events (such as a create followed by a write),
+    /// so events which are already waiting are combined into the same trigger. With native
+    /// notifications a file being written is only sent once it is closed, since its events may
+    /// not all arrive at once.
+    fn handle_events_immediately(
+        receiver: &std::sync::mpsc::Receiver<notify::RawEvent>,
+        watcher: Option<Arc<Mutex<Backend>>>,
+        filter: Arc<RwLock<impl EventFilter>>,
+        mut debounce: Debounce,
+        triggers: Sender<ExecutionTrigger>,
+    ) {
+        // changes which have not been sent yet, by group
+        let mut pending = BTreeMap::<usize, ChangedPaths>::new();
+
+        // files which are still open for writing
+        let mut writing = BTreeSet::<PathBuf>::new();
+
+        loop {
+            let first = match writing.is_empty() {
+                true => match receiver.recv() {
+                    Ok(event) => Some(event),
+                    Err(_) => break,
+                },
+                false => match receiver.recv_timeout(CLOSE_TIMEOUT) {
+                    Ok(event) => Some(event),
+                    Err(RecvTimeoutError::Timeout) => {
+                        debug!(paths = ?writing, "files were not closed after writing");
+                        writing.clear();
+                        None
+                    }
+                    Err(RecvTimeoutError::Disconnected) => break,
+                },
+            };
+
+            let events = first.into_iter().chain(receiver.try_iter());
+            let events =
+                events.flat_map(|event| Self::track_event(watcher.as_deref(), &filter, event));
+            for event in events.collect::<Vec<_>>() {
+                if debounce.wait_for_close && Self::closed_file(&event) {
+                    writing.retain(|path| Some(path) != event.path.as_ref());
+                }
+
+                let (path, kind) = match filter.read().unwrap().accepted_path(&event) {
+                    Some(accepted) => accepted,
+                    None => continue,
+                };
+
+                // a change of permissions doesn't open the file
+                let opened = kind == EventKind::Created
+                    || matches!(&event.op, Ok(op) if op.contains(notify::Op::WRITE));
+                if debounce.wait_for_close && opened && !path.is_dir() {
+                    writing.insert(path.clone());
+                }
+
+                let group = debounce.group_of(&path);
+                pending.entry(group).or_default().insert(path, kind);
             }
+
+            if writing.is_empty() {
+                for (group, paths) in std::mem::take(&mut pending) {
+                    debounce.send(&triggers, group, paths);
+                }
+            }
+        }
+
+        // the watcher is gone, so send whatever changes are left
+        for (group, paths) in pending {
+            debounce.send(&triggers, group, paths);
+        }
+    }
+
+    /// Keep the watches up to date with the directories created by an event, returning the
+    /// events to handle in its place
+    fn track_event(
+        watcher: Option<&Mutex<Backend>>,
+        filter: &RwLock<impl EventFilter>,
+        event: notify::RawEvent,
+    ) -> Vec<notify::RawEvent> {
+        let watcher = match watcher {
+            Some(watcher) => watcher,
+            None => return vec![event],
+        };
+
+        let arrivals = {
+            let mut watcher = watcher.lock().unwrap();
+            watcher.directory_created(&event);
+            watcher.missing_path_event(event)
+        };
+
+        for path in &arrivals.created {
+            info!(?path, "watched path was created");
+            filter.write().unwrap().root_created(path);
+        }
+        arrivals.events
+    }
+
+    /// Check if the event marks the end of writing to a file
+    fn closed_file(event: &notify::RawEvent) -> bool {
+        use notify::Op;
+        match &event.op {
+            Ok(op) => op.intersects(Op::CLOSE_WRITE | Op::REMOVE | Op::RENAME),
+            Err(_) => false,
         }
     }
 
-    /// Given an event, returns the path that has been modified (if any)
-    fn modified_file(event: &notify::RawEvent) -> Option<&Path> {
+    /// Given an event, returns the path that has been modified (if any) and what happened to it.
+    /// Only the operations within the mask are considered.
+    fn modified_file(event: &notify::RawEvent, mask: notify::Op) -> Option<(&Path, EventKind)> {
         use notify::Op;
-        let op = *event.op.as_ref().ok()?;
-        if op.intersects(Op::WRITE | Op::CREATE | Op::REMOVE | Op::RENAME | Op::CHMOD) {
-            event.path.as_deref()
+        let op = *event.op.as_ref().ok()? & mask;
+
+        // a single event may combine several operations, of which the removal matters the most
+        let kind = if op.contains(Op::REMOVE) {
+            EventKind::Removed
+        } else if op.contains(Op::RENAME) {
+            EventKind::Renamed
+        } else if op.contains(Op::CREATE) {
+            EventKind::Created
+        } else if op.intersects(Op::WRITE | Op::CHMOD) {
+            EventKind::Modified
         } else {
-            None
+            return None;
+        };
+
+        Some((event.path.as_deref()?, kind))
+    }
+}
+
+/// How many times the thread handling file events is started again after it panicked, before the
+/// failure is reported
+const EVENT_THREAD_RESTARTS: u32 = 1;
+
+/// Handle file events on a thread of its own. Should it panic, no more triggers would be sent, so
+/// it is restarted, and once it has panicked too often the failure is reported instead of leaving
+/// witness waiting forever.
+fn spawn_event_thread(
+    receiver: std::sync::mpsc::Receiver<notify::RawEvent>,
+    failures: UnboundedSender<anyhow::Error>,
+    handle_events: impl Fn(&std::sync::mpsc::Receiver<notify::RawEvent>) + Send + 'static,
+) {
+    std::thread::spawn(move || {
+        let mut restarts = 0;
+        loop {
+            let run = std::panic::AssertUnwindSafe(|| handle_events(&receiver));
+            let panic = match std::panic::catch_unwind(run) {
+                Ok(()) => return,
+                Err(panic) => panic,
+            };
+
+            let message = panic
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("unknown error");
+            if restarts < EVENT_THREAD_RESTARTS {
+                restarts += 1;
+                error!(message, "file watcher panicked, restarting it");
+                continue;
+            }
+
+            let error = anyhow::anyhow!("file watcher stopped after panicking: {message}");
+            let _ = failures.send(error);
+            return;
+        }
+    });
+}
+
+/// With a zero debounce, files which stay open for writing (such as logs) are sent after this long
+/// even if they haven't been closed.
+const CLOSE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Decides how changes are combined into triggers
+struct Debounce {
+    mode: cli::DebounceMode,
+
+    /// Native notifications tell when a file is closed after writing, which polling does not
+    wait_for_close: bool,
+
+    /// The groups given by `--debounce-group`, followed by a group for all other changes
+    groups: Vec<Group>,
+
+    /// Extensions are compared case-insensitively, and are stored in lowercase
+    ignore_case_ext: bool,
+
+    /// The latest contents of changed files, with `--content-hash`
+    hashes: Option<ContentHashes>,
+
+    /// What the triggers are reported to come from
+    source: TriggerSource,
+}
+
+/// Changes which are debounced independently of other changes
+struct Group {
+    name: Option<String>,
+
+    /// Files with these extensions belong to the group. Empty for the last group, which contains
+    /// all files not in any other group.
+    extensions: BTreeSet<OsString>,
+
+    duration: Duration,
+
+    /// Adjusts the duration to the rate of changes, with `--debounce-adaptive`
+    adaptive: Option<Adaptive>,
+}
+
+/// Lengthens the debounce window of a group while changes keep arriving just after it closes.
+///
+/// Changes within the window are already combined, so they don't affect its length. A change
+/// shortly after the window closed means the window was too short, so it is doubled. A long
+/// quiet period halves it again, until it's back at the base duration.
+struct Adaptive {
+    base: Duration,
+    max: Duration,
+
+    /// When the latest change arrived
+    last: Option<Instant>,
+}
+
+/// The open debounce window of each group
+struct Windows<C> {
+    clock: C,
+    open: BTreeMap<usize, Window>,
+}
+
+/// A change arriving this many windows after the previous one counts as a quiet period
+const QUIET_WINDOWS: u32 = 3;
+
+/// Changes within a group waiting for the debounce window to close.
+///
+/// With `--debounce-mode=settle` the deadline is extended whenever a path changes, so a path which
+/// is modified continuously keeps the window open.
+struct Window {
+    paths: ChangedPaths,
+    deadline: Instant,
+
+    /// The trigger was sent when the window opened, with `--debounce-mode=leading`
+    sent: bool,
+}
+
+impl Debounce {
+    fn from_args(options: &cli::FileOptions) -> Debounce {
+        let fold = |ext: &OsString| match options.ignore_case_ext {
+            true => fold_case(ext),
+            false => ext.clone(),
+        };
+
+        let mut groups = options
+            .debounce_groups
+            .iter()
+            .map(|group| Group {
+                name: Some(group.name.clone()),
+                extensions: group.extensions.iter().map(fold).collect(),
+                duration: group.debounce,
+                adaptive: Adaptive::from_args(options, group.debounce),
+            })
+            .collect::<Vec<_>>();
+        groups.push(Group {
+            name: None,
+            extensions: BTreeSet::new(),
+            duration: options.debounce,
+            adaptive: Adaptive::from_args(options, options.debounce),
+        });
+
+        Debounce {
+            mode: options.debounce_mode,
+            wait_for_close: options.poll.is_none(),
+            groups,
+            ignore_case_ext: options.ignore_case_ext,
+            hashes: options.content_hash.then(ContentHashes::new),
+            source: TriggerSource::File,
+        }
+    }
+
+    /// Send the changes of a group as a trigger, unless none of the files actually changed
+    fn send(&mut self, triggers: &Sender<ExecutionTrigger>, group: usize, mut paths: ChangedPaths) {
+        if let Some(hashes) = &mut self.hashes {
+            paths.retain(|path, kind| hashes.changed(path, kind));
+            if paths.is_empty() {
+                debug!("contents of changed files are the same");
+                return;
+            }
         }
+
+        let mut trigger = paths.into_trigger();
+        trigger.source = Some(self.source);
+        trigger.group = self.groups[group].name.clone();
+        info!(
+            paths = ?trigger.paths,
+            events = trigger.events,
+            group = ?trigger.group,
+            "file trigger"
+        );
+        let _ = triggers.try_send(trigger);
+    }
+
+    /// With a zero debounce every change is sent right away
+    fn is_immediate(&self) -> bool {
+        self.groups
+            .iter()
+            .all(|group| group.duration.is_zero() && group.adaptive.is_none())
+    }
+
+    /// The index of the first group the path belongs to
+    fn group_of(&self, path: &Path) -> usize {
+        let extensions = extensions_of(path, self.ignore_case_ext);
+        let in_group = |group: &Group| extensions.iter().any(|ext| group.extensions.contains(ext));
+
+        self.groups
+            .iter()
+            .position(in_group)
+            .unwrap_or(self.groups.len() - 1)
     }
 }
 
-pub struct FileFilter {
-    /// Only allow these specific extensions, or anything
-    extensions: Option<BTreeSet<OsString>>,
+impl<C: Clock> Windows<C> {
+    fn new(clock: C) -> Windows<C> {
+        Windows {
+            clock,
+            open: BTreeMap::new(),
+        }
+    }
 
-    /// Files ignored by git should be respected
-    git_ignore: bool,
+    /// Add a change to the window of its group, opening one if there is none
+    fn insert(
+        &mut self,
+        debounce: &mut Debounce,
+        triggers: &Sender<ExecutionTrigger>,
+        path: PathBuf,
+        kind: EventKind,
+    ) {
+        let index = debounce.group_of(&path);
+        let now = self.clock.now();
+        let duration = debounce.groups[index].window(now);
+        match self.open.entry(index) {
+            Entry::Occupied(entry) => {
+                // with `--debounce-mode=leading` the rest of the burst is ignored
+                let window = entry.into_mut();
+                if !window.sent {
+                    window.paths.insert(path, kind);
+                    if debounce.mode == cli::DebounceMode::Settle {
+                        // extend the window while changes keep arriving
+                        window.deadline = now + duration;
+                    }
+                }
+            }
+            Entry::Vacant(entry) => {
+                let mut paths = ChangedPaths::default();
+                paths.insert(path, kind);
 
-    /// Paths which are ignored
-    ignored: Vec<PathBuf>,
+                let sent = debounce.mode == cli::DebounceMode::Leading;
+                if sent {
+                    debounce.send(triggers, index, std::mem::take(&mut paths));
+                }
+
+                entry.insert(Window {
+                    paths,
+                    deadline: now + duration,
+                    sent,
+                });
+            }
+        }
+    }
+
+    /// Send the changes of the windows which have closed by now
+    fn close_expired(&mut self, debounce: &mut Debounce, triggers: &Sender<ExecutionTrigger>) {
+        let now = self.clock.now();
+        self.open.retain(|&group, window| {
+            if window.deadline > now {
+                return true;
+            }
+            if !window.sent {
+                let paths = std::mem::take(&mut window.paths);
+                debounce.send(triggers, group, paths);
+            }
+            false
+        });
+    }
+
+    /// How long until the first open window closes, if any is open
+    fn until_next_deadline(&self) -> Option<Duration> {
+        let deadline = self.open.values().map(|window| window.deadline).min()?;
+        Some(deadline.saturating_duration_since(self.clock.now()))
+    }
+
+    /// Send the changes of all windows, in the order they would have closed
+    fn close_all(self, debounce: &mut Debounce, triggers: &Sender<ExecutionTrigger>) {
+        let mut windows = self.open.into_iter().collect::<Vec<_>>();
+        windows.sort_by_key(|(_, window)| window.deadline);
+        for (group, window) in windows {
+            if !window.sent {
+                debounce.send(triggers, group, window.paths);
+            }
+        }
+    }
 }
 
-#[derive(Debug)]
-enum FilterReason {
-    Extension,
-    GitIgnore,
-    Ignored,
+impl Group {
+    /// The debounce window for a change arriving now
+    fn window(&mut self, now: Instant) -> Duration {
+        if let Some(adaptive) = &mut self.adaptive {
+            self.duration = adaptive.next(self.duration, now);
+        }
+        self.duration
+    }
 }
 
-impl FileFilter {
-    pub fn from_args(options: &cli::FileOptions) -> FileFilter {
-        FileFilter {
-            extensions: options
-                .extensions
-                .as_ref()
-                .map(|extensions| extensions.iter().cloned().collect()),
+impl Adaptive {
+    fn from_args(options: &cli::FileOptions, base: Duration) -> Option<Adaptive> {
+        options.debounce_adaptive.then(|| Adaptive {
+            base,
+            max: options.debounce_max.max(base),
+            last: None,
+        })
+    }
 
-            git_ignore: !options.no_git_ignore,
+    /// The window to use for a change arriving now, given the current window
+    fn next(&mut self, current: Duration, now: Instant) -> Duration {
+        let gap = match self.last.replace(now) {
+            None => return current,
+            Some(last) => now.saturating_duration_since(last),
+        };
 
-            ignored: options.ignore.clone(),
+        let mut window = current;
+        if gap < window {
+            // combined with the previous change
+        } else if gap < window * QUIET_WINDOWS {
+            window = window.saturating_mul(2).min(self.max);
+        } else {
+            // halve the window for every quiet period
+            let mut quiet = gap;
+            while quiet >= window * QUIET_WINDOWS && window > self.base {
+                quiet -= window * QUIET_WINDOWS;
+                window = (window / 2).max(self.base);
+            }
+        }
+
+        if window != current {
+            debug!(?window, "adapted debounce window");
         }
+        window
     }
+}
 
-    fn matches_path(&self, path: &Path) -> Result<(), FilterReason> {
-        self.check_extension(path)?;
-        self.check_ignored(path)?;
-        if self.git_ignore {
-            self.check_git_ignore(path)?;
+/// The set of paths modified within a debounce window, in the order they were first modified.
+#[derive(Default)]
+struct ChangedPaths {
+    /// What last happened to each path
+    seen: HashMap<PathBuf, EventKind>,
+    paths: Vec<PathBuf>,
+    /// Number of events, including repeated modifications of the same path
+    events: usize,
+    /// The kind of the latest event
+    latest: Option<EventKind>,
+}
+
+impl ChangedPaths {
+    fn insert(&mut self, path: PathBuf, kind: EventKind) {
+        self.events += 1;
+        self.latest = Some(kind);
+        if self.seen.insert(path.clone(), kind).is_none() {
+            self.paths.push(path);
         }
-        Ok(())
     }
 
-    fn check_extension(&self, path: &Path) -> Result<(), FilterReason> {
-        if let Some(extensions) = &self.extensions {
-            match path.extension() {
-                Some(ext) if extensions.contains(ext) => {}
-                _ => return Err(FilterReason::Extension),
+    /// Keep only the paths for which the predicate holds
+    fn retain(&mut self, mut keep: impl FnMut(&Path, EventKind) -> bool) {
+        let seen = &mut self.seen;
+        self.paths.retain(|path| {
+            let kept = keep(path, seen[path]);
+            if !kept {
+                seen.remove(path);
             }
+            kept
+        });
+        if self.paths.is_empty() {
+            self.events = 0;
+            self.latest = None;
         }
+    }
 
-        Ok(())
+    fn is_empty(&self) -> bool {
+        self.paths.is_empty()
     }
 
-    fn check_git_ignore(&self, path: &Path) -> Result<(), FilterReason> {
-        use std::process::{Command, Stdio};
+    /// Any file which is gone by the end of the window makes this a removal
+    fn kind(&self) -> Option<EventKind> {
+        if self.seen.values().any(|&kind| kind == EventKind::Removed) {
+            Some(EventKind::Removed)
+        } else {
+            self.latest
+        }
+    }
 
-        if Self::contains_git_dir(path) {
-            return Err(FilterReason::GitIgnore);
+    fn into_trigger(self) -> ExecutionTrigger {
+        ExecutionTrigger {
+            kind: self.kind(),
+            paths: self.paths,
+            source: Some(TriggerSource::File),
+            events: self.events,
+            time: Some(std::time::SystemTime::now()),
+            group: None,
+            ..Default::default()
         }
+    }
+}
 
-        let result = Command::new("git")
-            .arg("check-ignore")
-            .arg(path)
-            .stdin(Stdio::null())
-            .stdout(Stdio::null())
-            .stderr(Stdio::null())
-            .status();
+/// How many files `--content-hash` remembers the contents of. Files which were forgotten trigger
+/// execution the next time they change.
+const HASH_CAPACITY: usize = 4096;
 
-        match result {
-            Err(error) => warn!(?error, "could not execute `git`"),
-            Ok(status) if status.code() == Some(0) => return Err(FilterReason::GitIgnore),
-            Ok(status) if status.code() == Some(1) => return Ok(()),
-            Ok(status) => warn!(?status, "`git-check-ignore` exited with error"),
+/// Hashes of the latest contents of changed files, to tell when a file was written without
+/// actually changing
+struct ContentHashes {
+    hashes: lru::LruCache<PathBuf, u64>,
+}
+
+impl ContentHashes {
+    fn new() -> ContentHashes {
+        let capacity = std::num::NonZeroUsize::new(HASH_CAPACITY).unwrap();
+        ContentHashes {
+            hashes: lru::LruCache::new(capacity),
+        }
+    }
+
+    /// Check if the contents of the path differ from when it was last seen, and remember them.
+    ///
+    /// Paths which were never seen before, or which can't be read (such as removed files), are
+    /// always considered changed.
+    fn changed(&mut self, path: &Path, kind: EventKind) -> bool {
+        if matches!(kind, EventKind::Removed | EventKind::Renamed) {
+            self.hashes.pop(path);
+            return true;
         }
 
+        match hash_file(path) {
+            Ok(hash) => self.hashes.put(path.to_path_buf(), hash) != Some(hash),
+            Err(error) => {
+                debug!(%error, ?path, "failed to hash file contents");
+                self.hashes.pop(path);
+                true
+            }
+        }
+    }
+}
+
+fn hash_file(path: &Path) -> std::io::Result<u64> {
+    use std::hash::Hasher;
+    use std::io::Read;
+
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        match file.read(&mut buffer)? {
+            0 => break,
+            read => hasher.write(&buffer[..read]),
+        }
+    }
+    Ok(hasher.finish())
+}
+
+impl Backend {
+    fn new(
+        poll: Option<Duration>,
+        ignore_dirs: &[OsString],
+        sender: std::sync::mpsc::Sender<notify::RawEvent>,
+    ) -> anyhow::Result<Backend> {
+        let notifier = match poll {
+            None => Notifier::Native(notify::raw_watcher(sender)?),
+            Some(interval) => {
+                info!(?interval, "polling for file changes");
+                let delay = u32::try_from(interval.as_millis()).unwrap_or(u32::MAX);
+                Notifier::Poll(notify::PollWatcher::with_delay_ms(sender, delay)?)
+            }
+        };
+
+        Ok(Backend {
+            notifier,
+            ignore_dirs: ignore_dirs
+                .iter()
+                .filter(|name| !name.is_empty())
+                .cloned()
+                .collect(),
+            split: BTreeMap::new(),
+            follow_symlinks: false,
+            watched: BTreeSet::new(),
+            missing: Vec::new(),
+        })
+    }
+
+    fn watch(&mut self, path: &Path, mode: notify::RecursiveMode) -> notify::Result<()> {
+        use notify::Watcher as _;
+        match &mut self.notifier {
+            Notifier::Native(watcher) => watcher.watch(path, mode),
+            Notifier::Poll(watcher) => watcher.watch(path, mode),
+        }
+    }
+
+    fn unwatch(&mut self, path: &Path) -> notify::Result<()> {
+        use notify::Watcher as _;
+        match &mut self.notifier {
+            Notifier::Native(watcher) => watcher.unwatch(path),
+            Notifier::Poll(watcher) => watcher.unwatch(path),
+        }
+    }
+
+    fn watch_path(&mut self, path: &Path) -> anyhow::Result<()> {
+        if !path.exists() {
+            return self.watch_missing(path);
+        }
+
+        info!(?path, "watching path");
+
+        // Editors often save files by renaming a new file over the old one, which would end
+        // a watch on the file itself. Watching the parent directory survives that.
+        if path.is_file() {
+            self.watch(parent_dir(path), notify::RecursiveMode::NonRecursive)
+                .with_context(|| format!("failed to watch path: {}", path.display()))?;
+            self.watched.insert(absolute(parent_dir(path)));
+            return Ok(());
+        }
+
+        self.watch_dir(path)
+            .with_context(|| format!("failed to watch path: {}", path.display()))?;
+        self.watched.insert(absolute(path));
         Ok(())
     }
 
-    fn contains_git_dir(path: &Path) -> bool {
-        path.ancestors()
-            .any(|path| path.file_name() == Some(OsStr::new(".git")))
+    /// Watch the closest existing ancestor of a path which doesn't exist yet, until it is created
+    fn watch_missing(&mut self, path: &Path) -> anyhow::Result<()> {
+        let path = absolute(path);
+        let ancestor = path
+            .ancestors()
+            .skip(1)
+            .find(|ancestor| ancestor.is_dir())
+            .ok_or_else(|| anyhow::anyhow!("path does not exist: {}", path.display()))?
+            .to_path_buf();
+
+        warn!(
+            ?path,
+            ?ancestor,
+            "path does not exist yet, waiting for it to be created"
+        );
+        self.watch(&ancestor, notify::RecursiveMode::NonRecursive)
+            .with_context(|| format!("failed to watch path: {}", path.display()))?;
+        self.missing.push(MissingPath { path, ancestor });
+        Ok(())
     }
 
-    fn check_ignored(&self, path: &Path) -> Result<(), FilterReason> {
-        let current_dir = std::env::current_dir().ok();
+    /// Follow missing paths closer as their ancestors are created, and watch them once they
+    /// exist. Events on an ancestor which is only watched for a missing path are dropped, while
+    /// files already within a created path are reported as created, since they appeared before
+    /// it was watched.
+    fn missing_path_event(&mut self, event: notify::RawEvent) -> Arrivals {
+        let parent = match event.path.as_deref().and_then(Path::parent) {
+            Some(parent)
+                if self
+                    .missing
+                    .iter()
+                    .any(|missing| missing.ancestor == parent) =>
+            {
+                parent.to_path_buf()
+            }
+            _ => {
+                return Arrivals {
+                    events: vec![event],
+                    created: Vec::new(),
+                }
+            }
+        };
 
-        let relative = current_dir
-            .as_ref()
-            .and_then(|cwd| path.strip_prefix(cwd).ok());
+        let mut arrivals = Arrivals::default();
+        let mut replaced = vec![parent.clone()];
+        for mut missing in std::mem::take(&mut self.missing) {
+            // the path may have been created along with its ancestors before we noticed
+            while missing.ancestor == parent || replaced.contains(&missing.ancestor) {
+                let nearest = match missing.path.ancestors().find(|path| path.exists()) {
+                    Some(nearest) if nearest != missing.ancestor => nearest.to_path_buf(),
+                    _ => break,
+                };
 
-        for ignored in self.ignored.iter() {
-            let is_ignored = if let Some(relative) = relative.as_ref() {
-                if ignored.is_relative() {
-                    relative.starts_with(ignored)
-                } else {
-                    path.starts_with(ignored)
+                if nearest == missing.path {
+                    replaced.push(missing.ancestor.clone());
+                    match self.watch_path(&missing.path) {
+                        Ok(()) => {
+                            arrivals.events.extend(created_events(&missing.path));
+                            arrivals.created.push(missing.path.clone());
+                        }
+                        Err(error) => warn!(path = ?missing.path, %error, "failed to watch path"),
+                    }
+                    missing.ancestor = nearest;
+                    break;
                 }
-            } else if ignored.is_absolute() {
-                path.starts_with(ignored)
-            } else {
-                // the other path is pointing outside this directory (we couldn't strip the
-                // current dir) and this path is relative. Thus they cannot be the same.
-                false
-            };
 
-            if is_ignored {
-                return Err(FilterReason::Ignored);
+                debug!(path = ?missing.path, ancestor = ?nearest, "ancestor of missing path created");
+                if let Err(error) = self.watch(&nearest, notify::RecursiveMode::NonRecursive) {
+                    warn!(path = ?nearest, %error, "failed to watch ancestor of missing path");
+                    break;
+                }
+                replaced.push(std::mem::replace(&mut missing.ancestor, nearest));
+            }
+
+            if missing.ancestor != missing.path {
+                self.missing.push(missing);
             }
         }
 
-        Ok(())
+        // stop watching ancestors which are no longer needed by anything
+        for ancestor in replaced {
+            let needed = self.watched.contains(&ancestor)
+                || self
+                    .missing
+                    .iter()
+                    .any(|missing| missing.ancestor == ancestor);
+            if !needed {
+                let _ = self.unwatch(&ancestor);
+            }
+        }
+
+        // the directory may be watched for another path as well
+        if self.watched.contains(&parent) {
+            arrivals.events.insert(0, event);
+        }
+        arrivals
+    }
+
+    /// Watch the directory recursively, except for any ignored directories directly within it and
+    /// any symlinks which shouldn't be followed
+    fn watch_dir(&mut self, dir: &Path) -> anyhow::Result<()> {
+        let skipped = skipped_symlinks(dir, &self.ignore_dirs, self.follow_symlinks);
+        self.watch_tree(dir, &skipped, true)
+    }
+
+    fn watch_tree(&mut self, dir: &Path, skipped: &[PathBuf], top: bool) -> anyhow::Result<()> {
+        let mut subdirs = Vec::new();
+        let mut ignored = false;
+        if (top && !self.ignore_dirs.is_empty()) || skipped.iter().any(|path| path.starts_with(dir))
+        {
+            for entry in std::fs::read_dir(dir)? {
+                let path = entry?.path();
+                if skipped.contains(&path) || !path.is_dir() {
+                    continue;
+                }
+                let name = path.file_name().unwrap_or_default();
+                if top && self.ignore_dirs.contains(name) {
+                    debug!(?path, "not watching ignored directory");
+                    ignored = true;
+                } else {
+                    subdirs.push(path);
+                }
+            }
+        }
+
+        if !ignored && !skipped.iter().any(|path| path.starts_with(dir)) {
+            self.watch(dir, notify::RecursiveMode::Recursive)?;
+            return Ok(());
+        }
+
+        self.watch(dir, notify::RecursiveMode::NonRecursive)?;
+        for subdir in subdirs.iter() {
+            self.watch_tree(subdir, skipped, false)?;
+        }
+        self.split.insert(dir.to_path_buf(), subdirs);
+        Ok(())
+    }
+
+    /// Directories created within a split directory are not covered by any watch yet
+    fn directory_created(&mut self, event: &notify::RawEvent) {
+        use notify::Op;
+
+        let op = match &event.op {
+            Ok(op) => *op,
+            Err(_) => return,
+        };
+        let path = match &event.path {
+            Some(path) if op.intersects(Op::CREATE | Op::RENAME) && path.is_dir() => path,
+            _ => return,
+        };
+        let (parent, name) = match (path.parent(), path.file_name()) {
+            (Some(parent), Some(name)) => (parent, name),
+            _ => return,
+        };
+        if self.ignore_dirs.contains(name) {
+            return;
+        }
+
+        if !self.split.contains_key(parent) {
+            return;
+        }
+
+        if !self.follow_symlinks && path.is_symlink() {
+            debug!(?path, "not following new symlink");
+            return;
+        }
+
+        // the directory may have been removed and created again, which ends its previous watch
+        debug!(?path, "watching new directory");
+        if let Err(error) = self.watch(path, notify::RecursiveMode::Recursive) {
+            warn!(?path, %error, "failed to watch new directory");
+            return;
+        }
+
+        if let Some(subdirs) = self.split.get_mut(parent) {
+            if !subdirs.contains(path) {
+                subdirs.push(path.clone());
+            }
+        }
+    }
+
+    /// Stop watching a path, which may have been removed since it was watched
+    fn unwatch_path(&mut self, path: &Path, is_file: bool) -> anyhow::Result<()> {
+        info!(?path, "no longer watching path");
+
+        let target = match is_file {
+            true => parent_dir(path),
+            false => path,
+        };
+
+        self.unwatch_split(target);
+        self.watched.remove(&absolute(target));
+        self.unwatch(target)
+            .with_context(|| format!("failed to unwatch path: {}", path.display()))
+    }
+
+    /// Stop watching the subdirectories of a split directory, and theirs in turn
+    fn unwatch_split(&mut self, dir: &Path) {
+        for subdir in self.split.remove(dir).unwrap_or_default() {
+            self.unwatch_split(&subdir);
+            // the directory may have been removed since
+            let _ = self.unwatch(&subdir);
+        }
+    }
+}
+
+impl WatchHandle {
+    /// Start watching another path.
+    ///
+    /// Ignore files are only loaded for the paths given on startup, so git ignore rules of a new
+    /// path outside those repositories are checked with `git check-ignore` instead.
+    pub fn watch(&self, path: &Path) -> anyhow::Result<()> {
+        let path = absolute(path);
+        if self.filter.read().unwrap().is_root(&path) {
+            return Err(anyhow::anyhow!("already watching path: {}", path.display()));
+        }
+
+        self.watcher.lock().unwrap().watch_path(&path)?;
+        self.filter.write().unwrap().add_root(&path);
+        Ok(())
+    }
+
+    /// Stop watching a path previously passed to `--path` or [`WatchHandle::watch`]
+    pub fn unwatch(&self, path: &Path) -> anyhow::Result<()> {
+        let path = absolute(path);
+        let is_file = {
+            let filter = self.filter.read().unwrap();
+            if !filter.is_root(&path) {
+                return Err(anyhow::anyhow!("not watching path: {}", path.display()));
+            }
+            filter.files.contains(&path)
+        };
+
+        self.watcher.lock().unwrap().unwatch_path(&path, is_file)?;
+        self.filter.write().unwrap().remove_root(&path);
+        Ok(())
+    }
+}
+
+pub struct FileFilter {
+    /// Only allow these specific extensions, or anything
+    extensions: Option<BTreeSet<OsString>>,
+
+    /// Extensions allowed within specific watched paths, instead of `extensions`
+    root_extensions: Vec<(PathBuf, BTreeSet<OsString>)>,
+
+    /// Never allow these extensions
+    excluded_extensions: BTreeSet<OsString>,
+
+    /// Extensions are compared case-insensitively, and are stored in lowercase
+    ignore_case_ext: bool,
+
+    /// Files ignored by git should be respected
+    git_ignore: Option<GitIgnore>,
+
+    /// Files ignored by `.ignore` and `.witnessignore` files should be respected
+    ignore_files: Option<IgnoreFiles>,
+
+    /// Paths which are ignored
+    ignored: Vec<PathBuf>,
+
+    /// Only allow paths matching these globs, or anything
+    include: Option<GlobSet>,
+
+    /// Paths matching these globs are ignored
+    exclude: GlobSet,
+
+    /// Only allow paths matching one of these regular expressions, or anything if empty
+    path_regex: Vec<regex::Regex>,
+
+    /// Only allow paths within one of these canonical directories, or anything if empty
+    under: Vec<PathBuf>,
+
+    /// Paths within these canonical directories are ignored
+    not_under: Vec<PathBuf>,
+
+    /// The watched paths, which globs are matched relative to
+    roots: Vec<PathBuf>,
+
+    /// Hidden files and directories should be included
+    hidden: bool,
+
+    /// Paths nested deeper than this below their watched path are ignored
+    max_depth: Option<usize>,
+
+    /// Names of directories directly within the watched paths which are not watched
+    ignore_dirs: BTreeSet<OsString>,
+
+    /// Watched paths which are files. Only these are accepted from their parent directories.
+    files: Vec<PathBuf>,
+
+    /// The kinds of events which are accepted
+    events: notify::Op,
+}
+
+/// Why a path doesn't trigger execution
+#[derive(Debug)]
+pub enum FilterReason {
+    Extension,
+    /// The extension is not one of those given for the watched path containing it
+    RootExtension,
+    ExcludedExtension,
+    GitIgnore,
+    /// The path was ignored by this ignore file
+    IgnoreFile(PathBuf),
+    Ignored,
+    Glob,
+    /// The path didn't match any `--path-regex`
+    Regex,
+    /// The path is outside every `--under` directory, or within a `--not-under` directory
+    Prefix,
+    /// The path is a sibling of a watched file, but not watched itself
+    NotWatchedFile,
+    /// The path is nested too deep below the watched path
+    TooDeep,
+    /// The path is within a hidden file or directory
+    Hidden,
+    /// The path is within the `.git` directory while respecting git
+    GitDir,
+    /// The path is within a directory given by `--ignore-dir`
+    IgnoredDir,
+}
+
+impl std::fmt::Display for FilterReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            FilterReason::Extension => write!(f, "its extension is not one of `--extensions`"),
+            FilterReason::RootExtension => {
+                write!(
+                    f,
+                    "its extension is not one of those given for its `--path`"
+                )
+            }
+            FilterReason::ExcludedExtension => {
+                write!(f, "its extension is excluded by `--exclude-extensions`")
+            }
+            FilterReason::GitIgnore => write!(f, "it is ignored by git"),
+            FilterReason::IgnoreFile(file) => write!(f, "it is ignored by {}", file.display()),
+            FilterReason::Ignored => write!(f, "it is within a path given by `--ignore`"),
+            FilterReason::Glob => write!(f, "it doesn't match `--include`, or matches `--exclude`"),
+            FilterReason::Regex => write!(f, "it doesn't match any `--path-regex`"),
+            FilterReason::Prefix => write!(
+                f,
+                "it is outside every `--under` directory, or within a `--not-under` directory"
+            ),
+            FilterReason::NotWatchedFile => {
+                write!(f, "it is next to a watched file, but isn't watched itself")
+            }
+            FilterReason::TooDeep => write!(f, "it is nested deeper than `--max-depth`"),
+            FilterReason::Hidden => write!(f, "it is hidden, which `--hidden` would include"),
+            FilterReason::GitDir => write!(f, "it is inside the `.git` directory"),
+            FilterReason::IgnoredDir => {
+                write!(f, "it is within a directory given by `--ignore-dir`")
+            }
+        }
+    }
+}
+
+/// Decides which file events trigger execution
+trait EventFilter {
+    /// Returns the modified path if the event should trigger execution
+    fn accepted_path(&self, event: &notify::RawEvent) -> Option<(PathBuf, EventKind)>;
+
+    /// Called once a watched path which didn't exist has been created
+    fn root_created(&mut self, _path: &Path) {}
+}
+
+impl EventFilter for FileFilter {
+    fn root_created(&mut self, path: &Path) {
+        if path.is_file() && !self.files.iter().any(|file| file == path) {
+            self.files.push(path.to_path_buf());
+        }
+    }
+
+    fn accepted_path(&self, event: &notify::RawEvent) -> Option<(PathBuf, EventKind)> {
+        let (path, kind) = FileWatcher::modified_file(event, self.events)?;
+        match self.matches_path(path) {
+            Ok(()) => {
+                debug!(?path, ?event, "file modified");
+                Some((path.to_path_buf(), kind))
+            }
+            Err(reason) => {
+                info!(?reason, ?path, "ignoring modification");
+                None
+            }
+        }
+    }
+}
+
+impl FileFilter {
+    pub fn from_args(options: &cli::FileOptions) -> anyhow::Result<FileFilter> {
+        let extension_set = |extensions: &[OsString]| {
+            extensions
+                .iter()
+                .map(|ext| match options.ignore_case_ext {
+                    true => fold_case(ext),
+                    false => ext.clone(),
+                })
+                .collect()
+        };
+
+        fn glob_set(globs: &[globset::Glob]) -> anyhow::Result<GlobSet> {
+            let mut builder = GlobSetBuilder::new();
+            for glob in globs {
+                builder.add(glob.clone());
+            }
+            builder.build().context("failed to compile glob patterns")
+        }
+
+        // files in a debounce group are wanted even if their extension was not listed
+        let with_groups = |extensions: &[OsString]| {
+            let grouped = options
+                .debounce_groups
+                .iter()
+                .flat_map(|group| group.extensions.iter().cloned());
+            extension_set(
+                &extensions
+                    .iter()
+                    .cloned()
+                    .chain(grouped)
+                    .collect::<Vec<_>>(),
+            )
+        };
+
+        let roots = options
+            .paths
+            .iter()
+            .map(|path| absolute(path))
+            .collect::<Vec<_>>();
+
+        Ok(FileFilter {
+            extensions: options.extensions.as_deref().map(with_groups),
+
+            root_extensions: options
+                .paths
+                .root_extensions
+                .iter()
+                .flat_map(|entry| {
+                    let extensions = with_groups(&entry.extensions);
+                    matching_roots(&entry.root, &roots)
+                        .into_iter()
+                        .map(move |root| (root, extensions.clone()))
+                })
+                .collect(),
+
+            excluded_extensions: options
+                .exclude_extensions
+                .as_deref()
+                .map(extension_set)
+                .unwrap_or_default(),
+
+            ignore_case_ext: options.ignore_case_ext,
+
+            git_ignore: if options.no_git_ignore || options.no_ignore {
+                None
+            } else {
+                Some(GitIgnore::new(&options.paths))
+            },
+
+            ignore_files: if options.no_ignore {
+                None
+            } else {
+                Some(IgnoreFiles::new(&options.paths))
+            },
+
+            ignored: options.ignore.clone(),
+
+            include: if options.include.is_empty() {
+                None
+            } else {
+                Some(glob_set(&options.include)?)
+            },
+
+            exclude: glob_set(&options.exclude)?,
+
+            path_regex: options.path_regex.clone(),
+
+            under: options.under.iter().map(|dir| canonical(dir)).collect(),
+
+            not_under: options.not_under.iter().map(|dir| canonical(dir)).collect(),
+
+            roots,
+
+            hidden: options.hidden,
+
+            max_depth: options.max_depth,
+
+            ignore_dirs: options
+                .ignore_dirs
+                .iter()
+                .filter(|name| !name.is_empty())
+                .cloned()
+                .collect(),
+
+            files: options
+                .paths
+                .iter()
+                .filter(|path| path.is_file())
+                .map(|path| absolute(path))
+                .collect(),
+
+            events: options.events.iter().copied().map(event_op).collect(),
+        })
+    }
+
+    fn is_root(&self, path: &Path) -> bool {
+        self.roots.iter().any(|root| root == path)
+    }
+
+    fn add_root(&mut self, path: &Path) {
+        self.roots.push(path.to_path_buf());
+        if path.is_file() {
+            self.files.push(path.to_path_buf());
+        }
+    }
+
+    fn remove_root(&mut self, path: &Path) {
+        self.roots.retain(|root| root != path);
+        self.files.retain(|file| file != path);
+    }
+
+    pub fn matches_path(&self, path: &Path) -> Result<(), FilterReason> {
+        self.check_watched_file(path)?;
+        self.check_depth(path)?;
+        self.check_hidden(path)?;
+        self.check_extension(path)?;
+        self.check_glob(path)?;
+        self.check_regex(path)?;
+        self.check_prefix(path)?;
+        self.check_ignored(path)?;
+        if let Some(ignore_files) = &self.ignore_files {
+            if let Some(file) = ignore_files.ignored_by(path) {
+                return Err(FilterReason::IgnoreFile(file.to_path_buf()));
+            }
+        }
+        if let Some(git_ignore) = &self.git_ignore {
+            Self::check_git_ignore(git_ignore, path)?;
+        }
+        self.check_ignored_dir(path)?;
+        Ok(())
+    }
+
+    fn check_watched_file(&self, path: &Path) -> Result<(), FilterReason> {
+        if self.files.is_empty() {
+            return Ok(());
+        }
+
+        let path = absolute(path);
+        let within_root = |root: &PathBuf| !self.files.contains(root) && path.starts_with(root);
+        if self.files.contains(&path) || self.roots.iter().any(within_root) {
+            Ok(())
+        } else {
+            Err(FilterReason::NotWatchedFile)
+        }
+    }
+
+    fn check_depth(&self, path: &Path) -> Result<(), FilterReason> {
+        let max_depth = match self.max_depth {
+            Some(max_depth) => max_depth,
+            None => return Ok(()),
+        };
+
+        // measure the distance to the nearest watched path
+        let depth = self
+            .relative_to_root(path)
+            .map(|relative| relative.components().count());
+
+        match depth {
+            Some(depth) if depth > max_depth => Err(FilterReason::TooDeep),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_hidden(&self, path: &Path) -> Result<(), FilterReason> {
+        // the watched paths themselves may be hidden
+        let relative = self
+            .relative_to_root(path)
+            .unwrap_or_else(|| path.to_path_buf());
+
+        for component in relative.components() {
+            let name = match component {
+                std::path::Component::Normal(name) => name,
+                _ => continue,
+            };
+
+            // changes within `.git` are never interesting while we respect git
+            if name == OsStr::new(".git") && self.git_ignore.is_some() {
+                return Err(FilterReason::GitDir);
+            }
+            if name.to_string_lossy().starts_with('.') && !self.hidden {
+                return Err(FilterReason::Hidden);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Ignored directories are not watched, but the directories themselves are
+    fn check_ignored_dir(&self, path: &Path) -> Result<(), FilterReason> {
+        let relative = match self.relative_to_root(path) {
+            Some(relative) => relative,
+            None => return Ok(()),
+        };
+
+        match relative.components().next() {
+            Some(std::path::Component::Normal(name)) if self.ignore_dirs.contains(name) => {
+                Err(FilterReason::IgnoredDir)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The path relative to the nearest watched path containing it
+    fn relative_to_root(&self, path: &Path) -> Option<PathBuf> {
+        let path = absolute(path);
+        self.roots
+            .iter()
+            .filter_map(|root| path.strip_prefix(root).ok())
+            .min_by_key(|relative| relative.components().count())
+            .map(Path::to_path_buf)
+    }
+
+    fn check_extension(&self, path: &Path) -> Result<(), FilterReason> {
+        let candidates = extensions_of(path, self.ignore_case_ext);
+
+        if candidates
+            .iter()
+            .any(|ext| self.excluded_extensions.contains(ext))
+        {
+            return Err(FilterReason::ExcludedExtension);
+        }
+
+        let (extensions, reason) = match self.root_extensions_of(path) {
+            Some(extensions) => (Some(extensions), FilterReason::RootExtension),
+            None => (self.extensions.as_ref(), FilterReason::Extension),
+        };
+        if let Some(extensions) = extensions {
+            if !candidates.iter().any(|ext| extensions.contains(ext)) {
+                return Err(reason);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The extensions given for the closest watched path containing the path, if any
+    fn root_extensions_of(&self, path: &Path) -> Option<&BTreeSet<OsString>> {
+        if self.root_extensions.is_empty() {
+            return None;
+        }
+
+        let path = absolute(path);
+        let root = self
+            .roots
+            .iter()
+            .filter(|root| path.starts_with(root))
+            .max_by_key(|root| root.components().count())?;
+        self.root_extensions
+            .iter()
+            .find(|(other, _)| other == root)
+            .map(|(_, extensions)| extensions)
+    }
+
+    /// The forms of an absolute path which patterns are matched against: relative to the watched
+    /// paths when possible
+    fn pattern_candidates<'a>(&self, path: &'a Path) -> Vec<&'a Path> {
+        let mut candidates = self
+            .roots
+            .iter()
+            .filter_map(|root| path.strip_prefix(root).ok())
+            .collect::<Vec<_>>();
+        if candidates.is_empty() {
+            candidates.push(path);
+        }
+        candidates
+    }
+
+    fn check_glob(&self, path: &Path) -> Result<(), FilterReason> {
+        let path = absolute(path);
+        let candidates = self.pattern_candidates(&path);
+
+        if candidates.iter().any(|path| self.exclude.is_match(path)) {
+            return Err(FilterReason::Glob);
+        }
+
+        if let Some(include) = &self.include {
+            if !candidates.iter().any(|path| include.is_match(path)) {
+                return Err(FilterReason::Glob);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_regex(&self, path: &Path) -> Result<(), FilterReason> {
+        if self.path_regex.is_empty() {
+            return Ok(());
+        }
+
+        let path = absolute(path);
+        let matched = self.pattern_candidates(&path).iter().any(|path| {
+            let text = path.to_string_lossy();
+            self.path_regex.iter().any(|regex| regex.is_match(&text))
+        });
+        match matched {
+            true => Ok(()),
+            false => Err(FilterReason::Regex),
+        }
+    }
+
+    fn check_prefix(&self, path: &Path) -> Result<(), FilterReason> {
+        if self.under.is_empty() && self.not_under.is_empty() {
+            return Ok(());
+        }
+
+        let path = canonical(path);
+        let within = |dirs: &[PathBuf]| dirs.iter().any(|dir| path.starts_with(dir));
+        if (!self.under.is_empty() && !within(&self.under)) || within(&self.not_under) {
+            return Err(FilterReason::Prefix);
+        }
+        Ok(())
+    }
+
+    fn check_git_ignore(git_ignore: &GitIgnore, path: &Path) -> Result<(), FilterReason> {
+        match git_ignore.is_ignored(path) {
+            Some(true) => Err(FilterReason::GitIgnore),
+            Some(false) => Ok(()),
+            // we could not locate the repository ourselves, so let git decide
+            None => Self::check_git_ignore_command(path),
+        }
+    }
+
+    fn check_git_ignore_command(path: &Path) -> Result<(), FilterReason> {
+        use std::process::{Command, Stdio};
+
+        let result = Command::new("git")
+            .arg("check-ignore")
+            .arg(path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+
+        match result {
+            Err(error) => warn!(?error, "could not execute `git`"),
+            Ok(status) if status.code() == Some(0) => return Err(FilterReason::GitIgnore),
+            Ok(status) if status.code() == Some(1) => return Ok(()),
+            Ok(status) => warn!(?status, "`git-check-ignore` exited with error"),
+        }
+
+        Ok(())
+    }
+
+    fn check_ignored(&self, path: &Path) -> Result<(), FilterReason> {
+        let current_dir = std::env::current_dir().ok();
+
+        let relative = current_dir
+            .as_ref()
+            .and_then(|cwd| path.strip_prefix(cwd).ok());
+
+        for ignored in self.ignored.iter() {
+            let is_ignored = if let Some(relative) = relative.as_ref() {
+                if ignored.is_relative() {
+                    relative.starts_with(ignored)
+                } else {
+                    path.starts_with(ignored)
+                }
+            } else if ignored.is_absolute() {
+                path.starts_with(ignored)
+            } else {
+                // the other path is pointing outside this directory (we couldn't strip the
+                // current dir) and this path is relative. Thus they cannot be the same.
+                false
+            };
+
+            if is_ignored {
+                return Err(FilterReason::Ignored);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The extensions of the path, from the last one (`gz`) to the longest (`tar.gz`), so that
+/// extensions with several parts, such as `tar.gz` or `d.ts`, match as well
+fn extensions_of(path: &Path, ignore_case: bool) -> Vec<OsString> {
+    let fold = |ext: &OsStr| match ignore_case {
+        true => fold_case(ext),
+        false => ext.to_os_string(),
+    };
+
+    let name = match path.file_name().and_then(OsStr::to_str) {
+        Some(name) => name,
+        None => return path.extension().map(fold).into_iter().collect(),
+    };
+
+    // like `Path::extension`, a leading dot doesn't start an extension
+    name.match_indices('.')
+        .rev()
+        .filter(|&(index, _)| index > 0)
+        .map(|(index, _)| fold(OsStr::new(&name[index + 1..])))
+        .collect()
+}
+
+/// Convert the text to lowercase. Text which is not valid Unicode is converted byte-wise where
+/// possible, only affecting ASCII letters.
+fn fold_case(text: &OsStr) -> OsString {
+    match text.to_str() {
+        Some(text) => OsString::from(text.to_lowercase()),
+        None => fold_case_bytes(text),
+    }
+}
+
+#[cfg(unix)]
+fn fold_case_bytes(text: &OsStr) -> OsString {
+    use std::os::unix::ffi::{OsStrExt, OsStringExt};
+    OsString::from_vec(text.as_bytes().to_ascii_lowercase())
+}
+
+#[cfg(not(unix))]
+fn fold_case_bytes(text: &OsStr) -> OsString {
+    text.to_os_string()
+}
+
+/// The operations reported for a kind of event
+fn event_op(event: cli::FileEvent) -> notify::Op {
+    use notify::Op;
+    match event {
+        cli::FileEvent::Write => Op::WRITE,
+        cli::FileEvent::Create => Op::CREATE,
+        cli::FileEvent::Remove => Op::REMOVE,
+        cli::FileEvent::Rename => Op::RENAME,
+        cli::FileEvent::Chmod => Op::CHMOD,
+    }
+}
+
+/// Events for the files within a path which was just created, or the path itself if it is a file
+fn created_events(path: &Path) -> Vec<notify::RawEvent> {
+    fn files_within(dir: &Path, files: &mut Vec<PathBuf>) {
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+        for entry in entries.flatten() {
+            match entry.file_type() {
+                Ok(kind) if kind.is_dir() => files_within(&entry.path(), files),
+                Ok(_) => files.push(entry.path()),
+                Err(_) => {}
+            }
+        }
+    }
+
+    let mut files = Vec::new();
+    match path.is_dir() {
+        true => files_within(path, &mut files),
+        false => files.push(path.to_path_buf()),
+    }
+
+    files
+        .into_iter()
+        .map(|path| notify::RawEvent {
+            path: Some(path),
+            op: Ok(notify::Op::CREATE),
+            cookie: None,
+        })
+        .collect()
+}
+
+/// The watched paths given by a path from the command line, which may be a glob pattern
+fn matching_roots(path: &Path, roots: &[PathBuf]) -> Vec<PathBuf> {
+    let path = absolute(path);
+    let pattern = path.to_str().and_then(|text| glob::Pattern::new(text).ok());
+    roots
+        .iter()
+        .filter(|root| {
+            **root == path
+                || pattern
+                    .as_ref()
+                    .is_some_and(|pattern| pattern.matches_path(root))
+        })
+        .cloned()
+        .collect()
+}
+
+/// Replace paths containing glob patterns, such as `crates/*/src`, with the paths they match.
+/// Matching files are watched just like files given directly.
+pub fn expand_globs(paths: &[PathBuf]) -> anyhow::Result<Vec<PathBuf>> {
+    let mut expanded = Vec::with_capacity(paths.len());
+    for path in paths {
+        let pattern = match path.to_str() {
+            Some(pattern) if glob::Pattern::escape(pattern) != pattern => pattern,
+            _ => {
+                expanded.push(path.clone());
+                continue;
+            }
+        };
+
+        let matches = glob::glob(pattern)
+            .with_context(|| format!("invalid pattern in path: {pattern}"))?
+            .filter_map(|entry| match entry {
+                Ok(path) => Some(path),
+                Err(error) => {
+                    warn!(%error, "failed to expand path pattern");
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+
+        if matches.is_empty() {
+            warn!(pattern, "path pattern did not match anything");
+        }
+        debug!(pattern, ?matches, "expanded path pattern");
+        expanded.extend(matches);
+    }
+    Ok(expanded)
+}
+
+/// Drop any paths which are already watched through another path, which would otherwise report
+/// their changes twice. Paths are compared after resolving symlinks.
+fn minimal_paths(paths: &[PathBuf]) -> Vec<&PathBuf> {
+    let resolved = paths
+        .iter()
+        .map(|path| match path.canonicalize() {
+            Ok(resolved) => resolved,
+            Err(error) => {
+                // such as a symlink loop, which we leave for the watcher to report
+                debug!(?path, %error, "could not resolve path");
+                absolute(path)
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let mut minimal = Vec::with_capacity(paths.len());
+    for (index, path) in paths.iter().enumerate() {
+        let resolved_path = &resolved[index];
+
+        // duplicates are only kept the first time they appear
+        let covered_by = resolved
+            .iter()
+            .enumerate()
+            .position(|(other, resolved_other)| {
+                resolved_path.starts_with(resolved_other)
+                    && (resolved_path != resolved_other || other < index)
+            });
+
+        match covered_by {
+            None => minimal.push(path),
+            Some(other) => {
+                info!(?path, covered_by = ?paths[other], "path is already watched")
+            }
+        }
+    }
+
+    minimal
+}
+
+/// Find the symlinks to directories below the directory which shouldn't be watched: all of them,
+/// or with `--follow-symlinks` those leading to a directory which was already seen, such as one
+/// of their ancestors. Ignored directories directly within the directory are left out.
+fn skipped_symlinks(dir: &Path, ignore_dirs: &BTreeSet<OsString>, follow: bool) -> Vec<PathBuf> {
+    fn visit(
+        dir: &Path,
+        ignore_dirs: Option<&BTreeSet<OsString>>,
+        follow: bool,
+        visited: &mut BTreeSet<PathBuf>,
+        skipped: &mut Vec<PathBuf>,
+    ) {
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(error) => {
+                debug!(?dir, %error, "failed to look for symlinks");
+                return;
+            }
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let file_type = match entry.file_type() {
+                Ok(file_type) => file_type,
+                Err(_) => continue,
+            };
+            if ignore_dirs.is_some_and(|ignored| ignored.contains(&entry.file_name())) {
+                continue;
+            }
+
+            if file_type.is_symlink() {
+                if !path.is_dir() {
+                    continue;
+                }
+                if !follow {
+                    info!(?path, "not following symlink to directory");
+                    skipped.push(path);
+                    continue;
+                }
+                let target = std::fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+                if !visited.insert(target) {
+                    warn!(
+                        ?path,
+                        "not following symlink to a directory which is already watched"
+                    );
+                    skipped.push(path);
+                    continue;
+                }
+            } else if file_type.is_dir() {
+                // the directory may already have been seen through a symlink
+                let canonical = std::fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+                if !visited.insert(canonical) {
+                    continue;
+                }
+            } else {
+                continue;
+            }
+
+            visit(&path, None, follow, visited, skipped);
+        }
+    }
+
+    let mut visited = BTreeSet::new();
+    visited.insert(std::fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf()));
+    let mut skipped = Vec::new();
+    visit(dir, Some(ignore_dirs), follow, &mut visited, &mut skipped);
+    skipped
+}
+
+/// The directory containing the path, which is the current directory for bare file names.
+fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(parent) if parent != Path::new("") => parent,
+        _ => Path::new("."),
+    }
+}
+
+/// Make the path absolute and remove any redundant `./` components.
+fn absolute(path: &Path) -> PathBuf {
+    match std::env::current_dir() {
+        Ok(cwd) => cwd.join(path).components().collect(),
+        Err(_) => path.components().collect(),
+    }
+}
+
+/// The canonical form of a path, which may no longer exist. Falls back to its canonical parent
+/// directory, and then to resolving `..` without following symlinks.
+fn canonical(path: &Path) -> PathBuf {
+    if let Ok(path) = std::fs::canonicalize(path) {
+        return path;
+    }
+
+    let path = absolute(path);
+    if let (Some(parent), Some(name)) = (path.parent(), path.file_name()) {
+        if let Ok(parent) = std::fs::canonicalize(parent) {
+            return parent.join(name);
+        }
+    }
+
+    let mut resolved = PathBuf::new();
+    for component in path.components() {
+        match component {
+            std::path::Component::ParentDir => {
+                resolved.pop();
+            }
+            component => resolved.push(component),
+        }
+    }
+    resolved
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn filter_for(root: &Path, flags: &[&str]) -> FileFilter {
+        let root = root.to_str().unwrap();
+        let args = ["witness", "--path", root]
+            .into_iter()
+            .chain(flags.iter().copied())
+            .chain(["true"]);
+        let args = cli::Arguments::parse_from(args);
+        FileFilter::from_args(&args.files).unwrap()
+    }
+
+    fn create_file(path: &Path, contents: &str) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    fn git_repository() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        create_file(&root.join(".git/info/exclude"), "secret.txt\n");
+        create_file(&root.join(".gitignore"), "target/\n*.log\n");
+        create_file(&root.join("src/.gitignore"), "generated.rs\n");
+        dir
+    }
+
+    /// Paths ignored by git are rejected without asking `git`
+    #[test]
+    fn git_ignore_native() {
+        let repo = git_repository();
+        let root = repo.path();
+        let filter = filter_for(root, &[]);
+        let git_ignore = filter.git_ignore.as_ref().unwrap();
+
+        let ignored = [
+            "target/debug/witness",
+            "build.log",
+            "src/build.log",
+            "src/generated.rs",
+            "secret.txt",
+        ];
+        for path in ignored {
+            let path = root.join(path);
+            assert_eq!(git_ignore.is_ignored(&path), Some(true), "{path:?}");
+            assert!(matches!(
+                filter.matches_path(&path),
+                Err(FilterReason::GitIgnore)
+            ));
+        }
+
+        let allowed = ["src/main.rs", "generated.rs", "Cargo.toml"];
+        for path in allowed {
+            let path = root.join(path);
+            assert_eq!(git_ignore.is_ignored(&path), Some(false), "{path:?}");
+            assert!(filter.matches_path(&path).is_ok());
+        }
+    }
+
+    /// Nested ignore files may re-include paths ignored further up
+    #[test]
+    fn git_ignore_nested_whitelist() {
+        let repo = git_repository();
+        let root = repo.path();
+        create_file(&root.join("logs/.gitignore"), "!keep.log\n");
+
+        let filter = filter_for(root, &[]);
+        assert!(filter.matches_path(&root.join("logs/keep.log")).is_ok());
+        assert!(filter.matches_path(&root.join("logs/other.log")).is_err());
+    }
+
+    /// Watching a directory deep within a repository respects the ignore files of its ancestors
+    #[test]
+    fn git_ignore_ancestors() {
+        let repo = git_repository();
+        let root = repo.path();
+        create_file(
+            &root.join(".gitignore"),
+            "*.tmp
+/crates/core/src/out/
+",
+        );
+        create_file(
+            &root.join("crates/.gitignore"),
+            "!keep.tmp
+cache/
+",
+        );
+        create_file(
+            &root.join("crates/core/.gitignore"),
+            "*.bak
+",
+        );
+        let watched = root.join("crates/core/src");
+        std::fs::create_dir_all(&watched).unwrap();
+
+        let filter = filter_for(&watched, &[]);
+        let ignored = [
+            "deep/nested/dir/file.tmp",
+            "out/generated.rs",
+            "deep/cache/entry",
+            "deep/file.bak",
+        ];
+        for path in ignored {
+            let path = watched.join(path);
+            assert!(
+                matches!(filter.matches_path(&path), Err(FilterReason::GitIgnore)),
+                "{path:?}"
+            );
+        }
+
+        // closer ignore files take precedence over those further up
+        let allowed = ["deep/nested/dir/keep.tmp", "deep/nested/dir/lib.rs"];
+        for path in allowed {
+            let path = watched.join(path);
+            assert!(filter.matches_path(&path).is_ok(), "{path:?}");
+        }
+    }
+
+    /// Files within the `.git` directory are always ignored
+    #[test]
+    fn git_ignore_git_dir() {
+        let repo = git_repository();
+        let root = repo.path();
+        let filter = filter_for(root, &[]);
+        assert!(filter.matches_path(&root.join(".git/index")).is_err());
+
+        let filter = filter_for(root, &["--hidden"]);
+        assert!(matches!(
+            filter.matches_path(&root.join(".git/index")),
+            Err(FilterReason::GitDir)
+        ));
+        assert!(filter.matches_path(&root.join(".env")).is_ok());
+    }
+
+    /// Ignore rules are not applied when disabled
+    #[test]
+    fn no_git_ignore() {
+        let repo = git_repository();
+        let root = repo.path();
+        let filter = filter_for(root, &["--no-git-ignore"]);
+        assert!(filter.matches_path(&root.join("build.log")).is_ok());
+    }
+
+    /// Only paths matching an include glob pass
+    #[test]
+    fn glob_include() {
+        let root = Path::new("/project");
+        let filter = filter_for(root, &["--no-git-ignore", "--include", "src/**/*.rs"]);
+        assert!(filter.matches_path(&root.join("src/main.rs")).is_ok());
+        assert!(filter.matches_path(&root.join("src/cli/parse.rs")).is_ok());
+        assert!(matches!(
+            filter.matches_path(&root.join("src/notes.txt")),
+            Err(FilterReason::Glob)
+        ));
+        assert!(filter.matches_path(&root.join("build.rs")).is_err());
+    }
+
+    /// Paths matching an exclude glob are rejected
+    #[test]
+    fn glob_exclude() {
+        let root = Path::new("/project");
+        let filter = filter_for(root, &["--no-git-ignore", "--exclude", "src/generated/*"]);
+        assert!(filter.matches_path(&root.join("src/main.rs")).is_ok());
+        assert!(filter.matches_path(&root.join("README.md")).is_ok());
+        assert!(matches!(
+            filter.matches_path(&root.join("src/generated/bindings.rs")),
+            Err(FilterReason::Glob)
+        ));
+    }
+
+    /// Exclude globs take precedence over include globs
+    #[test]
+    fn glob_include_and_exclude() {
+        let root = Path::new("/project");
+        let filter = filter_for(
+            root,
+            &[
+                "--no-git-ignore",
+                "--include",
+                "src/**/*.rs",
+                "--exclude",
+                "src/generated/*",
+            ],
+        );
+        assert!(filter.matches_path(&root.join("src/main.rs")).is_ok());
+        assert!(filter
+            .matches_path(&root.join("src/generated/bindings.rs"))
+            .is_err());
+        assert!(filter.matches_path(&root.join("Cargo.toml")).is_err());
+    }
+
+    /// With `--path-regex`, only paths matching one of the patterns are accepted
+    #[test]
+    fn path_regex() {
+        let root = Path::new("/project");
+        let filter = filter_for(
+            root,
+            &[
+                "--no-git-ignore",
+                "--path-regex",
+                r"^src/.*_test\.rs$",
+                "--path-regex",
+                "^docs/",
+            ],
+        );
+        assert!(filter.matches_path(&root.join("src/parse_test.rs")).is_ok());
+        assert!(filter
+            .matches_path(&root.join("src/cli/args_test.rs"))
+            .is_ok());
+        assert!(filter.matches_path(&root.join("docs/guide.md")).is_ok());
+        assert!(matches!(
+            filter.matches_path(&root.join("src/parse.rs")),
+            Err(FilterReason::Regex)
+        ));
+        assert!(matches!(
+            filter.matches_path(&root.join("tests/src/parse_test.rs")),
+            Err(FilterReason::Regex)
+        ));
+    }
+
+    /// Regular expressions must pass along with the extension and glob filters
+    #[test]
+    fn path_regex_with_other_filters() {
+        let root = Path::new("/project");
+        let filter = filter_for(
+            root,
+            &[
+                "--no-git-ignore",
+                "--extensions",
+                "rs",
+                "--exclude",
+                "src/generated/*",
+                "--path-regex",
+                "^src/",
+            ],
+        );
+        assert!(filter.matches_path(&root.join("src/main.rs")).is_ok());
+        assert!(matches!(
+            filter.matches_path(&root.join("src/notes.txt")),
+            Err(FilterReason::Extension)
+        ));
+        assert!(matches!(
+            filter.matches_path(&root.join("src/generated/bindings.rs")),
+            Err(FilterReason::Glob)
+        ));
+        assert!(matches!(
+            filter.matches_path(&root.join("build.rs")),
+            Err(FilterReason::Regex)
+        ));
+    }
+
+    /// `--under` and `--not-under` compare directories after resolving `..`
+    #[test]
+    fn path_prefixes() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        for file in ["src/main.rs", "src/gen/out.rs", "docs/guide.md", "build.rs"] {
+            create_file(&root.join(file), "");
+        }
+
+        let under = root.join("docs/../src");
+        let not_under = root.join("src/./gen");
+        let filter = filter_for(
+            root,
+            &[
+                "--no-git-ignore",
+                "--under",
+                under.to_str().unwrap(),
+                "--not-under",
+                not_under.to_str().unwrap(),
+            ],
+        );
+        assert!(filter.matches_path(&root.join("src/main.rs")).is_ok());
+        assert!(filter.matches_path(&root.join("src/removed.rs")).is_ok());
+        assert!(filter
+            .matches_path(&root.join("docs/../src/main.rs"))
+            .is_ok());
+        for ignored in [
+            "src/gen/out.rs",
+            "docs/guide.md",
+            "build.rs",
+            "src/../build.rs",
+        ] {
+            assert!(
+                matches!(
+                    filter.matches_path(&root.join(ignored)),
+                    Err(FilterReason::Prefix)
+                ),
+                "{ignored}"
+            );
+        }
+
+        // a directory named like the prefix is not within it
+        create_file(&root.join("src-old/main.rs"), "");
+        assert!(matches!(
+            filter.matches_path(&root.join("src-old/main.rs")),
+            Err(FilterReason::Prefix)
+        ));
+    }
+
+    /// Prefixes must pass along with the other filters
+    #[test]
+    fn path_prefixes_with_other_filters() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        create_file(&root.join("src/main.rs"), "");
+        create_file(&root.join("src/notes.txt"), "");
+
+        let filter = filter_for(
+            root,
+            &[
+                "--no-git-ignore",
+                "--extensions",
+                "rs",
+                "--not-under",
+                root.join("docs").to_str().unwrap(),
+            ],
+        );
+        assert!(filter.matches_path(&root.join("src/main.rs")).is_ok());
+        assert!(matches!(
+            filter.matches_path(&root.join("src/notes.txt")),
+            Err(FilterReason::Extension)
+        ));
+        assert!(matches!(
+            filter.matches_path(&root.join("docs/api.rs")),
+            Err(FilterReason::Prefix)
+        ));
+    }
+
+    fn write_event(path: &Path) -> notify::RawEvent {
+        event(path, notify::Op::WRITE)
+    }
+
+    fn event(path: &Path, op: notify::Op) -> notify::RawEvent {
+        notify::RawEvent {
+            path: Some(path.to_path_buf()),
+            op: Ok(op),
+            cookie: None,
+        }
+    }
+
+    /// Run the event handler on a stream of events, returning the emitted triggers
+    fn debounce_events(
+        debounce: Duration,
+        events: impl FnOnce(std::sync::mpsc::Sender<notify::RawEvent>) + Send + 'static,
+    ) -> Vec<ExecutionTrigger> {
+        debounce_events_in(cli::DebounceMode::Settle, debounce, events)
+    }
+
+    fn debounce_events_in(
+        mode: cli::DebounceMode,
+        debounce: Duration,
+        events: impl FnOnce(std::sync::mpsc::Sender<notify::RawEvent>) + Send + 'static,
+    ) -> Vec<ExecutionTrigger> {
+        let mode = clap::ArgEnum::to_possible_value(&mode).unwrap().get_name();
+        let debounce = format!("{}ms", debounce.as_millis());
+        debounce_events_with(&["--debounce", &debounce, "--debounce-mode", mode], events)
+    }
+
+    fn debounce_events_with(
+        flags: &[&str],
+        events: impl FnOnce(std::sync::mpsc::Sender<notify::RawEvent>) + Send + 'static,
+    ) -> Vec<ExecutionTrigger> {
+        let root = Path::new("/project");
+        let args = [
+            "witness",
+            "--path",
+            root.to_str().unwrap(),
+            "--no-git-ignore",
+        ];
+        let args = cli::Arguments::parse_from(args.iter().chain(flags).chain(&["true"]));
+        let filter = FileFilter::from_args(&args.files).unwrap();
+        let debounce = Debounce::from_args(&args.files);
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let (triggers, mut trigger_receiver) = tokio::sync::mpsc::channel(16);
+
+        let producer = std::thread::spawn(move || events(sender));
+        let filter = Arc::new(RwLock::new(filter));
+        FileWatcher::handle_events(&receiver, None, filter, debounce, triggers, SystemClock);
+        producer.join().unwrap();
+
+        std::iter::from_fn(|| trigger_receiver.try_recv().ok()).collect()
+    }
+
+    /// With a zero debounce, a change is sent right away as one trigger once the file is closed
+    #[test]
+    fn zero_debounce() {
+        let args = [
+            "witness",
+            "--path",
+            "/project",
+            "--no-git-ignore",
+            "--debounce",
+            "0ms",
+        ];
+        let args = cli::Arguments::parse_from(args.iter().chain(&["true"]));
+        let filter = Arc::new(RwLock::new(FileFilter::from_args(&args.files).unwrap()));
+        let debounce = Debounce::from_args(&args.files);
+        assert!(debounce.is_immediate());
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let (triggers, mut trigger_receiver) = tokio::sync::mpsc::channel(16);
+
+        let main = Path::new("/project/src/main.rs");
+        let lib = Path::new("/project/src/lib.rs");
+        let producer = std::thread::spawn(move || {
+            // the events of a single write don't necessarily arrive together
+            sender.send(event(main, notify::Op::CREATE)).unwrap();
+            std::thread::sleep(Duration::from_millis(20));
+            sender.send(event(main, notify::Op::WRITE)).unwrap();
+            sender.send(event(main, notify::Op::CLOSE_WRITE)).unwrap();
+
+            std::thread::sleep(Duration::from_millis(100));
+            sender.send(event(lib, notify::Op::CHMOD)).unwrap();
+            std::thread::sleep(Duration::from_millis(100));
+        });
+
+        let start = Instant::now();
+        let handler = std::thread::spawn(move || {
+            FileWatcher::handle_events(&receiver, None, filter, debounce, triggers, SystemClock)
+        });
+
+        let first = trigger_receiver.blocking_recv().unwrap();
+        assert!(start.elapsed() < Duration::from_millis(100));
+        assert_eq!(first.paths, [main]);
+        assert_eq!(first.events, 2);
+
+        // nothing waits for a change of permissions to be closed
+        let second = trigger_receiver.blocking_recv().unwrap();
+        assert!(start.elapsed() < Duration::from_millis(200));
+        assert_eq!(second.paths, [lib]);
+
+        producer.join().unwrap();
+        handler.join().unwrap();
+        assert!(trigger_receiver.try_recv().is_err());
+    }
+
+    /// Files which are never closed are sent eventually
+    #[test]
+    fn zero_debounce_unclosed() {
+        let triggers = debounce_events(Duration::ZERO, |sender| {
+            let log = Path::new("/project/build.log");
+            sender.send(event(log, notify::Op::WRITE)).unwrap();
+            std::thread::sleep(CLOSE_TIMEOUT * 2);
+        });
+        assert_eq!(triggers.len(), 1);
+    }
+
+    /// A burst of events across many files results in a single trigger
+    #[test]
+    fn debounce_burst() {
+        let triggers = debounce_events(Duration::from_millis(50), |sender| {
+            for i in 0..50 {
+                let path = PathBuf::from(format!("/project/src/file{i}.rs"));
+                sender.send(write_event(&path)).unwrap();
+            }
+        });
+        assert_eq!(triggers.len(), 1);
+        assert_eq!(triggers[0].paths.len(), 50);
+        assert_eq!(
+            triggers[0].paths.last().map(PathBuf::as_path),
+            Some(Path::new("/project/src/file49.rs"))
+        );
+    }
+
+    /// Events arriving within the window keep extending it
+    #[test]
+    fn debounce_extends_window() {
+        let triggers = debounce_events(Duration::from_millis(100), |sender| {
+            for _ in 0..5 {
+                sender
+                    .send(write_event(Path::new("/project/a.rs")))
+                    .unwrap();
+                std::thread::sleep(Duration::from_millis(40));
+            }
+        });
+        assert_eq!(triggers.len(), 1);
+    }
+
+    /// Paths are deduplicated in the order they were first modified
+    #[test]
+    fn debounce_deduplicate_paths() {
+        let triggers = debounce_events(Duration::from_millis(50), |sender| {
+            for path in ["/project/b.rs", "/project/a.rs", "/project/b.rs"] {
+                sender.send(write_event(Path::new(path))).unwrap();
+            }
+        });
+        assert_eq!(triggers.len(), 1);
+        assert_eq!(
+            triggers[0].paths,
+            [
+                PathBuf::from("/project/b.rs"),
+                PathBuf::from("/project/a.rs")
+            ]
+        );
+        assert_eq!(triggers[0].events, 3);
+        assert_eq!(triggers[0].source, Some(TriggerSource::File));
+    }
+
+    /// Bursts separated by a quiet period trigger separately
+    #[test]
+    fn debounce_separate_bursts() {
+        let triggers = debounce_events(Duration::from_millis(20), |sender| {
+            sender
+                .send(write_event(Path::new("/project/a.rs")))
+                .unwrap();
+            sender
+                .send(write_event(Path::new("/project/b.rs")))
+                .unwrap();
+            std::thread::sleep(Duration::from_millis(200));
+            sender
+                .send(write_event(Path::new("/project/c.rs")))
+                .unwrap();
+        });
+        assert_eq!(triggers.len(), 2);
+    }
+
+    /// Both `.ignore` and `.witnessignore` files are respected
+    #[test]
+    fn ignore_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        create_file(&root.join(".ignore"), "*.tmp\n");
+        create_file(&root.join(".witnessignore"), "dist/\n!keep.tmp\n");
+
+        let filter = filter_for(root, &["--no-git-ignore"]);
+        assert!(filter.matches_path(&root.join("src/main.rs")).is_ok());
+        assert!(matches!(
+            filter.matches_path(&root.join("scratch.tmp")),
+            Err(FilterReason::IgnoreFile(file)) if file.ends_with(".ignore")
+        ));
+        assert!(matches!(
+            filter.matches_path(&root.join("dist/index.js")),
+            Err(FilterReason::IgnoreFile(file)) if file.ends_with(".witnessignore")
+        ));
+
+        // `.witnessignore` takes precedence over `.ignore`
+        assert!(filter.matches_path(&root.join("keep.tmp")).is_ok());
+    }
+
+    /// `--no-ignore` disables all ignore files, while `--no-git-ignore` only disables git
+    #[test]
+    fn no_ignore() {
+        let repo = git_repository();
+        let root = repo.path();
+        create_file(&root.join(".ignore"), "*.tmp\n");
+
+        let filter = filter_for(root, &["--no-git-ignore"]);
+        assert!(filter.matches_path(&root.join("build.log")).is_ok());
+        assert!(filter.matches_path(&root.join("scratch.tmp")).is_err());
+
+        let filter = filter_for(root, &["--no-ignore"]);
+        assert!(filter.matches_path(&root.join("build.log")).is_ok());
+        assert!(filter.matches_path(&root.join("scratch.tmp")).is_ok());
+    }
+
+    /// Only the watched file is accepted from its parent directory
+    #[test]
+    fn watched_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        create_file(&root.join("Cargo.toml"), "");
+        create_file(&root.join("src/main.rs"), "");
+
+        let file = root.join("Cargo.toml");
+        let filter = filter_for(&file, &["--no-git-ignore"]);
+        assert!(filter.matches_path(&file).is_ok());
+        assert!(matches!(
+            filter.matches_path(&root.join("Cargo.toml.tmp")),
+            Err(FilterReason::NotWatchedFile)
+        ));
+        assert!(matches!(
+            filter.matches_path(&root.join("Cargo.lock")),
+            Err(FilterReason::NotWatchedFile)
+        ));
+    }
+
+    /// Watching a file next to a directory keeps accepting everything within the directory
+    #[test]
+    fn watched_file_and_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        create_file(&root.join("Cargo.toml"), "");
+        create_file(&root.join("src/main.rs"), "");
+
+        let file = root.join("Cargo.toml");
+        let src = root.join("src");
+        let filter = filter_for(&file, &["--no-git-ignore", "--path", src.to_str().unwrap()]);
+        assert!(filter.matches_path(&file).is_ok());
+        assert!(filter.matches_path(&src.join("main.rs")).is_ok());
+        assert!(filter.matches_path(&root.join("Cargo.lock")).is_err());
+    }
+
+    /// Paths nested below the maximum depth are rejected
+    #[test]
+    fn max_depth() {
+        let root = Path::new("/project");
+        let filter = filter_for(root, &["--no-git-ignore", "--max-depth", "2"]);
+        assert!(filter.matches_path(&root.join("Cargo.toml")).is_ok());
+        assert!(filter.matches_path(&root.join("src/main.rs")).is_ok());
+        assert!(matches!(
+            filter.matches_path(&root.join("src/cli/parse.rs")),
+            Err(FilterReason::TooDeep)
+        ));
+        assert!(matches!(
+            filter.matches_path(&root.join("node_modules/a/b/c/index.js")),
+            Err(FilterReason::TooDeep)
+        ));
+
+        let filter = filter_for(root, &["--no-git-ignore", "--max-depth", "0"]);
+        assert!(filter.matches_path(&root.join("Cargo.toml")).is_err());
+    }
+
+    /// Depth is measured from the nearest watched path
+    #[test]
+    fn max_depth_nearest_root() {
+        let root = Path::new("/project");
+        let filter = filter_for(
+            root,
+            &[
+                "--no-git-ignore",
+                "--max-depth",
+                "1",
+                "--path",
+                "/project/src/cli",
+            ],
+        );
+        assert!(filter.matches_path(&root.join("build.rs")).is_ok());
+        assert!(filter.matches_path(&root.join("src/main.rs")).is_err());
+        assert!(filter.matches_path(&root.join("src/cli/parse.rs")).is_ok());
+    }
+
+    /// Hidden files and directories are ignored by default
+    #[test]
+    fn hidden_files() {
+        let root = Path::new("/project");
+        let filter = filter_for(root, &["--no-git-ignore"]);
+        let hidden = [
+            ".git/index",
+            ".main.rs.swp",
+            ".DS_Store",
+            ".idea/workspace.xml",
+        ];
+        for path in hidden {
+            assert!(
+                matches!(
+                    filter.matches_path(&root.join(path)),
+                    Err(FilterReason::Hidden)
+                ),
+                "{path}"
+            );
+        }
+        assert!(filter.matches_path(&root.join("src/main.rs")).is_ok());
+        assert!(filter
+            .matches_path(&root.join("src/.hidden/main.rs"))
+            .is_err());
+
+        // `.git` is also left out by `--ignore-dir` unless told otherwise
+        let filter = filter_for(root, &["--no-git-ignore", "--hidden", "--ignore-dir="]);
+        for path in hidden {
+            assert!(filter.matches_path(&root.join(path)).is_ok(), "{path}");
+        }
+
+        let filter = filter_for(root, &["--no-git-ignore", "--hidden", "--no-hidden"]);
+        assert!(filter.matches_path(&root.join(".DS_Store")).is_err());
+    }
+
+    /// A hidden watched path does not hide its contents
+    #[test]
+    fn hidden_root() {
+        let root = Path::new("/project/.config");
+        let filter = filter_for(root, &["--no-git-ignore"]);
+        assert!(filter.matches_path(&root.join("settings.toml")).is_ok());
+        assert!(filter.matches_path(&root.join(".cache")).is_err());
+    }
+
+    /// Send a write event for each path, pausing between them
+    fn steady_events(
+        paths: &'static [&'static str],
+        pause: Duration,
+    ) -> impl FnOnce(std::sync::mpsc::Sender<notify::RawEvent>) + Send + 'static {
+        move |sender| {
+            for path in paths {
+                sender.send(write_event(Path::new(path))).unwrap();
+                std::thread::sleep(pause);
+            }
+        }
+    }
+
+    const STEADY_PATHS: &[&str] = &[
+        "/project/a.rs",
+        "/project/b.rs",
+        "/project/c.rs",
+        "/project/d.rs",
+        "/project/e.rs",
+        "/project/f.rs",
+    ];
+
+    /// Settling waits for the steady stream of events to stop
+    #[test]
+    fn debounce_mode_settle() {
+        let triggers = debounce_events_in(
+            cli::DebounceMode::Settle,
+            Duration::from_millis(150),
+            steady_events(STEADY_PATHS, Duration::from_millis(50)),
+        );
+        assert_eq!(triggers.len(), 1);
+        assert_eq!(triggers[0].paths.len(), STEADY_PATHS.len());
+    }
+
+    /// A fixed window splits a long stream of events
+    #[test]
+    fn debounce_mode_trailing() {
+        let triggers = debounce_events_in(
+            cli::DebounceMode::Trailing,
+            Duration::from_millis(150),
+            steady_events(STEADY_PATHS, Duration::from_millis(50)),
+        );
+        assert!(triggers.len() >= 2, "{triggers:?}");
+        assert_eq!(triggers[0].paths[0], Path::new("/project/a.rs"));
+        let total = triggers
+            .iter()
+            .map(|trigger| trigger.paths.len())
+            .sum::<usize>();
+        assert_eq!(total, STEADY_PATHS.len());
+    }
+
+    /// Only the first event of a burst triggers
+    #[test]
+    fn debounce_mode_leading() {
+        let triggers = debounce_events_in(
+            cli::DebounceMode::Leading,
+            Duration::from_millis(100),
+            |sender| {
+                for path in ["/project/a.rs", "/project/b.rs", "/project/c.rs"] {
+                    sender.send(write_event(Path::new(path))).unwrap();
+                }
+                std::thread::sleep(Duration::from_millis(300));
+                sender
+                    .send(write_event(Path::new("/project/d.rs")))
+                    .unwrap();
+            },
+        );
+        assert_eq!(triggers.len(), 2);
+        assert_eq!(triggers[0].paths, [PathBuf::from("/project/a.rs")]);
+        assert_eq!(triggers[1].paths, [PathBuf::from("/project/d.rs")]);
+    }
+
+    /// Extensions are case-sensitive unless requested otherwise
+    #[test]
+    fn extension_case() {
+        let root = Path::new("/project");
+        let filter = filter_for(root, &["--no-git-ignore", "-e", "rs,JPG"]);
+        assert!(filter.matches_path(&root.join("main.rs")).is_ok());
+        assert!(filter.matches_path(&root.join("photo.JPG")).is_ok());
+        assert!(matches!(
+            filter.matches_path(&root.join("MAIN.RS")),
+            Err(FilterReason::Extension)
+        ));
+        assert!(filter.matches_path(&root.join("photo.jpg")).is_err());
+
+        let filter = filter_for(
+            root,
+            &["--no-git-ignore", "-e", "rs,JPG", "--ignore-case-ext"],
+        );
+        for path in [
+            "main.rs",
+            "MAIN.RS",
+            "lib.Rs",
+            "photo.jpg",
+            "photo.JPG",
+            "photo.jPg",
+        ] {
+            assert!(filter.matches_path(&root.join(path)).is_ok(), "{path}");
+        }
+        assert!(filter.matches_path(&root.join("notes.txt")).is_err());
+    }
+
+    /// Extensions which are not valid Unicode are folded byte-wise
+    #[cfg(unix)]
+    #[test]
+    fn extension_case_non_unicode() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let ext = OsStr::from_bytes(b"D\xffT");
+        assert_eq!(fold_case(ext), OsStr::from_bytes(b"d\xfft"));
+
+        let root = Path::new("/project");
+        let filter = filter_for(root, &["--no-git-ignore", "-e", "dat", "--ignore-case-ext"]);
+        let path = root.join(OsStr::from_bytes(b"file\xff.DAT"));
+        assert!(filter.matches_path(&path).is_ok());
+        let path = root.join(OsStr::from_bytes(b"file.D\xffT"));
+        assert!(filter.matches_path(&path).is_err());
+    }
+
+    #[test]
+    fn extensions_include_only() {
+        let root = Path::new("/project");
+        let filter = filter_for(root, &["--no-git-ignore", "-e", "rs"]);
+        assert!(filter.matches_path(&root.join("main.rs")).is_ok());
+        assert!(matches!(
+            filter.matches_path(&root.join("Cargo.lock")),
+            Err(FilterReason::Extension)
+        ));
+    }
+
+    #[test]
+    fn extensions_per_root() {
+        let root = Path::new("/project");
+        let frontend = format!("{}:ts,tsx", root.join("frontend").display());
+        let backend = format!("{}:rs", root.join("backend").display());
+        let filter = filter_for(
+            root,
+            &[
+                "--no-git-ignore",
+                "-e",
+                "md",
+                "--path",
+                &frontend,
+                "--path",
+                &backend,
+            ],
+        );
+
+        assert!(filter.matches_path(&root.join("frontend/app.tsx")).is_ok());
+        assert!(filter
+            .matches_path(&root.join("frontend/lib/api.ts"))
+            .is_ok());
+        assert!(filter.matches_path(&root.join("backend/main.rs")).is_ok());
+        for rejected in ["frontend/main.rs", "backend/app.ts", "backend/README.md"] {
+            assert!(
+                matches!(
+                    filter.matches_path(&root.join(rejected)),
+                    Err(FilterReason::RootExtension)
+                ),
+                "{rejected}"
+            );
+        }
+
+        // paths outside both fall back to `--extensions`
+        assert!(filter.matches_path(&root.join("README.md")).is_ok());
+        assert!(filter.matches_path(&root.join("build.rs")).is_err());
+    }
+
+    #[test]
+    fn extensions_per_root_glob() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("crates/core/src")).unwrap();
+        std::fs::create_dir_all(root.join("web")).unwrap();
+
+        let crates = format!("{}:rs", root.join("crates/*/src").display());
+        let web = root.join("web");
+        let mut args = cli::Arguments::parse_from([
+            "witness",
+            "--no-git-ignore",
+            "--path",
+            &crates,
+            "--path",
+            web.to_str().unwrap(),
+            "true",
+        ]);
+        args.files.paths.paths = expand_globs(&args.files.paths).unwrap();
+        let filter = FileFilter::from_args(&args.files).unwrap();
+
+        assert!(filter
+            .matches_path(&root.join("crates/core/src/lib.rs"))
+            .is_ok());
+        assert!(filter
+            .matches_path(&root.join("crates/core/src/notes.txt"))
+            .is_err());
+        assert!(filter.matches_path(&root.join("web/notes.txt")).is_ok());
+    }
+
+    #[test]
+    fn extensions_exclude_only() {
+        let root = Path::new("/project");
+        let filter = filter_for(
+            root,
+            &["--no-git-ignore", "--exclude-extensions", "lock,log"],
+        );
+        assert!(filter.matches_path(&root.join("main.rs")).is_ok());
+        assert!(filter.matches_path(&root.join("Makefile")).is_ok());
+        assert!(matches!(
+            filter.matches_path(&root.join("Cargo.lock")),
+            Err(FilterReason::ExcludedExtension)
+        ));
+        assert!(matches!(
+            filter.matches_path(&root.join("logs/build.log")),
+            Err(FilterReason::ExcludedExtension)
+        ));
+    }
+
+    /// Extensions with several parts are matched against the end of the file name
+    #[test]
+    fn extensions_multiple_parts() {
+        let root = Path::new("/project");
+        let filter = filter_for(root, &["--no-git-ignore", "-e", "tar.gz,d.ts,rs"]);
+        for path in ["archive.tar.gz", "index.d.ts", "src/main.rs", "v1.2.tar.gz"] {
+            assert!(filter.matches_path(&root.join(path)).is_ok(), "{path}");
+        }
+        for path in ["notes.gz", "index.ts", "archive.xtar.gz", "tar.gz", "d.ts"] {
+            assert!(
+                matches!(
+                    filter.matches_path(&root.join(path)),
+                    Err(FilterReason::Extension)
+                ),
+                "{path}"
+            );
+        }
+
+        // a single part still matches the last extension, so `gz` means any gzip
+        let filter = filter_for(root, &["--no-git-ignore", "-e", "gz"]);
+        assert!(filter.matches_path(&root.join("archive.tar.gz")).is_ok());
+        assert!(filter.matches_path(&root.join("notes.gz")).is_ok());
+
+        let filter = filter_for(
+            root,
+            &[
+                "--no-git-ignore",
+                "--exclude-extensions",
+                "d.ts",
+                "-e",
+                "ts",
+            ],
+        );
+        assert!(filter.matches_path(&root.join("index.ts")).is_ok());
+        assert!(matches!(
+            filter.matches_path(&root.join("index.d.ts")),
+            Err(FilterReason::ExcludedExtension)
+        ));
+
+        let filter = filter_for(
+            root,
+            &["--no-git-ignore", "-e", "TAR.GZ", "--ignore-case-ext"],
+        );
+        assert!(filter.matches_path(&root.join("Archive.Tar.Gz")).is_ok());
+    }
+
+    /// Excluded extensions win over included ones
+    #[test]
+    fn extensions_include_and_exclude() {
+        let root = Path::new("/project");
+        let filter = filter_for(
+            root,
+            &[
+                "--no-git-ignore",
+                "-e",
+                "rs,lock",
+                "--exclude-extensions",
+                "lock",
+            ],
+        );
+        assert!(filter.matches_path(&root.join("main.rs")).is_ok());
+        assert!(matches!(
+            filter.matches_path(&root.join("Cargo.lock")),
+            Err(FilterReason::ExcludedExtension)
+        ));
+        assert!(filter.matches_path(&root.join("notes.txt")).is_err());
+
+        let filter = filter_for(
+            root,
+            &[
+                "--no-git-ignore",
+                "--exclude-extensions",
+                "LOCK",
+                "--ignore-case-ext",
+            ],
+        );
+        assert!(filter.matches_path(&root.join("Cargo.lock")).is_err());
+    }
+
+    /// Each debounce group has its own window, and its triggers are tagged with its name
+    #[test]
+    fn debounce_groups() {
+        let flags = [
+            "--debounce",
+            "50ms",
+            "--debounce-group",
+            "assets:png,svg:400ms",
+        ];
+        let triggers = debounce_events_with(&flags, |sender| {
+            for path in ["/project/logo.png", "/project/a.rs", "/project/icon.svg"] {
+                sender.send(write_event(Path::new(path))).unwrap();
+                std::thread::sleep(Duration::from_millis(20));
+            }
+            // the rust window has closed by now, while the assets window is still open
+            std::thread::sleep(Duration::from_millis(150));
+            sender
+                .send(write_event(Path::new("/project/b.rs")))
+                .unwrap();
+            std::thread::sleep(Duration::from_millis(150));
+            sender
+                .send(write_event(Path::new("/project/banner.png")))
+                .unwrap();
+            std::thread::sleep(Duration::from_millis(600));
+        });
+
+        let summary = triggers
+            .iter()
+            .map(|trigger| (trigger.group.as_deref(), trigger.paths.len()))
+            .collect::<Vec<_>>();
+        assert_eq!(summary, [(None, 1), (None, 1), (Some("assets"), 3)]);
+        assert_eq!(triggers[0].paths, [PathBuf::from("/project/a.rs")]);
+        assert_eq!(triggers[1].paths, [PathBuf::from("/project/b.rs")]);
+        assert_eq!(triggers[2].paths[2], PathBuf::from("/project/banner.png"));
+    }
+
+    /// Grouped extensions are accepted even if they are not part of `--extensions`
+    #[test]
+    fn debounce_group_extensions() {
+        let root = Path::new("/project");
+        let flags = [
+            "--no-git-ignore",
+            "-e",
+            "rs",
+            "--debounce-group",
+            "assets:png:1s",
+        ];
+        let filter = filter_for(root, &flags);
+        assert!(filter.matches_path(&root.join("main.rs")).is_ok());
+        assert!(filter.matches_path(&root.join("logo.png")).is_ok());
+        assert!(filter.matches_path(&root.join("notes.txt")).is_err());
+    }
+
+    #[test]
+    fn glob_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_path_buf();
+        create_file(&root.join("crates/app/src/main.rs"), "");
+        create_file(&root.join("crates/lib/src/lib.rs"), "");
+        create_file(&root.join("crates/docs/index.md"), "");
+        create_file(&root.join("crates/README.md"), "");
+
+        let expand = |path: PathBuf| expand_globs(&[path]).unwrap();
+        assert_eq!(
+            expand(root.join("crates/*/src")),
+            [root.join("crates/app/src"), root.join("crates/lib/src")]
+        );
+
+        // files are watched as well as directories
+        assert_eq!(
+            expand(root.join("crates/*")),
+            [
+                root.join("crates/README.md"),
+                root.join("crates/app"),
+                root.join("crates/docs"),
+                root.join("crates/lib"),
+            ]
+        );
+
+        assert!(expand(root.join("crates/*/tests")).is_empty());
+
+        // paths without a pattern are kept, even if they don't exist
+        assert_eq!(expand(root.join("missing")), [root.join("missing")]);
+
+        assert!(expand_globs(&[root.join("crates/[")]).is_err());
+    }
+
+    #[test]
+    fn minimal_nested_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_path_buf();
+        create_file(&root.join("src/main.rs"), "");
+        create_file(&root.join("docs/index.md"), "");
+
+        let paths = [
+            root.join("src"),
+            root.clone(),
+            root.join("src/main.rs"),
+            root.join("docs"),
+        ];
+        assert_eq!(minimal_paths(&paths), [&root]);
+
+        // siblings are both needed
+        let paths = [root.join("src"), root.join("docs")];
+        assert_eq!(minimal_paths(&paths), [&paths[0], &paths[1]]);
+
+        // a directory with a similar name is not nested
+        create_file(&root.join("src-old/lib.rs"), "");
+        let paths = [root.join("src"), root.join("src-old")];
+        assert_eq!(minimal_paths(&paths).len(), 2);
+    }
+
+    #[test]
+    fn minimal_duplicate_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_path_buf();
+        create_file(&root.join("src/main.rs"), "");
+
+        let paths = [
+            root.join("src"),
+            root.join("./src"),
+            root.join("src/../src"),
+        ];
+        assert_eq!(minimal_paths(&paths), [&paths[0]]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn minimal_symlinked_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_path_buf();
+        create_file(&root.join("src/main.rs"), "");
+        std::os::unix::fs::symlink(root.join("src"), root.join("link")).unwrap();
+
+        // the same directory through a symlink
+        let paths = [root.join("src"), root.join("link")];
+        assert_eq!(minimal_paths(&paths), [&paths[0]]);
+
+        // a loop cannot be resolved, but is kept instead of failing
+        std::os::unix::fs::symlink(root.join("loop"), root.join("loop")).unwrap();
+        let paths = [root.join("loop"), root.join("src")];
+        assert_eq!(minimal_paths(&paths), [&paths[0], &paths[1]]);
+    }
+
+    /// A thread which keeps panicking is restarted once, then reported as failed
+    #[test]
+    fn event_thread_fails() {
+        use std::sync::atomic::{AtomicU32, Ordering};
+
+        let (_events, receiver) = std::sync::mpsc::channel();
+        let (failed, mut failures) = tokio::sync::mpsc::unbounded_channel();
+        let attempts = Arc::new(AtomicU32::new(0));
+        let counter = attempts.clone();
+        spawn_event_thread(receiver, failed, move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            panic!("simulated failure");
+        });
+
+        let error = failures.blocking_recv().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "file watcher stopped after panicking: simulated failure"
+        );
+        assert_eq!(attempts.load(Ordering::SeqCst), EVENT_THREAD_RESTARTS + 1);
+    }
+
+    /// After a restart the thread picks up the events which are still waiting
+    #[test]
+    fn event_thread_restarts() {
+        let (events, receiver) = std::sync::mpsc::channel();
+        let (failed, mut failures) = tokio::sync::mpsc::unbounded_channel();
+        let (handled, handled_paths) = std::sync::mpsc::channel();
+        let panicked = std::sync::atomic::AtomicBool::new(false);
+        spawn_event_thread(receiver, failed, move |receiver| {
+            if !panicked.swap(true, std::sync::atomic::Ordering::SeqCst) {
+                panic!("simulated failure");
+            }
+            for event in receiver.iter() {
+                handled.send(event.path).unwrap();
+            }
+        });
+
+        events
+            .send(write_event(Path::new("/project/a.rs")))
+            .unwrap();
+        drop(events);
+        assert_eq!(
+            handled_paths.recv().unwrap(),
+            Some(PathBuf::from("/project/a.rs"))
+        );
+        // the thread finished without failing
+        assert!(failures.blocking_recv().is_none());
+    }
+
+    /// Debounce windows driven by a clock which only moves when told to
+    struct MockWindows {
+        clock: clock::MockClock,
+        windows: Windows<clock::MockClock>,
+        debounce: Debounce,
+        triggers: Sender<ExecutionTrigger>,
+        received: tokio::sync::mpsc::Receiver<ExecutionTrigger>,
+    }
+
+    impl MockWindows {
+        fn new(flags: &[&str]) -> MockWindows {
+            let args = ["witness", "--no-git-ignore"]
+                .iter()
+                .chain(flags)
+                .chain(&["true"]);
+            let args = cli::Arguments::parse_from(args);
+            let clock = clock::MockClock::new();
+            let (triggers, received) = tokio::sync::mpsc::channel(16);
+            MockWindows {
+                windows: Windows::new(clock.clone()),
+                clock,
+                debounce: Debounce::from_args(&args.files),
+                triggers,
+                received,
+            }
+        }
+
+        fn change(&mut self, path: &str) {
+            let path = PathBuf::from(path);
+            let (debounce, triggers) = (&mut self.debounce, &self.triggers);
+            self.windows
+                .insert(debounce, triggers, path, EventKind::Modified);
+        }
+
+        /// Move the clock forward, and return the paths of the triggers sent by then
+        fn advance(&mut self, millis: u64) -> Vec<Vec<PathBuf>> {
+            self.clock.advance(Duration::from_millis(millis));
+            self.windows
+                .close_expired(&mut self.debounce, &self.triggers);
+            std::iter::from_fn(|| self.received.try_recv().ok())
+                .map(|trigger| trigger.paths)
+                .collect()
+        }
+    }
+
+    fn paths(paths: &[&str]) -> Vec<Vec<PathBuf>> {
+        vec![paths.iter().map(PathBuf::from).collect()]
+    }
+
+    /// The window closes exactly once the debounce has passed since the first change
+    #[test]
+    fn trailing_window_boundary() {
+        let mut mock = MockWindows::new(&["--debounce", "100ms", "--debounce-mode", "trailing"]);
+        mock.change("/project/a.rs");
+        assert!(mock.advance(60).is_empty());
+        mock.change("/project/b.rs");
+        assert_eq!(
+            mock.windows.until_next_deadline(),
+            Some(Duration::from_millis(40))
+        );
+        assert!(mock.advance(39).is_empty());
+        assert_eq!(mock.advance(1), paths(&["/project/a.rs", "/project/b.rs"]));
+        assert!(mock.windows.until_next_deadline().is_none());
+    }
+
+    /// Every change extends the window until the files settle
+    #[test]
+    fn settle_window_boundary() {
+        let mut mock = MockWindows::new(&["--debounce", "100ms", "--debounce-mode", "settle"]);
+        mock.change("/project/a.rs");
+        assert!(mock.advance(99).is_empty());
+        mock.change("/project/a.rs");
+        assert!(mock.advance(99).is_empty());
+        assert_eq!(mock.advance(1), paths(&["/project/a.rs"]));
+    }
+
+    /// The first change is sent right away, and the rest of the window is skipped
+    #[test]
+    fn leading_window_boundary() {
+        let mut mock = MockWindows::new(&["--debounce", "100ms", "--debounce-mode", "leading"]);
+        mock.change("/project/a.rs");
+        assert_eq!(mock.advance(0), paths(&["/project/a.rs"]));
+        assert!(mock.advance(50).is_empty());
+        mock.change("/project/b.rs");
+        assert!(mock.advance(49).is_empty());
+
+        // the window closes without sending the skipped change
+        assert!(mock.advance(1).is_empty());
+        mock.change("/project/c.rs");
+        assert_eq!(mock.advance(0), paths(&["/project/c.rs"]));
+    }
+
+    /// Each group closes its window on its own
+    #[test]
+    fn group_window_boundaries() {
+        let mut mock =
+            MockWindows::new(&["--debounce", "100ms", "--debounce-group", "docs:md:300ms"]);
+        mock.change("/project/README.md");
+        mock.change("/project/a.rs");
+        assert_eq!(mock.advance(100), paths(&["/project/a.rs"]));
+        assert!(mock.advance(199).is_empty());
+        assert_eq!(mock.advance(1), paths(&["/project/README.md"]));
+    }
+
+    fn adaptive(base: u64, max: u64) -> Adaptive {
+        Adaptive {
+            base: Duration::from_millis(base),
+            max: Duration::from_millis(max),
+            last: None,
+        }
+    }
+
+    /// Changes arriving just after the window closed lengthen it up to the maximum
+    #[test]
+    fn adaptive_bursty() {
+        let mut adaptive = adaptive(20, 160);
+        let mut now = Instant::now();
+        let mut window = adaptive.next(Duration::from_millis(20), now);
+
+        let mut windows = Vec::new();
+        for _ in 0..6 {
+            now += window * 3 / 2;
+            window = adaptive.next(window, now);
+            windows.push(window.as_millis());
+        }
+        assert_eq!(windows, [40, 80, 160, 160, 160, 160]);
+
+        // changes within the window don't affect it
+        now += Duration::from_millis(10);
+        assert_eq!(adaptive.next(window, now), window);
+    }
+
+    /// Quiet periods shorten the window back to the base, but never below it
+    #[test]
+    fn adaptive_steady() {
+        let mut adaptive = adaptive(20, 160);
+        let mut now = Instant::now();
+        adaptive.next(Duration::from_millis(160), now);
+
+        now += Duration::from_millis(160 * QUIET_WINDOWS as u64);
+        let window = adaptive.next(Duration::from_millis(160), now);
+        assert_eq!(window, Duration::from_millis(80));
+
+        now += Duration::from_secs(10);
+        let window = adaptive.next(window, now);
+        assert_eq!(window, Duration::from_millis(20));
+
+        now += Duration::from_secs(10);
+        assert_eq!(adaptive.next(window, now), Duration::from_millis(20));
+    }
+
+    /// A steady stream of changes just outside the window is combined once the window adapts
+    #[test]
+    fn debounce_adaptive() {
+        let events = |sender: std::sync::mpsc::Sender<notify::RawEvent>| {
+            for _ in 0..10 {
+                sender
+                    .send(write_event(Path::new("/project/a.rs")))
+                    .unwrap();
+                std::thread::sleep(Duration::from_millis(40));
+            }
+        };
+
+        let fixed = debounce_events_with(&["--debounce", "20ms"], events);
+        assert!(fixed.len() >= 8, "{}", fixed.len());
+
+        let flags = [
+            "--debounce",
+            "20ms",
+            "--debounce-adaptive",
+            "--debounce-max",
+            "1s",
+        ];
+        let adaptive = debounce_events_with(&flags, events);
+        assert!(adaptive.len() <= 4, "{}", adaptive.len());
+    }
+
+    /// Collect the paths of the events received within the duration
+    fn received_paths(
+        receiver: &std::sync::mpsc::Receiver<notify::RawEvent>,
+        duration: Duration,
+        mut on_event: impl FnMut(&notify::RawEvent),
+    ) -> Vec<PathBuf> {
+        let deadline = Instant::now() + duration;
+        let mut paths = Vec::new();
+        while let Ok(event) =
+            receiver.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+        {
+            on_event(&event);
+            paths.extend(event.path);
+        }
+        paths
+    }
+
+    /// Events within ignored directories are never reported by the OS
+    #[test]
+    fn ignore_dirs_not_watched() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("target/debug")).unwrap();
+        std::fs::create_dir_all(root.join("src")).unwrap();
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut backend = Backend::new(None, &["target".into()], sender).unwrap();
+        backend.watch_path(root).unwrap();
+
+        create_file(&root.join("target/debug/witness"), "");
+        create_file(&root.join("src/main.rs"), "");
+        create_file(&root.join("Cargo.toml"), "");
+
+        let paths = received_paths(&receiver, Duration::from_millis(300), |_| {});
+        assert!(
+            paths
+                .iter()
+                .all(|path| !path.starts_with(root.join("target"))),
+            "{paths:?}"
+        );
+        assert!(paths.contains(&root.join("src/main.rs")), "{paths:?}");
+        assert!(paths.contains(&root.join("Cargo.toml")), "{paths:?}");
+    }
+
+    /// Directories created next to an ignored directory are watched as well
+    #[test]
+    fn ignore_dirs_new_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("node_modules")).unwrap();
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut backend = Backend::new(None, &["node_modules".into()], sender).unwrap();
+        backend.watch_path(root).unwrap();
+
+        std::fs::create_dir(root.join("lib")).unwrap();
+        received_paths(&receiver, Duration::from_millis(200), |event| {
+            backend.directory_created(event)
+        });
+
+        create_file(&root.join("lib/index.js"), "");
+        create_file(&root.join("node_modules/index.js"), "");
+        let paths = received_paths(&receiver, Duration::from_millis(300), |_| {});
+        assert!(paths.contains(&root.join("lib/index.js")), "{paths:?}");
+        assert!(
+            !paths.contains(&root.join("node_modules/index.js")),
+            "{paths:?}"
+        );
+    }
+
+    /// Symlinks leading back up the tree are skipped even when following symlinks
+    #[test]
+    #[cfg(unix)]
+    fn symlink_loops() {
+        use std::os::unix::fs::symlink;
+
+        let dir = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("src/lib")).unwrap();
+        std::fs::create_dir_all(root.join("target")).unwrap();
+        symlink(root, root.join("src/lib/loop")).unwrap();
+        symlink(root, root.join("target/loop")).unwrap();
+        symlink(outside.path(), root.join("external")).unwrap();
+        symlink(root, outside.path().join("back")).unwrap();
+        symlink(root.join("missing"), root.join("broken")).unwrap();
+
+        let ignore_dirs = BTreeSet::from([OsString::from("target")]);
+        let mut skipped = skipped_symlinks(root, &ignore_dirs, false);
+        skipped.sort();
+        assert_eq!(skipped, [root.join("external"), root.join("src/lib/loop")]);
+
+        let mut skipped = skipped_symlinks(root, &ignore_dirs, true);
+        skipped.sort();
+        assert_eq!(
+            skipped,
+            [root.join("external/back"), root.join("src/lib/loop")]
+        );
+    }
+
+    /// Watching a tree with a symlink loop finishes, and only the skipped symlinks go unwatched
+    #[test]
+    #[cfg(unix)]
+    fn symlinks_not_followed() {
+        let dir = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("src/lib")).unwrap();
+        std::os::unix::fs::symlink(root, root.join("src/lib/loop")).unwrap();
+        std::os::unix::fs::symlink(outside.path(), root.join("external")).unwrap();
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut backend = Backend::new(None, &[], sender).unwrap();
+        backend.watch_path(root).unwrap();
+
+        create_file(&root.join("src/lib/mod.rs"), "");
+        create_file(&root.join("src/main.rs"), "");
+        create_file(&outside.path().join("config.toml"), "");
+        let paths = received_paths(&receiver, Duration::from_millis(300), |_| {});
+        assert!(paths.contains(&root.join("src/lib/mod.rs")), "{paths:?}");
+        assert!(paths.contains(&root.join("src/main.rs")), "{paths:?}");
+        assert!(
+            paths.iter().all(|path| !path.ends_with("config.toml")),
+            "{paths:?}"
+        );
+
+        backend.unwatch_path(root, false).unwrap();
+        assert!(backend.split.is_empty());
+    }
+
+    #[test]
+    fn missing_path_watched_once_created() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let dist = root.join("dist/assets");
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut backend = Backend::new(None, &[], sender).unwrap();
+        backend.watch_path(&dist).unwrap();
+
+        let received = |backend: &mut Backend| {
+            let mut paths = Vec::new();
+            for path in received_paths(&receiver, Duration::from_millis(300), |_| {}) {
+                let event = notify::RawEvent {
+                    path: Some(path),
+                    op: Ok(notify::Op::CREATE),
+                    cookie: None,
+                };
+                let arrivals = backend.missing_path_event(event);
+                paths.extend(arrivals.events.into_iter().filter_map(|event| event.path));
+            }
+            paths
+        };
+
+        create_file(&root.join("README.md"), "");
+        assert_eq!(received(&mut backend), Vec::<PathBuf>::new());
+
+        std::fs::create_dir_all(&dist).unwrap();
+        create_file(&dist.join("index.js"), "");
+        let paths = received(&mut backend);
+        assert!(paths.contains(&dist.join("index.js")), "{paths:?}");
+        assert!(backend.missing.is_empty());
+
+        create_file(&dist.join("style.css"), "");
+        let paths = received(&mut backend);
+        assert!(paths.contains(&dist.join("style.css")), "{paths:?}");
+    }
+
+    #[test]
+    fn ignore_dirs_filter() {
+        let root = Path::new("/project");
+        let filter = filter_for(root, &["--no-git-ignore"]);
+        assert!(matches!(
+            filter.matches_path(&root.join("node_modules")),
+            Err(FilterReason::IgnoredDir)
+        ));
+        assert!(filter
+            .matches_path(&root.join("target/debug/witness"))
+            .is_err());
+        assert!(filter.matches_path(&root.join("src/target/lib.rs")).is_ok());
+
+        let filter = filter_for(root, &["--no-git-ignore", "--ignore-dir", "build,dist"]);
+        assert!(filter
+            .matches_path(&root.join("target/debug/witness"))
+            .is_ok());
+        assert!(filter.matches_path(&root.join("dist/index.js")).is_err());
+    }
+
+    /// Triggers tell what happened to the files, where any removed file makes it a removal
+    #[test]
+    fn event_kinds() {
+        use notify::Op;
+
+        let kind_of = |events: &'static [(&'static str, Op)]| {
+            let triggers = debounce_events(Duration::from_millis(20), move |sender| {
+                for &(path, op) in events {
+                    sender.send(event(Path::new(path), op)).unwrap();
+                }
+            });
+            assert_eq!(triggers.len(), 1);
+            triggers[0].kind
+        };
+
+        assert_eq!(
+            kind_of(&[("/project/a.rs", Op::WRITE)]),
+            Some(EventKind::Modified)
+        );
+        assert_eq!(
+            kind_of(&[("/project/a.rs", Op::WRITE), ("/project/b.rs", Op::REMOVE)]),
+            Some(EventKind::Removed)
+        );
+        assert_eq!(
+            kind_of(&[("/project/b.rs", Op::REMOVE), ("/project/a.rs", Op::WRITE)]),
+            Some(EventKind::Removed)
+        );
+
+        // a file which is deleted and created again was not removed
+        assert_eq!(
+            kind_of(&[("/project/a.rs", Op::REMOVE), ("/project/a.rs", Op::CREATE)]),
+            Some(EventKind::Created)
+        );
+        assert_eq!(
+            kind_of(&[("/project/a.rs", Op::RENAME)]),
+            Some(EventKind::Renamed)
+        );
+    }
+
+    #[test]
+    fn event_mask() {
+        use notify::Op;
+
+        let flags = [
+            "--debounce",
+            "20ms",
+            "--events",
+            "write,create,remove,rename",
+        ];
+        let triggers = debounce_events_with(&flags, |sender| {
+            sender
+                .send(event(Path::new("/project/a.rs"), Op::CHMOD))
+                .unwrap();
+        });
+        assert!(triggers.is_empty());
+
+        let triggers = debounce_events_with(&flags, |sender| {
+            let path = Path::new("/project/a.rs");
+            sender.send(event(path, Op::CHMOD | Op::WRITE)).unwrap();
+        });
+        assert_eq!(triggers.len(), 1);
+
+        // only removals
+        let triggers = debounce_events_with(&["--events", "remove"], |sender| {
+            sender
+                .send(write_event(Path::new("/project/a.rs")))
+                .unwrap();
+            sender
+                .send(event(Path::new("/project/b.rs"), Op::CREATE))
+                .unwrap();
+            sender
+                .send(event(Path::new("/project/c.rs"), Op::REMOVE))
+                .unwrap();
+        });
+        assert_eq!(triggers.len(), 1);
+        assert_eq!(triggers[0].paths, [PathBuf::from("/project/c.rs")]);
+    }
+
+    #[test]
+    fn content_hash() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("main.rs");
+        let mut hashes = ContentHashes::new();
+
+        // the first sighting counts as a change
+        std::fs::write(&path, "fn main() {}").unwrap();
+        assert!(hashes.changed(&path, EventKind::Modified));
+
+        std::fs::write(&path, "fn main() {}").unwrap();
+        assert!(!hashes.changed(&path, EventKind::Modified));
+
+        std::fs::write(&path, "fn main() { todo!() }").unwrap();
+        assert!(hashes.changed(&path, EventKind::Modified));
+
+        // removed files can't be read, but still trigger
+        std::fs::remove_file(&path).unwrap();
+        assert!(hashes.changed(&path, EventKind::Removed));
+        assert!(hashes.changed(&path, EventKind::Modified));
+
+        // which also forgets what they contained
+        std::fs::write(&path, "fn main() { todo!() }").unwrap();
+        assert!(hashes.changed(&path, EventKind::Created));
+    }
+
+    #[test]
+    fn retain_changed_paths() {
+        let mut paths = ChangedPaths::default();
+        paths.insert(PathBuf::from("/project/a.rs"), EventKind::Modified);
+        paths.insert(PathBuf::from("/project/b.rs"), EventKind::Removed);
+        paths.insert(PathBuf::from("/project/a.rs"), EventKind::Modified);
+
+        paths.retain(|path, _| path.ends_with("b.rs"));
+        assert_eq!(paths.paths, [PathBuf::from("/project/b.rs")]);
+        assert_eq!(paths.kind(), Some(EventKind::Removed));
+
+        paths.retain(|_, _| false);
+        assert!(paths.is_empty());
+        assert_eq!(paths.into_trigger().events, 0);
     }
 }
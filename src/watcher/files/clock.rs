@@ -0,0 +1,45 @@
+//! The source of time for debouncing, so that tests can control it.
+
+use std::time::Instant;
+
+pub trait Clock {
+    fn now(&self) -> Instant;
+}
+
+/// The actual time
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    #[inline]
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A clock which only moves when told to
+#[cfg(test)]
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    now: std::rc::Rc<std::cell::Cell<Instant>>,
+}
+
+#[cfg(test)]
+impl MockClock {
+    pub fn new() -> MockClock {
+        MockClock {
+            now: std::rc::Rc::new(std::cell::Cell::new(Instant::now())),
+        }
+    }
+
+    pub fn advance(&self, duration: std::time::Duration) {
+        self.now.set(self.now.get() + duration);
+    }
+}
+
+#[cfg(test)]
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.now.get()
+    }
+}
@@ -0,0 +1,253 @@
+//! Triggers on changes to the refs of a git repository, such as switching branches or committing,
+//! with `--on-git-change`.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+};
+
+use anyhow::{anyhow, Context};
+use tokio::sync::mpsc::{Sender, UnboundedSender};
+
+use super::{
+    absolute, clock::SystemClock, gitignore::find_repository_root, spawn_event_thread, Backend,
+    Debounce, EventFilter, FileWatcher,
+};
+use crate::{
+    cli,
+    watcher::{EventKind, ExecutionTrigger, TriggerSource},
+};
+
+pub struct GitWatcher {
+    /// We keep the watcher around so that it keeps sending events in the background
+    #[allow(dead_code)]
+    watcher: Arc<Mutex<Backend>>,
+}
+
+/// Accepts changes to `HEAD` and the refs, which are otherwise excluded along with the rest of
+/// the `.git` directory
+struct GitFilter {
+    head: PathBuf,
+    packed_refs: PathBuf,
+    refs: PathBuf,
+}
+
+/// Where git keeps the metadata of a repository
+struct GitDirs {
+    /// Contains `HEAD`, which differs between worktrees
+    git_dir: PathBuf,
+    /// Contains the refs, which are shared between worktrees
+    common_dir: PathBuf,
+}
+
+impl GitWatcher {
+    /// Watch the refs of the repository containing the current directory
+    pub fn new(
+        options: &cli::FileOptions,
+        triggers: Sender<ExecutionTrigger>,
+        failures: UnboundedSender<anyhow::Error>,
+    ) -> anyhow::Result<GitWatcher> {
+        let cwd = absolute(Path::new("."));
+        let root = find_repository_root(&cwd)
+            .ok_or_else(|| anyhow!("not within a git repository: {}", cwd.display()))?;
+        let dirs = GitDirs::locate(&root)?;
+        info!(git_dir = ?dirs.git_dir, "watching git refs");
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut watcher = Backend::new(options.poll, &[], sender)?;
+
+        // `HEAD` and `packed-refs` are replaced by renaming, so watch the directories containing
+        // them rather than the files
+        let watches = [
+            (&dirs.git_dir, notify::RecursiveMode::NonRecursive),
+            (&dirs.common_dir, notify::RecursiveMode::NonRecursive),
+            (
+                &dirs.common_dir.join("refs"),
+                notify::RecursiveMode::Recursive,
+            ),
+        ];
+        for (path, mode) in watches {
+            watcher
+                .watch(path, mode)
+                .with_context(|| format!("failed to watch path: {}", path.display()))?;
+        }
+
+        let filter = Arc::new(RwLock::new(GitFilter::new(&dirs)));
+        let watcher = Arc::new(Mutex::new(watcher));
+        let shared = (watcher.clone(), options.clone());
+        spawn_event_thread(receiver, failures, move |receiver| {
+            let (watcher, options) = &shared;
+            let debounce = Debounce {
+                source: TriggerSource::Git,
+                ..Debounce::from_args(options)
+            };
+            FileWatcher::handle_events(
+                receiver,
+                Some(watcher.clone()),
+                filter.clone(),
+                debounce,
+                triggers.clone(),
+                SystemClock,
+            )
+        });
+
+        Ok(GitWatcher { watcher })
+    }
+}
+
+impl GitDirs {
+    /// Find the metadata of the repository at the root. In a worktree `.git` is a file pointing to
+    /// the actual git directory.
+    fn locate(root: &Path) -> anyhow::Result<GitDirs> {
+        let dot_git = root.join(".git");
+        let git_dir = match dot_git.is_file() {
+            false => dot_git,
+            true => {
+                let contents = std::fs::read_to_string(&dot_git)
+                    .with_context(|| format!("failed to read {}", dot_git.display()))?;
+                let target = contents
+                    .trim_end()
+                    .strip_prefix("gitdir: ")
+                    .ok_or_else(|| anyhow!("not a git directory link: {}", dot_git.display()))?;
+                root.join(target)
+            }
+        };
+
+        let common_dir = match std::fs::read_to_string(git_dir.join("commondir")) {
+            Ok(contents) => git_dir.join(contents.trim_end()),
+            Err(_) => git_dir.clone(),
+        };
+
+        // events are reported below the watched paths, which should compare equal to the refs
+        let canonical = |path: PathBuf| std::fs::canonicalize(&path).unwrap_or(path);
+        Ok(GitDirs {
+            git_dir: canonical(git_dir),
+            common_dir: canonical(common_dir),
+        })
+    }
+}
+
+impl GitFilter {
+    fn new(dirs: &GitDirs) -> GitFilter {
+        GitFilter {
+            head: dirs.git_dir.join("HEAD"),
+            packed_refs: dirs.common_dir.join("packed-refs"),
+            refs: dirs.common_dir.join("refs"),
+        }
+    }
+
+    fn is_ref(&self, path: &Path) -> bool {
+        // git writes to a lock file which is then renamed over the ref
+        if path.extension().is_some_and(|ext| ext == "lock") {
+            return false;
+        }
+        path == self.head || path == self.packed_refs || path.starts_with(&self.refs)
+    }
+}
+
+impl EventFilter for GitFilter {
+    fn accepted_path(&self, event: &notify::RawEvent) -> Option<(PathBuf, EventKind)> {
+        // a change of permissions doesn't move any refs
+        let mask = notify::Op::all() - notify::Op::CHMOD;
+        let (path, kind) = FileWatcher::modified_file(event, mask)?;
+        if !self.is_ref(path) || path.is_dir() {
+            return None;
+        }
+        debug!(?path, ?event, "git ref changed");
+        Some((path.to_path_buf(), kind))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use std::time::Duration;
+
+    fn git_dirs(root: &Path) -> GitDirs {
+        let git_dir = root.join(".git");
+        std::fs::create_dir_all(git_dir.join("refs/heads")).unwrap();
+        std::fs::write(git_dir.join("HEAD"), "ref: refs/heads/main\n").unwrap();
+        GitDirs::locate(root).unwrap()
+    }
+
+    #[test]
+    fn filter() {
+        let dir = tempfile::tempdir().unwrap();
+        let filter = GitFilter::new(&git_dirs(dir.path()));
+        let git_dir = dir.path().join(".git");
+
+        assert!(filter.is_ref(&git_dir.join("HEAD")));
+        assert!(filter.is_ref(&git_dir.join("packed-refs")));
+        assert!(filter.is_ref(&git_dir.join("refs/heads/main")));
+        assert!(filter.is_ref(&git_dir.join("refs/heads/feature/login")));
+        assert!(!filter.is_ref(&git_dir.join("refs/heads/main.lock")));
+        assert!(!filter.is_ref(&git_dir.join("index")));
+        assert!(!filter.is_ref(&git_dir.join("objects/ab/cdef")));
+        assert!(!filter.is_ref(&dir.path().join("src/main.rs")));
+    }
+
+    #[test]
+    fn worktree() {
+        let dir = tempfile::tempdir().unwrap();
+        let main = dir.path().join("main");
+        git_dirs(&main);
+
+        let worktree_git = main.join(".git/worktrees/feature");
+        std::fs::create_dir_all(&worktree_git).unwrap();
+        std::fs::write(worktree_git.join("commondir"), "../..\n").unwrap();
+        let worktree = dir.path().join("feature");
+        std::fs::create_dir_all(&worktree).unwrap();
+        let link = format!("gitdir: {}\n", worktree_git.display());
+        std::fs::write(worktree.join(".git"), link).unwrap();
+
+        let filter = GitFilter::new(&GitDirs::locate(&worktree).unwrap());
+        assert!(filter.is_ref(&worktree_git.join("HEAD")));
+        assert!(!filter.is_ref(&main.join(".git/HEAD")));
+        assert!(filter.is_ref(&main.join(".git/refs/heads/main")));
+    }
+
+    /// Moving a branch the way git does it triggers execution
+    #[test]
+    fn ref_change_triggers() {
+        let dir = tempfile::tempdir().unwrap();
+        let dirs = git_dirs(dir.path());
+        std::fs::write(dir.path().join("README"), "hello").unwrap();
+
+        let args = cli::Arguments::parse_from(["witness", "--debounce", "50ms", "true"]);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut backend = Backend::new(None, &[], sender).unwrap();
+        backend
+            .watch(&dirs.git_dir, notify::RecursiveMode::Recursive)
+            .unwrap();
+        backend
+            .watch(dir.path(), notify::RecursiveMode::NonRecursive)
+            .unwrap();
+
+        let (triggers, mut trigger_receiver) = tokio::sync::mpsc::channel(16);
+        let filter = Arc::new(RwLock::new(GitFilter::new(&dirs)));
+        let debounce = Debounce {
+            source: TriggerSource::Git,
+            ..Debounce::from_args(&args.files)
+        };
+        std::thread::spawn(move || {
+            FileWatcher::handle_events(&receiver, None, filter, debounce, triggers, SystemClock)
+        });
+
+        // other files in the repository are left to the file watcher
+        std::fs::write(dir.path().join("README"), "changed").unwrap();
+        std::fs::write(dirs.git_dir.join("index"), "staged").unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+        assert!(trigger_receiver.try_recv().is_err());
+
+        let lock = dirs.git_dir.join("refs/heads/main.lock");
+        std::fs::write(&lock, "0123456789abcdef\n").unwrap();
+        std::fs::rename(&lock, dirs.git_dir.join("refs/heads/main")).unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+
+        let trigger = trigger_receiver.try_recv().unwrap();
+        assert_eq!(trigger.source, Some(TriggerSource::Git));
+        assert_eq!(trigger.paths, [dirs.git_dir.join("refs/heads/main")]);
+        drop(backend);
+    }
+}
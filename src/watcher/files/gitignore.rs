@@ -0,0 +1,196 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+
+use super::absolute;
+
+/// Matches paths against the ignore rules of the git repositories containing the watched paths.
+pub struct GitIgnore {
+    repositories: Vec<Repository>,
+}
+
+struct Repository {
+    /// The directory containing the `.git` directory
+    root: PathBuf,
+
+    /// Ignore rules within the repository, ordered from lowest to highest precedence
+    matchers: Vec<Gitignore>,
+}
+
+impl GitIgnore {
+    /// Locate the repositories containing the given paths and load their ignore rules.
+    pub fn new(paths: &[PathBuf]) -> GitIgnore {
+        // all the ignore files which apply to a repository
+        let mut repositories = BTreeMap::<PathBuf, Vec<PathBuf>>::new();
+
+        for path in paths {
+            let path = absolute(path);
+            let root = match find_repository_root(&path) {
+                Some(root) => root,
+                None => {
+                    info!(?path, "could not locate git repository");
+                    continue;
+                }
+            };
+
+            let files = repositories.entry(root.clone()).or_default();
+
+            // ignore files between the repository root and the watched path
+            for ancestor in path.ancestors().skip(1) {
+                if !ancestor.starts_with(&root) {
+                    break;
+                }
+                files.push(ancestor.join(".gitignore"));
+            }
+
+            // ignore files nested within the watched path
+            let walker = ignore::WalkBuilder::new(&path)
+                .hidden(false)
+                .filter_entry(|entry| entry.file_name() != ".git")
+                .build();
+            for entry in walker.flatten() {
+                if entry.file_name() == ".gitignore" {
+                    files.push(entry.into_path());
+                }
+            }
+        }
+
+        let repositories = repositories
+            .into_iter()
+            .map(|(root, files)| Repository::new(root, files))
+            .collect();
+
+        GitIgnore { repositories }
+    }
+
+    /// Returns `Some(true)` if the path is ignored by git, or `None` if the path is not within any
+    /// of the located repositories.
+    pub fn is_ignored(&self, path: &Path) -> Option<bool> {
+        let path = absolute(path);
+        let repository = self
+            .repositories
+            .iter()
+            .filter(|repository| path.starts_with(&repository.root))
+            .max_by_key(|repository| repository.root.components().count())?;
+        Some(repository.is_ignored(&path))
+    }
+}
+
+impl Repository {
+    fn new(root: PathBuf, mut files: Vec<PathBuf>) -> Repository {
+        files.sort_by_key(|file| file.components().count());
+        files.dedup();
+
+        let mut matchers = Vec::with_capacity(files.len() + 1);
+
+        let exclude = root.join(".git").join("info").join("exclude");
+        matchers.extend(load(&root, &exclude));
+
+        for file in files.iter() {
+            let dir = file.parent().unwrap_or(&root);
+            matchers.extend(load(dir, file));
+        }
+
+        Repository { root, matchers }
+    }
+
+    fn is_ignored(&self, path: &Path) -> bool {
+        let is_dir = path.is_dir();
+
+        // rules closer to the path take precedence
+        for matcher in self.matchers.iter().rev() {
+            if !path.starts_with(matcher.path()) {
+                continue;
+            }
+
+            let matched = matcher.matched_path_or_any_parents(path, is_dir);
+            if matched.is_ignore() {
+                return true;
+            }
+            if matched.is_whitelist() {
+                return false;
+            }
+        }
+
+        false
+    }
+}
+
+/// Names of the ignore files which are not specific to git, from lowest to highest precedence.
+pub const IGNORE_FILE_NAMES: [&str; 2] = [".ignore", ".witnessignore"];
+
+/// Ignore files in the root of the watched paths which are not specific to git.
+pub struct IgnoreFiles {
+    /// Matchers and the files they were loaded from, ordered from highest to lowest precedence
+    matchers: Vec<(PathBuf, Gitignore)>,
+}
+
+impl IgnoreFiles {
+    pub fn new(paths: &[PathBuf]) -> IgnoreFiles {
+        let mut matchers = Vec::new();
+
+        for root in paths {
+            let root = absolute(root);
+            for name in IGNORE_FILE_NAMES.iter().rev() {
+                let file = root.join(name);
+                if let Some(matcher) = load(&root, &file) {
+                    matchers.push((file, matcher));
+                }
+            }
+        }
+
+        IgnoreFiles { matchers }
+    }
+
+    /// Returns the ignore file which ignores the path, if any
+    pub fn ignored_by(&self, path: &Path) -> Option<&Path> {
+        let path = absolute(path);
+        let is_dir = path.is_dir();
+
+        for (file, matcher) in self.matchers.iter() {
+            if !path.starts_with(matcher.path()) {
+                continue;
+            }
+
+            let matched = matcher.matched_path_or_any_parents(&path, is_dir);
+            if matched.is_ignore() {
+                return Some(file);
+            }
+            if matched.is_whitelist() {
+                return None;
+            }
+        }
+
+        None
+    }
+}
+
+/// Load the rules of an ignore file, which are relative to the given directory.
+fn load(dir: &Path, file: &Path) -> Option<Gitignore> {
+    if !file.is_file() {
+        return None;
+    }
+
+    let mut builder = GitignoreBuilder::new(dir);
+    if let Some(error) = builder.add(file) {
+        warn!(?file, %error, "failed to parse ignore file");
+    }
+
+    match builder.build() {
+        Ok(matcher) => Some(matcher),
+        Err(error) => {
+            warn!(?file, %error, "failed to build ignore matcher");
+            None
+        }
+    }
+}
+
+/// Find the closest directory containing a `.git` directory (or file, in the case of worktrees).
+pub(super) fn find_repository_root(path: &Path) -> Option<PathBuf> {
+    path.ancestors()
+        .find(|ancestor| ancestor.join(".git").exists())
+        .map(Path::to_path_buf)
+}
@@ -1,8 +1,17 @@
-use std::{net::SocketAddr, sync::Arc};
+pub mod auth;
+mod http;
+mod json;
+mod rate_limit;
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
 
 use anyhow::Context;
 use tokio::{
-    io::AsyncReadExt,
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader},
     net::{TcpListener, UdpSocket},
     sync::{
         broadcast::{
@@ -14,7 +23,9 @@ use tokio::{
     time::timeout,
 };
 
-use super::ExecutionTrigger;
+use self::{auth::Auth, json::Response, rate_limit::RateLimiter};
+use super::{ExecutionTrigger, TriggerSource};
+use crate::cidr::{self, Cidr};
 
 pub struct NetworkWatcher {
     stop_signal: BroadcastSender<Stop>,
@@ -24,45 +35,124 @@ pub struct NetworkWatcher {
 #[derive(Debug, Copy, Clone)]
 struct Stop;
 
+/// Sent back to TCP clients with `--ack` when their trigger was accepted
+pub const ACK_OK: &[u8] = b"OK\n";
+
+/// Sent back to TCP clients with `--ack` when their trigger was rejected
+pub const ACK_DENIED: &[u8] = b"DENIED\n";
+
+/// What TCP clients send
+#[derive(Debug, Clone, Copy)]
+enum TcpProtocol {
+    /// Just the key, optionally answered with `ACK_OK` or `ACK_DENIED`
+    Key { ack: bool },
+    /// Lines of JSON, with `--tcp-json`
+    Json,
+}
+
+/// How TCP clients are handled
+#[derive(Debug, Clone, Copy)]
+struct TcpOptions {
+    protocol: TcpProtocol,
+    /// Clients are dropped if they don't send their request within this duration
+    timeout: Duration,
+}
+
+impl TcpOptions {
+    fn from_args(network: &crate::cli::NetworkOptions) -> TcpOptions {
+        let protocol = match network.tcp_json {
+            true => TcpProtocol::Json,
+            false => TcpProtocol::Key { ack: network.ack },
+        };
+        TcpOptions {
+            protocol,
+            timeout: network.tcp_timeout,
+        }
+    }
+}
+
 impl NetworkWatcher {
     pub fn new(
         network: &crate::cli::NetworkOptions,
         triggers: Sender<ExecutionTrigger>,
     ) -> anyhow::Result<NetworkWatcher> {
         let (stop_sender, _) = broadcast_channel(1);
-        let key = Arc::<str>::from(network.key.as_str());
+        let auth = Auth::from_args(network)?;
+        let limiter = RateLimiter::new(network.rate_limit);
+        let allow = Arc::<[Cidr]>::from(network.allow.as_slice());
         let mut handles = Vec::new();
 
         for &port in network.udp.iter() {
-            let socket = std::net::UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], port)))
-                .with_context(|| format!("failed to bind UDP to port {port}"))?;
-            socket
-                .set_nonblocking(true)
-                .context("could not make UDP socket nonblocking")?;
-            let socket = UdpSocket::from_std(socket).unwrap();
-
+            let socket = bind_udp(network.bind, port)?;
             handles.push(tokio::spawn(handle_udp_stream(
                 socket,
                 stop_sender.subscribe(),
-                key.clone(),
+                allow.clone(),
+                auth.clone(),
+                limiter.clone(),
                 triggers.clone(),
             )));
         }
 
         for &port in network.tcp.iter() {
-            let listener = std::net::TcpListener::bind(SocketAddr::from(([0, 0, 0, 0], port)))
-                .with_context(|| format!("failed to bind TCP to port {port}"))?;
-            listener
-                .set_nonblocking(true)
-                .context("could not make TCP socket nonblocking")?;
-            let listener = TcpListener::from_std(listener).unwrap();
-
+            let listener = bind_listener(network.bind, port, "TCP")?;
             handles.push(tokio::spawn(handle_tcp_stream(
                 listener,
                 stop_sender.subscribe(),
-                key.clone(),
+                allow.clone(),
+                auth.clone(),
+                limiter.clone(),
+                triggers.clone(),
+                TcpOptions::from_args(network),
+            )));
+        }
+
+        for &port in network.http.iter() {
+            let listener = bind_listener(network.bind, port, "HTTP")?;
+            handles.push(tokio::spawn(handle_http_stream(
+                listener,
+                stop_sender.subscribe(),
+                allow.clone(),
+                auth.clone(),
+                limiter.clone(),
+                triggers.clone(),
+                network.tcp_timeout,
+            )));
+        }
+
+        for path in network.unix.iter() {
+            #[cfg(unix)]
+            handles.push(tokio::spawn(handle_unix_stream(
+                SocketFile::bind(path)?,
+                stop_sender.subscribe(),
+                auth.clone(),
+                limiter.clone(),
+                triggers.clone(),
+                network.tcp_timeout,
+            )));
+
+            #[cfg(not(unix))]
+            return Err(anyhow::anyhow!(
+                "Unix sockets are not supported on this platform: {}",
+                path.display()
+            ));
+        }
+
+        for path in network.fifo.iter() {
+            #[cfg(unix)]
+            handles.push(tokio::spawn(handle_fifo(
+                Fifo::open(path)?,
+                stop_sender.subscribe(),
+                auth.clone(),
+                limiter.clone(),
                 triggers.clone(),
             )));
+
+            #[cfg(not(unix))]
+            return Err(anyhow::anyhow!(
+                "FIFOs are not supported on this platform: {}",
+                path.display()
+            ));
         }
 
         Ok(NetworkWatcher {
@@ -83,13 +173,50 @@ impl NetworkWatcher {
     }
 }
 
+fn bind_udp(addr: IpAddr, port: u16) -> anyhow::Result<UdpSocket> {
+    let socket = bind_socket(SocketAddr::new(addr, port), socket2::Type::DGRAM)
+        .with_context(|| format!("failed to bind UDP to port {port}"))?;
+    Ok(UdpSocket::from_std(socket.into())?)
+}
+
+fn bind_listener(addr: IpAddr, port: u16, protocol: &str) -> anyhow::Result<TcpListener> {
+    let socket = bind_socket(SocketAddr::new(addr, port), socket2::Type::STREAM)
+        .with_context(|| format!("failed to bind {protocol} to port {port}"))?;
+    Ok(TcpListener::from_std(socket.into())?)
+}
+
+/// Create a nonblocking socket bound to the address. Binding to `::` accepts both IPv6 and IPv4
+/// clients, regardless of the platform's default.
+fn bind_socket(addr: SocketAddr, kind: socket2::Type) -> std::io::Result<socket2::Socket> {
+    let socket = socket2::Socket::new(socket2::Domain::for_address(addr), kind, None)?;
+
+    if addr.is_ipv6() && addr.ip().is_unspecified() {
+        socket.set_only_v6(false)?;
+    }
+
+    // the same as `std::net::TcpListener`, so that we can restart while old connections linger
+    #[cfg(unix)]
+    if kind == socket2::Type::STREAM {
+        socket.set_reuse_address(true)?;
+    }
+
+    socket.bind(&addr.into())?;
+    if kind == socket2::Type::STREAM {
+        socket.listen(128)?;
+    }
+    socket.set_nonblocking(true)?;
+    Ok(socket)
+}
+
 async fn handle_udp_stream(
     socket: UdpSocket,
     mut stop_signal: BroadcastReceiver<Stop>,
-    key: Arc<str>,
+    allow: Arc<[Cidr]>,
+    auth: Auth,
+    limiter: RateLimiter,
     triggers: Sender<ExecutionTrigger>,
 ) -> anyhow::Result<()> {
-    let mut buffer = vec![0u8; key.len() + 1];
+    let mut buffer = vec![0u8; auth.message_length() + 1];
 
     loop {
         debug!(addr = ?socket.local_addr(), "waiting on UDP");
@@ -100,9 +227,18 @@ async fn handle_udp_stream(
         };
 
         let (count, addr) = result.context("failed to receive message")?;
-        if buffer[..count].starts_with(key.as_bytes()) {
-            info!(?addr, "triggered by UDP client");
-            let _ = triggers.try_send(ExecutionTrigger);
+        if !cidr::allowed(&allow, addr.ip()) {
+            debug!(?addr, "dropping UDP packet from disallowed address");
+            continue;
+        }
+
+        match auth.verify(&buffer[..count]) {
+            Ok(()) if !limiter.allow() => debug!(?addr, "rate limit exceeded, dropping trigger"),
+            Ok(()) => {
+                info!(?addr, "triggered by UDP client");
+                let _ = triggers.try_send(ExecutionTrigger::network(TriggerSource::Udp(addr)));
+            }
+            Err(reason) => debug!(?addr, ?reason, "rejected UDP client"),
         }
     }
 }
@@ -110,8 +246,11 @@ async fn handle_udp_stream(
 async fn handle_tcp_stream(
     listener: TcpListener,
     mut stop_signal: BroadcastReceiver<Stop>,
-    key: Arc<str>,
+    allow: Arc<[Cidr]>,
+    auth: Auth,
+    limiter: RateLimiter,
     triggers: Sender<ExecutionTrigger>,
+    options: TcpOptions,
 ) -> anyhow::Result<()> {
     loop {
         debug!(addr = ?listener.local_addr(), "waiting on TCP");
@@ -124,23 +263,1019 @@ async fn handle_tcp_stream(
         let (mut stream, addr) = incoming.context("failed to accept incoming client")?;
         debug!(?addr, "incoming TCP client");
 
-        let key = key.clone();
+        if !cidr::allowed(&allow, addr.ip()) {
+            debug!(?addr, "dropping TCP client from disallowed address");
+            continue;
+        }
+
+        let auth = auth.clone();
+        let limiter = limiter.clone();
         let triggers = triggers.clone();
+        let ack = match options.protocol {
+            TcpProtocol::Key { ack } => ack,
+            TcpProtocol::Json => {
+                let client = handle_json_client(stream, addr, auth, limiter, triggers, options);
+                tokio::spawn(client);
+                continue;
+            }
+        };
         tokio::spawn(async move {
-            let mut buffer = vec![0u8; key.len()];
             debug!(?addr, "waiting on keyphrase");
 
-            let duration = std::time::Duration::from_secs(5);
-            match timeout(duration, stream.read_exact(&mut buffer)).await {
-                Err(_) => debug!(?addr, "client timed out"),
-                Ok(Err(error)) => debug!(?addr, %error, "failed to receive keyphrase"),
-                Ok(Ok(count)) => {
-                    if buffer[..count].starts_with(key.as_bytes()) {
+            let read = read_key(&mut stream, &auth);
+            let accepted = match timeout(options.timeout, read).await {
+                Err(_) => {
+                    debug!(?addr, "client timed out");
+                    return;
+                }
+                Ok(Err(error)) => {
+                    debug!(?addr, %error, "failed to receive keyphrase");
+                    return;
+                }
+                Ok(Ok(message)) => match auth.verify(&message) {
+                    Ok(()) if !limiter.allow() => {
+                        debug!(?addr, "rate limit exceeded, dropping trigger");
+                        false
+                    }
+                    Ok(()) => {
                         info!(?addr, "triggered by TCP client");
-                        let _ = triggers.try_send(ExecutionTrigger);
+                        let _ =
+                            triggers.try_send(ExecutionTrigger::network(TriggerSource::Tcp(addr)));
+                        true
                     }
+                    Err(reason) => {
+                        debug!(?addr, ?reason, "rejected TCP client");
+                        false
+                    }
+                },
+            };
+
+            if ack {
+                let response = if accepted { ACK_OK } else { ACK_DENIED };
+                if let Err(error) = stream.write_all(response).await {
+                    debug!(?addr, %error, "failed to acknowledge TCP trigger");
                 }
             }
         });
     }
 }
+
+/// Answer each line of JSON sent by a TCP client with `--tcp-json`, until the client closes the
+/// stream or stops sending
+async fn handle_json_client(
+    stream: tokio::net::TcpStream,
+    addr: SocketAddr,
+    auth: Auth,
+    limiter: RateLimiter,
+    triggers: Sender<ExecutionTrigger>,
+    options: TcpOptions,
+) {
+    let mut stream = BufReader::new(stream);
+    loop {
+        let line = match timeout(options.timeout, read_json_line(&mut stream)).await {
+            Err(_) => {
+                debug!(?addr, "client timed out");
+                return;
+            }
+            Ok(Err(error)) => {
+                debug!(?addr, %error, "failed to receive request");
+                return;
+            }
+            Ok(Ok(None)) => return,
+            Ok(Ok(Some(line))) => line,
+        };
+
+        let response = match line.and_then(|line| json::Request::parse(&line)) {
+            Err(error) => {
+                debug!(?addr, %error, "malformed JSON request");
+                Response::Error { error }
+            }
+            Ok(request) => match auth.verify(request.key.as_bytes()) {
+                Ok(()) if !limiter.allow() => {
+                    debug!(?addr, "rate limit exceeded, dropping trigger");
+                    Response::Denied
+                }
+                Ok(()) => {
+                    info!(?addr, rule = ?request.rule, args = ?request.args, "triggered by TCP client");
+                    let mut trigger = ExecutionTrigger::network(TriggerSource::Tcp(addr));
+                    trigger.rule = request.rule;
+                    trigger.args = request.args;
+                    let _ = triggers.try_send(trigger);
+                    Response::Ok
+                }
+                Err(reason) => {
+                    debug!(?addr, ?reason, "rejected TCP client");
+                    Response::Denied
+                }
+            },
+        };
+
+        if let Err(error) = stream.write_all(&response.to_line()).await {
+            debug!(?addr, %error, "failed to respond to TCP client");
+            return;
+        }
+    }
+}
+
+/// Read the next line of a JSON request, or `None` once the client is done. Lines which are too
+/// long or not valid UTF-8 are an error for the client.
+async fn read_json_line(
+    stream: &mut (impl tokio::io::AsyncBufRead + Unpin),
+) -> std::io::Result<Option<Result<String, String>>> {
+    let mut line = Vec::new();
+    let mut limited = stream.take(json::MAX_REQUEST as u64 + 1);
+    if limited.read_until(b'\n', &mut line).await? == 0 {
+        return Ok(None);
+    }
+    if line.len() > json::MAX_REQUEST {
+        return Ok(Some(Err(format!(
+            "requests are limited to {} bytes",
+            json::MAX_REQUEST
+        ))));
+    }
+    Ok(Some(
+        String::from_utf8(line).map_err(|_| "the request is not valid UTF-8".to_owned()),
+    ))
+}
+
+/// Read from the stream until the message either authenticates or never could, or the client
+/// stops sending. Clients may wait for an `--ack` without closing the stream, so we cannot wait
+/// for the end of the stream when keys have different lengths.
+async fn read_key(stream: &mut (impl AsyncRead + Unpin), auth: &Auth) -> std::io::Result<Vec<u8>> {
+    let mut message = vec![0u8; auth.message_length()];
+    let mut length = 0;
+
+    while length < message.len() && auth.incomplete(&message[..length]) {
+        match stream.read(&mut message[length..]).await? {
+            0 => break,
+            count => length += count,
+        }
+    }
+
+    message.truncate(length);
+    Ok(message)
+}
+
+async fn handle_http_stream(
+    listener: TcpListener,
+    mut stop_signal: BroadcastReceiver<Stop>,
+    allow: Arc<[Cidr]>,
+    auth: Auth,
+    limiter: RateLimiter,
+    triggers: Sender<ExecutionTrigger>,
+    duration: Duration,
+) -> anyhow::Result<()> {
+    loop {
+        debug!(addr = ?listener.local_addr(), "waiting on HTTP");
+
+        let incoming = tokio::select! {
+            _ = stop_signal.recv() => return Ok(()),
+            incoming = listener.accept() => incoming,
+        };
+
+        let (stream, addr) = incoming.context("failed to accept incoming client")?;
+        debug!(?addr, "incoming HTTP client");
+
+        if !cidr::allowed(&allow, addr.ip()) {
+            debug!(?addr, "dropping HTTP client from disallowed address");
+            continue;
+        }
+
+        let auth = auth.clone();
+        let limiter = limiter.clone();
+        let triggers = triggers.clone();
+        tokio::spawn(async move {
+            let mut stream = BufReader::new(stream);
+
+            let request = match timeout(duration, http::read_request(&mut stream)).await {
+                Err(_) => {
+                    debug!(?addr, "client timed out");
+                    return;
+                }
+                Ok(Err(error)) => {
+                    debug!(?addr, %error, "invalid HTTP request");
+                    let _ = http::write_response(&mut stream, 400, "Bad Request").await;
+                    return;
+                }
+                Ok(Ok(request)) => request,
+            };
+
+            let message = match request.header(http::KEY_HEADER) {
+                Some(header) => header.as_bytes(),
+                None => &request.body,
+            };
+
+            let response = if request.method != "POST" {
+                http::write_response(&mut stream, 405, "Method Not Allowed").await
+            } else {
+                match auth.verify(message) {
+                    Ok(()) if !limiter.allow() => {
+                        debug!(?addr, "rate limit exceeded, dropping trigger");
+                        http::write_response(&mut stream, 429, "Too Many Requests").await
+                    }
+                    Ok(()) => {
+                        info!(?addr, "triggered by HTTP client");
+                        let _ =
+                            triggers.try_send(ExecutionTrigger::network(TriggerSource::Http(addr)));
+                        http::write_response(&mut stream, 200, "OK").await
+                    }
+                    Err(reason) => {
+                        debug!(?addr, ?reason, "rejected HTTP client");
+                        http::write_response(&mut stream, 401, "Unauthorized").await
+                    }
+                }
+            };
+
+            if let Err(error) = response {
+                debug!(?addr, %error, "failed to respond to HTTP client");
+            }
+        });
+    }
+}
+
+/// A Unix socket listener which removes its socket file when dropped
+#[cfg(unix)]
+pub(super) struct SocketFile {
+    pub listener: tokio::net::UnixListener,
+    pub path: std::path::PathBuf,
+}
+
+#[cfg(unix)]
+impl SocketFile {
+    pub fn bind(path: &std::path::Path) -> anyhow::Result<SocketFile> {
+        // a previous instance may have exited without removing its socket
+        if path.exists() && std::os::unix::net::UnixStream::connect(path).is_err() {
+            debug!(?path, "removing stale Unix socket");
+            let _ = std::fs::remove_file(path);
+        }
+
+        let listener = tokio::net::UnixListener::bind(path)
+            .with_context(|| format!("failed to bind Unix socket: {}", path.display()))?;
+        Ok(SocketFile {
+            listener,
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+#[cfg(unix)]
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        if let Err(error) = std::fs::remove_file(&self.path) {
+            warn!(path = ?self.path, %error, "failed to remove Unix socket");
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn handle_unix_stream(
+    socket: SocketFile,
+    mut stop_signal: BroadcastReceiver<Stop>,
+    auth: Auth,
+    limiter: RateLimiter,
+    triggers: Sender<ExecutionTrigger>,
+    duration: Duration,
+) -> anyhow::Result<()> {
+    let path = &socket.path;
+    loop {
+        debug!(?path, "waiting on Unix socket");
+
+        let incoming = tokio::select! {
+            _ = stop_signal.recv() => return Ok(()),
+            incoming = socket.listener.accept() => incoming,
+        };
+
+        let (mut stream, _) = incoming.context("failed to accept incoming client")?;
+        debug!(?path, "incoming Unix socket client");
+
+        let auth = auth.clone();
+        let limiter = limiter.clone();
+        let triggers = triggers.clone();
+        let path = path.clone();
+        tokio::spawn(async move {
+            match timeout(duration, read_key(&mut stream, &auth)).await {
+                Err(_) => debug!(?path, "client timed out"),
+                Ok(Err(error)) => debug!(?path, %error, "failed to receive keyphrase"),
+                Ok(Ok(message)) => match auth.verify(&message) {
+                    Ok(()) if !limiter.allow() => {
+                        debug!(?path, "rate limit exceeded, dropping trigger")
+                    }
+                    Ok(()) => {
+                        info!(?path, "triggered by Unix socket client");
+                        let _ = triggers.try_send(ExecutionTrigger::network(TriggerSource::Unix));
+                    }
+                    Err(reason) => debug!(?path, ?reason, "rejected Unix socket client"),
+                },
+            }
+        });
+    }
+}
+
+/// Lines longer than this are dropped by the FIFO reader instead of being buffered
+#[cfg(unix)]
+const MAX_FIFO_LINE: usize = 4096;
+
+/// A named pipe which triggers are read from. The FIFO is removed when dropped if we created it.
+#[cfg(unix)]
+pub(super) struct Fifo {
+    pub file: tokio::io::unix::AsyncFd<std::fs::File>,
+    pub path: std::path::PathBuf,
+    created: bool,
+}
+
+#[cfg(unix)]
+impl Fifo {
+    pub fn open(path: &std::path::Path) -> anyhow::Result<Fifo> {
+        use std::os::unix::{ffi::OsStrExt, fs::FileTypeExt, fs::OpenOptionsExt};
+
+        let created = match std::fs::metadata(path) {
+            Ok(metadata) if metadata.file_type().is_fifo() => false,
+            Ok(_) => return Err(anyhow::anyhow!("not a FIFO: {}", path.display())),
+            Err(_) => {
+                let name = std::ffi::CString::new(path.as_os_str().as_bytes())
+                    .with_context(|| format!("invalid FIFO path: {}", path.display()))?;
+                if unsafe { libc::mkfifo(name.as_ptr(), 0o600) } != 0 {
+                    return Err(std::io::Error::last_os_error())
+                        .with_context(|| format!("failed to create FIFO: {}", path.display()));
+                }
+                true
+            }
+        };
+
+        let fifo = |file| -> std::io::Result<Fifo> {
+            Ok(Fifo {
+                file: tokio::io::unix::AsyncFd::new(file)?,
+                path: path.to_path_buf(),
+                created,
+            })
+        };
+
+        // holding the FIFO open for writing as well means that it never reaches EOF when a writer
+        // closes it, so we don't have to reopen it (or spin) between writers
+        std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(path)
+            .and_then(fifo)
+            .with_context(|| format!("failed to open FIFO: {}", path.display()))
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Fifo {
+    fn drop(&mut self) {
+        if !self.created {
+            return;
+        }
+        if let Err(error) = std::fs::remove_file(&self.path) {
+            warn!(path = ?self.path, %error, "failed to remove FIFO");
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn handle_fifo(
+    fifo: Fifo,
+    mut stop_signal: BroadcastReceiver<Stop>,
+    auth: Auth,
+    limiter: RateLimiter,
+    triggers: Sender<ExecutionTrigger>,
+) -> anyhow::Result<()> {
+    use std::io::Read;
+
+    let path = &fifo.path;
+    let mut pending = Vec::new();
+    let mut buffer = [0u8; 512];
+    loop {
+        debug!(?path, "waiting on FIFO");
+
+        let mut guard = tokio::select! {
+            _ = stop_signal.recv() => return Ok(()),
+            guard = fifo.file.readable() => guard.context("failed to wait on FIFO")?,
+        };
+
+        let count = match guard.try_io(|file| {
+            let mut file: &std::fs::File = file.get_ref();
+            file.read(&mut buffer)
+        }) {
+            // spurious wakeup
+            Err(_) => continue,
+            Ok(result) => result.context("failed to read from FIFO")?,
+        };
+        pending.extend_from_slice(&buffer[..count]);
+
+        while let Some(end) = pending.iter().position(|&byte| byte == b'\n') {
+            let line = pending.drain(..=end).collect::<Vec<_>>();
+            match auth.verify(&line[..end]) {
+                Ok(()) if !limiter.allow() => {
+                    debug!(?path, "rate limit exceeded, dropping trigger")
+                }
+                Ok(()) => {
+                    info!(?path, "triggered by FIFO");
+                    let _ = triggers.try_send(ExecutionTrigger::network(TriggerSource::Fifo));
+                }
+                Err(reason) => debug!(?path, ?reason, "rejected line from FIFO"),
+            }
+        }
+
+        if pending.len() > MAX_FIFO_LINE {
+            debug!(?path, "dropping overlong line from FIFO");
+            pending.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+
+    struct Listener {
+        addr: SocketAddr,
+        triggers: tokio::sync::mpsc::Receiver<ExecutionTrigger>,
+        /// The listener stops once this is dropped
+        _stop: BroadcastSender<Stop>,
+    }
+
+    /// Start listening for HTTP requests on an arbitrary port
+    async fn http_listener(key: &str) -> Listener {
+        http_listener_with_timeout(key, Duration::from_secs(5)).await
+    }
+
+    /// Start listening for HTTP requests, dropping clients which take longer than the timeout
+    async fn http_listener_with_timeout(key: &str, timeout: Duration) -> Listener {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (stop_sender, _) = broadcast_channel(1);
+        let (triggers, receiver) = tokio::sync::mpsc::channel(1);
+        tokio::spawn(handle_http_stream(
+            listener,
+            stop_sender.subscribe(),
+            Arc::from([]),
+            Auth::Keys(Arc::from([key.to_owned()])),
+            RateLimiter::new(None),
+            triggers,
+            timeout,
+        ));
+        Listener {
+            addr,
+            triggers: receiver,
+            _stop: stop_sender,
+        }
+    }
+
+    async fn send_http(addr: SocketAddr, request: &str) -> String {
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    /// Send a request after a delay to a listener with the timeout, returning whether it triggered
+    async fn http_triggers_after(delay: Duration, timeout: Duration) -> bool {
+        let mut listener = http_listener_with_timeout("secret", timeout).await;
+        let mut stream = tokio::net::TcpStream::connect(listener.addr).await.unwrap();
+        tokio::time::sleep(delay).await;
+        let request = "POST / HTTP/1.1\r\nHost: localhost\r\nX-Witness-Key: secret\r\n\r\n";
+        // the listener may already have hung up
+        let _ = stream.write_all(request.as_bytes()).await;
+        let mut response = String::new();
+        let _ = stream.read_to_string(&mut response).await;
+        listener.triggers.try_recv().is_ok()
+    }
+
+    /// HTTP clients get as long as `--tcp-timeout` to send their request
+    #[tokio::test]
+    async fn http_timeout() {
+        let delay = Duration::from_millis(300);
+        assert!(http_triggers_after(delay, Duration::from_secs(10)).await);
+        assert!(!http_triggers_after(delay, Duration::from_millis(100)).await);
+    }
+
+    #[tokio::test]
+    async fn http_key_header() {
+        let mut listener = http_listener("secret").await;
+        let response = send_http(
+            listener.addr,
+            "POST / HTTP/1.1\r\nHost: localhost\r\nX-Witness-Key: secret\r\n\r\n",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+
+        let trigger = listener.triggers.try_recv().unwrap();
+        let addr = trigger.source.and_then(|source| source.addr()).unwrap();
+        assert_eq!(trigger.source, Some(TriggerSource::Http(addr)));
+        assert!(addr.ip().is_loopback());
+        assert_eq!(trigger.events, 1);
+    }
+
+    #[tokio::test]
+    async fn http_key_body() {
+        let mut listener = http_listener("secret").await;
+        let response = send_http(
+            listener.addr,
+            "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 6\r\n\r\nsecret",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+        assert!(listener.triggers.try_recv().is_ok());
+    }
+
+    #[tokio::test]
+    async fn http_wrong_key() {
+        let mut listener = http_listener("secret").await;
+
+        let response = send_http(
+            listener.addr,
+            "POST / HTTP/1.1\r\nHost: localhost\r\nX-Witness-Key: guess\r\n\r\n",
+        )
+        .await;
+        assert!(
+            response.starts_with("HTTP/1.1 401 Unauthorized\r\n"),
+            "{response}"
+        );
+
+        let response = send_http(listener.addr, "POST / HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        assert!(
+            response.starts_with("HTTP/1.1 401 Unauthorized\r\n"),
+            "{response}"
+        );
+
+        assert!(listener.triggers.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn http_malformed_request() {
+        let mut listener = http_listener("").await;
+        let response = send_http(listener.addr, "hello\r\n\r\n").await;
+        assert!(
+            response.starts_with("HTTP/1.1 400 Bad Request\r\n"),
+            "{response}"
+        );
+        assert!(listener.triggers.try_recv().is_err());
+    }
+
+    /// Find an address of this machine which is not a loopback address
+    fn non_loopback_addr() -> Option<IpAddr> {
+        // connecting a UDP socket does not send any packets, but selects a local interface
+        let socket = std::net::UdpSocket::bind("0.0.0.0:0").ok()?;
+        socket.connect("192.0.2.1:9").ok()?;
+        let addr = socket.local_addr().ok()?.ip();
+        (!addr.is_loopback() && !addr.is_unspecified()).then_some(addr)
+    }
+
+    #[tokio::test]
+    async fn bind_loopback_rejects_remote() {
+        let listener = bind_listener(IpAddr::from([127, 0, 0, 1]), 0, "TCP").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        assert!(tokio::net::TcpStream::connect(("127.0.0.1", port))
+            .await
+            .is_ok());
+
+        match non_loopback_addr() {
+            None => eprintln!("no non-loopback interface available, skipping"),
+            Some(addr) => {
+                let result = tokio::net::TcpStream::connect((addr, port)).await;
+                assert!(result.is_err(), "connected through {addr}");
+            }
+        }
+    }
+
+    /// A flood of valid UDP triggers is capped by the rate limit
+    #[tokio::test]
+    async fn udp_rate_limit() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let (stop_sender, _) = broadcast_channel(1);
+        let (triggers, mut receiver) = tokio::sync::mpsc::channel(100);
+        let limiter = RateLimiter::new(Some(crate::cli::RateLimit {
+            count: 5,
+            period: std::time::Duration::from_secs(60),
+        }));
+        tokio::spawn(handle_udp_stream(
+            socket,
+            stop_sender.subscribe(),
+            Arc::from([]),
+            Auth::Keys(Arc::from(["secret".to_owned()])),
+            limiter,
+            triggers,
+        ));
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        for _ in 0..50 {
+            client.send_to(b"secret", addr).await.unwrap();
+            tokio::task::yield_now().await;
+        }
+
+        // give the listener a chance to process the remaining messages
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        let mut count = 0;
+        while receiver.try_recv().is_ok() {
+            count += 1;
+        }
+        assert_eq!(count, 5);
+    }
+
+    fn allow_list(ranges: &[&str]) -> Arc<[Cidr]> {
+        ranges.iter().map(|range| range.parse().unwrap()).collect()
+    }
+
+    /// Send a valid UDP trigger from the loopback interface, returning `true` if it triggered
+    async fn udp_triggers(allow: Arc<[Cidr]>) -> bool {
+        udp_triggers_between(IpAddr::from([127, 0, 0, 1]), [127, 0, 0, 1].into(), allow).await
+    }
+
+    /// Send a valid UDP trigger from the client address to a listener bound on the address
+    async fn udp_triggers_between(bind: IpAddr, client: IpAddr, allow: Arc<[Cidr]>) -> bool {
+        let socket = bind_udp(bind, 0).unwrap();
+        let port = socket.local_addr().unwrap().port();
+        let addr = SocketAddr::new(target_for(bind, client), port);
+        let (stop_sender, _) = broadcast_channel(1);
+        let (triggers, mut receiver) = tokio::sync::mpsc::channel(1);
+        tokio::spawn(handle_udp_stream(
+            socket,
+            stop_sender.subscribe(),
+            allow,
+            Auth::Keys(Arc::from(["secret".to_owned()])),
+            RateLimiter::new(None),
+            triggers,
+        ));
+
+        let client = UdpSocket::bind(SocketAddr::new(client, 0)).await.unwrap();
+        client.send_to(b"secret", addr).await.unwrap();
+        let timeout = std::time::Duration::from_millis(500);
+        tokio::time::timeout(timeout, receiver.recv()).await.is_ok()
+    }
+
+    /// Send a valid TCP trigger from the loopback interface, returning `true` if it triggered
+    async fn tcp_triggers(allow: Arc<[Cidr]>) -> bool {
+        tcp_triggers_between(IpAddr::from([127, 0, 0, 1]), [127, 0, 0, 1].into(), allow).await
+    }
+
+    /// Send a valid TCP trigger from the client address to a listener bound on the address
+    async fn tcp_triggers_between(bind: IpAddr, client: IpAddr, allow: Arc<[Cidr]>) -> bool {
+        let listener = bind_listener(bind, 0, "TCP").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let addr = SocketAddr::new(target_for(bind, client), port);
+        let (stop_sender, _) = broadcast_channel(1);
+        let (triggers, mut receiver) = tokio::sync::mpsc::channel(1);
+        tokio::spawn(handle_tcp_stream(
+            listener,
+            stop_sender.subscribe(),
+            allow,
+            Auth::Keys(Arc::from(["secret".to_owned()])),
+            RateLimiter::new(None),
+            triggers,
+            tcp_options(TcpProtocol::Key { ack: false }),
+        ));
+
+        let socket = match client {
+            IpAddr::V4(_) => tokio::net::TcpSocket::new_v4(),
+            IpAddr::V6(_) => tokio::net::TcpSocket::new_v6(),
+        };
+        let socket = socket.unwrap();
+        socket.bind(SocketAddr::new(client, 0)).unwrap();
+        let mut client = socket.connect(addr).await.unwrap();
+        let _ = client.write_all(b"secret").await;
+        let timeout = std::time::Duration::from_millis(500);
+        tokio::time::timeout(timeout, receiver.recv()).await.is_ok()
+    }
+
+    #[tokio::test]
+    async fn allowed_addresses() {
+        assert!(udp_triggers(allow_list(&[])).await);
+        assert!(udp_triggers(allow_list(&["127.0.0.0/8"])).await);
+        assert!(udp_triggers(allow_list(&["10.0.0.0/8", "127.0.0.1"])).await);
+
+        assert!(tcp_triggers(allow_list(&[])).await);
+        assert!(tcp_triggers(allow_list(&["127.0.0.0/8"])).await);
+    }
+
+    /// Where a client should send its trigger to reach a listener bound on the address
+    fn target_for(bind: IpAddr, client: IpAddr) -> IpAddr {
+        match bind.is_unspecified() {
+            true => client,
+            false => bind,
+        }
+    }
+
+    #[tokio::test]
+    async fn ipv6() {
+        let any = IpAddr::from(std::net::Ipv6Addr::UNSPECIFIED);
+        let loopback = IpAddr::from(std::net::Ipv6Addr::LOCALHOST);
+        assert!(udp_triggers_between(any, loopback, allow_list(&[])).await);
+        assert!(tcp_triggers_between(any, loopback, allow_list(&[])).await);
+        assert!(udp_triggers_between(loopback, loopback, allow_list(&["::1"])).await);
+        assert!(tcp_triggers_between(loopback, loopback, allow_list(&["::1"])).await);
+    }
+
+    /// Listening on `::` also accepts IPv4 clients
+    #[tokio::test]
+    async fn dual_stack() {
+        let any = IpAddr::from(std::net::Ipv6Addr::UNSPECIFIED);
+        let ipv4 = IpAddr::from([127, 0, 0, 1]);
+        assert!(udp_triggers_between(any, ipv4, allow_list(&["127.0.0.1"])).await);
+        assert!(tcp_triggers_between(any, ipv4, allow_list(&["127.0.0.1"])).await);
+    }
+
+    #[tokio::test]
+    async fn disallowed_addresses() {
+        assert!(!udp_triggers(allow_list(&["10.0.0.0/8"])).await);
+        assert!(!udp_triggers(allow_list(&["127.0.0.2"])).await);
+
+        assert!(!tcp_triggers(allow_list(&["10.0.0.0/8"])).await);
+        assert!(!tcp_triggers(allow_list(&["::1"])).await);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn unix_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("witness.sock");
+
+        // a stale socket from an earlier run is replaced
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+
+        let (stop_sender, _) = broadcast_channel(1);
+        let (triggers, mut receiver) = tokio::sync::mpsc::channel(1);
+        let handle = tokio::spawn(handle_unix_stream(
+            SocketFile::bind(&path).unwrap(),
+            stop_sender.subscribe(),
+            Auth::Keys(Arc::from(["secret".to_owned()])),
+            RateLimiter::new(None),
+            triggers,
+            Duration::from_secs(5),
+        ));
+
+        let timeout = std::time::Duration::from_secs(5);
+        let mut client = tokio::net::UnixStream::connect(&path).await.unwrap();
+        client.write_all(b"secret").await.unwrap();
+        let trigger = tokio::time::timeout(timeout, receiver.recv()).await;
+        let trigger = trigger.unwrap().unwrap();
+        assert_eq!(trigger.source, Some(TriggerSource::Unix));
+
+        let mut client = tokio::net::UnixStream::connect(&path).await.unwrap();
+        client.write_all(b"guess!").await.unwrap();
+        let short = std::time::Duration::from_millis(200);
+        assert!(tokio::time::timeout(short, receiver.recv()).await.is_err());
+
+        // the socket is removed once the listener stops
+        stop_sender.send(Stop).unwrap();
+        handle.await.unwrap().unwrap();
+        assert!(!path.exists());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn fifo() {
+        use std::io::Write;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("witness.fifo");
+
+        let (stop_sender, _) = broadcast_channel(1);
+        let (triggers, mut receiver) = tokio::sync::mpsc::channel(4);
+        let handle = tokio::spawn(handle_fifo(
+            Fifo::open(&path).unwrap(),
+            stop_sender.subscribe(),
+            Auth::Keys(Arc::from(["secret".to_owned()])),
+            RateLimiter::new(None),
+            triggers,
+        ));
+
+        let timeout = std::time::Duration::from_secs(5);
+        let short = std::time::Duration::from_millis(200);
+
+        // every writer closes the FIFO again, which must not stop the reader
+        for _ in 0..2 {
+            let mut writer = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+            writer.write_all(b"secret\n").unwrap();
+            drop(writer);
+
+            let trigger = tokio::time::timeout(timeout, receiver.recv()).await;
+            let trigger = trigger.unwrap().unwrap();
+            assert_eq!(trigger.source, Some(TriggerSource::Fifo));
+        }
+
+        // a line is only checked once it is complete
+        let mut writer = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        writer.write_all(b"guess!\nsec").unwrap();
+        assert!(tokio::time::timeout(short, receiver.recv()).await.is_err());
+        writer.write_all(b"ret\n").unwrap();
+        let trigger = tokio::time::timeout(timeout, receiver.recv()).await;
+        assert!(trigger.unwrap().is_some());
+
+        // the FIFO is removed once the listener stops, since it created it
+        stop_sender.send(Stop).unwrap();
+        handle.await.unwrap().unwrap();
+        assert!(!path.exists());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn fifo_existing() {
+        let dir = tempfile::tempdir().unwrap();
+
+        // a FIFO which already exists is kept
+        let path = dir.path().join("witness.fifo");
+        let name = std::ffi::CString::new(path.to_str().unwrap()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(name.as_ptr(), 0o600) }, 0);
+        drop(Fifo::open(&path).unwrap());
+        assert!(path.exists());
+
+        let file = dir.path().join("regular");
+        std::fs::write(&file, "").unwrap();
+        assert!(Fifo::open(&file).is_err());
+    }
+
+    /// Send the key over TCP to a listener with `--ack`, returning its response
+    async fn tcp_ack_response(key: &[u8]) -> Vec<u8> {
+        tcp_ack_response_with(&["secret"], key).await
+    }
+
+    fn tcp_options(protocol: TcpProtocol) -> TcpOptions {
+        TcpOptions {
+            protocol,
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    /// Send the key after a delay to a listener with the timeout, returning whether it triggered
+    async fn tcp_triggers_after(delay: Duration, timeout: Duration) -> bool {
+        let listener = bind_listener(IpAddr::from([127, 0, 0, 1]), 0, "TCP").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (stop_sender, _) = broadcast_channel(1);
+        let (triggers, mut receiver) = tokio::sync::mpsc::channel(1);
+        let options = TcpOptions {
+            protocol: TcpProtocol::Key { ack: true },
+            timeout,
+        };
+        tokio::spawn(handle_tcp_stream(
+            listener,
+            stop_sender.subscribe(),
+            allow_list(&[]),
+            Auth::Keys(Arc::from(["secret".to_owned()])),
+            RateLimiter::new(None),
+            triggers,
+            options,
+        ));
+
+        let mut client = tokio::net::TcpStream::connect(addr).await.unwrap();
+        tokio::time::sleep(delay).await;
+        // the listener may already have hung up
+        let _ = client.write_all(b"secret").await;
+        let mut response = Vec::new();
+        let _ = client.read_to_end(&mut response).await;
+        receiver.try_recv().is_ok()
+    }
+
+    /// A slow client is still accepted once the timeout is raised above the default
+    #[tokio::test]
+    async fn tcp_timeout() {
+        let slow = Duration::from_millis(5500);
+        assert!(tcp_triggers_after(slow, Duration::from_secs(10)).await);
+
+        let delay = Duration::from_millis(300);
+        assert!(!tcp_triggers_after(delay, Duration::from_millis(100)).await);
+    }
+
+    /// Send the key over TCP to a listener accepting the keys, returning its response
+    async fn tcp_ack_response_with(keys: &[&str], key: &[u8]) -> Vec<u8> {
+        let listener = bind_listener(IpAddr::from([127, 0, 0, 1]), 0, "TCP").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (stop_sender, _) = broadcast_channel(1);
+        let (triggers, _receiver) = tokio::sync::mpsc::channel(1);
+        tokio::spawn(handle_tcp_stream(
+            listener,
+            stop_sender.subscribe(),
+            allow_list(&[]),
+            Auth::Keys(keys.iter().map(|key| key.to_string()).collect()),
+            RateLimiter::new(None),
+            triggers,
+            tcp_options(TcpProtocol::Key { ack: true }),
+        ));
+
+        let mut client = tokio::net::TcpStream::connect(addr).await.unwrap();
+        client.write_all(key).await.unwrap();
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn tcp_ack() {
+        assert_eq!(tcp_ack_response(b"secret").await, ACK_OK);
+        assert_eq!(tcp_ack_response(b"public").await, ACK_DENIED);
+    }
+
+    /// Any of the keys is accepted, even when the client waits for a response before closing
+    #[tokio::test]
+    async fn multiple_keys() {
+        let keys = ["alice-key", "bob"];
+        assert_eq!(tcp_ack_response_with(&keys, b"alice-key").await, ACK_OK);
+        assert_eq!(tcp_ack_response_with(&keys, b"bob").await, ACK_OK);
+        assert_eq!(tcp_ack_response_with(&keys, b"eve").await, ACK_DENIED);
+    }
+
+    /// Start listening for JSON requests over TCP on an arbitrary port
+    async fn json_listener(key: &str) -> Listener {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (stop_sender, _) = broadcast_channel(1);
+        let (triggers, receiver) = tokio::sync::mpsc::channel(4);
+        tokio::spawn(handle_tcp_stream(
+            listener,
+            stop_sender.subscribe(),
+            Arc::from([]),
+            Auth::Keys(Arc::from([key.to_owned()])),
+            RateLimiter::new(None),
+            triggers,
+            tcp_options(TcpProtocol::Json),
+        ));
+        Listener {
+            addr,
+            triggers: receiver,
+            _stop: stop_sender,
+        }
+    }
+
+    /// Send each line as a request, and return the responses
+    async fn send_json(addr: SocketAddr, lines: &[&str]) -> Vec<String> {
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let mut stream = BufReader::new(stream);
+        let mut responses = Vec::new();
+        for line in lines {
+            stream.write_all(line.as_bytes()).await.unwrap();
+            stream.write_all(b"\n").await.unwrap();
+            let mut response = String::new();
+            stream.read_line(&mut response).await.unwrap();
+            responses.push(response);
+        }
+        responses
+    }
+
+    #[tokio::test]
+    async fn json_request() {
+        let mut listener = json_listener("secret").await;
+        let responses = send_json(
+            listener.addr,
+            &[
+                r#"{"key": "secret", "rule": "deploy", "args": ["--fast"]}"#,
+                r#"{"key": "secret"}"#,
+            ],
+        )
+        .await;
+        assert_eq!(responses, ["{\"status\":\"ok\"}\n"; 2]);
+
+        let trigger = listener.triggers.try_recv().unwrap();
+        assert!(matches!(trigger.source, Some(TriggerSource::Tcp(_))));
+        assert_eq!(trigger.rule.as_deref(), Some("deploy"));
+        assert_eq!(trigger.args, ["--fast"]);
+
+        let trigger = listener.triggers.try_recv().unwrap();
+        assert_eq!(trigger.rule, None);
+        assert!(trigger.args.is_empty());
+    }
+
+    #[tokio::test]
+    async fn json_rejected() {
+        let mut listener = json_listener("secret").await;
+        let responses = send_json(
+            listener.addr,
+            &[
+                "secret",
+                r#"{"key": "secret", "rule": "#,
+                r#"{"key": "guess", "rule": "deploy"}"#,
+                r#"{"rule": "deploy"}"#,
+            ],
+        )
+        .await;
+        assert!(responses[0].starts_with("{\"status\":\"error\",\"error\":"));
+        assert!(responses[1].starts_with("{\"status\":\"error\",\"error\":"));
+        assert_eq!(responses[2], "{\"status\":\"denied\"}\n");
+        assert!(
+            responses[3].contains("missing field `key`"),
+            "{}",
+            responses[3]
+        );
+        assert!(listener.triggers.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn json_request_too_long() {
+        let mut listener = json_listener("secret").await;
+        let args = vec!["x"; json::MAX_REQUEST];
+        let request = serde_json::json!({ "key": "secret", "args": args }).to_string();
+        let responses = send_json(listener.addr, &[&request]).await;
+        assert!(
+            responses[0].contains("requests are limited to"),
+            "{}",
+            responses[0]
+        );
+        assert!(listener.triggers.try_recv().is_err());
+    }
+}
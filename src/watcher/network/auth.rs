@@ -0,0 +1,418 @@
+//! Authentication of network triggers.
+//!
+//! By default a trigger is authenticated by sending a plaintext key. In HMAC mode the client
+//! instead sends the current time followed by `HMAC-SHA256(secret, timestamp)`, both hex-encoded:
+//!
+//! ```text
+//! <16 hex digits: milliseconds since the UNIX epoch><64 hex digits: MAC>
+//! ```
+//!
+//! The listener rejects messages whose timestamp is too far from its own clock, and remembers the
+//! timestamps it accepted until then, so a captured message cannot be replayed.
+
+use std::{
+    collections::BTreeSet,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::cli;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Number of hex digits used to encode the timestamp
+const TIMESTAMP_LENGTH: usize = 16;
+
+/// Number of bytes in a SHA-256 MAC
+const MAC_BYTES: usize = 32;
+
+/// Length of a signed message
+const MESSAGE_LENGTH: usize = TIMESTAMP_LENGTH + 2 * MAC_BYTES;
+
+/// Signed messages are only valid if their timestamp is within this duration of the current time.
+const MAX_CLOCK_SKEW: Duration = Duration::from_secs(30);
+
+/// Environment variable which takes precedence over `--key`
+const KEY_VARIABLE: &str = "WITNESS_KEY";
+
+/// Decides if a network message should trigger execution.
+#[derive(Clone)]
+pub enum Auth {
+    /// The message must start with any of these keys
+    Keys(Arc<[String]>),
+    /// The message must be signed with this secret, with a timestamp which was not accepted before
+    Hmac {
+        secret: Arc<[u8]>,
+        /// Timestamps of accepted messages which are not stale yet
+        accepted: Arc<Mutex<BTreeSet<u64>>>,
+    },
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum AuthError {
+    /// The message did not contain the expected key
+    WrongKey,
+    /// The message was not a valid signed message
+    Malformed,
+    /// The MAC did not match the timestamp
+    InvalidMac,
+    /// The timestamp was too far from the current time
+    Stale,
+    /// A message with the same timestamp was already accepted
+    Replayed,
+}
+
+impl Auth {
+    pub fn from_args(options: &cli::NetworkOptions) -> anyhow::Result<Auth> {
+        match &options.hmac {
+            Some(secret) => Ok(Auth::signed(secret.as_bytes())),
+            None => {
+                let variable = std::env::var(KEY_VARIABLE).ok();
+                let keys = select_keys(options, variable)?;
+                Ok(Auth::Keys(Arc::from(keys)))
+            }
+        }
+    }
+
+    /// Messages must be signed with the secret
+    fn signed(secret: &[u8]) -> Auth {
+        Auth::Hmac {
+            secret: Arc::from(secret),
+            accepted: Arc::default(),
+        }
+    }
+
+    /// Number of bytes needed to authenticate a message with any of the keys
+    pub fn message_length(&self) -> usize {
+        match self {
+            Auth::Keys(keys) => keys.iter().map(String::len).max().unwrap_or(0),
+            Auth::Hmac { .. } => MESSAGE_LENGTH,
+        }
+    }
+
+    /// Returns `true` if the message does not authenticate yet, but could once more of it has
+    /// been received.
+    pub fn incomplete(&self, message: &[u8]) -> bool {
+        // only checked, so that the message is still accepted once it is verified
+        if self.authenticate_at(message, SystemTime::now()).is_ok() {
+            return false;
+        }
+
+        match self {
+            Auth::Keys(keys) => keys.iter().any(|key| key.as_bytes().starts_with(message)),
+            Auth::Hmac { .. } => message.len() < MESSAGE_LENGTH,
+        }
+    }
+
+    /// Create a message which authenticates a trigger sent at the current time. Only the first
+    /// key is sent.
+    pub fn message(&self) -> Vec<u8> {
+        self.message_at(SystemTime::now())
+    }
+
+    fn message_at(&self, time: SystemTime) -> Vec<u8> {
+        match self {
+            Auth::Keys(keys) => keys
+                .first()
+                .map(String::as_bytes)
+                .unwrap_or_default()
+                .to_vec(),
+            Auth::Hmac { secret, .. } => {
+                let timestamp = format!("{:016x}", unix_millis(time));
+                let mac = hmac(secret, timestamp.as_bytes()).finalize().into_bytes();
+                let mut message = timestamp.into_bytes();
+                message.extend(encode_hex(&mac).into_bytes());
+                message
+            }
+        }
+    }
+
+    /// Check that the message authenticates a trigger. A signed message is only accepted once.
+    pub fn verify(&self, message: &[u8]) -> Result<(), AuthError> {
+        self.verify_at(message, SystemTime::now())
+    }
+
+    fn verify_at(&self, message: &[u8], now: SystemTime) -> Result<(), AuthError> {
+        let timestamp = self.authenticate_at(message, now)?;
+        let (Auth::Hmac { accepted, .. }, Some(timestamp)) = (self, timestamp) else {
+            return Ok(());
+        };
+
+        // older timestamps are rejected as stale, so they need not be remembered
+        let mut accepted = accepted.lock().unwrap();
+        let oldest = unix_millis(now).saturating_sub(MAX_CLOCK_SKEW.as_millis() as u64);
+        *accepted = accepted.split_off(&oldest);
+        match accepted.insert(timestamp) {
+            true => Ok(()),
+            false => Err(AuthError::Replayed),
+        }
+    }
+
+    /// Check that the message authenticates a trigger, returning the timestamp of a signed
+    /// message
+    fn authenticate_at(&self, message: &[u8], now: SystemTime) -> Result<Option<u64>, AuthError> {
+        match self {
+            Auth::Keys(keys) if keys.iter().any(|key| message.starts_with(key.as_bytes())) => {
+                Ok(None)
+            }
+            Auth::Keys(_) => Err(AuthError::WrongKey),
+            Auth::Hmac { secret, .. } => {
+                let message = message.get(..MESSAGE_LENGTH).ok_or(AuthError::Malformed)?;
+                let (timestamp, mac) = message.split_at(TIMESTAMP_LENGTH);
+                let mac = decode_hex(mac).ok_or(AuthError::Malformed)?;
+
+                hmac(secret, timestamp)
+                    .verify_slice(&mac)
+                    .map_err(|_| AuthError::InvalidMac)?;
+
+                let timestamp = std::str::from_utf8(timestamp).map_err(|_| AuthError::Malformed)?;
+                let timestamp =
+                    u64::from_str_radix(timestamp, 16).map_err(|_| AuthError::Malformed)?;
+                let skew = unix_millis(now).abs_diff(timestamp);
+                if u128::from(skew) > MAX_CLOCK_SKEW.as_millis() {
+                    return Err(AuthError::Stale);
+                }
+
+                Ok(Some(timestamp))
+            }
+        }
+    }
+}
+
+fn hmac(secret: &[u8], data: &[u8]) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    u64::try_from(since_epoch.as_millis()).unwrap_or(u64::MAX)
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn decode_hex(text: &[u8]) -> Option<Vec<u8>> {
+    fn digit(char: u8) -> Option<u8> {
+        (char as char).to_digit(16).map(|digit| digit as u8)
+    }
+
+    text.chunks(2)
+        .map(|pair| match *pair {
+            [high, low] => Some(digit(high)? << 4 | digit(low)?),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Pick the key from `--key-file`, the environment variable or `--key`, in that order. Only
+/// `--key` may be given several keys.
+fn select_keys(
+    options: &cli::NetworkOptions,
+    variable: Option<String>,
+) -> anyhow::Result<Vec<String>> {
+    if let Some(path) = &options.key_file {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read key file: {}", path.display()))?;
+        let key = contents.strip_suffix('\n').unwrap_or(&contents);
+        let key = key.strip_suffix('\r').unwrap_or(key);
+        return Ok(vec![key.to_owned()]);
+    }
+
+    match variable {
+        Some(key) => Ok(vec![key]),
+        None => Ok(options.key.clone()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hmac_auth() -> Auth {
+        Auth::signed(b"secret")
+    }
+
+    #[test]
+    fn valid_mac() {
+        let auth = hmac_auth();
+        let message = auth.message();
+        assert_eq!(message.len(), auth.message_length());
+        assert_eq!(auth.verify(&message), Ok(()));
+    }
+
+    #[test]
+    fn tampered_mac() {
+        let auth = hmac_auth();
+
+        let mut message = auth.message();
+        let last = message.last_mut().unwrap();
+        *last = if *last == b'0' { b'1' } else { b'0' };
+        assert_eq!(auth.verify(&message), Err(AuthError::InvalidMac));
+
+        // the timestamp is covered by the MAC
+        let mut message = auth.message();
+        message[TIMESTAMP_LENGTH - 1] ^= 1;
+        assert_eq!(auth.verify(&message), Err(AuthError::InvalidMac));
+
+        // signed with a different secret
+        let other = Auth::signed(b"other");
+        assert_eq!(auth.verify(&other.message()), Err(AuthError::InvalidMac));
+    }
+
+    #[test]
+    fn stale_timestamp() {
+        let auth = hmac_auth();
+        let now = SystemTime::now();
+        let skew = MAX_CLOCK_SKEW + Duration::from_secs(1);
+
+        let old = auth.message_at(now - skew);
+        assert_eq!(auth.verify_at(&old, now), Err(AuthError::Stale));
+
+        let future = auth.message_at(now + skew);
+        assert_eq!(auth.verify_at(&future, now), Err(AuthError::Stale));
+
+        let recent = auth.message_at(now - Duration::from_secs(1));
+        assert_eq!(auth.verify_at(&recent, now), Ok(()));
+    }
+
+    #[test]
+    fn replayed_message() {
+        let auth = hmac_auth();
+        let now = SystemTime::now();
+
+        let message = auth.message_at(now);
+        assert!(!auth.incomplete(&message));
+        assert_eq!(auth.verify_at(&message, now), Ok(()));
+        assert_eq!(auth.verify_at(&message, now), Err(AuthError::Replayed));
+
+        // copies of the listener share the accepted messages
+        let copy = auth.clone();
+        let later = now + Duration::from_secs(1);
+        assert_eq!(copy.verify_at(&message, later), Err(AuthError::Replayed));
+
+        let next = auth.message_at(now + Duration::from_millis(1));
+        assert_eq!(auth.verify_at(&next, later), Ok(()));
+
+        // once stale, the timestamps are forgotten
+        let future = now + MAX_CLOCK_SKEW + Duration::from_secs(2);
+        let message = auth.message_at(future);
+        assert_eq!(auth.verify_at(&message, future), Ok(()));
+        assert_eq!(*auth_accepted(&auth), [unix_millis(future)].into());
+    }
+
+    fn auth_accepted(auth: &Auth) -> std::sync::MutexGuard<'_, BTreeSet<u64>> {
+        match auth {
+            Auth::Hmac { accepted, .. } => accepted.lock().unwrap(),
+            Auth::Keys(_) => panic!("not signed"),
+        }
+    }
+
+    #[test]
+    fn malformed_message() {
+        let auth = hmac_auth();
+        assert_eq!(auth.verify(b"witness-key"), Err(AuthError::Malformed));
+        assert_eq!(
+            auth.verify(&[b'z'; MESSAGE_LENGTH]),
+            Err(AuthError::Malformed)
+        );
+    }
+
+    #[test]
+    fn plaintext_key() {
+        let auth = Auth::Keys(Arc::from(["witness-key".to_owned()]));
+        assert_eq!(auth.message(), b"witness-key");
+        assert_eq!(auth.verify(b"witness-key"), Ok(()));
+        assert_eq!(auth.verify(b"witness"), Err(AuthError::WrongKey));
+    }
+
+    #[test]
+    fn multiple_keys() {
+        let auth = Auth::Keys(Arc::from(["first".to_owned(), "second-key".to_owned()]));
+        assert_eq!(auth.message(), b"first");
+        assert_eq!(auth.message_length(), "second-key".len());
+        assert_eq!(auth.verify(b"first"), Ok(()));
+        assert_eq!(auth.verify(b"second-key"), Ok(()));
+        assert_eq!(auth.verify(b"third"), Err(AuthError::WrongKey));
+
+        assert!(auth.incomplete(b"sec"));
+        assert!(!auth.incomplete(b"first"));
+        assert!(!auth.incomplete(b"thi"));
+
+        let options = network_options(&["--key", "first", "--key", "second-key"]);
+        assert_eq!(
+            select_keys(&options, None).unwrap(),
+            ["first", "second-key"]
+        );
+    }
+
+    fn network_options(args: &[&str]) -> cli::NetworkOptions {
+        use clap::Parser;
+        let args = ["witness"].iter().chain(args).chain(&["true"]);
+        cli::Arguments::parse_from(args).network
+    }
+
+    fn key_file(contents: &str) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut file, contents.as_bytes()).unwrap();
+        file
+    }
+
+    #[test]
+    fn key_sources() {
+        let options = network_options(&[]);
+        assert_eq!(select_keys(&options, None).unwrap(), ["witness-key"]);
+
+        let options = network_options(&["--key", "flag"]);
+        assert_eq!(select_keys(&options, None).unwrap(), ["flag"]);
+
+        let options = network_options(&[]);
+        assert_eq!(select_keys(&options, Some("env".into())).unwrap(), ["env"]);
+
+        // only a single trailing newline is removed
+        let file = key_file("from file\n");
+        let options = network_options(&["--key-file", file.path().to_str().unwrap()]);
+        assert_eq!(select_keys(&options, None).unwrap(), ["from file"]);
+        let file = key_file("crlf\r\n");
+        let options = network_options(&["--key-file", file.path().to_str().unwrap()]);
+        assert_eq!(select_keys(&options, None).unwrap(), ["crlf"]);
+        let file = key_file(" spaced \n\n");
+        let options = network_options(&["--key-file", file.path().to_str().unwrap()]);
+        assert_eq!(select_keys(&options, None).unwrap(), [" spaced \n"]);
+    }
+
+    /// The key file takes precedence over the environment, which takes precedence over `--key`
+    #[test]
+    fn key_precedence() {
+        let file = key_file("from file\n");
+        let path = file.path().to_str().unwrap();
+
+        let options = network_options(&["--key", "flag", "--key-file", path]);
+        assert_eq!(
+            select_keys(&options, Some("env".into())).unwrap(),
+            ["from file"]
+        );
+
+        let options = network_options(&["--key", "flag"]);
+        assert_eq!(select_keys(&options, Some("env".into())).unwrap(), ["env"]);
+    }
+
+    #[test]
+    fn missing_key_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("missing.key");
+        let options = network_options(&["--key-file", path.to_str().unwrap()]);
+        let error = select_keys(&options, None).unwrap_err();
+        assert!(
+            error.to_string().contains("failed to read key file"),
+            "{error}"
+        );
+        assert!(Auth::from_args(&options).is_err());
+    }
+}
@@ -0,0 +1,120 @@
+//! Just enough HTTP/1.1 to accept webhook triggers.
+
+use anyhow::{anyhow, Context};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Header which may contain the key
+pub const KEY_HEADER: &str = "X-Witness-Key";
+
+/// Upper bound on the size of the request line and each header
+const MAX_LINE_LENGTH: u64 = 8 * 1024;
+
+/// Upper bound on the number of headers in a request
+const MAX_HEADERS: usize = 64;
+
+/// Upper bound on the size of the request body
+const MAX_BODY_LENGTH: usize = 64 * 1024;
+
+#[derive(Debug)]
+pub struct Request {
+    pub method: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// Get the value of the header with the given name, ignoring case
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+pub async fn read_request<R>(reader: &mut R) -> anyhow::Result<Request>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let request_line = read_line(reader).await?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().context("missing request method")?.to_owned();
+    let _target = parts.next().context("missing request target")?;
+    let version = parts.next().context("missing HTTP version")?;
+    if !version.starts_with("HTTP/1.") {
+        return Err(anyhow!("unsupported HTTP version: {version}"));
+    }
+
+    let mut headers = Vec::new();
+    loop {
+        let line = read_line(reader).await?;
+        if line.is_empty() {
+            break;
+        }
+        if headers.len() == MAX_HEADERS {
+            return Err(anyhow!("too many headers"));
+        }
+        let (name, value) = line
+            .split_once(':')
+            .with_context(|| format!("malformed header: {line:?}"))?;
+        headers.push((name.trim().to_owned(), value.trim().to_owned()));
+    }
+
+    let mut request = Request {
+        method,
+        headers,
+        body: Vec::new(),
+    };
+
+    if let Some(length) = request.header("Content-Length") {
+        let length: usize = length
+            .parse()
+            .with_context(|| format!("invalid content length: {length}"))?;
+        if length > MAX_BODY_LENGTH {
+            return Err(anyhow!("request body too large: {length} bytes"));
+        }
+        request.body.resize(length, 0);
+        reader
+            .read_exact(&mut request.body)
+            .await
+            .context("failed to read request body")?;
+    }
+
+    Ok(request)
+}
+
+/// Read a single line, without the trailing line break
+async fn read_line<R>(reader: &mut R) -> anyhow::Result<String>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let mut line = String::new();
+    let count = reader
+        .take(MAX_LINE_LENGTH)
+        .read_line(&mut line)
+        .await
+        .context("failed to read request")?;
+    if count == 0 {
+        return Err(anyhow!("unexpected end of request"));
+    }
+    if !line.ends_with('\n') {
+        return Err(anyhow!("request line too long"));
+    }
+    line.truncate(line.trim_end_matches(&['\r', '\n'][..]).len());
+    Ok(line)
+}
+
+pub async fn write_response<W>(writer: &mut W, status: u16, reason: &str) -> anyhow::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let response = format!(
+        "HTTP/1.1 {status} {reason}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{reason}\n",
+        reason.len() + 1
+    );
+    writer
+        .write_all(response.as_bytes())
+        .await
+        .context("failed to write response")?;
+    writer.flush().await.context("failed to write response")
+}
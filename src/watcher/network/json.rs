@@ -0,0 +1,117 @@
+//! The line-delimited JSON protocol of `--tcp-json`.
+//!
+//! Each line sent by the client is a request naming the rule to trigger, along with arguments for
+//! the command:
+//!
+//! ```text
+//! {"key": "secret", "rule": "deploy", "args": ["--fast"]}
+//! ```
+//!
+//! Only `key` is required. Every request is answered with a single line:
+//!
+//! ```text
+//! {"status":"ok"}
+//! {"status":"denied"}
+//! {"status":"error","error":"missing field `key` at line 1 column 2"}
+//! ```
+
+use serde::{Deserialize, Serialize};
+
+/// Requests longer than this are rejected, so that a client cannot make us buffer without bound
+pub const MAX_REQUEST: usize = 64 * 1024;
+
+#[derive(Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Request {
+    /// Authenticates the request, in the same way as the plain TCP protocol
+    pub key: String,
+
+    /// The name of the rule to trigger
+    #[serde(default)]
+    pub rule: Option<String>,
+
+    /// Appended to the command line
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum Response {
+    Ok,
+    Denied,
+    Error { error: String },
+}
+
+impl Request {
+    pub fn parse(line: &str) -> Result<Request, String> {
+        let request = serde_json::from_str::<Request>(line).map_err(|error| error.to_string())?;
+        if request.rule.as_deref().is_some_and(str::is_empty) {
+            return Err("the rule must not be empty".to_owned());
+        }
+        Ok(request)
+    }
+}
+
+impl Response {
+    /// The response as sent to the client, including the trailing newline
+    pub fn to_line(&self) -> Vec<u8> {
+        let mut line = serde_json::to_vec(self).expect("responses are valid JSON");
+        line.push(b'\n');
+        line
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_requests() {
+        let request =
+            Request::parse(r#"{"key": "secret", "rule": "deploy", "args": ["--fast", "a b"]}"#)
+                .unwrap();
+        assert_eq!(
+            request,
+            Request {
+                key: "secret".to_owned(),
+                rule: Some("deploy".to_owned()),
+                args: vec!["--fast".to_owned(), "a b".to_owned()],
+            }
+        );
+
+        let request = Request::parse(r#"{"key": ""}"#).unwrap();
+        assert_eq!(request.rule, None);
+        assert!(request.args.is_empty());
+    }
+
+    #[test]
+    fn invalid_requests() {
+        let invalid = [
+            "secret",
+            "{",
+            "[]",
+            r#"{"rule": "deploy"}"#,
+            r#"{"key": 42}"#,
+            r#"{"key": "secret", "rule": ""}"#,
+            r#"{"key": "secret", "args": "--fast"}"#,
+            r#"{"key": "secret", "ruel": "deploy"}"#,
+        ];
+        for line in invalid {
+            assert!(Request::parse(line).is_err(), "{line}");
+        }
+    }
+
+    #[test]
+    fn responses() {
+        assert_eq!(Response::Ok.to_line(), b"{\"status\":\"ok\"}\n");
+        assert_eq!(Response::Denied.to_line(), b"{\"status\":\"denied\"}\n");
+        let error = Response::Error {
+            error: "bad \"json\"".to_owned(),
+        };
+        assert_eq!(
+            error.to_line(),
+            b"{\"status\":\"error\",\"error\":\"bad \\\"json\\\"\"}\n"
+        );
+    }
+}
@@ -0,0 +1,109 @@
+//! Limits how often network triggers are accepted.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::cli;
+
+/// A token bucket shared by all network listeners.
+///
+/// The bucket holds up to `count` tokens and regains them at a steady rate of `count` per
+/// `period`. Every trigger consumes one token, and triggers are rejected while the bucket is empty.
+#[derive(Clone)]
+pub struct RateLimiter {
+    bucket: Option<Arc<Mutex<TokenBucket>>>,
+}
+
+struct TokenBucket {
+    capacity: f64,
+    /// Time to regain a single token
+    refill: Duration,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(limit: Option<cli::RateLimit>) -> RateLimiter {
+        let bucket = limit.map(|limit| {
+            Arc::new(Mutex::new(TokenBucket {
+                capacity: f64::from(limit.count),
+                refill: limit.period / limit.count,
+                tokens: f64::from(limit.count),
+                updated: Instant::now(),
+            }))
+        });
+        RateLimiter { bucket }
+    }
+
+    /// Consume a token if one is available, returning `true` if the trigger should be accepted
+    pub fn allow(&self) -> bool {
+        self.allow_at(Instant::now())
+    }
+
+    fn allow_at(&self, now: Instant) -> bool {
+        let bucket = match &self.bucket {
+            None => return true,
+            Some(bucket) => bucket,
+        };
+
+        let mut bucket = bucket.lock().unwrap();
+        let elapsed = now.saturating_duration_since(bucket.updated);
+        let regained = elapsed.as_secs_f64() / bucket.refill.as_secs_f64();
+        bucket.tokens = f64::min(bucket.capacity, bucket.tokens + regained);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(count: u32, period: Duration) -> RateLimiter {
+        RateLimiter::new(Some(cli::RateLimit { count, period }))
+    }
+
+    #[test]
+    fn unlimited() {
+        let limiter = RateLimiter::new(None);
+        assert!((0..1000).all(|_| limiter.allow()));
+    }
+
+    #[test]
+    fn burst_then_refill() {
+        let limiter = limiter(3, Duration::from_secs(3));
+        let start = Instant::now();
+
+        let accepted = (0..10).filter(|_| limiter.allow_at(start)).count();
+        assert_eq!(accepted, 3);
+
+        // a token is regained every second
+        assert!(!limiter.allow_at(start + Duration::from_millis(500)));
+        assert!(limiter.allow_at(start + Duration::from_millis(1000)));
+        assert!(!limiter.allow_at(start + Duration::from_millis(1100)));
+
+        // never more than the capacity
+        let later = start + Duration::from_secs(60);
+        let accepted = (0..10).filter(|_| limiter.allow_at(later)).count();
+        assert_eq!(accepted, 3);
+    }
+
+    /// Clones share the same bucket
+    #[test]
+    fn shared() {
+        let limiter = limiter(2, Duration::from_secs(60));
+        let other = limiter.clone();
+        assert!(limiter.allow());
+        assert!(other.allow());
+        assert!(!limiter.allow());
+        assert!(!other.allow());
+    }
+}
@@ -0,0 +1,2156 @@
+//! End-to-end tests which run the `witness` binary against a temporary directory.
+
+use std::{
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    time::{Duration, Instant},
+};
+
+/// A running instance of `witness` which is killed when dropped.
+struct Witness {
+    child: Child,
+}
+
+impl Witness {
+    fn spawn(args: &[&str]) -> Witness {
+        Self::spawn_with_stdout(args, Stdio::null())
+    }
+
+    fn spawn_with_stdout(args: &[&str], stdout: impl Into<Stdio>) -> Witness {
+        let child = Command::new(env!("CARGO_BIN_EXE_witness"))
+            .args(["--shell", "sh", "--no-clear"])
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(stdout)
+            .stderr(Stdio::null())
+            .env_remove("WITNESS_LOG")
+            .spawn()
+            .expect("failed to spawn witness");
+        Witness { child }
+    }
+
+    /// Wait for witness to exit on its own
+    fn wait_exit(&mut self, timeout: Duration) -> Option<std::process::ExitStatus> {
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
+            if let Some(status) = self.child.try_wait().unwrap() {
+                return Some(status);
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        None
+    }
+}
+
+/// Run `witness --trigger` with the given arguments
+fn trigger(args: &[&str]) -> std::process::ExitStatus {
+    Command::new(env!("CARGO_BIN_EXE_witness"))
+        .arg("--trigger")
+        .args(args)
+        .stderr(Stdio::null())
+        .status()
+        .expect("failed to run witness --trigger")
+}
+
+/// Find a port which is currently not in use
+fn free_port() -> u16 {
+    let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.local_addr().unwrap().port()
+}
+
+impl Drop for Witness {
+    fn drop(&mut self) {
+        // interrupt witness so that it gets a chance to kill the command
+        #[cfg(unix)]
+        unsafe {
+            libc::kill(self.child.id() as libc::pid_t, libc::SIGINT);
+        }
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            if let Ok(Some(_)) = self.child.try_wait() {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Wait until the file's contents satisfy the predicate, returning the last contents read.
+fn wait_for(path: &Path, predicate: impl Fn(&str) -> bool) -> String {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        let contents = std::fs::read_to_string(path).unwrap_or_default();
+        if predicate(&contents) || Instant::now() > deadline {
+            return contents;
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+}
+
+fn path_str(path: &Path) -> &str {
+    path.to_str().unwrap()
+}
+
+/// Create a directory to watch and a separate directory for output files.
+fn directories() -> (tempfile::TempDir, tempfile::TempDir) {
+    (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap())
+}
+
+fn output_file(dir: &tempfile::TempDir) -> PathBuf {
+    dir.path().join("output")
+}
+
+#[test]
+fn changed_path_variable() {
+    let (watched, output) = directories();
+    let output = output_file(&output);
+
+    let command = format!(
+        "printf '%s;' \"${{WITNESS_CHANGED_PATH-unset}}\" >> {}",
+        path_str(&output)
+    );
+    let _witness = Witness::spawn(&[
+        "--path",
+        path_str(watched.path()),
+        "--no-git-ignore",
+        "--initial",
+        "--debounce",
+        "10ms",
+        &command,
+    ]);
+
+    // the first run is not caused by any file
+    assert_eq!(wait_for(&output, |text| !text.is_empty()), "unset;");
+
+    let file = watched.path().join("file.txt");
+    std::fs::write(&file, "hello").unwrap();
+
+    let contents = wait_for(&output, |text| text.ends_with("file.txt;"));
+    assert_eq!(contents, format!("unset;{};", path_str(&file)));
+}
+
+#[test]
+fn poll_watcher() {
+    let (watched, output) = directories();
+    let output = output_file(&output);
+
+    let command = format!("echo run >> {}", path_str(&output));
+    let _witness = Witness::spawn(&[
+        "--path",
+        path_str(watched.path()),
+        "--no-git-ignore",
+        "--initial",
+        "--poll",
+        "50ms",
+        "--debounce",
+        "10ms",
+        &command,
+    ]);
+    assert_eq!(wait_for(&output, |text| !text.is_empty()), "run\n");
+
+    std::fs::write(watched.path().join("file.txt"), "hello").unwrap();
+    assert_eq!(wait_for(&output, |text| text.len() > 4), "run\nrun\n");
+}
+
+#[test]
+fn initial_run() {
+    let (watched, output) = directories();
+    let output = output_file(&output);
+
+    let command = format!("echo run >> {}", path_str(&output));
+    let _witness = Witness::spawn(&["--path", path_str(watched.path()), "--initial", &command]);
+
+    assert_eq!(wait_for(&output, |text| !text.is_empty()), "run\n");
+    std::thread::sleep(Duration::from_millis(500));
+    assert_eq!(std::fs::read_to_string(&output).unwrap(), "run\n");
+}
+
+#[test]
+fn wait_for_first_trigger() {
+    let (watched, output) = directories();
+    let output = output_file(&output);
+
+    let command = format!("echo run >> {}", path_str(&output));
+    let _witness = Witness::spawn(&[
+        "--path",
+        path_str(watched.path()),
+        "--no-git-ignore",
+        "--debounce",
+        "10ms",
+        &command,
+    ]);
+
+    std::thread::sleep(Duration::from_millis(500));
+    assert!(!output.exists());
+
+    std::fs::write(watched.path().join("file.txt"), "hello").unwrap();
+    assert_eq!(wait_for(&output, |text| !text.is_empty()), "run\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn terminate_with_signal() {
+    let (watched, output) = directories();
+    let output = output_file(&output);
+
+    let command = format!(
+        "trap 'echo cleanup >> {0}; exit 0' TERM; echo started >> {0}; while true; do sleep 0.05; done",
+        path_str(&output)
+    );
+    let _witness = Witness::spawn(&[
+        "--path",
+        path_str(watched.path()),
+        "--no-git-ignore",
+        "--initial",
+        "--debounce",
+        "10ms",
+        "--signal",
+        "TERM",
+        &command,
+    ]);
+    assert_eq!(wait_for(&output, |text| !text.is_empty()), "started\n");
+
+    std::fs::write(watched.path().join("file.txt"), "hello").unwrap();
+    let contents = wait_for(&output, |text| text.matches("started").count() == 2);
+    assert_eq!(contents, "started\ncleanup\nstarted\n");
+}
+
+#[test]
+fn restart_delay() {
+    let (watched, output) = directories();
+    let output = output_file(&output);
+
+    let command = format!("echo run >> {}; sleep 10", path_str(&output));
+    let _witness = Witness::spawn(&[
+        "--path",
+        path_str(watched.path()),
+        "--no-git-ignore",
+        "--initial",
+        "--debounce",
+        "10ms",
+        "--restart-delay",
+        "500ms",
+        &command,
+    ]);
+    assert_eq!(wait_for(&output, |text| !text.is_empty()), "run\n");
+
+    let start = Instant::now();
+    std::fs::write(watched.path().join("file.txt"), "hello").unwrap();
+    assert_eq!(wait_for(&output, |text| text.len() > 4), "run\nrun\n");
+    assert!(start.elapsed() >= Duration::from_millis(500));
+}
+
+#[test]
+fn once_exit_code() {
+    let port = free_port().to_string();
+    let mut witness = Witness::spawn(&["--udp", &port, "--once", "exit 3"]);
+
+    // keep triggering until witness starts listening
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let status = loop {
+        trigger(&["--udp", &port]);
+        if let Some(status) = witness.wait_exit(Duration::from_millis(100)) {
+            break status;
+        }
+        assert!(Instant::now() < deadline, "witness did not exit");
+    };
+
+    assert_eq!(status.code(), Some(3));
+}
+
+/// Replace the file's contents the way many editors do: write a new file and rename it over the
+/// old one.
+fn atomic_save(path: &Path, contents: &str) {
+    let temporary = path.with_extension("tmp");
+    std::fs::write(&temporary, contents).unwrap();
+    std::fs::rename(&temporary, path).unwrap();
+}
+
+#[test]
+fn watch_single_file() {
+    let (watched, output) = directories();
+    let output = output_file(&output);
+    let file = watched.path().join("config.toml");
+    std::fs::write(&file, "").unwrap();
+
+    let command = format!("echo \"$WITNESS_CHANGED_PATH\" >> {}", path_str(&output));
+    let _witness = Witness::spawn(&[
+        "--path",
+        path_str(&file),
+        "--no-git-ignore",
+        "--debounce",
+        "10ms",
+        &command,
+    ]);
+    std::thread::sleep(Duration::from_millis(500));
+
+    // the watch survives the file being replaced
+    let expected = format!("{}\n", path_str(&file));
+    atomic_save(&file, "a");
+    assert_eq!(wait_for(&output, |text| !text.is_empty()), expected);
+
+    std::thread::sleep(Duration::from_millis(200));
+    atomic_save(&file, "b");
+    let contents = wait_for(&output, |text| text.lines().count() >= 2);
+    assert_eq!(contents, expected.repeat(2));
+
+    // other files in the same directory are not watched
+    std::fs::write(watched.path().join("other.txt"), "").unwrap();
+    std::thread::sleep(Duration::from_millis(200));
+    assert_eq!(
+        std::fs::read_to_string(&output).unwrap(),
+        expected.repeat(2)
+    );
+}
+
+#[test]
+fn dry_run() {
+    let (watched, output) = directories();
+    let output = output_file(&output);
+
+    let _witness = Witness::spawn_with_stdout(
+        &[
+            "--path",
+            path_str(watched.path()),
+            "--no-git-ignore",
+            "--debounce",
+            "10ms",
+            "-e",
+            "txt",
+            "--dry-run",
+        ],
+        std::fs::File::create(&output).unwrap(),
+    );
+    std::thread::sleep(Duration::from_millis(500));
+
+    std::fs::write(watched.path().join("ignored.log"), "").unwrap();
+    let accepted = watched.path().join("accepted.txt");
+    std::fs::write(&accepted, "").unwrap();
+
+    let contents = wait_for(&output, |text| text.contains("accepted.txt"));
+    let line = contents.lines().next().unwrap();
+    let (time, path) = line.split_once(' ').unwrap();
+    assert!(time.ends_with('Z'), "{line}");
+    assert_eq!(path, path_str(&accepted));
+    assert!(!contents.contains("ignored.log"), "{contents}");
+}
+
+/// A command which records how many times it has run in the file, failing until it has run
+/// `successful_run` times.
+fn flaky_command(counter: &Path, successful_run: u32) -> String {
+    let counter = path_str(counter);
+    format!(
+        "n=$(( $(cat {counter} 2>/dev/null || echo 0) + 1 )); echo $n > {counter}; \
+         [ $n -ge {successful_run} ]"
+    )
+}
+
+#[test]
+fn retry_until_success() {
+    let (watched, output) = directories();
+    let counter = output_file(&output);
+
+    let command = flaky_command(&counter, 3);
+    let mut witness = Witness::spawn(&[
+        "--path",
+        path_str(watched.path()),
+        "--initial",
+        "--once",
+        "--retry",
+        "5",
+        "--retry-delay",
+        "10ms",
+        &command,
+    ]);
+
+    let status = witness.wait_exit(Duration::from_secs(10));
+    assert_eq!(status.and_then(|status| status.code()), Some(0));
+    assert_eq!(std::fs::read_to_string(&counter).unwrap(), "3\n");
+}
+
+#[test]
+fn retry_gives_up() {
+    let (watched, output) = directories();
+    let counter = output_file(&output);
+
+    let command = flaky_command(&counter, 10);
+    let mut witness = Witness::spawn(&[
+        "--path",
+        path_str(watched.path()),
+        "--initial",
+        "--once",
+        "--retry",
+        "2",
+        "--retry-delay",
+        "10ms",
+        "--retry-backoff",
+        &command,
+    ]);
+
+    let status = witness.wait_exit(Duration::from_secs(10));
+    assert_eq!(status.and_then(|status| status.code()), Some(1));
+    assert_eq!(std::fs::read_to_string(&counter).unwrap(), "3\n");
+}
+
+#[test]
+fn trigger_variables() {
+    let (watched, output) = directories();
+    let output = output_file(&output);
+    let port = free_port().to_string();
+
+    let command = format!(
+        "echo \"$WITNESS_TRIGGER_KIND $WITNESS_EVENT_COUNT $WITNESS_TRIGGER_TIME\" >> {}",
+        path_str(&output)
+    );
+    let _witness = Witness::spawn(&[
+        "--path",
+        path_str(watched.path()),
+        "--no-git-ignore",
+        "--debounce",
+        "10ms",
+        "--udp",
+        &port,
+        "--wait",
+        &command,
+    ]);
+    std::thread::sleep(Duration::from_millis(500));
+
+    std::fs::write(watched.path().join("file.txt"), "hello").unwrap();
+    let contents = wait_for(&output, |text| text.lines().count() >= 1);
+    let fields = contents.split_whitespace().collect::<Vec<_>>();
+    assert_eq!(fields[0], "file", "{contents}");
+    assert!(fields[1].parse::<u32>().unwrap() >= 1, "{contents}");
+    assert!(fields[2].ends_with('Z'), "{contents}");
+
+    trigger(&["--udp", &port]);
+    let contents = wait_for(&output, |text| text.lines().count() >= 2);
+    let line = contents.lines().nth(1).unwrap_or_default();
+    assert!(line.starts_with("udp 1 "), "{contents}");
+}
+
+#[cfg(unix)]
+#[test]
+fn unix_socket_trigger() {
+    let (sockets, output) = directories();
+    let output = output_file(&output);
+    let socket = sockets.path().join("witness.sock");
+
+    let command = format!("echo \"$WITNESS_TRIGGER_KIND\" >> {}", path_str(&output));
+    let witness = Witness::spawn(&["--unix", path_str(&socket), &command]);
+
+    // keep triggering until witness starts listening
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !trigger(&["--unix", path_str(&socket)]).success() {
+        assert!(Instant::now() < deadline, "could not connect to witness");
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    assert_eq!(wait_for(&output, |text| !text.is_empty()), "unix\n");
+
+    // the socket is removed on exit
+    drop(witness);
+    assert!(!socket.exists());
+}
+
+#[test]
+fn workdir() {
+    let (workdir, output) = directories();
+    let output = output_file(&output);
+
+    let command = format!("pwd > {}", path_str(&output));
+    let mut witness = Witness::spawn(&[
+        "--path",
+        path_str(output.parent().unwrap()),
+        "--initial",
+        "--once",
+        "--workdir",
+        path_str(workdir.path()),
+        &command,
+    ]);
+    assert!(witness.wait_exit(Duration::from_secs(10)).is_some());
+
+    let expected = workdir.path().canonicalize().unwrap();
+    let actual = std::fs::read_to_string(&output).unwrap();
+    assert_eq!(Path::new(actual.trim()).canonicalize().unwrap(), expected);
+}
+
+#[test]
+fn workdir_missing() {
+    let (watched, _) = directories();
+    let missing = watched.path().join("missing");
+    let mut witness = Witness::spawn(&[
+        "--path",
+        path_str(watched.path()),
+        "--initial",
+        "--workdir",
+        path_str(&missing),
+        "true",
+    ]);
+    let status = witness.wait_exit(Duration::from_secs(10)).unwrap();
+    assert!(!status.success());
+}
+
+#[test]
+fn command_timeout() {
+    let (watched, _) = directories();
+    let start = Instant::now();
+    let mut witness = Witness::spawn(&[
+        "--path",
+        path_str(watched.path()),
+        "--initial",
+        "--once",
+        "--command-timeout",
+        "200ms",
+        "sleep 30",
+    ]);
+
+    let status = witness.wait_exit(Duration::from_secs(10)).unwrap();
+    assert!(!status.success());
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn event_log() {
+    let (watched, output) = directories();
+    let log = output_file(&output);
+
+    let mut witness = Witness::spawn(&[
+        "--path",
+        path_str(watched.path()),
+        "--no-git-ignore",
+        "--debounce",
+        "10ms",
+        "--once",
+        "--event-log",
+        path_str(&log),
+        "exit 2",
+    ]);
+    std::thread::sleep(Duration::from_millis(500));
+    std::fs::write(watched.path().join("file.txt"), "").unwrap();
+    assert!(witness.wait_exit(Duration::from_secs(10)).is_some());
+
+    let events = std::fs::read_to_string(&log)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .collect::<Vec<_>>();
+    let kinds = events
+        .iter()
+        .map(|event| event["event"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(kinds, ["trigger", "started", "exited"]);
+    assert_eq!(events[0]["kind"], "file");
+    assert_eq!(events[2]["code"], 2);
+    assert_eq!(events[2]["success"], false);
+}
+
+#[test]
+#[cfg(unix)]
+fn graceful_shutdown() {
+    let (watched, output) = directories();
+    let output = output_file(&output);
+
+    let command = format!(
+        "trap 'echo cleanup > {}; exit 0' TERM; echo started > {0}; while true; do sleep 0.05; done",
+        path_str(&output)
+    );
+    let mut witness = Witness::spawn(&[
+        "--path",
+        path_str(watched.path()),
+        "--initial",
+        "--signal",
+        "TERM",
+        "--graceful-shutdown=5s",
+        &command,
+    ]);
+    assert_eq!(wait_for(&output, |text| !text.is_empty()), "started\n");
+
+    unsafe {
+        libc::kill(witness.child.id() as libc::pid_t, libc::SIGINT);
+    }
+    assert!(witness.wait_exit(Duration::from_secs(10)).is_some());
+
+    // the command was able to clean up before witness exited
+    assert_eq!(std::fs::read_to_string(&output).unwrap(), "cleanup\n");
+}
+
+#[test]
+#[cfg(unix)]
+fn control_socket() {
+    use std::io::{BufRead, BufReader, Write};
+
+    let (watched, output) = directories();
+    let added = tempfile::tempdir().unwrap();
+    let socket = output.path().join("control.sock");
+    let output = output_file(&output);
+
+    let command = format!("echo \"$WITNESS_CHANGED_PATH\" >> {}", path_str(&output));
+    let _witness = Witness::spawn(&[
+        "--path",
+        path_str(watched.path()),
+        "--no-git-ignore",
+        "--debounce",
+        "10ms",
+        "--control",
+        path_str(&socket),
+        &command,
+    ]);
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let mut stream = loop {
+        match std::os::unix::net::UnixStream::connect(&socket) {
+            Ok(stream) => break stream,
+            Err(_) if Instant::now() < deadline => std::thread::sleep(Duration::from_millis(20)),
+            Err(error) => panic!("failed to connect to control socket: {error}"),
+        }
+    };
+    writeln!(stream, "watch {}", path_str(added.path())).unwrap();
+    let mut response = String::new();
+    BufReader::new(&stream).read_line(&mut response).unwrap();
+    assert_eq!(response, "ok\n");
+
+    // changes to the added path now trigger the command
+    let file = added.path().join("file.txt");
+    std::fs::write(&file, "hello").unwrap();
+    let contents = wait_for(&output, |text| !text.is_empty());
+    assert_eq!(contents, format!("{}\n", path_str(&file)));
+}
+
+#[test]
+fn banner_and_elapsed_time() {
+    let (watched, output) = directories();
+    let output = output_file(&output);
+
+    let mut witness = Witness::spawn_with_stdout(
+        &[
+            "--path",
+            path_str(watched.path()),
+            "--initial",
+            "--once",
+            "--verbose",
+            "--log-format",
+            "json",
+            "echo hello",
+        ],
+        std::fs::File::create(&output).unwrap(),
+    );
+    assert!(witness.wait_exit(Duration::from_secs(10)).is_some());
+
+    let contents = std::fs::read_to_string(&output).unwrap();
+    let banner = contents.lines().position(|line| line == "> echo hello");
+    let hello = contents.lines().position(|line| line == "hello");
+    assert!(banner.is_some() && banner < hello, "{contents}");
+
+    let terminated = contents
+        .lines()
+        .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+        .find(|event| event["message"] == "command terminated")
+        .unwrap();
+    assert_eq!(terminated["exit_status"], 0);
+    assert!(terminated["elapsed"].as_str().unwrap().ends_with('s'));
+}
+
+#[test]
+fn no_banner() {
+    let (watched, output) = directories();
+    let output = output_file(&output);
+
+    let mut witness = Witness::spawn_with_stdout(
+        &[
+            "--path",
+            path_str(watched.path()),
+            "--initial",
+            "--once",
+            "--no-banner",
+            "echo hello",
+        ],
+        std::fs::File::create(&output).unwrap(),
+    );
+    assert!(witness.wait_exit(Duration::from_secs(10)).is_some());
+    assert_eq!(std::fs::read_to_string(&output).unwrap(), "hello\n");
+}
+
+#[test]
+fn ipv6_trigger() {
+    let port = free_port().to_string();
+    let mut witness = Witness::spawn(&["--bind", "::", "--tcp", &port, "--once", "exit 4"]);
+
+    // keep triggering until witness starts listening
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let status = loop {
+        trigger(&["--target", "::1", "--tcp", &port]);
+        if let Some(status) = witness.wait_exit(Duration::from_millis(100)) {
+            break status;
+        }
+        assert!(Instant::now() < deadline, "witness did not exit");
+    };
+
+    assert_eq!(status.code(), Some(4));
+}
+
+#[test]
+fn changed_list_variable() {
+    let (watched, output) = directories();
+    let output = output_file(&output);
+
+    let command = format!(
+        "cat \"$WITNESS_CHANGED_LIST\" > {0}.tmp && mv {0}.tmp {0}",
+        path_str(&output)
+    );
+    let _witness = Witness::spawn(&[
+        "--path",
+        path_str(watched.path()),
+        "--no-git-ignore",
+        "--debounce",
+        "200ms",
+        &command,
+    ]);
+    std::thread::sleep(Duration::from_millis(500));
+
+    let first = watched.path().join("first.txt");
+    let second = watched.path().join("second.txt");
+    std::fs::write(&first, "").unwrap();
+    std::fs::write(&second, "").unwrap();
+
+    let contents = wait_for(&output, |text| !text.is_empty());
+    assert_eq!(
+        contents,
+        format!("{}\n{}\n", path_str(&first), path_str(&second))
+    );
+}
+
+#[test]
+fn config_rules() {
+    let (first, output) = directories();
+    let second = tempfile::tempdir().unwrap();
+    let first_output = output.path().join("first.txt");
+    let second_output = output.path().join("second.txt");
+
+    let config = output.path().join("witness.toml");
+    let rule = |dir: &Path, output: &Path| {
+        format!(
+            "[[rule]]\nname = \"{}\"\npaths = [\"{}\"]\ncommand = \"echo $WITNESS_RULE >> {}\"\n",
+            dir.file_name().unwrap().to_str().unwrap(),
+            path_str(dir),
+            path_str(output),
+        )
+    };
+    let contents = format!(
+        "{}\n{}",
+        rule(first.path(), &first_output),
+        rule(second.path(), &second_output)
+    );
+    std::fs::write(&config, contents).unwrap();
+
+    let _witness = Witness::spawn(&[
+        "--config",
+        path_str(&config),
+        "--no-git-ignore",
+        "--debounce",
+        "10ms",
+    ]);
+    std::thread::sleep(Duration::from_millis(500));
+
+    std::fs::write(first.path().join("file.txt"), "").unwrap();
+    let name = first.path().file_name().unwrap().to_str().unwrap();
+    assert_eq!(
+        wait_for(&first_output, |text| !text.is_empty()),
+        format!("{name}\n")
+    );
+    assert!(!second_output.exists());
+
+    std::fs::write(second.path().join("file.txt"), "").unwrap();
+    let name = second.path().file_name().unwrap().to_str().unwrap();
+    assert_eq!(
+        wait_for(&second_output, |text| !text.is_empty()),
+        format!("{name}\n")
+    );
+    assert_eq!(
+        std::fs::read_to_string(&first_output)
+            .unwrap()
+            .lines()
+            .count(),
+        1
+    );
+}
+
+#[test]
+#[cfg(unix)]
+fn summary_on_interrupt() {
+    let (watched, output) = directories();
+    let runs = output.path().join("runs.txt");
+    let output = output_file(&output);
+
+    let command = format!(
+        "echo run >> {}; case \"$WITNESS_CHANGED_PATH\" in *fail*) exit 1;; esac",
+        path_str(&runs)
+    );
+    let mut witness = Witness::spawn_with_stdout(
+        &[
+            "--path",
+            path_str(watched.path()),
+            "--no-git-ignore",
+            "--debounce",
+            "10ms",
+            "--no-banner",
+            &command,
+        ],
+        std::fs::File::create(&output).unwrap(),
+    );
+    std::thread::sleep(Duration::from_millis(500));
+
+    for (count, name) in ["a.txt", "fail.txt", "b.txt"].iter().enumerate() {
+        std::fs::write(watched.path().join(name), "").unwrap();
+        wait_for(&runs, |text| text.lines().count() > count);
+        std::thread::sleep(Duration::from_millis(200));
+    }
+
+    unsafe {
+        libc::kill(witness.child.id() as libc::pid_t, libc::SIGINT);
+    }
+    assert!(witness.wait_exit(Duration::from_secs(10)).is_some());
+
+    let contents = std::fs::read_to_string(&output).unwrap();
+    let summary = contents.lines().last().unwrap_or_default();
+    assert!(
+        summary.starts_with("3 runs: 2 succeeded, 1 failed, "),
+        "{contents}"
+    );
+    assert!(summary.ends_with(" on average"), "{contents}");
+}
+
+#[test]
+#[cfg(unix)]
+fn no_summary() {
+    let (watched, output) = directories();
+    let output = output_file(&output);
+
+    let mut witness = Witness::spawn_with_stdout(
+        &[
+            "--path",
+            path_str(watched.path()),
+            "--no-banner",
+            "--no-summary",
+            "true",
+        ],
+        std::fs::File::create(&output).unwrap(),
+    );
+    std::thread::sleep(Duration::from_millis(500));
+
+    unsafe {
+        libc::kill(witness.child.id() as libc::pid_t, libc::SIGINT);
+    }
+    assert!(witness.wait_exit(Duration::from_secs(10)).is_some());
+    assert_eq!(std::fs::read_to_string(&output).unwrap(), "");
+}
+
+/// Run `witness --initial --once` with a terminal-like stdin which never closes
+fn run_with_open_stdin(args: &[&str]) -> Option<std::process::ExitStatus> {
+    use std::io::Write;
+
+    let watched = tempfile::tempdir().unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_witness"))
+        .args([
+            "--shell",
+            "sh",
+            "--no-clear",
+            "--no-banner",
+            "--initial",
+            "--once",
+        ])
+        .args(["--path", path_str(watched.path())])
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .env_remove("WITNESS_LOG")
+        .spawn()
+        .expect("failed to spawn witness");
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(b"from terminal\n").unwrap();
+
+    let mut witness = Witness { child };
+    let status = witness.wait_exit(Duration::from_secs(10));
+    drop(stdin);
+    status
+}
+
+#[test]
+fn no_stdin() {
+    let output = tempfile::tempdir().unwrap();
+    let output = output_file(&output);
+
+    let command = format!("cat > {}", path_str(&output));
+    let status = run_with_open_stdin(&["--no-stdin", &command]);
+
+    // the command sees the end of its input right away
+    assert!(status.unwrap().success());
+    assert_eq!(std::fs::read_to_string(&output).unwrap(), "");
+}
+
+#[test]
+fn stdin_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("input.txt");
+    std::fs::write(&input, "first\nsecond\n").unwrap();
+    let output = output_file(&dir);
+
+    let command = format!("cat > {}", path_str(&output));
+    let status = run_with_open_stdin(&["--stdin-file", path_str(&input), &command]);
+
+    assert!(status.unwrap().success());
+    assert_eq!(std::fs::read_to_string(&output).unwrap(), "first\nsecond\n");
+}
+
+#[test]
+fn stdin_file_missing() {
+    let dir = tempfile::tempdir().unwrap();
+    let missing = dir.path().join("missing.txt");
+
+    let status = run_with_open_stdin(&["--stdin-file", path_str(&missing), "cat"]);
+    assert!(!status.unwrap().success());
+}
+
+#[test]
+fn timestamps() {
+    let (watched, output) = directories();
+    let output = output_file(&output);
+
+    let mut witness = Witness::spawn_with_stdout(
+        &[
+            "--path",
+            path_str(watched.path()),
+            "--initial",
+            "--once",
+            "--no-banner",
+            "--timestamps=tagged",
+            "echo first; echo second",
+        ],
+        std::fs::File::create(&output).unwrap(),
+    );
+    assert!(witness.wait_exit(Duration::from_secs(10)).is_some());
+
+    let contents = std::fs::read_to_string(&output).unwrap();
+    let lines = contents
+        .lines()
+        .map(|line| {
+            let (time, text) = line.split_once(' ').unwrap();
+            assert!(humantime::parse_rfc3339(time).is_ok(), "{line}");
+            text
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(lines, ["[out] first", "[out] second"]);
+}
+
+#[test]
+fn tcp_ack() {
+    let port = free_port().to_string();
+    let _witness = Witness::spawn(&["--tcp", &port, "--ack", "--key", "secret", "true"]);
+
+    // keep triggering until witness starts listening
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !trigger(&["--tcp", &port, "--ack", "--key", "secret"]).success() {
+        assert!(Instant::now() < deadline, "trigger was never accepted");
+        std::thread::sleep(Duration::from_millis(50));
+    }
+
+    let denied = trigger(&["--tcp", &port, "--ack", "--key", "public"]);
+    assert!(!denied.success());
+}
+
+#[test]
+fn startup_grace() {
+    let (watched, output) = directories();
+    let output = output_file(&output);
+
+    let command = format!("echo \"$WITNESS_CHANGED_PATH\" >> {}", path_str(&output));
+    let started = Instant::now();
+    let _witness = Witness::spawn(&[
+        "--path",
+        path_str(watched.path()),
+        "--no-git-ignore",
+        "--debounce",
+        "10ms",
+        "--startup-grace",
+        "1500ms",
+        &command,
+    ]);
+    std::thread::sleep(Duration::from_millis(500));
+
+    // changes during the grace period are dropped
+    std::fs::write(watched.path().join("early.txt"), "").unwrap();
+    std::thread::sleep(Duration::from_millis(300));
+    assert!(started.elapsed() < Duration::from_millis(1500));
+    assert!(!output.exists());
+
+    let grace_end = started + Duration::from_millis(1500);
+    std::thread::sleep(grace_end.saturating_duration_since(Instant::now()));
+    std::thread::sleep(Duration::from_millis(200));
+    let late = watched.path().join("late.txt");
+    std::fs::write(&late, "").unwrap();
+    assert_eq!(
+        wait_for(&output, |text| !text.is_empty()),
+        format!("{}\n", path_str(&late))
+    );
+}
+
+#[test]
+fn on_delete() {
+    let (watched, output) = directories();
+    let output = output_file(&output);
+    let file = watched.path().join("file.txt");
+    std::fs::write(&file, "").unwrap();
+
+    let command = format!(
+        "echo \"edited $WITNESS_CHANGED_PATH\" >> {}",
+        path_str(&output)
+    );
+    let on_delete = format!(
+        "echo \"deleted $WITNESS_CHANGED_PATH\" >> {}",
+        path_str(&output)
+    );
+    let _witness = Witness::spawn(&[
+        "--path",
+        path_str(watched.path()),
+        "--no-git-ignore",
+        "--debounce",
+        "10ms",
+        "--on-delete",
+        &on_delete,
+        &command,
+    ]);
+    std::thread::sleep(Duration::from_millis(500));
+
+    std::fs::write(&file, "changed").unwrap();
+    let edited = format!("edited {}\n", path_str(&file));
+    assert_eq!(wait_for(&output, |text| !text.is_empty()), edited);
+
+    std::thread::sleep(Duration::from_millis(200));
+    std::fs::remove_file(&file).unwrap();
+    let contents = wait_for(&output, |text| text.lines().count() >= 2);
+    assert_eq!(contents, format!("{edited}deleted {}\n", path_str(&file)));
+}
+
+#[test]
+fn max_restarts() {
+    let (watched, output) = directories();
+    let counter = output_file(&output);
+
+    // the command never succeeds, so without a limit it would run forever
+    let command = flaky_command(&counter, u32::MAX);
+    let mut witness = Witness::spawn(&[
+        "--path",
+        path_str(watched.path()),
+        "--initial",
+        "--retry",
+        "1000",
+        "--retry-delay",
+        "0s",
+        "--max-restarts",
+        "3",
+        "--restart-window",
+        "1m",
+        &command,
+    ]);
+
+    assert_eq!(wait_for(&counter, |text| text == "4\n"), "4\n");
+    std::thread::sleep(Duration::from_millis(500));
+    assert_eq!(std::fs::read_to_string(&counter).unwrap(), "4\n");
+    assert!(witness.child.try_wait().unwrap().is_none());
+
+    // a new trigger starts over
+    std::fs::write(watched.path().join("file"), "changed").unwrap();
+    assert_eq!(wait_for(&counter, |text| text == "8\n"), "8\n");
+}
+
+/// A command which always fails is retried at most `--max-restarts` times per window, no matter
+/// how many retries are allowed
+#[test]
+fn max_restarts_window() {
+    let (watched, output) = directories();
+    let output = output_file(&output);
+
+    let command = format!("echo run >> {}; exit 1", path_str(&output));
+    let mut witness = Witness::spawn(&[
+        "--path",
+        path_str(watched.path()),
+        "--initial",
+        "--retry",
+        &u32::MAX.to_string(),
+        "--retry-delay",
+        "0s",
+        "--max-restarts",
+        "2",
+        "--restart-window",
+        "1s",
+        &command,
+    ]);
+
+    // the initial run and two retries, then nothing more until the next trigger, even once the
+    // window has passed
+    wait_for(&output, |text| text.lines().count() == 3);
+    std::thread::sleep(Duration::from_millis(1500));
+    let runs = std::fs::read_to_string(&output).unwrap().lines().count();
+    assert_eq!(runs, 3);
+    assert!(witness.child.try_wait().unwrap().is_none());
+}
+
+#[test]
+fn zero_debounce() {
+    let (watched, output) = directories();
+    let output = output_file(&output);
+
+    let command = format!("echo run >> {}", path_str(&output));
+    let _witness = Witness::spawn(&[
+        "--path",
+        path_str(watched.path()),
+        "--no-git-ignore",
+        "--debounce",
+        "0ms",
+        &command,
+    ]);
+    std::thread::sleep(Duration::from_millis(500));
+
+    let start = Instant::now();
+    std::fs::write(watched.path().join("file.txt"), "changed").unwrap();
+    assert_eq!(wait_for(&output, |text| !text.is_empty()), "run\n");
+    assert!(start.elapsed() < Duration::from_secs(1));
+
+    // the events of the single write don't cause any more runs
+    std::thread::sleep(Duration::from_millis(500));
+    assert_eq!(std::fs::read_to_string(&output).unwrap(), "run\n");
+}
+
+#[test]
+fn wait_combines_triggers() {
+    let (watched, output) = directories();
+    let output = output_file(&output);
+    let port = free_port().to_string();
+
+    let command = format!(
+        "echo \"run $WITNESS_EVENT_COUNT\" >> {}; sleep 1",
+        path_str(&output)
+    );
+    let _witness = Witness::spawn(&[
+        "--path",
+        path_str(watched.path()),
+        "--tcp",
+        &port,
+        "--initial",
+        "--wait",
+        &command,
+    ]);
+    wait_for(&output, |text| !text.is_empty());
+
+    // both triggers arrive while the command is still running
+    assert!(trigger(&["--tcp", &port]).success());
+    assert!(trigger(&["--tcp", &port]).success());
+
+    let contents = wait_for(&output, |text| text.lines().count() >= 2);
+    assert_eq!(contents.lines().nth(1), Some("run 2"));
+
+    // and only cause a single run
+    std::thread::sleep(Duration::from_millis(1500));
+    assert_eq!(std::fs::read_to_string(&output).unwrap().lines().count(), 2);
+}
+
+/// Check if the process has exited, even if nobody has reaped it yet
+#[cfg(unix)]
+fn process_exited(pid: &str) -> bool {
+    match std::fs::read_to_string(format!("/proc/{pid}/stat")) {
+        Err(_) => true,
+        // the state follows the executable name, which is in parentheses
+        Ok(stat) => stat
+            .rsplit_once(") ")
+            .is_some_and(|(_, rest)| rest.starts_with('Z')),
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn interrupt_reaps_process_group() {
+    let (watched, output) = directories();
+    let pids = output_file(&output);
+
+    // the shell ignores interrupts in the background process
+    let command = format!(
+        "sleep 30 & echo $! >> {pids}; sh -c 'echo $$ >> {pids}; sleep 30'",
+        pids = path_str(&pids)
+    );
+    let mut witness = Witness::spawn(&["--path", path_str(watched.path()), "--initial", &command]);
+    let contents = wait_for(&pids, |text| text.lines().count() >= 2);
+    let pids = contents.lines().collect::<Vec<_>>();
+    assert_eq!(pids.len(), 2);
+
+    unsafe {
+        libc::kill(witness.child.id() as libc::pid_t, libc::SIGINT);
+    }
+    assert!(witness.wait_exit(Duration::from_secs(10)).is_some());
+
+    for pid in pids {
+        assert!(process_exited(pid), "process {pid} is still running");
+    }
+}
+
+#[test]
+fn glob_path() {
+    let (watched, output) = directories();
+    let output = output_file(&output);
+    for dir in ["crates/app/src", "crates/app/tests", "crates/lib/src"] {
+        std::fs::create_dir_all(watched.path().join(dir)).unwrap();
+    }
+
+    let command = format!("echo \"$WITNESS_CHANGED_PATH\" >> {}", path_str(&output));
+    let pattern = watched.path().join("crates/*/src");
+    let _witness = Witness::spawn(&[
+        "--path",
+        path_str(&pattern),
+        "--no-git-ignore",
+        "--debounce",
+        "10ms",
+        &command,
+    ]);
+    std::thread::sleep(Duration::from_millis(500));
+
+    // not matched by the pattern
+    std::fs::write(watched.path().join("crates/app/tests/it.rs"), "").unwrap();
+    std::thread::sleep(Duration::from_millis(200));
+
+    let lib = watched.path().join("crates/lib/src/lib.rs");
+    std::fs::write(&lib, "").unwrap();
+    let contents = wait_for(&output, |text| !text.is_empty());
+    assert_eq!(contents, format!("{}\n", path_str(&lib)));
+}
+
+/// Keep modifying a file every 100ms for the given duration
+fn keep_modifying(path: &Path, duration: Duration) {
+    let deadline = Instant::now() + duration;
+    let mut count = 0;
+    while Instant::now() < deadline {
+        count += 1;
+        std::fs::write(path, count.to_string()).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+    }
+}
+
+#[test]
+fn cooldown_defers_runs() {
+    let (watched, output) = directories();
+    let output = output_file(&output);
+
+    let command = format!("date +%s%N >> {}", path_str(&output));
+    let _witness = Witness::spawn(&[
+        "--path",
+        path_str(watched.path()),
+        "--no-git-ignore",
+        "--debounce",
+        "10ms",
+        "--initial",
+        "--cooldown",
+        "1s",
+        &command,
+    ]);
+    wait_for(&output, |text| !text.is_empty());
+
+    keep_modifying(
+        &watched.path().join("file.txt"),
+        Duration::from_millis(2500),
+    );
+    std::thread::sleep(Duration::from_millis(1500));
+
+    let starts = std::fs::read_to_string(&output)
+        .unwrap()
+        .lines()
+        .map(|line| line.parse::<u64>().unwrap())
+        .collect::<Vec<_>>();
+    assert!((3..=4).contains(&starts.len()), "{starts:?}");
+    for pair in starts.windows(2) {
+        // allow for the time it takes `date` to start
+        let gap = Duration::from_nanos(pair[1] - pair[0]);
+        assert!(gap > Duration::from_millis(900), "{starts:?}");
+    }
+}
+
+#[test]
+fn cooldown_drops_triggers() {
+    let (watched, output) = directories();
+    let output = output_file(&output);
+
+    let command = format!("echo run >> {}", path_str(&output));
+    let _witness = Witness::spawn(&[
+        "--path",
+        path_str(watched.path()),
+        "--no-git-ignore",
+        "--debounce",
+        "10ms",
+        "--initial",
+        "--cooldown",
+        "2s",
+        "--cooldown-mode",
+        "drop",
+        &command,
+    ]);
+    wait_for(&output, |text| !text.is_empty());
+
+    keep_modifying(&watched.path().join("file.txt"), Duration::from_millis(500));
+    std::thread::sleep(Duration::from_millis(2000));
+    assert_eq!(std::fs::read_to_string(&output).unwrap(), "run\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn ignored_events() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let (watched, output) = directories();
+    let output = output_file(&output);
+    let file = watched.path().join("script.sh");
+    std::fs::write(&file, "").unwrap();
+
+    let command = format!("echo run >> {}", path_str(&output));
+    let _witness = Witness::spawn(&[
+        "--path",
+        path_str(watched.path()),
+        "--no-git-ignore",
+        "--debounce",
+        "10ms",
+        "--events",
+        "write,create,remove,rename",
+        &command,
+    ]);
+    std::thread::sleep(Duration::from_millis(500));
+
+    let permissions = std::fs::Permissions::from_mode(0o755);
+    std::fs::set_permissions(&file, permissions).unwrap();
+    std::thread::sleep(Duration::from_millis(500));
+    assert!(!output.exists());
+
+    std::fs::write(&file, "echo hello").unwrap();
+    assert_eq!(wait_for(&output, |text| !text.is_empty()), "run\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn status_file() {
+    let (watched, output) = directories();
+    let status = output.path().join("status");
+    let marker = output_file(&output);
+
+    let command = format!(
+        "touch {}; while [ -e {} ]; do sleep 0.05; done; exit 3",
+        path_str(&marker),
+        path_str(&marker)
+    );
+    let mut witness = Witness::spawn(&[
+        "--path",
+        path_str(watched.path()),
+        "--initial",
+        "--status-file",
+        path_str(&status),
+        &command,
+    ]);
+    let pid = witness.child.id();
+
+    wait_for(&marker, |_| marker.exists());
+    let contents = wait_for(&status, |text| text.contains("running"));
+    assert_eq!(contents, format!("state=running\npid={pid}\n"));
+
+    std::fs::remove_file(&marker).unwrap();
+    let contents = wait_for(&status, |text| text.contains("exited"));
+    assert_eq!(contents, format!("state=exited:3\npid={pid}\n"));
+
+    unsafe {
+        libc::kill(witness.child.id() as libc::pid_t, libc::SIGINT);
+    }
+    assert!(witness.wait_exit(Duration::from_secs(10)).is_some());
+    assert!(!status.exists());
+}
+
+/// Run a command once with `--log-output` and `--show-on-failure`, returning what was logged and
+/// what was shown on the terminal.
+fn run_captured(command: &str) -> (String, String) {
+    let (watched, output) = directories();
+    let log = output.path().join("output.log");
+    let terminal = output.path().join("terminal");
+
+    let mut witness = Witness::spawn_with_stdout(
+        &[
+            "--path",
+            path_str(watched.path()),
+            "--initial",
+            "--once",
+            "--no-banner",
+            "--no-summary",
+            "--log-output",
+            path_str(&log),
+            "--show-on-failure",
+            command,
+        ],
+        std::fs::File::create(&terminal).unwrap(),
+    );
+    assert!(witness.wait_exit(Duration::from_secs(10)).is_some());
+
+    (
+        std::fs::read_to_string(&log).unwrap(),
+        std::fs::read_to_string(&terminal).unwrap(),
+    )
+}
+
+#[test]
+fn log_output_success() {
+    let (log, terminal) = run_captured("echo building; sleep 0.1; echo warning >&2");
+    assert_eq!(log, "building\nwarning\n");
+    assert_eq!(terminal, "");
+}
+
+#[test]
+fn log_output_failure() {
+    let (log, terminal) = run_captured("echo building; sleep 0.1; echo error >&2; exit 1");
+    assert_eq!(log, "building\nerror\n");
+    assert_eq!(terminal, "building\nerror\n");
+}
+
+#[test]
+fn content_hash() {
+    let (watched, output) = directories();
+    let output = output_file(&output);
+    let file = watched.path().join("main.rs");
+    std::fs::write(&file, "fn main() {}").unwrap();
+
+    let command = format!("echo run >> {}", path_str(&output));
+    let _witness = Witness::spawn(&[
+        "--path",
+        path_str(watched.path()),
+        "--no-git-ignore",
+        "--debounce",
+        "10ms",
+        "--content-hash",
+        &command,
+    ]);
+    std::thread::sleep(Duration::from_millis(500));
+
+    // the first change of a file always triggers
+    std::fs::write(&file, "fn main() {}\n").unwrap();
+    assert_eq!(wait_for(&output, |text| !text.is_empty()), "run\n");
+
+    // saving it again without changes doesn't
+    std::fs::write(&file, "fn main() {}\n").unwrap();
+    std::thread::sleep(Duration::from_millis(500));
+    assert_eq!(std::fs::read_to_string(&output).unwrap(), "run\n");
+
+    std::fs::write(&file, "fn main() { todo!() }\n").unwrap();
+    assert_eq!(wait_for(&output, |text| text.len() > 4), "run\nrun\n");
+}
+
+#[test]
+fn interactive() {
+    use std::io::Write;
+
+    let (watched, output) = directories();
+    let output = output_file(&output);
+    let file = watched.path().join("main.rs");
+
+    let original = format!("echo original >> {}", path_str(&output));
+    let child = Command::new(env!("CARGO_BIN_EXE_witness"))
+        .args(["--shell", "sh", "--no-clear", "--interactive"])
+        .args(["--path", path_str(watched.path()), "--no-git-ignore"])
+        .args(["--debounce", "10ms", &original])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .env_remove("WITNESS_LOG")
+        .spawn()
+        .expect("failed to spawn witness");
+    let mut witness = Witness { child };
+    let mut stdin = witness.child.stdin.take().unwrap();
+
+    // a typed command runs right away
+    let typed = format!("echo typed >> {}\n", path_str(&output));
+    stdin.write_all(typed.as_bytes()).unwrap();
+    assert_eq!(wait_for(&output, |text| !text.is_empty()), "typed\n");
+
+    // and keeps running on changes
+    std::thread::sleep(Duration::from_millis(500));
+    std::fs::write(&file, "fn main() {}").unwrap();
+    assert_eq!(
+        wait_for(&output, |text| text.lines().count() == 2),
+        "typed\ntyped\n"
+    );
+
+    // until the input ends
+    drop(stdin);
+    std::thread::sleep(Duration::from_millis(500));
+    std::fs::write(&file, "fn main() { todo!() }").unwrap();
+    assert_eq!(
+        wait_for(&output, |text| text.lines().count() == 3),
+        "typed\ntyped\noriginal\n"
+    );
+}
+
+#[test]
+fn queue_runs() {
+    let (watched, output) = directories();
+    let output = output_file(&output);
+    let port = free_port().to_string();
+
+    let command = format!(
+        "echo \"run $WITNESS_EVENT_COUNT\" >> {}; sleep 1",
+        path_str(&output)
+    );
+    let _witness = Witness::spawn(&[
+        "--path",
+        path_str(watched.path()),
+        "--tcp",
+        &port,
+        "--initial",
+        "--wait",
+        "--queue-runs",
+        "2",
+        &command,
+    ]);
+    wait_for(&output, |text| !text.is_empty());
+
+    // three triggers arrive while the command is still running
+    for _ in 0..3 {
+        assert!(trigger(&["--tcp", &port]).success());
+        std::thread::sleep(Duration::from_millis(100));
+    }
+
+    // the first is run on its own, and the last two are combined
+    let contents = wait_for(&output, |text| text.lines().count() >= 3);
+    assert_eq!(contents, "run \nrun 1\nrun 2\n");
+
+    std::thread::sleep(Duration::from_millis(1500));
+    assert_eq!(std::fs::read_to_string(&output).unwrap().lines().count(), 3);
+}
+
+/// Run `witness --initial --once` without `--no-clear`, returning what the commands wrote
+fn run_with_clear_command(args: &[&str]) -> String {
+    let (watched, output) = directories();
+    let output = output_file(&output);
+
+    let clear = format!("echo clear >> {}; exit 3", path_str(&output));
+    let command = format!("echo run >> {}", path_str(&output));
+    let status = Command::new(env!("CARGO_BIN_EXE_witness"))
+        .args(["--shell", "sh", "--initial", "--once"])
+        .args(["--path", path_str(watched.path())])
+        .args(["--clear-command", &clear])
+        .args(args)
+        .arg(&command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .env_remove("WITNESS_LOG")
+        .status()
+        .expect("failed to run witness");
+    assert!(status.success());
+    std::fs::read_to_string(&output).unwrap()
+}
+
+#[test]
+fn clear_command() {
+    // a failing clear command doesn't keep the command from running
+    assert_eq!(run_with_clear_command(&[]), "clear\nrun\n");
+    assert_eq!(run_with_clear_command(&["--no-clear"]), "run\n");
+}
+
+#[test]
+fn ready_check() {
+    let (watched, output) = directories();
+    let log = output.path().join("log");
+    let attempts = output.path().join("attempts");
+
+    // fails on the first two attempts, then succeeds
+    let check = format!(
+        "echo attempt >> {0}; [ $(wc -l < {0}) -ge 3 ]",
+        path_str(&attempts)
+    );
+    let _witness = Witness::spawn_with_stdout(
+        &[
+            "--verbose",
+            "--path",
+            path_str(watched.path()),
+            "--initial",
+            "--ready-check",
+            &check,
+            "--ready-interval",
+            "50ms",
+            "sleep 10",
+        ],
+        std::fs::File::create(&log).unwrap(),
+    );
+
+    wait_for(&log, |log| log.contains("command is ready"));
+
+    // the check stops once it has succeeded
+    std::thread::sleep(Duration::from_millis(300));
+    let log = std::fs::read_to_string(&log).unwrap();
+    assert_eq!(log.matches("command is ready").count(), 1);
+    let attempts = std::fs::read_to_string(&attempts).unwrap();
+    assert_eq!(attempts.lines().count(), 3);
+}
+
+#[test]
+fn initial_once_exit_code() {
+    let (watched, _output) = directories();
+    let mut witness = Witness::spawn(&[
+        "--path",
+        path_str(watched.path()),
+        "--initial",
+        "--once",
+        "exit 3",
+    ]);
+    let status = witness.wait_exit(Duration::from_secs(10));
+    assert_eq!(status.and_then(|status| status.code()), Some(3));
+}
+
+/// Interrupting witness exits with the exit code of the latest run
+#[test]
+#[cfg(unix)]
+fn interrupt_exit_code() {
+    let (watched, output) = directories();
+    let output = output_file(&output);
+
+    let command = format!("echo done >> {}; exit 3", path_str(&output));
+    let mut witness = Witness::spawn(&[
+        "--path",
+        path_str(watched.path()),
+        "--initial",
+        "--no-summary",
+        &command,
+    ]);
+    wait_for(&output, |text| text.contains("done"));
+    std::thread::sleep(Duration::from_millis(200));
+
+    unsafe {
+        libc::kill(witness.child.id() as libc::pid_t, libc::SIGINT);
+    }
+    let status = witness.wait_exit(Duration::from_secs(10));
+    assert_eq!(status.and_then(|status| status.code()), Some(3));
+}
+
+/// A symlink pointing back up the tree doesn't keep witness from starting
+#[test]
+#[cfg(unix)]
+fn symlink_loop() {
+    let (watched, _output) = directories();
+    std::fs::create_dir_all(watched.path().join("src/nested")).unwrap();
+    std::os::unix::fs::symlink(watched.path(), watched.path().join("src/nested/loop")).unwrap();
+
+    for follow in [&[][..], &["--follow-symlinks"]] {
+        let mut args = vec!["--path", path_str(watched.path()), "--initial", "--once"];
+        args.extend_from_slice(follow);
+        args.push("true");
+
+        let mut witness = Witness::spawn(&args);
+        let status = witness.wait_exit(Duration::from_secs(10));
+        assert!(status.is_some_and(|status| status.success()), "{follow:?}");
+    }
+}
+
+/// Run a command once with the given environment flags, returning the variables it saw
+fn run_with_env(args: &[&str]) -> String {
+    let (watched, output) = directories();
+    let output = output_file(&output);
+
+    let command = format!(
+        "echo \"$LEVEL:${{INHERITED-unset}}:${{REMOVED-unset}}\" > {}",
+        path_str(&output)
+    );
+    let status = Command::new(env!("CARGO_BIN_EXE_witness"))
+        .args(["--shell", "sh", "--no-clear", "--initial", "--once"])
+        .args(["--path", path_str(watched.path())])
+        .args(args)
+        .arg(&command)
+        .env("INHERITED", "inherited")
+        .env("REMOVED", "removed")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .env_remove("WITNESS_LOG")
+        .status()
+        .expect("failed to run witness");
+    assert!(status.success());
+    std::fs::read_to_string(&output).unwrap()
+}
+
+#[test]
+fn command_environment() {
+    assert_eq!(run_with_env(&[]), ":inherited:removed\n");
+    assert_eq!(
+        run_with_env(&["--env", "LEVEL=debug", "--env-remove", "REMOVED"]),
+        "debug:inherited:unset\n"
+    );
+    assert_eq!(
+        run_with_env(&["--env-clear", "--env", "LEVEL=trace"]),
+        "trace:unset:unset\n"
+    );
+}
+
+/// Run `witness --check-path` within the directory, returning whether it succeeded and what it
+/// printed
+fn check_path(dir: &Path, args: &[&str]) -> (bool, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_witness"))
+        .arg("--check-path")
+        .args(args)
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .env_remove("WITNESS_LOG")
+        .output()
+        .expect("failed to run witness");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    (output.status.success(), stdout)
+}
+
+#[test]
+fn check_path_reasons() {
+    let (repository, _output) = directories();
+    std::fs::create_dir(repository.path().join(".git")).unwrap();
+    std::fs::write(repository.path().join(".gitignore"), "*.log\n").unwrap();
+
+    let (triggers, output) = check_path(repository.path(), &["src/main.rs", "--extensions", "rs"]);
+    assert!(triggers);
+    assert_eq!(output, "src/main.rs: would trigger\n");
+
+    let (triggers, output) = check_path(repository.path(), &["notes.txt", "--extensions", "rs"]);
+    assert!(!triggers);
+    assert_eq!(
+        output,
+        "notes.txt: ignored, because its extension is not one of `--extensions`\n"
+    );
+
+    let (triggers, output) = check_path(repository.path(), &["build.log"]);
+    assert!(!triggers);
+    assert_eq!(output, "build.log: ignored, because it is ignored by git\n");
+
+    let (triggers, _) = check_path(repository.path(), &["build.log", "--no-git-ignore"]);
+    assert!(triggers);
+
+    // `--hidden` doesn't include the `.git` directory
+    let (triggers, output) = check_path(repository.path(), &[".git/index", "--hidden"]);
+    assert!(!triggers);
+    assert_eq!(
+        output,
+        ".git/index: ignored, because it is inside the `.git` directory
+"
+    );
+}
+
+/// A change in one group restarts only the command of that group
+#[cfg(unix)]
+#[test]
+fn group_commands() {
+    let (watched, output) = directories();
+    let runs = output.path().join("runs");
+    let pid = output.path().join("pid");
+
+    let command_a = format!("echo a >> {}; exec sleep 30", path_str(&runs));
+    let command_b = format!(
+        "echo $$ > {}; echo b >> {}; exec sleep 30",
+        path_str(&pid),
+        path_str(&runs)
+    );
+    let _witness = Witness::spawn(&[
+        "--path",
+        path_str(watched.path()),
+        "--no-git-ignore",
+        "--debounce",
+        "10ms",
+        "--debounce-group",
+        "a:a:10ms",
+        "--debounce-group",
+        "b:b:10ms",
+        "--group-command",
+        &format!("a:{command_a}"),
+        "--group-command",
+        &format!("b:{command_b}"),
+        "true",
+    ]);
+    std::thread::sleep(Duration::from_millis(500));
+
+    std::fs::write(watched.path().join("file.b"), "").unwrap();
+    wait_for(&runs, |runs| runs.contains('b'));
+    let pid = wait_for(&pid, |pid| !pid.trim().is_empty());
+
+    for count in 1..=2 {
+        std::fs::write(watched.path().join("file.a"), count.to_string()).unwrap();
+        wait_for(&runs, |runs| runs.matches('a').count() == count);
+        std::thread::sleep(Duration::from_millis(100));
+    }
+
+    assert!(!process_exited(pid.trim()));
+    let runs = std::fs::read_to_string(&runs).unwrap();
+    assert_eq!(runs.matches('b').count(), 1, "{runs}");
+}
+
+#[test]
+fn trigger_retry() {
+    let port = free_port().to_string();
+
+    // nothing is listening, so the trigger gives up once its retries are used up
+    let started = Instant::now();
+    assert!(!trigger(&["--tcp", &port, "--retry", "2", "--retry-delay", "50ms"]).success());
+    assert!(started.elapsed() >= Duration::from_millis(150));
+
+    let (watched, output) = directories();
+    let output = output_file(&output);
+    let sender = {
+        let port = port.clone();
+        std::thread::spawn(move || {
+            trigger(&["--tcp", &port, "--retry", "6", "--retry-delay", "100ms"])
+        })
+    };
+
+    // the listener comes up after the first attempt has already failed
+    std::thread::sleep(Duration::from_millis(300));
+    let mut witness = Witness::spawn(&[
+        "--tcp",
+        &port,
+        "--once",
+        "--workdir",
+        path_str(watched.path()),
+        &format!("echo triggered > {}", path_str(&output)),
+    ]);
+
+    assert!(sender.join().unwrap().success());
+    assert!(witness.wait_exit(Duration::from_secs(10)).is_some());
+    assert_eq!(std::fs::read_to_string(&output).unwrap(), "triggered\n");
+}
+
+#[test]
+fn emit_triggers() {
+    let (watched, output) = directories();
+    let output = output_file(&output);
+
+    let _witness = Witness::spawn_with_stdout(
+        &[
+            "--path",
+            path_str(watched.path()),
+            "--no-git-ignore",
+            "--debounce",
+            "10ms",
+            "-e",
+            "txt",
+            "--emit-triggers",
+        ],
+        std::fs::File::create(&output).unwrap(),
+    );
+    std::thread::sleep(Duration::from_millis(500));
+
+    let changed = watched.path().join("with space.txt");
+    std::fs::write(&changed, "").unwrap();
+
+    let contents = wait_for(&output, |text| text.ends_with('\n'));
+    let line = contents.lines().next().unwrap();
+    let columns = line.split('\t').collect::<Vec<_>>();
+    assert_eq!(columns.len(), 3, "{line}");
+    assert_eq!(columns[0], path_str(&changed));
+    assert!(["created", "modified"].contains(&columns[1]), "{line}");
+    assert!(humantime::parse_rfc3339(columns[2]).is_ok(), "{line}");
+
+    // no banner and no command
+    assert!(!contents.contains("$ "), "{contents}");
+}
+
+#[test]
+fn emit_triggers_null() {
+    let (watched, output) = directories();
+    let output = output_file(&output);
+
+    let _witness = Witness::spawn_with_stdout(
+        &[
+            "--path",
+            path_str(watched.path()),
+            "--no-git-ignore",
+            "--debounce",
+            "10ms",
+            "--emit-triggers",
+            "--null",
+        ],
+        std::fs::File::create(&output).unwrap(),
+    );
+    std::thread::sleep(Duration::from_millis(500));
+
+    let changed = watched.path().join("tab\tand space.txt");
+    std::fs::write(&changed, "").unwrap();
+
+    let contents = wait_for(&output, |text| text.matches('\0').count() >= 3);
+    let fields = contents.split('\0').take(3).collect::<Vec<_>>();
+    assert_eq!(fields[0], path_str(&changed));
+    assert!(["created", "modified"].contains(&fields[1]), "{contents:?}");
+    assert!(humantime::parse_rfc3339(fields[2]).is_ok(), "{contents:?}");
+    assert!(!contents.contains('\n'), "{contents:?}");
+}
+
+#[cfg(unix)]
+#[test]
+fn fifo_trigger() {
+    let (_, dir) = directories();
+    let fifo = dir.path().join("witness.fifo");
+    let fifo = path_str(&fifo);
+    let mut witness = Witness::spawn(&["--fifo", fifo, "--key", "secret", "--once", "exit 3"]);
+
+    // keep triggering until witness has created the FIFO and reads from it
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !trigger(&["--fifo", fifo, "--key", "secret"]).success() {
+        assert!(Instant::now() < deadline, "FIFO was never opened");
+        std::thread::sleep(Duration::from_millis(50));
+    }
+
+    let status = witness.wait_exit(Duration::from_secs(10)).unwrap();
+    assert_eq!(status.code(), Some(3));
+}
+
+#[test]
+fn max_runs() {
+    let (watched, output) = directories();
+    let output = output_file(&output);
+
+    let command = format!("echo run >> {}; exit 4", path_str(&output));
+    let mut witness = Witness::spawn(&[
+        "--path",
+        path_str(watched.path()),
+        "--no-git-ignore",
+        "--debounce",
+        "10ms",
+        "--max-runs",
+        "2",
+        &command,
+    ]);
+    std::thread::sleep(Duration::from_millis(500));
+
+    let changed = watched.path().join("file.txt");
+    std::fs::write(&changed, "1").unwrap();
+    wait_for(&output, |text| text.lines().count() == 1);
+    std::thread::sleep(Duration::from_millis(200));
+    std::fs::write(&changed, "2").unwrap();
+
+    let status = witness.wait_exit(Duration::from_secs(10));
+    assert_eq!(status.and_then(|status| status.code()), Some(4));
+
+    // witness has exited, so the third change runs nothing
+    std::fs::write(&changed, "3").unwrap();
+    std::thread::sleep(Duration::from_millis(300));
+    assert_eq!(std::fs::read_to_string(&output).unwrap(), "run\nrun\n");
+}
+
+#[test]
+fn exit_on_stdin_eof() {
+    let (watched, output) = directories();
+    let output = output_file(&output);
+
+    let command = format!("echo started > {}; sleep 30", path_str(&output));
+    let child = Command::new(env!("CARGO_BIN_EXE_witness"))
+        .args([
+            "--shell",
+            "sh",
+            "--no-clear",
+            "--initial",
+            "--exit-on-stdin-eof",
+        ])
+        .args(["--path", path_str(watched.path()), &command])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .env_remove("WITNESS_LOG")
+        .spawn()
+        .expect("failed to spawn witness");
+    let mut witness = Witness { child };
+    let stdin = witness.child.stdin.take().unwrap();
+
+    wait_for(&output, |text| text.contains("started"));
+    assert!(witness.wait_exit(Duration::from_millis(300)).is_none());
+
+    // the parent going away closes the pipe, which shuts witness down
+    drop(stdin);
+    let status = witness.wait_exit(Duration::from_secs(10));
+    assert!(
+        status.is_some(),
+        "witness kept running after its stdin closed"
+    );
+}
+
+#[test]
+fn print_config() {
+    let output = Command::new(env!("CARGO_BIN_EXE_witness"))
+        .args([
+            "--print-config=json",
+            "--debounce",
+            "1m30s",
+            "--hmac",
+            "secret",
+        ])
+        .stderr(Stdio::null())
+        .output()
+        .expect("failed to run witness --print-config");
+    assert!(output.status.success());
+
+    let text = String::from_utf8(output.stdout).unwrap();
+    let config = serde_json::from_str::<serde_json::Value>(&text).unwrap();
+    assert_eq!(config["files"]["debounce"], "1m30s");
+    assert_eq!(config["network"]["hmac"], "<redacted>");
+    assert!(!text.contains("secret"), "{text}");
+}
+
+#[test]
+fn missing_path() {
+    let (watched, output) = directories();
+    let output = output_file(&output);
+    let dist = watched.path().join("dist");
+
+    let command = format!("echo run >> {}", path_str(&output));
+    let mut witness = Witness::spawn(&[
+        "--path",
+        path_str(&dist),
+        "--no-git-ignore",
+        "--debounce",
+        "10ms",
+        &command,
+    ]);
+    std::thread::sleep(Duration::from_millis(500));
+
+    // changes next to the missing path are ignored
+    std::fs::write(watched.path().join("README.md"), "").unwrap();
+    std::thread::sleep(Duration::from_millis(300));
+    assert!(!output.exists());
+
+    std::fs::create_dir_all(&dist).unwrap();
+    std::fs::write(dist.join("index.js"), "").unwrap();
+    wait_for(&output, |text| text.contains("run"));
+
+    assert_eq!(witness.wait_exit(Duration::from_millis(100)), None);
+}
+
+#[test]
+fn interval() {
+    let (watched, output) = directories();
+    let output = output_file(&output);
+
+    let command = format!("echo \"$WITNESS_TRIGGER_KIND\" >> {}", path_str(&output));
+    let _witness = Witness::spawn(&[
+        "--path",
+        path_str(watched.path()),
+        "--no-git-ignore",
+        "--interval",
+        "200ms",
+        &command,
+    ]);
+
+    // the command runs repeatedly without any changes
+    let text = wait_for(&output, |text| text.lines().count() >= 3);
+    assert!(text.lines().count() >= 3, "{text:?}");
+    assert!(text.lines().all(|line| line == "interval"), "{text:?}");
+}
+
+/// Run `--once` with the given `--color` and return what was printed
+fn colored_output(color: &str, command: &str) -> String {
+    let (watched, output) = directories();
+    let output = output_file(&output);
+
+    let mut witness = Witness::spawn_with_stdout(
+        &[
+            "--path",
+            path_str(watched.path()),
+            "--initial",
+            "--once",
+            "--verbose",
+            "--color",
+            color,
+            command,
+        ],
+        std::fs::File::create(&output).unwrap(),
+    );
+    assert!(witness.wait_exit(Duration::from_secs(10)).is_some());
+    std::fs::read_to_string(&output).unwrap()
+}
+
+#[test]
+fn color_never() {
+    for color in ["never", "auto"] {
+        let contents = colored_output(color, "echo hello");
+        assert!(!contents.contains('\x1b'), "{contents:?}");
+        assert!(contents.lines().any(|line| line == "> echo hello"));
+        assert!(contents.lines().any(|line| line == "exit status: 0"));
+    }
+}
+
+#[test]
+fn color_always() {
+    let contents = colored_output("always", "echo hello");
+    assert!(
+        contents.contains("\x1b[1m> echo hello\x1b[0m"),
+        "{contents:?}"
+    );
+    assert!(
+        contents.contains("\x1b[32mexit status: 0\x1b[0m"),
+        "{contents:?}"
+    );
+
+    let contents = colored_output("always", "exit 3");
+    assert!(
+        contents.contains("\x1b[31mexit status: 3\x1b[0m"),
+        "{contents:?}"
+    );
+}
+
+/// A command reading from the terminal isn't stopped for being outside of its foreground
+#[cfg(unix)]
+#[test]
+fn command_reads_terminal() {
+    use std::{
+        fs::File,
+        io::Write,
+        os::unix::{io::FromRawFd, process::CommandExt},
+    };
+
+    let (watched, output) = directories();
+    let output = output_file(&output);
+
+    let (mut terminal, controlling) = unsafe {
+        let (mut master, mut slave) = (0, 0);
+        let result = libc::openpty(
+            &mut master,
+            &mut slave,
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            std::ptr::null(),
+        );
+        assert_eq!(result, 0, "{}", std::io::Error::last_os_error());
+        (File::from_raw_fd(master), File::from_raw_fd(slave))
+    };
+
+    let command = format!("read line; echo \"$line\" > {}", path_str(&output));
+    let mut child = Command::new(env!("CARGO_BIN_EXE_witness"));
+    child
+        .args([
+            "--shell",
+            "sh",
+            "--no-clear",
+            "--no-banner",
+            "--initial",
+            "--once",
+        ])
+        .args(["--path", path_str(watched.path()), &command])
+        .stdin(controlling)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .env_remove("WITNESS_LOG");
+    // make the terminal the controlling terminal of witness, with witness in its foreground
+    unsafe {
+        child.pre_exec(|| {
+            if libc::setsid() < 0 || libc::ioctl(0, libc::TIOCSCTTY, 0) < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let mut witness = Witness {
+        child: child.spawn().unwrap(),
+    };
+
+    terminal.write_all(b"hello\n").unwrap();
+    assert_eq!(wait_for(&output, |text| !text.is_empty()), "hello\n");
+    assert!(witness.wait_exit(Duration::from_secs(10)).is_some());
+}